@@ -0,0 +1,55 @@
+#![feature(test)]
+
+extern crate compactmap;
+extern crate test;
+
+use compactmap::CompactMap;
+use test::{black_box, Bencher};
+
+const SIZE: usize = 100_000;
+
+/// Map with every third key removed, so the iterators have holes to skip.
+fn holey_map() -> CompactMap<u32> {
+    let mut map: CompactMap<u32> = (0..SIZE as u32).collect();
+    for i in (0..SIZE).step_by(3) {
+        map.remove(i);
+    }
+    map
+}
+
+#[bench]
+fn iter_nth(b: &mut Bencher) {
+    let map = holey_map();
+    b.iter(|| map.iter().nth(black_box(SIZE / 2)));
+}
+
+#[bench]
+fn iter_mut_nth(b: &mut Bencher) {
+    let mut map = holey_map();
+    b.iter(|| map.iter_mut().nth(black_box(SIZE / 2)).map(|(k, _)| k));
+}
+
+#[bench]
+fn keys_nth(b: &mut Bencher) {
+    let map = holey_map();
+    b.iter(|| map.keys().nth(black_box(SIZE / 2)));
+}
+
+#[bench]
+fn iter_sum(b: &mut Bencher) {
+    let map = holey_map();
+    b.iter(|| black_box(&map).iter().map(|(_, v)| *v as u64).sum::<u64>());
+}
+
+#[bench]
+fn iter_mut_sum(b: &mut Bencher) {
+    let mut map = holey_map();
+    b.iter(|| black_box(&mut map).iter_mut().map(|(_, v)| *v as u64).sum::<u64>());
+}
+
+#[bench]
+fn wrapped_iter_sum(b: &mut Bencher) {
+    let map: compactmap::wrapped::CompactMap<usize, u32> =
+        compactmap::wrapped::CompactMap::from_unwrapped(holey_map());
+    b.iter(|| black_box(&map).iter().map(|(_, v)| *v as u64).sum::<u64>());
+}
@@ -0,0 +1,56 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+#[macro_use]
+extern crate arbitrary;
+#[macro_use]
+extern crate compactmap;
+
+use std::collections::BTreeMap;
+
+declare_compactmap_token!(Token);
+
+#[derive(Arbitrary, Debug)]
+enum Action {
+    Insert(u16),
+    Remove(u16),
+    RemoveTyped(u16),
+    ShrinkToFit,
+    Drain,
+}
+
+// Check against BTreeMap, starting from an arbitrary map
+fuzz_target!(|input: (compactmap::wrapped::CompactMap<Token, u16>, Vec<Action>)| {
+    let (wm, actions) = input;
+    let mut cm = wm.into_unwrapped();
+    let mut model: BTreeMap<usize, u16> = cm.iter().map(|(k, &v)| (k, v)).collect();
+
+    for a in actions {
+        match a {
+            Action::Insert(x) => {
+                let k = cm.insert(x);
+                assert!(model.insert(k, x).is_none());
+            }
+            Action::Remove(k) => {
+                let k = k as usize;
+                assert_eq!(cm.remove(k), model.remove(&k));
+            }
+            Action::RemoveTyped(k) => {
+                let mut wm: compactmap::wrapped::CompactMap<Token, u16> =
+                    compactmap::wrapped::CompactMap::from_unwrapped(cm);
+                let k = k as usize;
+                assert_eq!(wm.remove(Token(k)), model.remove(&k));
+                cm = wm.into_unwrapped();
+            }
+            Action::ShrinkToFit => cm.shrink_to_fit(),
+            Action::Drain => {
+                let drained: Vec<_> = cm.drain().collect();
+                let expected: Vec<_> = model.iter().map(|(&k, &v)| (k, v)).collect();
+                assert_eq!(drained, expected);
+                model.clear();
+            }
+        }
+    }
+
+    assert!(cm.iter().map(|(k, &v)| (k, v)).eq(model.into_iter()));
+});
@@ -0,0 +1,207 @@
+use ::std::ops::{Index, IndexMut};
+use ::std::fmt;
+
+use super::{CompactMap, Iter, IterMut, Keys, Values, ValuesMut};
+
+/// Version of `CompactMap` that never reuses keys of removed entries.
+///
+/// `insert` always returns a key greater than any key it returned before,
+/// so keys handed out to external code (e.g. a C library) stay unique
+/// even after the entry is removed. Removed slots stay vacant;
+/// only [`clear`](#method.clear) starts numbering keys from zero again.
+///
+/// Use [`into_reusing`](#method.into_reusing) to switch to a regular
+/// `CompactMap` once reuse is no longer a concern.
+///
+/// ```
+/// use compactmap::ArenaMap;
+///
+/// let mut arena = ArenaMap::new();
+/// let a = arena.insert("a");
+/// let b = arena.insert("b");
+/// arena.remove(b);
+/// arena.remove(a);
+/// assert_eq!(arena.insert("c"), 2);
+///
+/// let mut map = arena.into_reusing();
+/// assert_eq!(map.insert("d"), 1);
+/// ```
+#[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ArenaMap<V> {
+    inner: CompactMap<V>,
+    next: usize,
+}
+
+impl<V> ArenaMap<V> {
+    /// Creates an empty `ArenaMap`.
+    pub fn new() -> ArenaMap<V> {
+        ArenaMap {
+            inner: CompactMap::new(),
+            next: 0,
+        }
+    }
+
+    /// Creates an empty `ArenaMap` with space for at least `capacity`
+    /// elements before resizing.
+    pub fn with_capacity(capacity: usize) -> ArenaMap<V> {
+        ArenaMap {
+            inner: CompactMap::with_capacity(capacity),
+            next: 0,
+        }
+    }
+
+    /// Inserts a value into the map and returns a key never returned before
+    /// (since the last [`clear`](#method.clear)).
+    ///
+    /// O(1) amortized while the entry with the highest key is still there.
+    /// Otherwise the map first grows back to the new key: every key skipped
+    /// since the last occupied slot becomes a vacant slot linked into the
+    /// free list of the underlying `CompactMap`, so this costs
+    /// O(`next_key()` minus one past the highest occupied key).
+    /// Alternating `insert` and `remove` of the newest entry therefore costs
+    /// O(n) per call, not O(1).
+    pub fn insert(&mut self, v: V) -> usize {
+        let i = self.next;
+        self.inner.insert_at_vacant(i, v);
+        self.next += 1;
+        i
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key
+    /// was previously in the map. The key is not given out again.
+    ///
+    /// O(1), except when removing the entry with the highest key: the
+    /// vacant slots before it are then dropped and unlinked from the free
+    /// list, which walks up to the whole free list.
+    pub fn remove(&mut self, i: usize) -> Option<V> {
+        self.inner.remove(i)
+    }
+
+    /// Returns the key the next [`insert`](#method.insert) will return.
+    pub fn next_key(&self) -> usize {
+        self.next
+    }
+
+    /// Removes all values and resets keys to start from zero.
+    /// Keeps the allocated memory; follow with
+    /// [`shrink_to_fit`](#method.shrink_to_fit) to release it.
+    pub fn clear(&mut self) {
+        self.inner.clear();
+        self.next = 0;
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get(&self, i: usize) -> Option<&V> {
+        self.inner.get(i)
+    }
+
+    /// Returns a mutable reference to the value corresponding to the key.
+    pub fn get_mut(&mut self, i: usize) -> Option<&mut V> {
+        self.inner.get_mut(i)
+    }
+
+    /// Returns the number of elements in the map.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Returns true if the map contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Returns the number of elements the map can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    /// Releases unused memory. Does not affect which keys get returned by
+    /// [`insert`](#method.insert).
+    pub fn shrink_to_fit(&mut self) {
+        self.inner.shrink_to_fit();
+    }
+
+    /// Returns an iterator visiting all key-value pairs in ascending order of the keys.
+    pub fn iter(&self) -> Iter<'_, V> {
+        self.inner.iter()
+    }
+
+    /// Returns an iterator visiting all key-value pairs in ascending order of the keys,
+    /// with mutable references to the values.
+    pub fn iter_mut(&mut self) -> IterMut<'_, V> {
+        self.inner.iter_mut()
+    }
+
+    /// Returns an iterator visiting all keys in ascending order.
+    pub fn keys(&self) -> Keys<'_, V> {
+        self.inner.keys()
+    }
+
+    /// Returns an iterator visiting all values in ascending order of the keys.
+    pub fn values(&self) -> Values<'_, V> {
+        self.inner.values()
+    }
+
+    /// Returns an iterator visiting all values in ascending order of the keys,
+    /// with mutable references.
+    pub fn values_mut(&mut self) -> ValuesMut<'_, V> {
+        self.inner.values_mut()
+    }
+
+    /// Borrows the contents as a regular `CompactMap`.
+    pub fn as_map(&self) -> &CompactMap<V> {
+        &self.inner
+    }
+
+    /// Converts into a regular `CompactMap` with the same entries,
+    /// whose `insert` reuses keys of removed entries.
+    pub fn into_reusing(self) -> CompactMap<V> {
+        self.inner
+    }
+}
+
+impl<V> Default for ArenaMap<V> {
+    fn default() -> ArenaMap<V> {
+        ArenaMap::new()
+    }
+}
+
+impl<V> Index<usize> for ArenaMap<V> {
+    type Output = V;
+
+    #[inline]
+    fn index(&self, i: usize) -> &V {
+        &self.inner[i]
+    }
+}
+
+impl<V> IndexMut<usize> for ArenaMap<V> {
+    #[inline]
+    fn index_mut(&mut self, i: usize) -> &mut V {
+        &mut self.inner[i]
+    }
+}
+
+impl<V: fmt::Debug> fmt::Debug for ArenaMap<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl<'a, V> IntoIterator for &'a ArenaMap<V> {
+    type Item = (usize, &'a V);
+    type IntoIter = Iter<'a, V>;
+
+    fn into_iter(self) -> Iter<'a, V> {
+        self.iter()
+    }
+}
+
+impl<'a, V> IntoIterator for &'a mut ArenaMap<V> {
+    type Item = (usize, &'a mut V);
+    type IntoIter = IterMut<'a, V>;
+
+    fn into_iter(self) -> IterMut<'a, V> {
+        self.iter_mut()
+    }
+}
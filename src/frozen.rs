@@ -0,0 +1,117 @@
+use ::std::ops::Index;
+use ::std::fmt;
+
+use super::{CompactMap, Iter, Keys, Values};
+
+/// Read-only version of `CompactMap`, created by
+/// [`CompactMap::freeze`](../struct.CompactMap.html#method.freeze).
+///
+/// Offers lookups and iteration only, so a map that is fully built up front
+/// can be shared between threads (e.g. behind an `Arc`) without a lock.
+/// It is `Sync` whenever `V` is. [`thaw`](#method.thaw) turns it back
+/// into a regular `CompactMap`.
+///
+/// Freezing and thawing reuse the same storage without copying.
+///
+/// ```
+/// use compactmap::CompactMap;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let mut map = CompactMap::new();
+/// let a = map.insert("a");
+/// let frozen = Arc::new(map.freeze());
+/// let f = frozen.clone();
+/// thread::spawn(move || assert_eq!(f[a], "a")).join().unwrap();
+///
+/// let mut map = Arc::try_unwrap(frozen).unwrap().thaw();
+/// map.insert("b");
+/// ```
+#[derive(Clone, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ImmutableCompactMap<V> {
+    inner: CompactMap<V>,
+}
+
+impl<V> ImmutableCompactMap<V> {
+    /// Converts back into a mutable `CompactMap` with the same entries
+    /// and free slots.
+    pub fn thaw(self) -> CompactMap<V> {
+        self.inner
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get(&self, i: usize) -> Option<&V> {
+        self.inner.get(i)
+    }
+
+    /// Returns true if there is a value at the key.
+    pub fn contains_key(&self, i: usize) -> bool {
+        self.inner.get(i).is_some()
+    }
+
+    /// Returns the number of elements in the map.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Returns true if the map contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Returns an iterator visiting all key-value pairs in ascending order of the keys.
+    pub fn iter(&self) -> Iter<'_, V> {
+        self.inner.iter()
+    }
+
+    /// Returns an iterator visiting all keys in ascending order.
+    pub fn keys(&self) -> Keys<'_, V> {
+        self.inner.keys()
+    }
+
+    /// Returns an iterator visiting all values in ascending order of the keys.
+    pub fn values(&self) -> Values<'_, V> {
+        self.inner.values()
+    }
+
+    /// Borrows the contents as a regular `CompactMap`.
+    pub fn as_map(&self) -> &CompactMap<V> {
+        &self.inner
+    }
+}
+
+impl<V> From<CompactMap<V>> for ImmutableCompactMap<V> {
+    fn from(map: CompactMap<V>) -> ImmutableCompactMap<V> {
+        ImmutableCompactMap { inner: map }
+    }
+}
+
+impl<V> Index<usize> for ImmutableCompactMap<V> {
+    type Output = V;
+
+    #[inline]
+    fn index(&self, i: usize) -> &V {
+        &self.inner[i]
+    }
+}
+
+impl<V: fmt::Debug> fmt::Debug for ImmutableCompactMap<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl<V: fmt::Display> fmt::Display for ImmutableCompactMap<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl<'a, V> IntoIterator for &'a ImmutableCompactMap<V> {
+    type Item = (usize, &'a V);
+    type IntoIter = Iter<'a, V>;
+
+    fn into_iter(self) -> Iter<'a, V> {
+        self.iter()
+    }
+}
@@ -1,49 +1,219 @@
 #![deny(missing_docs)]
 #![allow(unknown_lints)]
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
 
 //! A map-esque data structure that small integer keys for you on insertion.
 //! Key of removed entries are reused for new insertions.
 //! Underlying data is stored in a vector, keys are just indexes of that vector.
 //! The main trick is keeping in-place linked list of freed indexes for reuse.
 //!
-//! Serde is supported. If you need pre-computed length at serialization time
-//! (for example, for bincode), use `serde_ser_len` feature.
+//! Serde is supported. The length is always known at serialization time, so
+//! formats like bincode or postcard work (the `serde_ser_len` feature is deprecated
+//! and does nothing).
+//! Deserializing allocates space up to the highest key, so for untrusted input use
+//! [`deserialize_with_limit`](struct.CompactMap.html#method.deserialize_with_limit)
+//! or enable `serde_safe` feature, which limits keys to below 2<sup>20</sup>.
+//! [`serde_seq`](serde_seq/index.html) provides an alternative sequence format.
+//!
+//! Zero-copy deserialization with [rkyv](https://docs.rs/rkyv) is available
+//! with `rkyv` feature. `rkyv_validation` feature additionally allows checking
+//! untrusted archives with `rkyv::check_archived_root`.
+//!
+//! `bincode2` feature implements bincode 2's native `Encode` and `Decode`,
+//! in the same format as its serde support produces.
+//!
+//! `rand` feature adds [random sampling](struct.CompactMap.html#method.sample_entry) of entries.
+//!
+//! `arbitrary` feature implements `arbitrary::Arbitrary` for fuzzing.
+//! `proptest` feature provides [a strategy](fn.compactmap_strategy.html) for property testing.
+//! `schemars` feature implements `schemars::JsonSchema` for the serde format.
+//! `sync` feature provides [a `RwLock` wrapper](sync/struct.RwCompactMap.html)
+//! for sharing a map between threads.
+//! `allocator_api` feature (nightly only) adds `CompactMap::new_in` for custom allocators.
 //!
 //! If you are worried about losing strict typing advantages because of those 
 //! `usize`s, you can use [special wrapper](wrapped/struct.CompactMap.html)
 //!
+//! If keys must never be reused after removal, use [`ArenaMap`](arena/struct.ArenaMap.html).
+//! Versioned keys that detect reuse are provided by [`slotmap`](slotmap/index.html).
+//! For keys that fit into `Option` without extra space, see [`nz`](nz/index.html).
+//! Maps that are no longer modified can be [frozen](frozen/struct.ImmutableCompactMap.html).
+//!
 //! See also: [Slab](https://docs.rs/slab)
 
+#[cfg(feature = "rkyv")]
+extern crate rkyv;
+#[cfg(feature = "proptest")]
+extern crate proptest;
+
 use std::mem;
-use std::usize;
 use std::hash::Hash;
 use std::hash::Hasher;
+use std::hash::BuildHasher;
+use std::hash::BuildHasherDefault;
+use std::collections::hash_map::DefaultHasher;
 use std::cmp::Ordering;
 use std::iter::FromIterator;
-use std::ops::{Index, IndexMut};
+use std::ops::{Bound, Index, IndexMut, RangeBounds};
 use std::slice;
 use std::vec;
 use std::fmt;
 use std::clone::Clone;
 use std::iter::DoubleEndedIterator;
+use std::iter::FusedIterator;
+use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "allocator_api")]
+use std::alloc::{Allocator, Global};
+
+/// Impls that work with any allocator when the `allocator_api` feature
+/// is on, and are plain impls for `CompactMap<V>` otherwise.
+/// Trait impls are written as `impl[generics] (Trait) for CompactMap { .. }`,
+/// or `for Type<params> { .. }` for the owning iterators,
+/// anything else is the body of an inherent impl.
+macro_rules! impl_any_alloc {
+    ($(#[$attr:meta])* impl[$($g:tt)*] ($($tr:tt)*) for CompactMap { $($body:tt)* }) => {
+        #[cfg(not(feature = "allocator_api"))]
+        $(#[$attr])*
+        impl<$($g)*> $($tr)* for CompactMap<V> { $($body)* }
+        #[cfg(feature = "allocator_api")]
+        $(#[$attr])*
+        impl<$($g)*, A: Allocator> $($tr)* for CompactMap<V, A> { $($body)* }
+    };
+    ($(#[$attr:meta])* impl[$($g:tt)*] ($($tr:tt)*) for &$lt:lifetime CompactMap { $($body:tt)* }) => {
+        #[cfg(not(feature = "allocator_api"))]
+        $(#[$attr])*
+        impl<$($g)*> $($tr)* for &$lt CompactMap<V> { $($body)* }
+        #[cfg(feature = "allocator_api")]
+        $(#[$attr])*
+        impl<$($g)*, A: Allocator> $($tr)* for &$lt CompactMap<V, A> { $($body)* }
+    };
+    ($(#[$attr:meta])* impl[$($g:tt)*] ($($tr:tt)*) for &$lt:lifetime mut CompactMap { $($body:tt)* }) => {
+        #[cfg(not(feature = "allocator_api"))]
+        $(#[$attr])*
+        impl<$($g)*> $($tr)* for &$lt mut CompactMap<V> { $($body)* }
+        #[cfg(feature = "allocator_api")]
+        $(#[$attr])*
+        impl<$($g)*, A: Allocator> $($tr)* for &$lt mut CompactMap<V, A> { $($body)* }
+    };
+    ($(#[$attr:meta])* impl[$($g:tt)*] ($($tr:tt)*) for $ty:ident<$($p:tt),+> { $($body:tt)* }) => {
+        #[cfg(not(feature = "allocator_api"))]
+        $(#[$attr])*
+        impl<$($g)*> $($tr)* for $ty<$($p),+> { $($body)* }
+        #[cfg(feature = "allocator_api")]
+        $(#[$attr])*
+        impl<$($g)*, A: Allocator> $($tr)* for $ty<$($p),+, A> { $($body)* }
+    };
+    ($($body:tt)*) => {
+        #[cfg(not(feature = "allocator_api"))]
+        impl<V> CompactMap<V> { $($body)* }
+        #[cfg(feature = "allocator_api")]
+        impl<V, A: Allocator> CompactMap<V, A> { $($body)* }
+    };
+}
+
+/// Names an iterator type inside `impl_any_alloc!` (and `impl_any_alloc_wrapped!`):
+/// `with_alloc!(IntoIter<V>)` is `IntoIter<V>`, or `IntoIter<V, A>` with the
+/// `allocator_api` feature.
+#[cfg(not(feature = "allocator_api"))]
+macro_rules! with_alloc {
+    ($ty:ident<$($p:tt),+>) => { $ty<$($p),+> };
+}
+#[cfg(feature = "allocator_api")]
+macro_rules! with_alloc {
+    ($ty:ident<$($p:tt),+>) => { $ty<$($p),+, A> };
+}
 
 const SENTINEL: usize = usize::MAX;
 
+/// A slot of the vector backing a `CompactMap`.
+///
+/// Only needed for low-level access through
+/// [`into_raw_parts`](struct.CompactMap.html#method.into_raw_parts),
+/// [`from_raw_parts`](struct.CompactMap.html#method.from_raw_parts),
+/// [`raw_entry`](struct.CompactMap.html#method.raw_entry) and
+/// [`raw_iter`](struct.CompactMap.html#method.raw_iter).
+///
+/// Slots can be read through the methods below, but not built outside
+/// of this crate: raw parts always come from an existing map.
 #[derive(Clone)]
-enum Entry<V> {
+#[cfg_attr(feature = "rkyv", derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize))]
+#[cfg_attr(feature = "rkyv_validation", archive(check_bytes))]
+pub enum Entry<V> {
+    /// Vacant slot. Contains index of the next vacant slot in the free list
+    /// or `usize::MAX` if this is the last one.
+    #[non_exhaustive]
     Empty(usize),
+    /// Slot holding a value
+    #[non_exhaustive]
     Occupied(V),
 }
 
 impl<V> Entry<V> {
-    fn is_not_empty(&self) -> bool {
+    /// Returns `true` if the slot holds a value.
+    pub fn is_occupied(&self) -> bool {
+        matches!(*self, Entry::Occupied(_))
+    }
+
+    /// Returns the value held in the slot, if any.
+    pub fn value(&self) -> Option<&V> {
+        match *self {
+            Entry::Occupied(ref v) => Some(v),
+            Entry::Empty(_) => None,
+        }
+    }
+
+    /// Returns the value held in the slot for modification, if any.
+    pub fn value_mut(&mut self) -> Option<&mut V> {
+        match *self {
+            Entry::Occupied(ref mut v) => Some(v),
+            Entry::Empty(_) => None,
+        }
+    }
+
+    /// For a vacant slot, returns the index of the next vacant slot
+    /// in the free list, or `usize::MAX` if this is the last one.
+    /// Returns `None` for an occupied slot.
+    pub fn next_free(&self) -> Option<usize> {
         match *self {
-            Entry::Empty(_) => false,
-            _ => true,
+            Entry::Empty(next) => Some(next),
+            Entry::Occupied(_) => None,
         }
     }
 }
 
+/// Turns an iterator over raw slots, as returned by
+/// [`CompactMap::raw_iter`](struct.CompactMap.html#method.raw_iter),
+/// into an iterator over key-value pairs.
+///
+/// Keys are counted from zero, so the iterator must start at the first slot.
+///
+/// # Examples
+///
+/// ```
+/// use compactmap::{skip_holes, CompactMap};
+///
+/// let mut map: CompactMap<u32> = (0..5).collect();
+/// map.remove(1);
+/// map.remove(3);
+/// assert_eq!(skip_holes(map.raw_iter()).collect::<Vec<_>>(), [(0, &0), (2, &2), (4, &4)]);
+/// ```
+pub fn skip_holes<'a, V: 'a, I>(iter: I) -> impl Iterator<Item = (usize, &'a V)>
+    where I: IntoIterator<Item = &'a Entry<V>>
+{
+    iter.into_iter().enumerate().filter_map(|(i, e)| match *e {
+        Entry::Occupied(ref v) => Some((i, v)),
+        Entry::Empty(_) => None,
+    })
+}
+
+/// Same as [`skip_holes`](fn.skip_holes.html), for iterators over mutable values
+/// of all slots, as returned by [`CompactMap::raw_iter_mut`](struct.CompactMap.html#method.raw_iter_mut).
+pub fn skip_holes_mut<'a, V: 'a, I>(iter: I) -> impl Iterator<Item = (usize, &'a mut V)>
+    where I: IntoIterator<Item = Option<&'a mut V>>
+{
+    iter.into_iter().enumerate().filter_map(|(i, v)| v.map(|v| (i, v)))
+}
+
 /// A map that chooses small integer keys for you.
 /// You store something into this map and then access it by ID returned by it.
 /// For small V entries are expected to take 16 bytes.
@@ -61,10 +231,26 @@ impl<V> Entry<V> {
 ///     println!("{}:{}", id, val);
 /// }
 /// ```
+#[cfg(not(feature = "allocator_api"))]
 #[derive(Clone)]
 pub struct CompactMap<V> {
     data: Vec<Entry<V>>,
     free_head: usize,
+    occupied: usize,
+}
+
+/// Vec-based map that assigns the keys of inserted values itself.
+///
+/// This is the `allocator_api` version: the slots live in a `Vec<_, A>`,
+/// so the map can use a custom allocator, see [`new_in`](#method.new_in).
+/// Methods that work on the map in place and the owning iterators are
+/// available for any `A`, the ones that build new maps only for `Global`.
+#[cfg(feature = "allocator_api")]
+#[derive(Clone)]
+pub struct CompactMap<V, A: Allocator = Global> {
+    data: Vec<Entry<V>, A>,
+    free_head: usize,
+    occupied: usize,
 }
 
 impl<V> CompactMap<V> {
@@ -80,6 +266,7 @@ impl<V> CompactMap<V> {
         CompactMap {
             data: vec![],
             free_head: SENTINEL,
+            occupied: 0,
         }
     }
 
@@ -96,9 +283,118 @@ impl<V> CompactMap<V> {
         CompactMap {
             data: Vec::with_capacity(capacity),
             free_head: SENTINEL,
+            occupied: 0,
+        }
+    }
+
+    /// Creates a map with keys `0..len`, the value at key `k` being `f(k)`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    /// let map = CompactMap::from_fn(3, |k| k * 10);
+    /// assert_eq!(map.values().collect::<Vec<_>>(), [&0, &10, &20]);
+    /// assert!(map.is_dense());
+    /// ```
+    pub fn from_fn<F>(len: usize, mut f: F) -> Self
+        where F: FnMut(usize) -> V
+    {
+        let mut data = Vec::with_capacity(len);
+        data.extend((0..len).map(|k| Entry::Occupied(f(k))));
+        CompactMap {
+            data,
+            free_head: SENTINEL,
+            occupied: len,
+        }
+    }
+
+    /// Fallible version of [`from_fn`](#method.from_fn): stops at the first
+    /// error and returns it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    /// let words = ["1", "2", "x"];
+    /// assert!(CompactMap::try_from_fn(2, |k| words[k].parse::<u8>()).is_ok());
+    /// assert!(CompactMap::try_from_fn(3, |k| words[k].parse::<u8>()).is_err());
+    /// ```
+    pub fn try_from_fn<F, E>(len: usize, mut f: F) -> Result<Self, E>
+        where F: FnMut(usize) -> Result<V, E>
+    {
+        let mut data = Vec::with_capacity(len);
+        for k in 0..len {
+            data.push(Entry::Occupied(f(k)?));
+        }
+        Ok(CompactMap {
+            data,
+            free_head: SENTINEL,
+            occupied: len,
+        })
+    }
+
+    /// Builds the map with the pairs at their keys.
+    /// For duplicate keys, the last value wins.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let map = CompactMap::from_keyed_pairs(vec![(2, "c"), (0, "a"), (2, "x")]);
+    /// assert_eq!(map.iter().collect::<Vec<_>>(), [(0, &"a"), (2, &"x")]);
+    /// ```
+    pub fn from_keyed_pairs<I>(iter: I) -> Self
+        where I: IntoIterator<Item = (usize, V)>
+    {
+        let mut c = CompactMap::new();
+        c.extend_keyed(iter);
+        c
+    }
+}
+
+#[cfg(feature = "allocator_api")]
+impl<V, A: Allocator> CompactMap<V, A> {
+    /// Creates an empty `CompactMap` that allocates its slots with `alloc`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #![feature(allocator_api)]
+    /// use std::alloc::Global;
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map: CompactMap<&str, Global> = CompactMap::new_in(Global);
+    /// let a = map.insert("a");
+    /// assert_eq!(map[a], "a");
+    /// assert_eq!(map.remove(a), Some("a"));
+    /// ```
+    pub fn new_in(alloc: A) -> Self {
+        CompactMap {
+            data: Vec::new_in(alloc),
+            free_head: SENTINEL,
+            occupied: 0,
+        }
+    }
+
+    /// Creates an empty `CompactMap` with space for at least `capacity`
+    /// elements, allocated with `alloc`.
+    pub fn with_capacity_in(capacity: usize, alloc: A) -> Self {
+        CompactMap {
+            data: Vec::with_capacity_in(capacity, alloc),
+            free_head: SENTINEL,
+            occupied: 0,
         }
     }
 
+    /// Returns a reference to the allocator of the map.
+    pub fn allocator(&self) -> &A {
+        self.data.allocator()
+    }
+}
+
+impl_any_alloc! {
     /// Returns capacity of the underlying vector.
     #[inline]
     pub fn capacity(&self) -> usize {
@@ -132,17 +428,164 @@ impl<V> CompactMap<V> {
     /// let mut a = CompactMap::new();
     /// a.insert("a");
     /// a.clear();
-    /// assert!(a.is_empty_slow());
+    /// assert!(a.is_empty());
     /// ```
     pub fn clear(&mut self) {
         self.free_head = SENTINEL;
+        self.occupied = 0;
         self.data.clear();
     }
 
     /// Iterating the map to check if it is empty.
     /// O(n) where n is historical maximum element count.
+    #[deprecated(since = "0.4.0", note = "use is_empty()")]
     pub fn is_empty_slow(&self) -> bool {
-        self.len_slow() == 0
+        self.iter().next().is_none()
+    }
+
+    /// Returns `true` if the map contains no elements. O(1).
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.occupied == 0
+    }
+
+    /// Returns the number of elements in the map. O(1).
+    ///
+    /// The count is updated on every insertion and removal rather than
+    /// cached lazily, which would need a `Cell` and make the map `!Sync`.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.occupied
+    }
+
+    /// Returns the number of occupied slots, same as [`len`](#method.len).
+    #[inline]
+    pub fn occupied_count(&self) -> usize {
+        self.occupied
+    }
+
+    /// Returns the number of vacant slots that will be reused before
+    /// the underlying vector grows. Unlike [`capacity`](#method.capacity),
+    /// does not count reserved, but unused memory.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::with_capacity(10);
+    /// map.insert("a");
+    /// map.insert("b");
+    /// map.insert("c");
+    /// map.remove(1);
+    /// assert_eq!(map.occupied_count(), 2);
+    /// assert_eq!(map.free_count(), 1);
+    /// ```
+    #[inline]
+    pub fn free_count(&self) -> usize {
+        self.data.len() - self.occupied
+    }
+
+    /// Same as [`free_count`](#method.free_count). O(1).
+    #[inline]
+    pub fn count_holes(&self) -> usize {
+        self.free_count()
+    }
+
+    /// Returns an iterator over vacant keys, i.e. the ones `insert` would
+    /// reuse before growing the map, in ascending order. O(n).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map: CompactMap<_> = (0..5).collect();
+    /// map.remove(1);
+    /// map.remove(3);
+    /// assert_eq!(map.iter_holes().collect::<Vec<_>>(), [1, 3]);
+    /// assert_eq!(map.count_holes(), 2);
+    /// ```
+    pub fn iter_holes(&self) -> impl Iterator<Item = usize> + '_ {
+        self.data.iter().enumerate().filter(|&(_, e)| !e.is_occupied()).map(|(i, _)| i)
+    }
+
+    /// Returns the vacant keys in the order `insert` would reuse them,
+    /// by walking the free list. O(number of vacant slots).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map: CompactMap<_> = (0..5).collect();
+    /// map.remove(3);
+    /// map.remove(1);
+    /// assert_eq!(map.free_list_vec(), [1, 3]);
+    /// assert_eq!(map.free_list_len(), 2);
+    /// ```
+    pub fn free_list_vec(&self) -> Vec<usize> {
+        self.walk_free_list().collect()
+    }
+
+    /// Returns the length of the free list by walking it.
+    /// Same as [`free_count`](#method.free_count) in a well-formed map.
+    /// O(number of vacant slots).
+    pub fn free_list_len(&self) -> usize {
+        self.walk_free_list().count()
+    }
+
+    /// Follows the free list from its head. Stops at a link to an occupied
+    /// or out of bounds slot, and after `data.len()` steps in case of a cycle.
+    fn walk_free_list(&self) -> impl Iterator<Item = usize> + '_ {
+        let mut i = self.free_head;
+        std::iter::from_fn(move || match self.data.get(i) {
+            Some(&Entry::Empty(next)) => Some(mem::replace(&mut i, next)),
+            _ => None,
+        }).take(self.data.len())
+    }
+
+    /// Returns `true` if the map has no vacant slots, i.e. its keys are
+    /// exactly `0..len()`, like indexes of a `Vec`. O(1).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map: CompactMap<_> = vec!['a', 'b', 'c'].into_iter().collect();
+    /// assert!(map.is_dense());
+    /// assert_eq!(map.dense_len(), Some(3));
+    /// map.remove(1);
+    /// assert!(!map.is_dense());
+    /// assert_eq!(map.dense_len(), None);
+    /// ```
+    #[inline]
+    pub fn is_dense(&self) -> bool {
+        self.free_head == SENTINEL
+    }
+
+    /// Returns the number of elements if the map [is dense](#method.is_dense),
+    /// `None` otherwise. O(1).
+    #[inline]
+    pub fn dense_len(&self) -> Option<usize> {
+        if self.is_dense() {
+            Some(self.data.len())
+        } else {
+            None
+        }
+    }
+
+    /// Panics if the map [is not dense](#method.is_dense).
+    pub fn assert_dense(&self) {
+        if !self.is_dense() {
+            panic!(
+                "CompactMap is not dense: {} vacant slot(s) among {}, the first free key is {}",
+                self.free_count(),
+                self.data.len(),
+                self.free_head,
+            );
+        }
     }
 
     /// Inserts a value into the map. The map generates and returns ID of
@@ -154,14 +597,14 @@ impl<V> CompactMap<V> {
     /// use compactmap::CompactMap;
     ///
     /// let mut map = CompactMap::new();
-    /// assert_eq!(map.is_empty_slow(), true);
+    /// assert_eq!(map.is_empty(), true);
     /// assert_eq!(map.insert(37), 0);
-    /// assert_eq!(map.is_empty_slow(), false);
+    /// assert_eq!(map.is_empty(), false);
     ///
     /// assert_eq!(map.insert(37), 1);
     /// assert_eq!(map.insert(37), 2);
     /// assert_eq!(map.insert(44), 3);
-    /// assert_eq!(map.len_slow(), 4);
+    /// assert_eq!(map.len(), 4);
     /// ```
     pub fn insert(&mut self, v: V) -> usize {
         self.insert_with(move |_|v)
@@ -192,7 +635,7 @@ impl<V> CompactMap<V> {
         where F: FnOnce(usize) -> V
     {
         let head = self.free_head;
-        if head == SENTINEL {
+        let key = if head == SENTINEL {
             let key = self.data.len();
             let entry = Entry::Occupied(f(key));
             self.data.push(entry);
@@ -207,12 +650,175 @@ impl<V> CompactMap<V> {
                 Entry::Occupied(_) => unreachable!(),
             }
             key
+        };
+        self.occupied += 1;
+        self.debug_validate();
+        key
+    }
+
+    /// Same as [`insert_with`](#method.insert_with): the function
+    /// receives the key the value is going to be stored at.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use compactmap::*;
+    /// struct Entity { id: usize }
+    ///
+    /// let mut cm = CompactMap::new();
+    /// let id = cm.insert_with_key(|id| Entity { id });
+    /// assert_eq!(cm[id].id, id);
+    /// ```
+    #[inline]
+    pub fn insert_with_key<F>(&mut self, f: F) -> usize
+        where F: FnOnce(usize) -> V
+    {
+        self.insert_with(f)
+    }
+
+    /// Same as [`insert_with`](#method.insert_with), for code written
+    /// against arena-style `allocate_with` APIs.
+    #[inline]
+    pub fn allocate_with<F>(&mut self, f: F) -> usize
+        where F: FnOnce(usize) -> V
+    {
+        self.insert_with(f)
+    }
+
+    /// Fallible version of [`allocate_with`](#method.allocate_with).
+    /// The function receives the key the value is going to be stored at,
+    /// the slot is only taken if it returns `Ok`. On `Err` the map is left
+    /// unchanged, so the same key is handed out by the next insertion.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use compactmap::*;
+    /// let mut cm: CompactMap<(usize, &str)> = CompactMap::new();
+    ///
+    /// assert_eq!(cm.try_allocate_with(|_| Err::<_, ()>(())), Err(()));
+    /// assert!(cm.is_empty());
+    /// let id = cm.try_allocate_with(|id| Ok::<_, ()>((id, "player"))).unwrap();
+    /// assert_eq!(cm[id], (0, "player"));
+    /// ```
+    pub fn try_allocate_with<F, E>(&mut self, f: F) -> Result<usize, E>
+        where F: FnOnce(usize) -> Result<V, E>
+    {
+        let key = if self.free_head == SENTINEL { self.data.len() } else { self.free_head };
+        let v = f(key)?;
+        let actual = self.insert(v);
+        debug_assert_eq!(actual, key);
+        Ok(actual)
+    }
+
+    /// Inserts a value like [`insert`](#method.insert), but only if that
+    /// needs no reallocation: into a vacant slot or into spare capacity.
+    /// Otherwise gives the value back.
+    ///
+    /// Together with [`with_capacity`](#method.with_capacity) this makes
+    /// a map with a fixed number of slots. Note that the actual capacity
+    /// may be larger than requested.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::with_capacity(1);
+    /// assert_eq!(map.try_insert("a"), Ok(0));
+    /// assert_eq!(map.try_insert("b"), Err("b"));
+    /// map.remove(0);
+    /// assert_eq!(map.try_insert("b"), Ok(0));
+    /// ```
+    pub fn try_insert(&mut self, v: V) -> Result<usize, V> {
+        if self.free_head == SENTINEL && self.data.len() == self.data.capacity() {
+            return Err(v);
+        }
+        Ok(self.insert(v))
+    }
+
+    /// Inserts all values from the iterator and returns their keys, in the
+    /// same order as calling [`insert`](#method.insert) for each value would.
+    /// Vacant slots are filled first, the remaining values are appended at once.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map: CompactMap<_> = vec!["a", "b", "c"].into_iter().collect();
+    /// map.remove(1);
+    /// assert_eq!(map.bulk_insert(vec!["x", "y", "z"]), [1, 3, 4]);
+    /// assert_eq!(map.len(), 5);
+    /// ```
+    pub fn bulk_insert<I>(&mut self, iter: I) -> Vec<usize>
+        where I: IntoIterator<Item = V>
+    {
+        let mut iter = iter.into_iter();
+        let mut keys = Vec::with_capacity(iter.size_hint().0);
+        while self.free_head != SENTINEL {
+            match iter.next() {
+                Some(v) => keys.push(self.insert(v)),
+                None => return keys,
+            }
+        }
+        let start = self.data.len();
+        {
+            // counted as we go, so that a panicking iterator leaves a consistent map
+            let occupied = &mut self.occupied;
+            self.data.extend(iter.map(|v| {
+                *occupied += 1;
+                Entry::Occupied(v)
+            }));
+        }
+        keys.extend(start..self.data.len());
+        self.debug_validate();
+        keys
+    }
+
+    /// Same as [`bulk_insert`](#method.bulk_insert) for a fixed number of
+    /// values, returning the keys without allocating.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::new();
+    /// let [a, b] = map.bulk_insert_array(["a", "b"]);
+    /// assert_eq!((map[a], map[b]), ("a", "b"));
+    /// ```
+    pub fn bulk_insert_array<const N: usize>(&mut self, values: [V; N]) -> [usize; N] {
+        values.map(|v| self.insert(v))
+    }
+
+    /// Inserts a value into the map, returning the generated key
+    /// and a mutable reference to the stored value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::new();
+    /// let (key, v) = map.insert_returning_ref(vec![]);
+    /// v.push(key);
+    /// assert_eq!(map[0], [0]);
+    /// ```
+    pub fn insert_returning_ref(&mut self, v: V) -> (usize, &mut V) {
+        let key = self.insert(v);
+        match self.data[key] {
+            Entry::Occupied(ref mut v) => (key, v),
+            Entry::Empty(_) => unreachable!(),
         }
     }
 
     /// Removes a key from the map, returning the value at the key if the key
     /// was previously in the map.
     ///
+    /// Removing the highest key also trims off all the vacant slots before it,
+    /// taking O(number of vacant slots) in that case.
+    ///
     /// ```
     /// use compactmap::CompactMap;
     ///
@@ -234,188 +840,2781 @@ impl<V> CompactMap<V> {
         if let Entry::Occupied(v) = mem::replace(&mut self.data[i], empty_entry) {
             if i == self.data.len() - 1 {
                 self.data.truncate(i);
+                let len = self.data.iter().rposition(Entry::is_occupied).map_or(0, |j| j + 1);
+                self.unlink_tail(len);
             } else {
                 self.free_head = i;
             }
+            self.occupied -= 1;
+            self.debug_validate();
             Some(v)
         } else {
             unreachable!();
         }
     }
 
-    /// Returns a reference to the value corresponding to the key.
-    pub fn get(&self, i: usize) -> Option<&V> {
-        self.data.get(i).and_then(|entry| match *entry {
+    /// Removes the value at the key if `predicate` returns `true` for it.
+    /// Returns the removed value, or `None` if the key is vacant or the
+    /// predicate returned `false`.
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::new();
+    /// let id = map.insert(3);
+    /// assert_eq!(map.remove_if(id, |&v| v > 5), None);
+    /// assert_eq!(map.remove_if(id, |&v| v < 5), Some(3));
+    /// ```
+    pub fn remove_if<F>(&mut self, i: usize, predicate: F) -> Option<V>
+        where F: FnOnce(&V) -> bool
+    {
+        self.remove_if_mut(i, |v| predicate(v))
+    }
+
+    /// Same as [`remove_if`](#method.remove_if), but the predicate may
+    /// modify the value, e.g. decrement a reference count stored in it.
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::new();
+    /// let id = map.insert(("texture", 2));
+    /// let release = |e: &mut (&str, u32)| { e.1 -= 1; e.1 == 0 };
+    /// assert_eq!(map.remove_if_mut(id, release), None);
+    /// assert_eq!(map.remove_if_mut(id, release), Some(("texture", 0)));
+    /// ```
+    pub fn remove_if_mut<F>(&mut self, i: usize, predicate: F) -> Option<V>
+        where F: FnOnce(&mut V) -> bool
+    {
+        if self.get_mut(i).map(predicate) == Some(true) {
+            self.remove(i)
+        } else {
+            None
+        }
+    }
+
+    /// Removes the entry with the lowest key and returns it,
+    /// or `None` if the map is empty.
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::new();
+    /// map.insert("a");
+    /// map.insert("b");
+    /// map.remove(0);
+    /// assert_eq!(map.pop_first(), Some((1, "b")));
+    /// assert_eq!(map.pop_first(), None);
+    /// ```
+    pub fn pop_first(&mut self) -> Option<(usize, V)> {
+        let i = self.data.iter().position(Entry::is_occupied)?;
+        self.remove(i).map(|v| (i, v))
+    }
+
+    /// Removes the entry with the highest key and returns it,
+    /// or `None` if the map is empty.
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::new();
+    /// map.insert("a");
+    /// map.insert("b");
+    /// assert_eq!(map.pop_last(), Some((1, "b")));
+    /// assert_eq!(map.pop_last(), Some((0, "a")));
+    /// assert_eq!(map.pop_last(), None);
+    /// ```
+    pub fn pop_last(&mut self) -> Option<(usize, V)> {
+        let i = self.max_occupied_key()?;
+        self.remove(i).map(|v| (i, v))
+    }
+
+    /// Removes all the given keys from the map, ignoring vacant ones.
+    /// Returns the number of removed entries.
+    ///
+    /// Unlike calling [`remove`](#method.remove) in a loop, the free list is
+    /// rebuilt and trailing vacant slots are stripped off once, at the end.
+    /// O(n) plus O(1) per key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map: CompactMap<_> = vec!['a', 'b', 'c', 'd'].into_iter().collect();
+    /// assert_eq!(map.remove_many(vec![3, 1, 1, 10]), 2);
+    /// assert_eq!(map.keys().collect::<Vec<_>>(), [0, 2]);
+    /// ```
+    pub fn remove_many<I>(&mut self, keys: I) -> usize
+        where I: IntoIterator<Item = usize>
+    {
+        let before = self.occupied;
+        for k in keys {
+            self.take_unindexed(k);
+        }
+        self.reindex();
+        before - self.occupied
+    }
+
+    /// Same as [`remove_many`](#method.remove_many), but returns
+    /// the removed values in ascending order of their keys.
+    pub fn remove_many_collect<I>(&mut self, keys: I) -> Vec<V>
+        where I: IntoIterator<Item = usize>
+    {
+        let mut removed = vec![];
+        for k in keys {
+            if let Some(v) = self.take_unindexed(k) {
+                removed.push((k, v));
+            }
+        }
+        self.reindex();
+        removed.sort_by_key(|&(k, _)| k);
+        removed.into_iter().map(|(_, v)| v).collect()
+    }
+
+    /// Keeps only the entries for which `f` returns `true`. The closure gets
+    /// mutable access to each value, so it can update the entries it keeps.
+    /// Trailing vacant slots are stripped off afterwards. O(n).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map: CompactMap<u32> = (0..6).collect();
+    /// map.retain(|k, v| {
+    ///     *v *= 10;
+    ///     k % 2 == 0
+    /// });
+    /// assert_eq!(map.iter().collect::<Vec<_>>(), [(0, &0), (2, &20), (4, &40)]);
+    /// assert_eq!(map.insert(1), 3);
+    /// ```
+    pub fn retain<F>(&mut self, mut f: F)
+        where F: FnMut(usize, &mut V) -> bool
+    {
+        let before = self.occupied;
+        for i in 0..self.data.len() {
+            let keep = match self.data[i] {
+                Entry::Occupied(ref mut v) => f(i, v),
+                Entry::Empty(_) => true,
+            };
+            if !keep {
+                self.take_unindexed(i);
+            }
+        }
+        if self.occupied != before {
+            self.reindex();
+        }
+    }
+
+    /// Same as [`retain`](#method.retain), but returns the number of
+    /// removed entries.
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut cache: CompactMap<u32> = (0..10).collect();
+    /// assert_eq!(cache.retain_and_count(|_, age| *age < 7), 3);
+    /// assert_eq!(cache.retain_and_count(|_, age| *age < 7), 0);
+    /// ```
+    pub fn retain_and_count<F>(&mut self, f: F) -> usize
+        where F: FnMut(usize, &mut V) -> bool
+    {
+        let before = self.occupied;
+        self.retain(f);
+        before - self.occupied
+    }
+
+    /// Same as [`retain`](#method.retain), named after `Vec::retain_mut`.
+    pub fn retain_mut<F>(&mut self, f: F)
+        where F: FnMut(usize, &mut V) -> bool
+    {
+        self.retain(f)
+    }
+
+    /// Removes a key from the map, returning the key and the value if the key
+    /// was previously in the map.
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::new();
+    /// let id = map.insert("a");
+    /// assert_eq!(map.remove_entry(id), Some((id, "a")));
+    /// assert_eq!(map.remove_entry(id), None);
+    /// ```
+    pub fn remove_entry(&mut self, i: usize) -> Option<(usize, V)> {
+        self.remove(i).map(|v| (i, v))
+    }
+
+    /// Removes the value at the key like `Vec::swap_remove`: the entry with
+    /// the highest key is moved into the freed slot, so a dense map stays dense.
+    /// Returns the removed value and, unless the removed entry was the
+    /// highest one, where the moved entry was relocated from and to,
+    /// for updating references to it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::{CompactMap, Relocation};
+    ///
+    /// let mut map: CompactMap<_> = vec!["a", "b", "c"].into_iter().collect();
+    /// assert_eq!(map.swap_remove(0), Some(("a", Some(Relocation { old_key: 2, new_key: 0 }))));
+    /// assert_eq!(map[0], "c");
+    /// assert_eq!(map.swap_remove(1), Some(("b", None)));
+    /// assert_eq!(map.swap_remove(1), None);
+    /// assert!(map.is_dense());
+    /// ```
+    pub fn swap_remove(&mut self, i: usize) -> Option<(V, Option<Relocation>)> {
+        self.get(i)?;
+        let last = self.max_occupied_key()?;
+        if i == last {
+            return self.remove(i).map(|v| (v, None));
+        }
+        let moved = self.remove(last)?;
+        let v = mem::replace(&mut self[i], moved);
+        Some((v, Some(Relocation { old_key: last, new_key: i })))
+    }
+
+    /// Returns a mutable reference to the value at the key, inserting
+    /// `default` there if the key is vacant. Grows the map if needed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::new();
+    /// *map.get_or_insert(3, 0) += 10;
+    /// *map.get_or_insert(3, 0) += 10;
+    /// assert_eq!(map[3], 20);
+    /// assert_eq!(map.len(), 1);
+    /// assert_eq!(map.insert(5), 2);
+    /// ```
+    pub fn get_or_insert(&mut self, i: usize, default: V) -> &mut V {
+        self.get_or_insert_with(i, move || default)
+    }
+
+    /// Returns a mutable reference to the value at the key, inserting
+    /// the value returned by `f` there if the key is vacant.
+    /// Grows the map if needed. `f` is not called if the key is occupied.
+    pub fn get_or_insert_with<F>(&mut self, i: usize, f: F) -> &mut V
+        where F: FnOnce() -> V
+    {
+        self.get_or_insert_with_key(i, move |_| f())
+    }
+
+    /// Same as [`get_or_insert`](#method.get_or_insert), inserting
+    /// `V::default()` if the key is vacant.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut counters: CompactMap<u32> = CompactMap::new();
+    /// for &k in &[2, 0, 2] {
+    ///     *counters.get_or_insert_default(k) += 1;
+    /// }
+    /// assert_eq!(counters.iter().collect::<Vec<_>>(), [(0, &1), (2, &2)]);
+    /// ```
+    pub fn get_or_insert_default(&mut self, i: usize) -> &mut V
+        where V: Default
+    {
+        self.get_or_insert_with(i, V::default)
+    }
+
+    /// Applies `modify` to the value at the key if it is occupied, otherwise
+    /// inserts the value returned by `insert` there. Returns a mutable
+    /// reference to the value. Only one of the functions is called.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut words: CompactMap<Vec<&str>> = CompactMap::new();
+    /// for &(k, w) in &[(1, "a"), (1, "b"), (3, "c")] {
+    ///     words.modify_or_insert(k, |v| v.push(w), || vec![w]);
+    /// }
+    /// assert_eq!(words[1], ["a", "b"]);
+    /// assert_eq!(words[3], ["c"]);
+    /// ```
+    pub fn modify_or_insert<M, F>(&mut self, i: usize, modify: M, insert: F) -> &mut V
+        where M: FnOnce(&mut V), F: FnOnce() -> V
+    {
+        let mut modify = Some(modify);
+        let v = self.get_or_insert_with(i, || {
+            modify = None;
+            insert()
+        });
+        if let Some(modify) = modify {
+            modify(v);
+        }
+        v
+    }
+
+    /// Same as [`get_or_insert_with`](#method.get_or_insert_with), but
+    /// `f` receives the key, for values that must contain their key.
+    /// `f` is called at most once, and not at all if the key is occupied.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// struct Entity { id: usize, name: String }
+    ///
+    /// let mut map = CompactMap::new();
+    /// let e = map.get_or_insert_with_key(4, |id| Entity { id, name: "foo".into() });
+    /// assert_eq!(e.id, 4);
+    /// e.name.push('!');
+    /// let e = map.get_or_insert_with_key(4, |_| unreachable!());
+    /// assert_eq!(e.name, "foo!");
+    /// ```
+    pub fn get_or_insert_with_key<F>(&mut self, i: usize, f: F) -> &mut V
+        where F: FnOnce(usize) -> V
+    {
+        match self.data.get(i) {
+            Some(&Entry::Occupied(_)) => (),
+            _ => self.insert_at_vacant(i, f(i)),
+        }
+        match self.data[i] {
+            Entry::Occupied(ref mut v) => v,
+            Entry::Empty(_) => unreachable!(),
+        }
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get(&self, i: usize) -> Option<&V> {
+        self.data.get(i).and_then(|entry| match *entry {
             Entry::Empty(_) => None,
             Entry::Occupied(ref v) => Some(v),
         })
     }
 
-    /// Returns a mutable reference to the value corresponding to the key.
-    pub fn get_mut(&mut self, i: usize) -> Option<&mut V> {
-        self.data.get_mut(i).and_then(|entry| match *entry {
-            Entry::Empty(_) => None,
-            Entry::Occupied(ref mut v) => Some(v),
-        })
+    /// Returns a mutable reference to the value corresponding to the key.
+    pub fn get_mut(&mut self, i: usize) -> Option<&mut V> {
+        self.data.get_mut(i).and_then(|entry| match *entry {
+            Entry::Empty(_) => None,
+            Entry::Occupied(ref mut v) => Some(v),
+        })
+    }
+
+    /// Looks up several keys at once. Keys may repeat.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let map: CompactMap<_> = vec!["a", "b"].into_iter().collect();
+    /// assert_eq!(map.get_multiple([1, 5, 1]), [Some(&"b"), None, Some(&"b")]);
+    /// ```
+    pub fn get_multiple<const N: usize>(&self, keys: [usize; N]) -> [Option<&V>; N] {
+        keys.map(|k| self.get(k))
+    }
+
+    /// Returns mutable references to the values at two distinct keys.
+    /// Returns `None` if `a == b` or if either key is vacant.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map: CompactMap<_> = vec![1, 2].into_iter().collect();
+    /// {
+    ///     let (a, b) = map.get_pair_mut(1, 0).unwrap();
+    ///     ::std::mem::swap(a, b);
+    /// }
+    /// assert_eq!(map.values().cloned().collect::<Vec<_>>(), [2, 1]);
+    /// assert!(map.get_pair_mut(0, 0).is_none());
+    /// assert!(map.get_pair_mut(0, 2).is_none());
+    /// ```
+    pub fn get_pair_mut(&mut self, a: usize, b: usize) -> Option<(&mut V, &mut V)> {
+        if a == b || a.max(b) >= self.data.len() {
+            return None;
+        }
+        let (lo, hi) = self.data.split_at_mut(a.max(b));
+        let (x, y) = match (&mut lo[a.min(b)], &mut hi[0]) {
+            (&mut Entry::Occupied(ref mut x), &mut Entry::Occupied(ref mut y)) => (x, y),
+            _ => return None,
+        };
+        if a < b {
+            Some((x, y))
+        } else {
+            Some((y, x))
+        }
+    }
+
+    /// Calls `f` on the value at the key, if any, and returns it.
+    /// `f` is not called if the key is vacant.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::new();
+    /// let i = map.insert(1);
+    /// assert_eq!(map.modify(i, |v| *v += 1), Some(&mut 2));
+    /// assert_eq!(map.modify(i + 1, |v| *v += 1), None);
+    /// ```
+    pub fn modify<F>(&mut self, i: usize, f: F) -> Option<&mut V>
+        where F: FnOnce(&mut V)
+    {
+        match self.get_mut(i) {
+            Some(v) => {
+                f(v);
+                Some(v)
+            }
+            None => None,
+        }
+    }
+
+    /// Calls `f` on the value at the key, if any, and returns its result.
+    /// `f` is not called if the key is vacant.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::new();
+    /// let i = map.insert(vec![1, 2]);
+    /// assert_eq!(map.update(i, |v| v.pop()), Some(Some(2)));
+    /// assert_eq!(map[i], [1]);
+    /// ```
+    pub fn update<F, R>(&mut self, i: usize, f: F) -> Option<R>
+        where F: FnOnce(&mut V) -> R
+    {
+        self.get_mut(i).map(f)
+    }
+
+    /// Replaces the value at the key, returning the old one.
+    /// If the key is vacant, returns `None` and `new_value` is dropped
+    /// without being inserted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::new();
+    /// let i = map.insert("a");
+    /// assert_eq!(map.replace(i, "b"), Some("a"));
+    /// assert_eq!(map.replace(i + 1, "c"), None);
+    /// assert_eq!(map.len(), 1);
+    /// ```
+    pub fn replace(&mut self, i: usize, new_value: V) -> Option<V> {
+        self.get_mut(i).map(|v| mem::replace(v, new_value))
+    }
+
+    /// Moves the value at key `from` to the vacant key `to`, growing the map
+    /// if `to` is out of bounds. Moving an entry to its own key does nothing.
+    ///
+    /// Fails without modifying the map if `from` is vacant or `to` is occupied.
+    /// O(number of vacant slots).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::{CompactMap, MoveError};
+    ///
+    /// let mut map: CompactMap<_> = vec!["a", "b"].into_iter().collect();
+    /// map.move_entry(0, 3).unwrap();
+    /// assert_eq!(map.get(0), None);
+    /// assert_eq!(map[3], "a");
+    /// assert_eq!(map.move_entry(1, 3), Err(MoveError::OccupiedTarget { key: 3 }));
+    /// assert_eq!(map.move_entry(0, 2), Err(MoveError::VacantSource { key: 0 }));
+    /// ```
+    pub fn move_entry(&mut self, from: usize, to: usize) -> Result<(), MoveError> {
+        if self.get(from).is_none() {
+            return Err(MoveError::VacantSource { key: from });
+        }
+        if from == to {
+            return Ok(());
+        }
+        if self.get(to).is_some() {
+            return Err(MoveError::OccupiedTarget { key: to });
+        }
+        let v = self.remove(from).unwrap();
+        self.insert_at_vacant(to, v);
+        Ok(())
+    }
+
+    /// Like [`move_entry`](#method.move_entry), but panics on failure.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `from` is vacant or `to` is occupied.
+    pub fn relocate_entry(&mut self, from: usize, to: usize) {
+        if let Err(e) = self.move_entry(from, to) {
+            panic!("relocate_entry failed: {}", e);
+        }
+    }
+
+    /// Returns the entry with the lowest key whose value satisfies the predicate.
+    /// O(n).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let map: CompactMap<_> = vec![1, 4, 6, 9].into_iter().collect();
+    /// assert_eq!(map.find(|&v| v % 2 == 0), Some((1, &4)));
+    /// assert_eq!(map.find_key(|&v| v > 5), Some(2));
+    /// assert_eq!(map.find(|&v| v > 10), None);
+    /// ```
+    pub fn find<F>(&self, mut f: F) -> Option<(usize, &V)>
+        where F: FnMut(&V) -> bool
+    {
+        self.iter().find(|&(_, v)| f(v))
+    }
+
+    /// Returns the lowest key whose value satisfies the predicate. O(n).
+    pub fn find_key<F>(&self, f: F) -> Option<usize>
+        where F: FnMut(&V) -> bool
+    {
+        self.find(f).map(|(k, _)| k)
+    }
+
+    /// Same as [`find`](#method.find), but returns a mutable reference.
+    pub fn find_mut<F>(&mut self, mut f: F) -> Option<(usize, &mut V)>
+        where F: FnMut(&V) -> bool
+    {
+        self.iter_mut().find(|e| f(e.1))
+    }
+
+    /// Returns `true` if some value in the map equals `value`. O(n).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let map: CompactMap<_> = vec!["a", "b"].into_iter().collect();
+    /// assert!(map.contains_value(&"b"));
+    /// assert!(!map.contains_value(&"c"));
+    /// assert!(map.contains_value_by(|v| v.len() == 1));
+    /// ```
+    pub fn contains_value(&self, value: &V) -> bool
+        where V: PartialEq
+    {
+        self.contains_value_by(|v| v == value)
+    }
+
+    /// Returns `true` if some value in the map satisfies the predicate. O(n).
+    pub fn contains_value_by<F>(&self, f: F) -> bool
+        where F: FnMut(&V) -> bool
+    {
+        self.find(f).is_some()
+    }
+
+    /// Returns an iterator visiting all key-value pairs in unspecified order.
+    /// The iterator's element type is `(usize, &'r V)`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::new();
+    /// map.insert("a");
+    /// map.insert("c");
+    /// map.insert("b");
+    ///
+    /// // Print `1: a`, `2: b` and `3: c`.
+    /// for (key, value) in map.iter() {
+    ///     println!("{}: {}", key, value);
+    /// }
+    /// ```
+    pub fn iter(&self) -> Iter<'_, V> {
+        IntoIterator::into_iter(self)
+    }
+
+    /// Returns an iterator visiting all key-value pairs in unspecified order,
+    /// with mutable references to the values.
+    /// The iterator's element type is `(usize, &'r mut V)`
+    pub fn iter_mut(&mut self) -> IterMut<'_, V> {
+        IntoIterator::into_iter(self)
+    }
+
+    /// Returns an iterator visiting key-value pairs with keys `>= start`,
+    /// in ascending key order. Skips the slots before `start` in O(1).
+    /// If `start` is out of bounds, the iterator is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let map: CompactMap<char> = "abcdef".chars().collect();
+    /// assert_eq!(map.iter_from(4).collect::<Vec<_>>(), [(4, &'e'), (5, &'f')]);
+    /// assert_eq!(map.iter_from(10).next(), None);
+    /// ```
+    pub fn iter_from(&self, start: usize) -> Iter<'_, V> {
+        self.range(start..)
+    }
+
+    /// Same as [`iter_from`](#method.iter_from), but with mutable
+    /// references to the values.
+    pub fn iter_from_mut(&mut self, start: usize) -> IterMut<'_, V> {
+        self.range_mut(start..)
+    }
+}
+
+impl<V> CompactMap<V> {
+    /// Returns a cursor positioned at `key`, or at the nearest occupied key
+    /// after it if `key` is vacant. If there is none, the cursor is at the end.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map: CompactMap<char> = "abcd".chars().collect();
+    /// map.remove(1);
+    /// let mut cursor = map.cursor_at(1);
+    /// assert_eq!((cursor.key(), cursor.value()), (2, &'c'));
+    /// cursor.move_prev();
+    /// assert_eq!(cursor.key(), 0);
+    /// cursor.move_prev();
+    /// assert!(cursor.is_at_end());
+    /// cursor.move_prev();
+    /// assert_eq!(cursor.key(), 3);
+    /// ```
+    pub fn cursor_at(&self, key: usize) -> Cursor<'_, V> {
+        Cursor {
+            pos: self.next_occupied(key),
+            map: self,
+        }
+    }
+
+    /// Same as [`cursor_at`](#method.cursor_at), but the cursor can modify
+    /// and remove entries.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map: CompactMap<u32> = (0..5).collect();
+    /// {
+    ///     let mut cursor = map.cursor_at_mut(0);
+    ///     while !cursor.is_at_end() {
+    ///         if *cursor.value() % 2 == 1 {
+    ///             cursor.remove_current();
+    ///         } else {
+    ///             *cursor.value_mut() *= 10;
+    ///             cursor.move_next();
+    ///         }
+    ///     }
+    /// }
+    /// assert_eq!(map.values().collect::<Vec<_>>(), [&0, &20, &40]);
+    /// ```
+    pub fn cursor_at_mut(&mut self, key: usize) -> CursorMut<'_, V> {
+        CursorMut {
+            pos: self.next_occupied(key),
+            map: self,
+        }
+    }
+}
+
+impl_any_alloc! {
+    /// Returns an iterator visiting key-value pairs with keys in `range`,
+    /// in ascending key order. The range is clamped to the allocated slots;
+    /// an empty or reversed range gives an empty iterator.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let map: CompactMap<char> = "abcdef".chars().collect();
+    /// assert_eq!(map.range(1..3).collect::<Vec<_>>(), [(1, &'b'), (2, &'c')]);
+    /// assert_eq!(map.range(4..=100).count(), 2);
+    /// assert_eq!(map.range(3..1).next(), None);
+    /// ```
+    pub fn range<R: RangeBounds<usize>>(&self, range: R) -> Iter<'_, V> {
+        let (start, end) = self.slot_range(&range);
+        Iter {
+            iter: self.data[start..end].iter(),
+            counter: start,
+            counter_back: end,
+        }
+    }
+
+    /// Same as [`range`](#method.range), but with mutable
+    /// references to the values.
+    pub fn range_mut<R: RangeBounds<usize>>(&mut self, range: R) -> IterMut<'_, V> {
+        let (start, end) = self.slot_range(&range);
+        IterMut {
+            iter: self.data[start..end].iter_mut(),
+            counter: start,
+            counter_back: end,
+        }
+    }
+
+    /// Resolves key bounds into a `start..end` slot range within `data`.
+    fn slot_range<R: RangeBounds<usize>>(&self, range: &R) -> (usize, usize) {
+        let start = match range.start_bound() {
+            Bound::Included(&s) => s,
+            Bound::Excluded(&s) => s.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&e) => e.saturating_add(1),
+            Bound::Excluded(&e) => e,
+            Bound::Unbounded => self.data.len(),
+        };
+        let end = end.min(self.data.len());
+        (start.min(end), end)
+    }
+
+    /// Returns an iterator visiting all key-value pairs in unspecified order,
+    /// the keys, consuming the original `CompactMap`.
+    /// The iterator's element type is `(usize, V)`.
+    #[allow(clippy::should_implement_trait)]
+    pub fn into_iter(self) -> with_alloc!(IntoIter<V>) {
+        IntoIterator::into_iter(self)
+    }
+
+    /// Returns an iterator visiting all keys in ascending order,
+    /// consuming the original `CompactMap`.
+    /// The iterator's element type is `usize`.
+    pub fn into_keys(self) -> with_alloc!(IntoKeys<V>) {
+        IntoKeys { iter: self.into_iter() }
+    }
+
+    /// Returns an iterator visiting all values in ascending order of the keys,
+    /// consuming the original `CompactMap`.
+    /// The iterator's element type is `V`.
+    pub fn into_values(self) -> with_alloc!(IntoValues<V>) {
+        IntoValues { iter: self.into_iter() }
+    }
+
+    /// Returns an iterator visiting all keys in some order.
+    /// The iterator's element type is `usize`.
+    pub fn keys(&self) -> Keys<'_, V> {
+        Keys { iter: self.iter() }
+    }
+    /// Returns an iterator visiting all values in ascending order of the keys.
+    /// The iterator's element type is `&'r V`.
+    pub fn values(&self) -> Values<'_, V> {
+        Values { iter: self.iter() }
+    }
+    /// Returns an iterator visiting all values in ascending order of the keys.
+    /// The iterator's element type is `&'r mut V`.
+    pub fn values_mut(&mut self) -> ValuesMut<'_, V> {
+        ValuesMut { iter_mut: self.iter_mut() }
+    }
+
+    /// Returns an iterator over all windows of `size` consecutive entries,
+    /// like `slice::windows`. Consecutive means in iteration order: vacant
+    /// slots are skipped, so the keys within a window may have gaps.
+    /// Each window is a new `Vec`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map: CompactMap<u32> = (0..5).collect();
+    /// map.remove(2);
+    /// let sums: Vec<u32> = map.windows_of_size(2).map(|w| w.iter().map(|&(_, v)| v).sum()).collect();
+    /// assert_eq!(sums, [1, 4, 7]);
+    /// ```
+    pub fn windows_of_size(&self, size: usize) -> impl Iterator<Item = Vec<(usize, &V)>> + '_ {
+        assert!(size != 0, "window size must be non-zero");
+        let mut iter = self.iter();
+        let mut window = Vec::with_capacity(size);
+        ::std::iter::from_fn(move || {
+            if window.len() == size {
+                window.remove(0);
+            }
+            while window.len() < size {
+                window.push(iter.next()?);
+            }
+            Some(window.clone())
+        })
+    }
+
+    /// Iterates the map to get number of elements.
+    /// O(n) where n is historical maximum element count.
+    #[deprecated(since = "0.4.0", note = "use len()")]
+    pub fn len_slow(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Trims the `CompactMap` of some excess capacity.
+    ///
+    /// Rescans the whole map to reindex empty slots. O(n).
+    ///
+    /// Empty slots that are not at the end of the map remain allocated.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    /// let mut map: CompactMap<&str> = CompactMap::with_capacity(10);
+    /// assert_eq!(map.capacity(), 10);
+    /// map.shrink_to_fit();
+    /// assert_eq!(map.capacity(), 0);
+    /// map.insert("qwe");
+    /// map.insert("345");
+    /// map.insert("555");
+    /// map.remove(1);
+    /// map.shrink_to_fit();
+    /// assert_eq!(map.capacity(), 3);
+    /// ```
+    pub fn shrink_to_fit(&mut self) {
+        // `reindex` trims trailing vacant slots, so it goes first
+        self.reindex();
+        self.data.shrink_to_fit();
+    }
+
+    /// Strips trailing vacant slots off like [`shrink_to_fit`](#method.shrink_to_fit),
+    /// but keeps the allocated memory. O(n) if there were any, O(1) otherwise.
+    ///
+    /// The map's own operations, including [`from_raw_parts`](#method.from_raw_parts),
+    /// never leave vacant slots at the end, so this only matters after slots
+    /// were swapped through [`raw_entry_mut`](#method.raw_entry_mut).
+    pub fn shrink_free_list(&mut self) {
+        if self.truncate_vacant_tail() {
+            self.reindex();
+        }
+    }
+
+    /// Returns the highest occupied key, or `None` if the map is empty. O(1).
+    ///
+    /// This is the last slot: the map never keeps vacant slots after the
+    /// last occupied one. `remove`, bulk operations like [`truncate`](#method.truncate)
+    /// and [`from_raw_parts`](#method.from_raw_parts) all strip them off.
+    /// Only slots swapped through [`raw_entry_mut`](#method.raw_entry_mut)
+    /// can break this, see [`trailing_holes`](#method.trailing_holes).
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map: CompactMap<char> = "abcd".chars().collect();
+    /// assert_eq!(map.max_occupied_key(), Some(3));
+    /// map.remove(2);
+    /// map.remove(3);
+    /// assert_eq!(map.max_occupied_key(), Some(1));
+    /// map.clear();
+    /// assert_eq!(map.max_occupied_key(), None);
+    /// ```
+    pub fn max_occupied_key(&self) -> Option<usize> {
+        self.data.len().checked_sub(1)
+    }
+
+    /// Returns the number of vacant slots after the last occupied one,
+    /// which [`shrink_free_list`](#method.shrink_free_list) would strip off.
+    /// Always zero unless slots were swapped through
+    /// [`raw_entry_mut`](#method.raw_entry_mut).
+    pub fn trailing_holes(&self) -> usize {
+        self.data.iter().rev().take_while(|e| !e.is_occupied()).count()
+    }
+
+    /// Moves entries to keys `0..len()` in place, keeping their order, and
+    /// returns a mapping from old keys to new ones: `Some(new_key)` for each
+    /// occupied slot up to the highest key, `None` for each vacant one.
+    /// Keeps the allocated memory; follow with
+    /// [`shrink_to_fit`](#method.shrink_to_fit) to release it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map: CompactMap<_> = vec!['a', 'b', 'c', 'd'].into_iter().collect();
+    /// map.remove(0);
+    /// map.remove(2);
+    /// assert_eq!(map.compact(), [None, Some(0), None, Some(1)]);
+    /// assert_eq!(map.into_iter().collect::<Vec<_>>(), [(0, 'b'), (1, 'd')]);
+    /// ```
+    pub fn compact(&mut self) -> Vec<Option<usize>> {
+        self.truncate_vacant_tail();
+        let mut next = 0;
+        let remap = (0..self.data.len()).map(|i| {
+            if self.data[i].is_occupied() {
+                self.data.swap(next, i);
+                next += 1;
+                Some(next - 1)
+            } else {
+                None
+            }
+        }).collect();
+        self.data.truncate(next);
+        self.free_head = SENTINEL;
+        self.debug_validate();
+        remap
+    }
+
+    /// Returns a copy of the map with entries moved to keys `0..len()`,
+    /// keeping their order, and a mapping from old keys to new ones:
+    /// `Some(new_key)` for each occupied slot and `None` for each vacant one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map: CompactMap<_> = vec!['a', 'b', 'c'].into_iter().collect();
+    /// map.remove(0);
+    /// let (compacted, remap) = map.compact_and_remap();
+    /// assert_eq!(compacted.into_iter().collect::<Vec<_>>(), [(0, 'b'), (1, 'c')]);
+    /// assert_eq!(remap, [None, Some(0), Some(1)]);
+    /// ```
+    pub fn compact_and_remap(&self) -> (CompactMap<V>, Vec<Option<usize>>)
+        where V: Clone
+    {
+        let mut data = Vec::with_capacity(self.occupied);
+        let remap = self.data.iter().map(|e| match *e {
+            Entry::Occupied(ref v) => {
+                data.push(Entry::Occupied(v.clone()));
+                Some(data.len() - 1)
+            }
+            Entry::Empty(_) => None,
+        }).collect();
+        (CompactMap { data, free_head: SENTINEL, occupied: self.occupied }, remap)
+    }
+
+    /// Same as [`compact_and_remap`](#method.compact_and_remap),
+    /// but consumes the map instead of cloning the values.
+    pub fn into_compact_and_remap(self) -> (CompactMap<V>, Vec<Option<usize>>) {
+        let occupied = self.occupied;
+        let mut data = Vec::with_capacity(occupied);
+        let remap = self.data.into_iter().map(|e| match e {
+            Entry::Occupied(v) => {
+                data.push(Entry::Occupied(v));
+                Some(data.len() - 1)
+            }
+            Entry::Empty(_) => None,
+        }).collect();
+        (CompactMap { data, free_head: SENTINEL, occupied }, remap)
+    }
+
+    /// Creates the reverse lookup map: for each entry, its key is stored
+    /// at the key equal to its value.
+    ///
+    /// Fails if two entries have the same value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let map: CompactMap<usize> = vec![2, 0, 5].into_iter().collect();
+    /// let inv = map.invert().unwrap();
+    /// assert_eq!(inv.into_iter().collect::<Vec<_>>(), [(0, 1), (2, 0), (5, 2)]);
+    ///
+    /// let map: CompactMap<usize> = vec![2, 0, 2].into_iter().collect();
+    /// let e = map.invert().unwrap_err();
+    /// assert_eq!((e.duplicate_value, e.key1, e.key2), (2, 0, 2));
+    /// ```
+    pub fn invert(&self) -> Result<CompactMap<usize>, InvertError>
+        where V: Copy + Into<usize>
+    {
+        let mut inv = CompactMap::new();
+        for (k, &v) in self {
+            let i = v.into();
+            if let Some(&prev) = inv.get(i) {
+                return Err(InvertError { duplicate_value: i, key1: prev, key2: k });
+            }
+            inv.put_unindexed(i, k);
+        }
+        inv.reindex();
+        Ok(inv)
+    }
+
+    /// Removes all entries with keys `>= len`, keeping the allocated memory.
+    /// Has no effect if `len` is beyond the allocated slots. O(n).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map: CompactMap<char> = "abcde".chars().collect();
+    /// map.truncate(3);
+    /// assert_eq!(map.len(), 3);
+    /// assert_eq!(map.get(3), None);
+    /// assert_eq!(map.insert('f'), 3);
+    /// ```
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.data.len() {
+            return;
+        }
+        let removed = self.data[len..].iter().filter(|e| e.is_occupied()).count();
+        self.occupied -= removed;
+        self.data.truncate(len);
+        self.reindex();
+    }
+}
+
+impl<V> CompactMap<V> {
+    /// Moves all entries with keys `>= at` into a new map, keeping their keys.
+    /// Keys below `at` are vacant in the returned map. O(n).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map: CompactMap<char> = "abcde".chars().collect();
+    /// let tail = map.split_off(3);
+    /// assert_eq!(map.values().collect::<String>(), "abc");
+    /// assert_eq!(tail.iter().collect::<Vec<_>>(), [(3, &'d'), (4, &'e')]);
+    /// ```
+    pub fn split_off(&mut self, at: usize) -> CompactMap<V> {
+        if at >= self.data.len() {
+            return CompactMap::new();
+        }
+        let mut data = Vec::with_capacity(self.data.len());
+        data.extend((0..at).map(|_| Entry::Empty(SENTINEL)));
+        data.extend(self.data.drain(at..));
+        let other = CompactMap::from_entries(data);
+        self.occupied -= other.occupied;
+        self.reindex();
+        other
+    }
+}
+
+impl_any_alloc! {
+    /// Fills every vacant key below `len` with values from `f`, growing the
+    /// map if needed, so that no free slots remain below `len`.
+    /// Returns the newly occupied keys in ascending order. O(n).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::new();
+    /// map.insert('a');
+    /// map.insert('b');
+    /// map.remove(0);
+    /// assert_eq!(map.fill_to(4, || 'z'), [0, 2, 3]);
+    /// assert_eq!(map.values().collect::<String>(), "zbzz");
+    /// ```
+    pub fn fill_to<F>(&mut self, len: usize, mut f: F) -> Vec<usize>
+        where F: FnMut() -> V
+    {
+        // slots are filled one by one, so that a panicking `f` leaves a consistent map
+        self.reindex_ascending();
+        let mut filled = vec![];
+        while self.free_head < len {
+            filled.push(self.insert_with(|_| f()));
+        }
+        while self.data.len() < len {
+            filled.push(self.insert_with(|_| f()));
+        }
+        filled
+    }
+
+    /// Fills every vacant slot with the value returned by `f` for its key,
+    /// in ascending order of the keys. Does not grow the map: afterwards
+    /// it [is dense](#method.is_dense) with the same number of slots. O(n).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map: CompactMap<usize> = (0..5).collect();
+    /// map.remove(1);
+    /// map.remove(3);
+    /// map.fill_gaps_with(|i| i * 10);
+    /// assert!(map.is_dense());
+    /// assert_eq!(map.values().cloned().collect::<Vec<_>>(), [0, 10, 2, 30, 4]);
+    /// ```
+    pub fn fill_gaps_with<F>(&mut self, mut f: F)
+        where F: FnMut(usize) -> V
+    {
+        self.reindex_ascending();
+        while self.free_head != SENTINEL {
+            self.insert_with(&mut f);
+        }
+    }
+
+    /// Same as [`fill_to`](#method.fill_to), filling with `V::default()`.
+    pub fn fill_to_default(&mut self, len: usize) -> Vec<usize>
+        where V: Default
+    {
+        self.fill_to(len, V::default)
+    }
+
+    /// Returns an iterator visiting all key-value pairs in ascending order of
+    /// the keys, emptying (but not consuming) the original `CompactMap`.
+    /// The iterator's element type is `(usize, V)`. Keeps the allocated memory for reuse.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::new();
+    /// map.insert("a");
+    /// map.insert("b");
+    /// map.insert("c");
+    /// map.remove(1);
+    ///
+    /// let vec: Vec<(usize, &str)> = map.drain().collect();
+    ///
+    /// assert_eq!(vec, [(0, "a"), (2, "c")]);
+    /// assert!(map.is_empty());
+    /// assert!(map.capacity() > 0);
+    /// ```
+    pub fn drain(&mut self) -> with_alloc!(Drain<'_, V>) {
+        fn filter<A>((i, v): (usize, Entry<A>)) -> Option<(usize, A)> {
+            match v {
+                Entry::Empty(_) => None,
+                Entry::Occupied(x) => Some((i,x)),
+            }
+        }
+        let filter: DrainFilter<V> = filter; // coerce to fn ptr
+
+        self.free_head = SENTINEL;
+        self.occupied = 0;
+        Drain { iter: self.data.drain(..).enumerate().filter_map(filter) }
+    }
+}
+
+impl<V> CompactMap<V> {
+    /// Same as [`drain`](#method.drain), but the iterator owns the entries
+    /// instead of borrowing the map, so the map can be used or dropped while
+    /// the values are still being processed. The allocated memory moves
+    /// into the iterator along with the entries. O(1).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map: CompactMap<_> = vec!["a", "b", "c"].into_iter().collect();
+    /// map.remove(1);
+    /// let drained = map.drain_owned();
+    /// map.insert("d");
+    /// drop(map);
+    /// assert_eq!(drained.collect::<Vec<_>>(), [(0, "a"), (2, "c")]);
+    /// ```
+    pub fn drain_owned(&mut self) -> DrainOwned<V> {
+        DrainOwned { inner: mem::take(self).into_iter() }
+    }
+}
+
+impl_any_alloc! {
+    /// Removes all values and returns them in ascending order of the keys,
+    /// dropping the keys. Unlike [`into_values`](#method.into_values) this
+    /// keeps the map and its allocated memory for reuse.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map: CompactMap<_> = vec!["a", "b", "c"].into_iter().collect();
+    /// map.remove(1);
+    /// assert_eq!(map.take_all(), ["a", "c"]);
+    /// assert!(map.is_empty());
+    /// ```
+    pub fn take_all(&mut self) -> Vec<V> {
+        let mut values = Vec::with_capacity(self.occupied);
+        values.extend(self.data.drain(..).filter_map(|e| match e {
+            Entry::Occupied(v) => Some(v),
+            Entry::Empty(_) => None,
+        }));
+        self.free_head = SENTINEL;
+        self.occupied = 0;
+        values
+    }
+}
+
+impl<V> CompactMap<V> {
+    /// Removes the entries with keys in `range`, returning them as an iterator
+    /// in ascending order of the keys. Other entries are left untouched.
+    ///
+    /// Entries not consumed by the iterator are removed when it is dropped.
+    /// If the range reaches the end of the map, trailing vacant slots are
+    /// then trimmed off like in [`remove`](#method.remove).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map: CompactMap<char> = "abcde".chars().collect();
+    /// assert_eq!(map.drain_range(1..3).collect::<Vec<_>>(), [(1, 'b'), (2, 'c')]);
+    /// assert_eq!(map.len(), 3);
+    /// assert_eq!(map.drain_range(3..).next(), Some((3, 'd')));
+    /// assert_eq!(map.len(), 1);
+    /// ```
+    pub fn drain_range<R: RangeBounds<usize>>(&mut self, range: R) -> DrainRange<'_, V> {
+        let (start, end) = self.slot_range(&range);
+        DrainRange {
+            map: self,
+            counter: start,
+            counter_back: end,
+        }
+    }
+
+    /// Decomposes the map into the backing vector and the head of the free list.
+    ///
+    /// Index of each entry in the vector is the key. The head is either the
+    /// index of the first `Entry::Empty` slot to be reused or `usize::MAX`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::new();
+    /// map.insert("a");
+    /// map.insert("b");
+    /// map.insert("c");
+    /// map.remove(1);
+    ///
+    /// let (data, free_head) = map.into_raw_parts();
+    /// assert_eq!(data.len(), 3);
+    /// assert_eq!(free_head, 1);
+    /// ```
+    pub fn into_raw_parts(self) -> (Vec<Entry<V>>, usize) {
+        (self.data, self.free_head)
+    }
+}
+
+impl_any_alloc! {
+    /// Returns the raw slot at the given index, vacant or not,
+    /// or `None` if it is out of bounds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::new();
+    /// map.insert("a");
+    /// map.insert("b");
+    /// map.remove(0);
+    ///
+    /// assert_eq!(map.raw_entry(0).unwrap().next_free(), Some(usize::MAX));
+    /// assert_eq!(map.raw_entry(1).unwrap().value(), Some(&"b"));
+    /// assert!(map.raw_entry(2).is_none());
+    /// ```
+    pub fn raw_entry(&self, i: usize) -> Option<&Entry<V>> {
+        self.data.get(i)
+    }
+
+    /// Returns the raw slot at the given index for modification,
+    /// or `None` if it is out of bounds.
+    ///
+    /// The free list is not updated. Once the reference is dropped, it should still
+    /// be well-formed, as described in [`from_raw_parts`](#method.from_raw_parts),
+    /// and the number of occupied slots should be the same as before. For example,
+    /// the value may be changed through [`Entry::value_mut`](enum.Entry.html#method.value_mut),
+    /// but the slot should not be swapped with a vacant one.
+    ///
+    /// Breaking these rules is not undefined behaviour, but leaves the map
+    /// inconsistent: later insertions may panic or overwrite values, and
+    /// [`validate`](#method.validate) reports the problem.
+    pub fn raw_entry_mut(&mut self, i: usize) -> Option<&mut Entry<V>> {
+        self.data.get_mut(i)
+    }
+
+    /// Returns an iterator over all raw slots, vacant or not, in order of the keys.
+    ///
+    /// See [`skip_holes`](fn.skip_holes.html) for turning it into key-value pairs.
+    pub fn raw_iter(&self) -> slice::Iter<'_, Entry<V>> {
+        self.data.iter()
+    }
+
+    /// Returns an iterator over all raw slots, vacant or not, in order of the keys,
+    /// yielding the value of each occupied slot for modification and `None`
+    /// for each vacant one.
+    ///
+    /// See [`skip_holes_mut`](fn.skip_holes_mut.html) for turning it into key-value pairs.
+    pub fn raw_iter_mut(&mut self) -> impl DoubleEndedIterator<Item = Option<&mut V>> + ExactSizeIterator + '_ {
+        self.data.iter_mut().map(Entry::value_mut)
+    }
+
+    /// Returns an iterator over all windows of `size` consecutive raw slots,
+    /// vacant or not, i.e. over key ranges of `size` keys.
+    /// The window starting at slot `i` covers keys `i..i + size`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is 0.
+    pub fn key_windows(&self, size: usize) -> slice::Windows<'_, Entry<V>> {
+        self.data.windows(size)
+    }
+}
+
+impl<V> CompactMap<V> {
+    /// Reconstructs the map from parts obtained by
+    /// [`into_raw_parts`](#method.into_raw_parts).
+    ///
+    /// Fails if the free list is malformed, i.e. unless:
+    ///
+    /// * `free_head` is either `usize::MAX` or an index of an `Entry::Empty` slot;
+    /// * every `Entry::Empty(next)` link either points to another `Entry::Empty` slot
+    ///   or equals `usize::MAX`;
+    /// * the list starting from `free_head` is acyclic and visits every `Entry::Empty`
+    ///   slot of `data` exactly once.
+    ///
+    /// Vacant slots after the last occupied one are dropped, see
+    /// [`max_occupied_key`](#method.max_occupied_key).
+    ///
+    /// Checks the free list and counts the occupied slots, so it is O(n).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::{CompactMap, ValidationError};
+    ///
+    /// let mut map: CompactMap<_> = vec!["a", "b", "c"].into_iter().collect();
+    /// map.remove(1);
+    /// let (data, free_head) = map.into_raw_parts();
+    ///
+    /// let err = CompactMap::from_raw_parts(data.clone(), 0).unwrap_err();
+    /// assert_eq!(err, ValidationError::OccupiedInFreeList { index: 0 });
+    ///
+    /// let mut map = CompactMap::from_raw_parts(data, free_head).unwrap();
+    /// assert_eq!(map[2], "c");
+    /// assert_eq!(map.insert("b"), 1);
+    /// ```
+    pub fn from_raw_parts(data: Vec<Entry<V>>, free_head: usize) -> Result<Self, ValidationError> {
+        let occupied = data.iter().filter(|e| e.is_occupied()).count();
+        let mut map = CompactMap { data, free_head, occupied };
+        map.validate()?;
+        map.shrink_free_list();
+        Ok(map)
+    }
+}
+
+impl_any_alloc! {
+    /// Checks internal consistency of the map. O(n).
+    ///
+    /// Walks the free list and verifies that it visits each vacant slot
+    /// exactly once and nothing else. Also checks the cached element count.
+    /// [`from_raw_parts`](#method.from_raw_parts) runs the same check.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::new();
+    /// map.insert("a");
+    /// map.insert("b");
+    /// map.remove(0);
+    /// assert_eq!(map.validate(), Ok(()));
+    /// ```
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let mut visited = vec![false; self.data.len()];
+        let mut i = self.free_head;
+        while i != SENTINEL {
+            match self.data.get(i) {
+                None => return Err(ValidationError::OutOfBounds { index: i }),
+                Some(&Entry::Occupied(_)) => {
+                    return Err(ValidationError::OccupiedInFreeList { index: i })
+                }
+                Some(&Entry::Empty(next)) => {
+                    if visited[i] {
+                        return Err(ValidationError::Cycle { index: i });
+                    }
+                    visited[i] = true;
+                    i = next;
+                }
+            }
+        }
+        let mut occupied = 0;
+        for (i, e) in self.data.iter().enumerate() {
+            match *e {
+                Entry::Empty(_) => if !visited[i] {
+                    return Err(ValidationError::Unlinked { index: i });
+                },
+                Entry::Occupied(_) => occupied += 1,
+            }
+        }
+        if occupied != self.occupied {
+            return Err(ValidationError::WrongCount { stored: self.occupied, actual: occupied });
+        }
+        Ok(())
+    }
+
+    /// Returns occupancy figures of the map, for inspecting memory usage.
+    /// O(number of vacant slots).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::{CompactMap, MapStats};
+    ///
+    /// let mut map = CompactMap::with_capacity(10);
+    /// map.insert("a");
+    /// map.insert("b");
+    /// map.insert("c");
+    /// map.remove(1);
+    /// assert_eq!(map.stats(), MapStats { occupied: 2, capacity: 10, holes: 1, free_list_len: 1 });
+    /// ```
+    pub fn stats(&self) -> MapStats {
+        MapStats {
+            occupied: self.occupied,
+            capacity: self.data.capacity(),
+            holes: self.data.len() - self.occupied,
+            free_list_len: self.free_list_len(),
+        }
+    }
+}
+
+impl<V> CompactMap<V> {
+    /// Returns an object whose `Debug` output shows occupancy figures
+    /// along with the entries. The `Debug` output of the map itself stays the same.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::with_capacity(10);
+    /// map.insert("a");
+    /// map.insert("b");
+    /// map.insert("c");
+    /// map.remove(1);
+    /// assert_eq!(
+    ///     format!("{:?}", map.debug_stats()),
+    ///     r#"CompactMap { len: 2, capacity: 10, holes: 1, data: {0: "a", 2: "c"} }"#
+    /// );
+    /// ```
+    pub fn debug_stats(&self) -> impl fmt::Debug + '_
+        where V: fmt::Debug
+    {
+        DebugStats { map: self }
+    }
+
+    /// Returns an object whose `Display` output lists the entries without
+    /// quotes, e.g. `{0: hello, 2: world}`. The alternate form `{:#}` puts
+    /// each entry on its own line. Same as the `Display` output of the map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map: CompactMap<_> = vec!["hello", "there", "world"].into_iter().collect();
+    /// map.remove(1);
+    /// assert_eq!(format!("{}", map.display()), "{0: hello, 2: world}");
+    /// assert_eq!(format!("{:#}", map.display()), "{\n    0: hello,\n    2: world,\n}");
+    /// ```
+    pub fn display(&self) -> impl fmt::Display + '_
+        where V: fmt::Display
+    {
+        DisplayMap { map: self }
+    }
+}
+
+impl_any_alloc! {
+    /// Returns a hash of all key-value pairs that does not depend on the
+    /// order of slots, for quickly checking whether two maps hold the same
+    /// entries. Equal maps give equal fingerprints no matter how they were built.
+    ///
+    /// Uses the standard library's `DefaultHasher` with fixed keys, so the
+    /// result only matches between programs built with the same Rust version.
+    /// Use [`fingerprint_with`](#method.fingerprint_with) to pick the hash function.
+    ///
+    /// The fingerprint is not cryptographically secure: different maps
+    /// can be crafted to have the same fingerprint.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut a = CompactMap::new();
+    /// a.insert("x");
+    /// a.insert("y");
+    /// let mut b = CompactMap::from_keyed_pairs(vec![(1, "y")]);
+    /// assert_ne!(a.fingerprint(), b.fingerprint());
+    /// b.extend_keyed(vec![(0, "x")]);
+    /// assert_eq!(a.fingerprint(), b.fingerprint());
+    /// ```
+    pub fn fingerprint(&self) -> u64
+        where V: Hash
+    {
+        self.fingerprint_with(&BuildHasherDefault::<DefaultHasher>::default())
+    }
+
+    /// Same as [`fingerprint`](#method.fingerprint), but hashes each entry
+    /// with a hasher built by `hash_builder`. The hasher must be deterministic
+    /// for the fingerprints to be comparable.
+    pub fn fingerprint_with<S: BuildHasher>(&self, hash_builder: &S) -> u64
+        where V: Hash
+    {
+        self.iter().map(|(k, v)| {
+            let mut h = hash_builder.build_hasher();
+            h.write_usize(k);
+            v.hash(&mut h);
+            h.finish()
+        }).fold(0, u64::wrapping_add)
+    }
+
+    /// Runs `validate` after mutations in the crate's own tests.
+    #[inline]
+    fn debug_validate(&self) {
+        #[cfg(test)]
+        debug_assert_eq!(self.validate(), Ok(()));
+        #[cfg(test)]
+        debug_assert_eq!(self.trailing_holes(), 0);
+    }
+}
+
+impl<V> CompactMap<V> {
+    /// Converts the map into a read-only [`ImmutableCompactMap`](frozen/struct.ImmutableCompactMap.html)
+    /// with the same entries, without copying them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let map: CompactMap<_> = vec!["a", "b"].into_iter().collect();
+    /// let frozen = map.freeze();
+    /// assert_eq!(frozen.get(1), Some(&"b"));
+    /// assert!(!frozen.contains_key(2));
+    /// ```
+    pub fn freeze(self) -> ImmutableCompactMap<V> {
+        ImmutableCompactMap::from(self)
+    }
+
+    /// Converts the map into a `HashMap` with the same key-value pairs.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::new();
+    /// map.insert("a");
+    /// map.insert("b");
+    /// map.remove(0);
+    ///
+    /// let hm = map.into_hash_map();
+    /// assert_eq!(hm.len(), 1);
+    /// assert_eq!(hm[&1], "b");
+    /// ```
+    pub fn into_hash_map(self) -> HashMap<usize, V> {
+        self.into_iter().collect()
+    }
+
+    /// Converts the map into a `BTreeMap` with the same key-value pairs.
+    pub fn into_btree_map(self) -> BTreeMap<usize, V> {
+        self.into_iter().collect()
+    }
+}
+
+impl_any_alloc! {
+    /// Same as [`into_hash_map`](#method.into_hash_map), but clones the values.
+    pub fn to_hash_map(&self) -> HashMap<usize, V>
+        where V: Clone
+    {
+        self.iter().map(|(k, v)| (k, v.clone())).collect()
+    }
+
+    /// Same as [`into_btree_map`](#method.into_btree_map), but clones the values.
+    pub fn to_btree_map(&self) -> BTreeMap<usize, V>
+        where V: Clone
+    {
+        self.iter().map(|(k, v)| (k, v.clone())).collect()
+    }
+
+    /// Transforms every value of the map, keeping the keys and the
+    /// free slots exactly as they were.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::new();
+    /// map.insert(1);
+    /// let id = map.insert(2);
+    /// map.remove(0);
+    ///
+    /// let strings = map.map_values(|x| x.to_string());
+    /// assert_eq!(strings[id], "2");
+    /// assert_eq!(strings.get(0), None);
+    /// ```
+    pub fn map_values<U, F>(self, mut f: F) -> CompactMap<U>
+        where F: FnMut(V) -> U
+    {
+        CompactMap {
+            data: self.data.into_iter().map(|e| match e {
+                Entry::Empty(next) => Entry::Empty(next),
+                Entry::Occupied(v) => Entry::Occupied(f(v)),
+            }).collect(),
+            free_head: self.free_head,
+            occupied: self.occupied,
+        }
+    }
+
+    /// Same as [`map_values`](#method.map_values), but does not consume the map.
+    pub fn map_values_ref<U, F>(&self, mut f: F) -> CompactMap<U>
+        where F: FnMut(&V) -> U
+    {
+        CompactMap {
+            data: self.data.iter().map(|e| match *e {
+                Entry::Empty(next) => Entry::Empty(next),
+                Entry::Occupied(ref v) => Entry::Occupied(f(v)),
+            }).collect(),
+            free_head: self.free_head,
+            occupied: self.occupied,
+        }
+    }
+
+    /// Fallible version of [`map_values`](#method.map_values). Stops at the
+    /// first error and returns it, dropping the remaining values. On success
+    /// every value keeps its key and the free list is preserved.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::new();
+    /// map.insert("1");
+    /// let id = map.insert("2");
+    /// map.remove(0);
+    ///
+    /// let parsed = map.try_map_values(|s| s.parse::<u32>()).unwrap();
+    /// assert_eq!(parsed[id], 2);
+    /// assert_eq!(parsed.get(0), None);
+    ///
+    /// let mut bad = CompactMap::new();
+    /// bad.insert("1");
+    /// bad.insert("x");
+    /// assert!(bad.try_map_values(|s| s.parse::<u32>()).is_err());
+    /// ```
+    pub fn try_map_values<U, E, F>(self, mut f: F) -> Result<CompactMap<U>, E>
+        where F: FnMut(V) -> Result<U, E>
+    {
+        let data = self.data.into_iter().map(|e| match e {
+            Entry::Empty(next) => Ok(Entry::Empty(next)),
+            Entry::Occupied(v) => f(v).map(Entry::Occupied),
+        }).collect::<Result<Vec<_>, E>>()?;
+        Ok(CompactMap {
+            data,
+            free_head: self.free_head,
+            occupied: self.occupied,
+        })
+    }
+
+    /// Same as [`try_map_values`](#method.try_map_values), but does not consume the map.
+    pub fn try_map_values_ref<U, E, F>(&self, mut f: F) -> Result<CompactMap<U>, E>
+        where F: FnMut(&V) -> Result<U, E>
+    {
+        let data = self.data.iter().map(|e| match *e {
+            Entry::Empty(next) => Ok(Entry::Empty(next)),
+            Entry::Occupied(ref v) => f(v).map(Entry::Occupied),
+        }).collect::<Result<Vec<_>, E>>()?;
+        Ok(CompactMap {
+            data,
+            free_head: self.free_head,
+            occupied: self.occupied,
+        })
+    }
+}
+
+impl<V> CompactMap<V> {
+    /// Builds a new map from the values for which `f` returns `Some`.
+    /// Retained values keep their original keys, other slots become vacant.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::new();
+    /// map.insert(1);
+    /// map.insert(2);
+    /// map.insert(3);
+    ///
+    /// let odd = map.filter_map(|_, x| if x % 2 == 1 { Some(x * 10) } else { None });
+    /// assert_eq!(odd.iter().collect::<Vec<_>>(), [(0, &10), (2, &30)]);
+    /// ```
+    pub fn filter_map<U, F>(self, mut f: F) -> CompactMap<U>
+        where F: FnMut(usize, V) -> Option<U>
+    {
+        CompactMap::from_entries(self.data.into_iter().enumerate().map(|(i, e)| match e {
+            Entry::Occupied(v) => match f(i, v) {
+                Some(u) => Entry::Occupied(u),
+                None => Entry::Empty(SENTINEL),
+            },
+            Entry::Empty(_) => Entry::Empty(SENTINEL),
+        }).collect())
+    }
+
+    /// Same as [`filter_map`](#method.filter_map), but does not consume the map.
+    pub fn filter_map_ref<U, F>(&self, mut f: F) -> CompactMap<U>
+        where F: FnMut(usize, &V) -> Option<U>
+    {
+        CompactMap::from_entries(self.data.iter().enumerate().map(|(i, e)| match *e {
+            Entry::Occupied(ref v) => match f(i, v) {
+                Some(u) => Entry::Occupied(u),
+                None => Entry::Empty(SENTINEL),
+            },
+            Entry::Empty(_) => Entry::Empty(SENTINEL),
+        }).collect())
+    }
+
+    /// Clones the entries for which `f` returns `true` into a new map,
+    /// keeping their keys. Other slots become vacant. Cheaper than
+    /// `clone()` followed by `retain()`, as rejected values are never cloned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let map: CompactMap<_> = vec![1, 2, 3, 4].into_iter().collect();
+    /// let even = map.clone_with_filter(|_, &x| x % 2 == 0);
+    /// assert_eq!(even.iter().collect::<Vec<_>>(), [(1, &2), (3, &4)]);
+    /// ```
+    pub fn clone_with_filter<F>(&self, mut f: F) -> CompactMap<V>
+        where V: Clone, F: FnMut(usize, &V) -> bool
+    {
+        self.filter_map_ref(|k, v| if f(k, v) { Some(v.clone()) } else { None })
+    }
+
+    /// Inner join on keys: creates a map with keys occupied in both maps,
+    /// with values computed by `f` from both values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut a: CompactMap<_> = vec![1, 2, 3].into_iter().collect();
+    /// a.remove(1);
+    /// let b: CompactMap<_> = vec!["x", "y"].into_iter().collect();
+    ///
+    /// let zipped = a.zip_map(&b, |&n, &s| format!("{}{}", s, n));
+    /// assert_eq!(zipped.into_iter().collect::<Vec<_>>(), [(0, "x1".to_string())]);
+    /// ```
+    pub fn zip_map<U, R, F>(&self, other: &CompactMap<U>, mut f: F) -> CompactMap<R>
+        where F: FnMut(&V, &U) -> R
+    {
+        CompactMap::from_entries(self.data.iter().zip(other.data.iter()).map(|e| match e {
+            (Entry::Occupied(v), Entry::Occupied(u)) => Entry::Occupied(f(v, u)),
+            _ => Entry::Empty(SENTINEL),
+        }).collect())
+    }
+
+    /// Outer join on keys: creates a map with keys occupied in either map,
+    /// with values computed by `f` from the values, with `None` for a side
+    /// that has no entry at the key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut a: CompactMap<_> = vec![1, 2, 3].into_iter().collect();
+    /// a.remove(1);
+    /// let b: CompactMap<_> = vec![10, 20].into_iter().collect();
+    ///
+    /// let zipped = a.zip_outer(&b, |x, y| x.unwrap_or(&0) + y.unwrap_or(&0));
+    /// assert_eq!(zipped.into_iter().collect::<Vec<_>>(), [(0, 11), (1, 20), (2, 3)]);
+    /// ```
+    pub fn zip_outer<U, R, F>(&self, other: &CompactMap<U>, mut f: F) -> CompactMap<R>
+        where F: FnMut(Option<&V>, Option<&U>) -> R
+    {
+        let len = self.data.len().max(other.data.len());
+        CompactMap::from_entries((0..len).map(|i| match (self.get(i), other.get(i)) {
+            (None, None) => Entry::Empty(SENTINEL),
+            (v, u) => Entry::Occupied(f(v, u)),
+        }).collect())
+    }
+}
+
+impl_any_alloc! {
+    /// Splits the map into two: entries for which `f` returns `true` and
+    /// the rest. Both maps keep original keys of their entries.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::new();
+    /// map.insert(1);
+    /// map.insert(2);
+    /// map.insert(3);
+    ///
+    /// let (odd, even) = map.partition(|_, x| x % 2 == 1);
+    /// assert_eq!(odd.iter().collect::<Vec<_>>(), [(0, &1), (2, &3)]);
+    /// assert_eq!(even.iter().collect::<Vec<_>>(), [(1, &2)]);
+    /// ```
+    pub fn partition<F>(self, mut f: F) -> (CompactMap<V>, CompactMap<V>)
+        where F: FnMut(usize, &V) -> bool
+    {
+        let len = self.data.len();
+        let mut yes = CompactMap::with_capacity(len);
+        let mut no = CompactMap::with_capacity(len);
+        for (i, e) in self.data.into_iter().enumerate() {
+            let (to, other) = match e {
+                Entry::Occupied(ref v) if f(i, v) => (&mut yes, &mut no),
+                Entry::Occupied(_) => (&mut no, &mut yes),
+                Entry::Empty(_) => {
+                    yes.data.push(Entry::Empty(SENTINEL));
+                    no.data.push(Entry::Empty(SENTINEL));
+                    continue;
+                }
+            };
+            to.data.push(e);
+            to.occupied += 1;
+            other.data.push(Entry::Empty(SENTINEL));
+        }
+        yes.reindex();
+        no.reindex();
+        (yes, no)
+    }
+
+    /// Same as [`partition`](#method.partition), but clones the values
+    /// instead of consuming the map.
+    pub fn partition_ref<F>(&self, mut f: F) -> (CompactMap<V>, CompactMap<V>)
+        where F: FnMut(usize, &V) -> bool, V: Clone
+    {
+        let len = self.data.len();
+        let mut yes = CompactMap::with_capacity(len);
+        let mut no = CompactMap::with_capacity(len);
+        for (i, e) in self.data.iter().enumerate() {
+            let (to, other) = match *e {
+                Entry::Occupied(ref v) if f(i, v) => (&mut yes, &mut no),
+                Entry::Occupied(_) => (&mut no, &mut yes),
+                Entry::Empty(_) => {
+                    yes.data.push(Entry::Empty(SENTINEL));
+                    no.data.push(Entry::Empty(SENTINEL));
+                    continue;
+                }
+            };
+            to.data.push(e.clone());
+            to.occupied += 1;
+            other.data.push(Entry::Empty(SENTINEL));
+        }
+        yes.reindex();
+        no.reindex();
+        (yes, no)
+    }
+
+    /// Groups the keys by the result of `f` on their values. Each list of
+    /// keys is in ascending order. O(n).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let map: CompactMap<_> = vec!["apple", "banana", "avocado"].into_iter().collect();
+    /// let groups = map.group_by(|s| s.chars().next());
+    /// assert_eq!(groups[&Some('a')], [0, 2]);
+    /// assert_eq!(groups[&Some('b')], [1]);
+    /// ```
+    pub fn group_by<K2, F>(&self, mut f: F) -> HashMap<K2, Vec<usize>>
+        where K2: Hash + Eq, F: FnMut(&V) -> K2
+    {
+        let mut groups = HashMap::new();
+        for (k, v) in self.iter() {
+            groups.entry(f(v)).or_insert_with(Vec::new).push(k);
+        }
+        groups
+    }
+
+    /// Same as [`group_by`](#method.group_by), but collects references
+    /// to the values instead of the keys.
+    pub fn group_by_values<K2, F>(&self, mut f: F) -> HashMap<K2, Vec<&V>>
+        where K2: Hash + Eq, F: FnMut(&V) -> K2
+    {
+        let mut groups = HashMap::new();
+        for v in self.values() {
+            groups.entry(f(v)).or_insert_with(Vec::new).push(v);
+        }
+        groups
+    }
+
+    /// Returns the largest value, or `None` if the map is empty. O(n).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let map: CompactMap<_> = vec![3, 7, 1].into_iter().collect();
+    /// assert_eq!(map.max_value(), Some(&7));
+    /// assert_eq!(map.min_value_key(), Some((2, &1)));
+    /// assert_eq!(map.max_by_key(|v| v % 3), Some((2, &1)));
+    /// ```
+    pub fn max_value(&self) -> Option<&V>
+        where V: Ord
+    {
+        self.values().max()
+    }
+
+    /// Returns the smallest value, or `None` if the map is empty. O(n).
+    pub fn min_value(&self) -> Option<&V>
+        where V: Ord
+    {
+        self.values().min()
+    }
+
+    /// Same as [`max_value`](#method.max_value), but also returns the key.
+    /// Of several equal maximums, returns the one with the highest key.
+    pub fn max_value_key(&self) -> Option<(usize, &V)>
+        where V: Ord
+    {
+        self.iter().max_by(|a, b| a.1.cmp(b.1))
+    }
+
+    /// Same as [`min_value`](#method.min_value), but also returns the key.
+    /// Of several equal minimums, returns the one with the lowest key.
+    pub fn min_value_key(&self) -> Option<(usize, &V)>
+        where V: Ord
+    {
+        self.iter().min_by(|a, b| a.1.cmp(b.1))
+    }
+
+    /// Returns the entry whose value gives the largest result of `f`,
+    /// preferring the highest key among equal ones. O(n).
+    pub fn max_by_key<K2, F>(&self, mut f: F) -> Option<(usize, &V)>
+        where K2: Ord, F: FnMut(&V) -> K2
+    {
+        self.iter().max_by_key(|&(_, v)| f(v))
+    }
+
+    /// Returns the entry whose value gives the smallest result of `f`,
+    /// preferring the lowest key among equal ones. O(n).
+    pub fn min_by_key<K2, F>(&self, mut f: F) -> Option<(usize, &V)>
+        where K2: Ord, F: FnMut(&V) -> K2
+    {
+        self.iter().min_by_key(|&(_, v)| f(v))
+    }
+
+    /// Sums the values, same as `self.values().sum()`. O(n).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let map: CompactMap<u32> = (1..5).collect();
+    /// assert_eq!(map.sum::<u32>(), 10);
+    /// assert_eq!(map.product::<u32>(), 24);
+    /// assert_eq!(map.sum_by(|&v| v as f64 / 2.0), 5.0);
+    /// assert_eq!(map.count_where(|&v| v % 2 == 0), 2);
+    /// ```
+    pub fn sum<'a, S>(&'a self) -> S
+        where S: ::std::iter::Sum<&'a V>
+    {
+        self.values().sum()
+    }
+
+    /// Multiplies the values, same as `self.values().product()`. O(n).
+    pub fn product<'a, P>(&'a self) -> P
+        where P: ::std::iter::Product<&'a V>
+    {
+        self.values().product()
+    }
+
+    /// Sums the results of `f` on the values. O(n).
+    pub fn sum_by<S, F>(&self, f: F) -> S
+        where S: ::std::iter::Sum<S>, F: FnMut(&V) -> S
+    {
+        self.values().map(f).sum()
+    }
+
+    /// Counts the values for which `f` returns `true`. O(n).
+    pub fn count_where<F>(&self, mut f: F) -> usize
+        where F: FnMut(&V) -> bool
+    {
+        self.values().filter(|v| f(v)).count()
+    }
+}
+
+impl<V> CompactMap<V> {
+    /// Compares the map with `other`, lazily: nothing is computed until
+    /// the iterators of the returned [`Diff`](struct.Diff.html) are consumed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let old: CompactMap<_> = vec!["a", "b", "c"].into_iter().collect();
+    /// let mut new = old.clone();
+    /// new.remove(0);
+    /// new[1] = "B";
+    /// new.get_or_insert(3, "d");
+    ///
+    /// let diff = old.diff(&new);
+    /// assert_eq!(diff.added().collect::<Vec<_>>(), [(3, &"d")]);
+    /// assert_eq!(diff.removed().collect::<Vec<_>>(), [0]);
+    /// assert_eq!(diff.changed().collect::<Vec<_>>(), [(1, &"B")]);
+    ///
+    /// let mut replica = old.clone();
+    /// replica.apply_diff(diff);
+    /// assert_eq!(replica, new);
+    /// ```
+    pub fn diff<'a>(&'a self, other: &'a CompactMap<V>) -> Diff<'a, V>
+        where V: PartialEq
+    {
+        Diff { old: self, new: other }
+    }
+}
+
+impl_any_alloc! {
+    /// Replays the changes described by `diff` onto this map: removes the
+    /// keys in [`removed`](struct.Diff.html#method.removed) and stores clones
+    /// of the values in [`added`](struct.Diff.html#method.added) and
+    /// [`changed`](struct.Diff.html#method.changed) at their keys.
+    ///
+    /// Applied to a map equal to the first map of the diff, this makes it
+    /// equal to the second one.
+    pub fn apply_diff(&mut self, diff: Diff<V>)
+        where V: PartialEq + Clone
+    {
+        for k in diff.removed() {
+            self.take_unindexed(k);
+        }
+        for (k, v) in diff.added().chain(diff.changed()) {
+            self.put_unindexed(k, v.clone());
+        }
+        self.reindex();
+    }
+}
+
+impl<V> CompactMap<V> {
+    /// Moves all values from `other` into this map, assigning them new keys
+    /// the same way as [`insert`](#method.insert) does.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let a: CompactMap<_> = vec!["a", "b"].into_iter().collect();
+    /// let b: CompactMap<_> = vec!["c"].into_iter().collect();
+    ///
+    /// let merged = a.merge(b);
+    /// assert_eq!(merged.values().cloned().collect::<Vec<_>>(), ["a", "b", "c"]);
+    /// ```
+    pub fn merge(mut self, other: CompactMap<V>) -> CompactMap<V> {
+        self.extend(other.into_iter().map(|(_, v)| v));
+        self
+    }
+
+    /// Combines two maps keeping keys of all entries.
+    ///
+    /// Fails if both maps have an entry with the same key. All keys are checked
+    /// before anything is moved, so the error gives back both maps unchanged.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut a = CompactMap::new();
+    /// a.insert("a");
+    /// a.insert("b");
+    /// a.remove(1);
+    /// let mut b = CompactMap::new();
+    /// b.insert("x");
+    /// b.insert("y");
+    /// b.remove(0);
+    ///
+    /// let merged = a.clone().merge_preserving_keys(b.clone()).unwrap();
+    /// assert_eq!(merged.iter().collect::<Vec<_>>(), [(0, &"a"), (1, &"y")]);
+    ///
+    /// let conflict = merged.merge_preserving_keys(b).unwrap_err();
+    /// assert_eq!(conflict.key, 1);
+    /// assert_eq!((conflict.ours.len(), conflict.theirs.len()), (2, 1));
+    /// ```
+    pub fn merge_preserving_keys(mut self, other: CompactMap<V>)
+        -> Result<CompactMap<V>, MergeConflict<CompactMap<V>>>
+    {
+        if let Some(key) = other.keys().find(|&k| self.get(k).is_some()) {
+            return Err(MergeConflict { key, ours: self, theirs: other });
+        }
+        for (k, v) in other {
+            self.put_unindexed(k, v);
+        }
+        self.reindex();
+        Ok(self)
+    }
+
+    /// Combines two maps keeping keys of all entries.
+    ///
+    /// If both maps have an entry with the same key, `f` is called with
+    /// values from this and the other map to produce the resulting value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let a: CompactMap<_> = vec![1, 2].into_iter().collect();
+    /// let b: CompactMap<_> = vec![10, 20, 30].into_iter().collect();
+    ///
+    /// let merged = a.merge_with(b, |x, y| x + y);
+    /// assert_eq!(merged.values().cloned().collect::<Vec<_>>(), [11, 22, 30]);
+    /// ```
+    pub fn merge_with<F>(mut self, other: CompactMap<V>, mut f: F) -> CompactMap<V>
+        where F: FnMut(V, V) -> V
+    {
+        for (k, v) in other {
+            let v = match self.take_unindexed(k) {
+                Some(ours) => f(ours, v),
+                None => v,
+            };
+            self.put_unindexed(k, v);
+        }
+        self.reindex();
+        self
+    }
+}
+
+impl_any_alloc! {
+    /// Moves all entries from `other` into this map, assigning them new keys
+    /// like [`insert`](#method.insert) does. Leaves `other` empty, but keeps
+    /// its allocated memory.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut a: CompactMap<_> = vec!["a", "b"].into_iter().collect();
+    /// let mut b: CompactMap<_> = vec!["c"].into_iter().collect();
+    /// a.append(&mut b);
+    /// assert_eq!(a.values().cloned().collect::<Vec<_>>(), ["a", "b", "c"]);
+    /// assert!(b.is_empty());
+    /// ```
+    pub fn append(&mut self, other: &mut CompactMap<V>) {
+        self.extend(other.drain().map(|(_, v)| v));
+    }
+
+    /// Moves all entries from `other` into this map, keeping their keys.
+    /// Leaves `other` empty, but keeps its allocated memory.
+    ///
+    /// Fails without modifying either map if both have an entry with the same key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut a: CompactMap<_> = vec!["a", "b"].into_iter().collect();
+    /// let mut b = CompactMap::from_keyed_pairs(vec![(3, "d")]);
+    /// a.append_preserving_keys(&mut b).unwrap();
+    /// assert_eq!(a.keys().collect::<Vec<_>>(), [0, 1, 3]);
+    ///
+    /// let mut c = CompactMap::from_keyed_pairs(vec![(1, "x")]);
+    /// assert_eq!(a.append_preserving_keys(&mut c).unwrap_err().key, 1);
+    /// assert_eq!(c.len(), 1);
+    /// ```
+    pub fn append_preserving_keys(&mut self, other: &mut CompactMap<V>)
+        -> Result<(), ConflictKey>
+    {
+        if let Some(key) = other.keys().find(|&k| self.get(k).is_some()) {
+            return Err(ConflictKey { key });
+        }
+        for (k, v) in other.drain() {
+            self.put_unindexed(k, v);
+        }
+        self.reindex();
+        Ok(())
+    }
+
+    /// Inserts the pairs at their keys, growing the map as needed.
+    /// A value already stored at a key is replaced and dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::new();
+    /// map.insert("a");
+    /// map.extend_keyed(vec![(3, "d"), (0, "x")]);
+    /// assert_eq!(map.iter().collect::<Vec<_>>(), [(0, &"x"), (3, &"d")]);
+    /// ```
+    pub fn extend_keyed<I>(&mut self, iter: I)
+        where I: IntoIterator<Item = (usize, V)>
+    {
+        for (k, v) in iter {
+            self.put_unindexed(k, v);
+        }
+        self.reindex();
+    }
+
+    /// Inserts the pairs at their keys, like [`extend_keyed`](#method.extend_keyed),
+    /// but fails on a key that is already occupied.
+    ///
+    /// Pairs before the conflicting one stay inserted. The value already
+    /// stored at the conflicting key stays in the map, the rejected value
+    /// is returned in the error. Pairs after it are dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map = CompactMap::new();
+    /// map.try_extend_keyed(vec![(2, "c"), (0, "a")]).unwrap();
+    /// assert_eq!(map.keys().collect::<Vec<_>>(), [0, 2]);
+    ///
+    /// let conflict = map.try_extend_keyed(vec![(1, "b"), (2, "x")]).unwrap_err();
+    /// assert_eq!((conflict.key, conflict.theirs), (2, "x"));
+    /// assert_eq!(map.keys().collect::<Vec<_>>(), [0, 1, 2]);
+    /// assert_eq!(map[2], "c");
+    /// ```
+    pub fn try_extend_keyed<I>(&mut self, iter: I) -> Result<(), ExtendConflict<V>>
+        where I: IntoIterator<Item = (usize, V)>
+    {
+        let mut result = Ok(());
+        for (k, v) in iter {
+            if self.get(k).is_some() {
+                result = Err(ExtendConflict { key: k, theirs: v });
+                break;
+            }
+            self.put_unindexed(k, v);
+        }
+        self.reindex();
+        result
+    }
+
+    /// Stores the value at the given key, growing the vector with vacant
+    /// slots if needed. Returns previous value, if any.
+    /// Does not maintain the free list: call `reindex` when done.
+    fn put_unindexed(&mut self, i: usize, v: V) -> Option<V> {
+        while self.data.len() <= i {
+            self.data.push(Entry::Empty(SENTINEL));
+        }
+        match mem::replace(&mut self.data[i], Entry::Occupied(v)) {
+            Entry::Empty(_) => {
+                self.occupied += 1;
+                None
+            }
+            Entry::Occupied(old) => Some(old),
+        }
+    }
+
+    /// Takes the value at the given key out, leaving a vacant slot.
+    /// Does not maintain the free list: call `reindex` when done.
+    fn take_unindexed(&mut self, i: usize) -> Option<V> {
+        match self.data.get_mut(i) {
+            Some(e @ &mut Entry::Occupied(_)) => match mem::replace(e, Entry::Empty(SENTINEL)) {
+                Entry::Occupied(v) => {
+                    self.occupied -= 1;
+                    Some(v)
+                }
+                Entry::Empty(_) => unreachable!(),
+            },
+            _ => None,
+        }
+    }
+
+    /// Reserves space for an iterator with the given size hint,
+    /// not trusting upper bounds above 4096.
+    fn reasonable_reserve(&mut self, (rmin, mbrmax) : (usize, Option<usize>)) {
+        use std::cmp::{min,max};
+        if let Some(rmax) = mbrmax {
+            if rmin == rmax {
+                self.data.reserve_exact(rmin);
+            } else {
+                let reasonable_reserve = max(rmin, min(rmax, 4096));
+                self.data.reserve(reasonable_reserve);
+            }
+        } else {
+            self.data.reserve(rmin);
+        }
+    }
+
+    /// Strips off trailing vacant slots. Returns whether there were any.
+    /// Does not maintain the free list: call `reindex` if it returns `true`.
+    fn truncate_vacant_tail(&mut self) -> bool {
+        let len = self.data.iter().rposition(Entry::is_occupied).map_or(0, |i| i + 1);
+        if len < self.data.len() {
+            self.data.truncate(len);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Stores the value at a vacant or out of bounds key, keeping the free list.
+    /// Slots added when growing get linked into the free list.
+    /// O(number of vacant slots).
+    fn insert_at_vacant(&mut self, i: usize, v: V) {
+        let len = self.data.len();
+        if i >= len {
+            for j in len..i {
+                self.data.push(Entry::Empty(self.free_head));
+                self.free_head = j;
+            }
+            self.data.push(Entry::Occupied(v));
+        } else {
+            let next = match mem::replace(&mut self.data[i], Entry::Occupied(v)) {
+                Entry::Empty(next) => next,
+                Entry::Occupied(_) => panic!("slot is occupied"),
+            };
+            // unlink `i` from the free list
+            if self.free_head == i {
+                self.free_head = next;
+            } else {
+                let mut j = self.free_head;
+                loop {
+                    match self.data[j] {
+                        Entry::Empty(ref mut link) if *link == i => {
+                            *link = next;
+                            break;
+                        }
+                        Entry::Empty(link) => j = link,
+                        Entry::Occupied(_) => unreachable!(),
+                    }
+                }
+            }
+        }
+        self.occupied += 1;
+        self.debug_validate();
+    }
+
+    /// Unlinks vacant slots `>= len` from the free list and truncates them.
+    /// All slots `>= len` must be vacant. O(free list length) if there are any.
+    fn unlink_tail(&mut self, len: usize) {
+        let mut left = self.data.len().saturating_sub(len);
+        let mut prev = SENTINEL;
+        let mut cur = self.free_head;
+        while left > 0 {
+            let next = match self.data[cur] {
+                Entry::Empty(next) => next,
+                Entry::Occupied(_) => unreachable!(),
+            };
+            if cur >= len {
+                if prev == SENTINEL {
+                    self.free_head = next;
+                } else {
+                    self.data[prev] = Entry::Empty(next);
+                }
+                left -= 1;
+            } else {
+                prev = cur;
+            }
+            cur = next;
+        }
+        self.data.truncate(len);
+    }
+
+    /// First occupied key `>= from`, or `SENTINEL`.
+    fn next_occupied(&self, from: usize) -> usize {
+        self.data.get(from..)
+            .and_then(|tail| tail.iter().position(Entry::is_occupied))
+            .map_or(SENTINEL, |i| from + i)
+    }
+
+    /// Last occupied key `< before`, or `SENTINEL`.
+    fn prev_occupied(&self, before: usize) -> usize {
+        let end = before.min(self.data.len());
+        self.data[..end].iter().rposition(Entry::is_occupied).unwrap_or(SENTINEL)
+    }
+}
+
+impl<V> CompactMap<V> {
+    /// Builds the map from slots, counting and linking the vacant ones. O(n).
+    fn from_entries(data: Vec<Entry<V>>) -> CompactMap<V> {
+        let occupied = data.iter().filter(|e| e.is_occupied()).count();
+        let mut c = CompactMap { data, free_head: SENTINEL, occupied };
+        c.reindex();
+        c
+    }
+}
+
+impl_any_alloc! {
+    /// Strips off trailing vacant slots and rebuilds the free list. O(n).
+    fn reindex(&mut self) {
+        self.truncate_vacant_tail();
+        self.free_head = SENTINEL;
+        for i in 0..self.data.len() {
+            if let Entry::Empty(ref mut head) = self.data[i] {
+                *head = self.free_head;
+                self.free_head = i;
+            }
+        }
+        self.debug_validate();
+    }
+
+    /// Rebuilds the free list with the lowest key first, so that
+    /// `insert` fills the vacant slots in ascending order. O(n).
+    fn reindex_ascending(&mut self) {
+        self.free_head = SENTINEL;
+        for i in (0..self.data.len()).rev() {
+            if let Entry::Empty(ref mut head) = self.data[i] {
+                *head = self.free_head;
+                self.free_head = i;
+            }
+        }
+        self.debug_validate();
     }
+}
 
-    /// Returns an iterator visiting all key-value pairs in unspecified order.
-    /// The iterator's element type is `(usize, &'r V)`.
+impl<A, B> CompactMap<(A, B)> {
+    /// Splits a map of pairs into two maps with the same keys
+    /// (and the same free list).
     ///
     /// # Examples
     ///
     /// ```
     /// use compactmap::CompactMap;
     ///
-    /// let mut map = CompactMap::new();
-    /// map.insert("a");
-    /// map.insert("c");
-    /// map.insert("b");
-    ///
-    /// // Print `1: a`, `2: b` and `3: c`.
-    /// for (key, value) in map.iter() {
-    ///     println!("{}: {}", key, value);
-    /// }
+    /// let mut map: CompactMap<_> = vec![(1, 'a'), (2, 'b')].into_iter().collect();
+    /// map.remove(0);
+    /// let (nums, chars) = map.unzip();
+    /// assert_eq!(nums.into_iter().collect::<Vec<_>>(), [(1, 2)]);
+    /// assert_eq!(chars.into_iter().collect::<Vec<_>>(), [(1, 'b')]);
     /// ```
-    pub fn iter(&self) -> Iter<V> {
-        IntoIterator::into_iter(self)
+    pub fn unzip(self) -> (CompactMap<A>, CompactMap<B>) {
+        let mut left = Vec::with_capacity(self.data.len());
+        let mut right = Vec::with_capacity(self.data.len());
+        for e in self.data {
+            match e {
+                Entry::Empty(next) => {
+                    left.push(Entry::Empty(next));
+                    right.push(Entry::Empty(next));
+                }
+                Entry::Occupied((a, b)) => {
+                    left.push(Entry::Occupied(a));
+                    right.push(Entry::Occupied(b));
+                }
+            }
+        }
+        (
+            CompactMap { data: left, free_head: self.free_head, occupied: self.occupied },
+            CompactMap { data: right, free_head: self.free_head, occupied: self.occupied },
+        )
     }
 
-    /// Returns an iterator visiting all key-value pairs in unspecified order,
-    /// with mutable references to the values.
-    /// The iterator's element type is `(usize, &'r mut V)`
-    pub fn iter_mut(&mut self) -> IterMut<V> {
-        IntoIterator::into_iter(self)
+    /// Same as [`unzip`](#method.unzip), but clones the values
+    /// instead of consuming the map.
+    pub fn unzip_ref(&self) -> (CompactMap<A>, CompactMap<B>)
+        where A: Clone, B: Clone
+    {
+        (
+            self.map_values_ref(|v| v.0.clone()),
+            self.map_values_ref(|v| v.1.clone()),
+        )
     }
+}
 
-    /// Returns an iterator visiting all key-value pairs in unspecified order,
-    /// the keys, consuming the original `CompactMap`.
-    /// The iterator's element type is `(usize, V)`.
-    pub fn into_iter(self) -> IntoIter<V> {
-        IntoIterator::into_iter(self)
+/// Error returned by [`CompactMap::merge_preserving_keys`](struct.CompactMap.html#method.merge_preserving_keys)
+/// when both maps have an entry with the same key. Both maps are returned unchanged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeConflict<M, K = usize> {
+    /// The first key occupied in both maps
+    pub key: K,
+    /// The map `merge_preserving_keys` was called on
+    pub ours: M,
+    /// The other map
+    pub theirs: M,
+}
+
+impl<M, K: fmt::Debug> fmt::Display for MergeConflict<M, K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "both maps have an entry with key {:?}", self.key)
     }
+}
 
-    /// Returns an iterator visiting all keys in some order.
-    /// The iterator's element type is `usize`.
-    pub fn keys(&self) -> Keys<V> {
-        Keys { iter: self.iter() }
+impl<M: fmt::Debug, K: fmt::Debug> std::error::Error for MergeConflict<M, K> {}
+
+/// Error returned by [`CompactMap::try_extend_keyed`](struct.CompactMap.html#method.try_extend_keyed)
+/// when a key is already occupied. The stored value stays in the map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendConflict<V, K = usize> {
+    /// The key that was already occupied
+    pub key: K,
+    /// The rejected value
+    pub theirs: V,
+}
+
+impl<V, K: fmt::Debug> fmt::Display for ExtendConflict<V, K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "key {:?} is already occupied", self.key)
     }
-    /// Returns an iterator visiting all values in ascending order of the keys.
-    /// The iterator's element type is `&'r V`.
-    pub fn values(&self) -> Values<V> {
-        Values { iter: self.iter() }
+}
+
+impl<V: fmt::Debug, K: fmt::Debug> std::error::Error for ExtendConflict<V, K> {}
+
+/// Error returned by [`CompactMap::invert`](struct.CompactMap.html#method.invert)
+/// when two entries have the same value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvertError<K = usize, V = usize> {
+    /// The value found more than once
+    pub duplicate_value: V,
+    /// Lower key with that value
+    pub key1: K,
+    /// Higher key with that value
+    pub key2: K,
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Display for InvertError<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "keys {:?} and {:?} have the same value {:?}", self.key1, self.key2, self.duplicate_value)
     }
-    /// Returns an iterator visiting all values in ascending order of the keys.
-    /// The iterator's element type is `&'r mut V`.
-    pub fn values_mut(&mut self) -> ValuesMut<V> {
-        ValuesMut { iter_mut: self.iter_mut() }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> std::error::Error for InvertError<K, V> {}
+
+/// Error returned by [`CompactMap::append_preserving_keys`](struct.CompactMap.html#method.append_preserving_keys)
+/// when both maps have an entry with the same key. Neither map is modified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConflictKey<K = usize> {
+    /// The lowest key occupied in both maps
+    pub key: K,
+}
+
+impl<K: fmt::Debug> fmt::Display for ConflictKey<K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "both maps have an entry with key {:?}", self.key)
     }
+}
 
-    /// Iterates the map to get number of elements.
-    /// O(n) where n is historical maximum element count.
-    pub fn len_slow(&self) -> usize {
-        self.iter().count()
+impl<K: fmt::Debug> std::error::Error for ConflictKey<K> {}
+
+/// Key change of the entry moved by
+/// [`CompactMap::swap_remove`](struct.CompactMap.html#method.swap_remove).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Relocation<K = usize> {
+    /// Key of the entry before the move
+    pub old_key: K,
+    /// Key of the entry after the move
+    pub new_key: K,
+}
+
+/// Error returned by [`CompactMap::move_entry`](struct.CompactMap.html#method.move_entry).
+/// The map is not modified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveError<K = usize> {
+    /// There is no entry to move
+    VacantSource {
+        /// The source key
+        key: K,
+    },
+    /// The destination already has an entry
+    OccupiedTarget {
+        /// The destination key
+        key: K,
+    },
+}
+
+impl<K: fmt::Debug> fmt::Display for MoveError<K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MoveError::VacantSource { ref key } =>
+                write!(f, "no entry to move at key {:?}", key),
+            MoveError::OccupiedTarget { ref key } =>
+                write!(f, "key {:?} is already occupied", key),
+        }
     }
+}
 
-    /// Trims the `CompactMap` of some excess capacity.
-    ///
-    /// Rescans the whole map to reindex empty slots. O(n).
-    ///
-    /// Empty slots that are not at the end of the map remain allocated.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use compactmap::CompactMap;
-    /// let mut map: CompactMap<&str> = CompactMap::with_capacity(10);
-    /// assert_eq!(map.capacity(), 10);
-    /// map.shrink_to_fit();
-    /// assert_eq!(map.capacity(), 0);
-    /// map.insert("qwe");
-    /// map.insert("345");
-    /// map.insert("555");
-    /// map.remove(1);
-    /// map.shrink_to_fit();
-    /// assert_eq!(map.capacity(), 3);
-    /// ```
-    pub fn shrink_to_fit(&mut self) {
-        
-        // strip off trailing `Empty`s
-        if let Some(idx) = self.data.iter().rposition(Entry::is_not_empty) {
-            self.data.truncate(idx + 1);
-        } else {
-            self.data.clear();
-        };
-        
+impl<K: fmt::Debug> std::error::Error for MoveError<K> {}
 
-        self.data.shrink_to_fit();
-        self.reindex();
+/// Occupancy figures returned by [`CompactMap::stats`](struct.CompactMap.html#method.stats).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapStats {
+    /// Number of entries
+    pub occupied: usize,
+    /// Capacity of the backing vector
+    pub capacity: usize,
+    /// Number of vacant slots below the highest allocated key
+    pub holes: usize,
+    /// Number of slots reachable from the free list.
+    /// Equals `holes` unless the map was built from corrupted raw parts.
+    pub free_list_len: usize,
+}
+
+/// Differences between two maps, returned by
+/// [`CompactMap::diff`](struct.CompactMap.html#method.diff).
+/// Each method walks both maps again, in ascending order of the keys.
+pub struct Diff<'a, V: 'a> {
+    old: &'a CompactMap<V>,
+    new: &'a CompactMap<V>,
+}
+
+impl<'a, V> Clone for Diff<'a, V> {
+    fn clone(&self) -> Diff<'a, V> {
+        *self
     }
+}
 
-    /// Returns an iterator visiting all key-value pairs in ascending order of
-    /// the keys, emptying (but not consuming) the original `CompactMap`.
-    /// The iterator's element type is `(usize, V)`. Keeps the allocated memory for reuse.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use compactmap::CompactMap;
-    ///
-    /// let mut map = CompactMap::new();
-    /// map.insert("a");
-    /// map.insert("b");
-    /// map.insert("c");
-    /// map.remove(1);
-    ///
-    /// let vec: Vec<(usize, &str)> = map.drain().collect();
-    ///
-    /// assert_eq!(vec, [(0, "a"), (2, "c")]);
-    /// assert!(map.is_empty_slow());
-    /// assert!(map.capacity() > 0);
-    /// ```
-    pub fn drain(&mut self) -> Drain<V> {
-        fn filter<A>((i, v): (usize, Entry<A>)) -> Option<(usize, A)> {
-            match v {
-                Entry::Empty(_) => None,
-                Entry::Occupied(x) => Some((i,x)),
-            }
-        }
-        let filter: fn((usize, Entry<V>)) -> Option<(usize, V)> = filter; // coerce to fn ptr
+impl<'a, V> Copy for Diff<'a, V> {}
 
-        self.free_head = SENTINEL;
-        Drain { iter: self.data.drain(..).enumerate().filter_map(filter) }
+impl<'a, V: PartialEq> Diff<'a, V> {
+    /// Entries of the second map whose keys are vacant in the first one
+    pub fn added(&self) -> impl Iterator<Item = (usize, &'a V)> + 'a {
+        let old = self.old;
+        self.new.iter().filter(move |&(k, _)| old.get(k).is_none())
     }
 
-    fn reindex(&mut self) {
-        self.free_head = SENTINEL;
-        for i in 0..self.data.len() {
-            if let Entry::Empty(ref mut head) = self.data[i] {
-                *head = self.free_head;
-                self.free_head = i;
+    /// Keys occupied in the first map, but not in the second one
+    pub fn removed(&self) -> impl Iterator<Item = usize> + 'a {
+        let new = self.new;
+        self.old.keys().filter(move |&k| new.get(k).is_none())
+    }
+
+    /// Entries of the second map whose keys have a different value in the first one
+    pub fn changed(&self) -> impl Iterator<Item = (usize, &'a V)> + 'a {
+        let old = self.old;
+        self.new.iter().filter(move |&(k, v)| match old.get(k) {
+            Some(o) => o != v,
+            None => false,
+        })
+    }
+}
+
+struct DebugStats<'a, V: 'a> {
+    map: &'a CompactMap<V>,
+}
+
+impl<'a, V: fmt::Debug> fmt::Debug for DebugStats<'a, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CompactMap")
+            .field("len", &self.map.len())
+            .field("capacity", &self.map.capacity())
+            .field("holes", &self.map.free_count())
+            .field("data", self.map)
+            .finish()
+    }
+}
+
+struct DisplayMap<'a, V: 'a> {
+    map: &'a CompactMap<V>,
+}
+
+impl<'a, V: fmt::Display> fmt::Display for DisplayMap<'a, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        display_entries(f, self.map)
+    }
+}
+
+/// Writes entries as `{k: v, k: v}`, or one per line in the alternate form.
+fn display_entries<K, V, I>(f: &mut fmt::Formatter, entries: I) -> fmt::Result
+    where K: fmt::Display, V: fmt::Display, I: IntoIterator<Item = (K, V)>
+{
+    let pretty = f.alternate();
+    let mut empty = true;
+    f.write_str("{")?;
+    for (k, v) in entries {
+        if pretty {
+            write!(f, "\n    {}: {},", k, v)?;
+        } else {
+            if !empty {
+                f.write_str(", ")?;
             }
+            write!(f, "{}: {}", k, v)?;
+        }
+        empty = false;
+    }
+    if pretty && !empty {
+        f.write_str("\n")?;
+    }
+    f.write_str("}")
+}
+
+/// Kind of free list corruption found by
+/// [`CompactMap::validate`](struct.CompactMap.html#method.validate)
+/// or [`CompactMap::from_raw_parts`](struct.CompactMap.html#method.from_raw_parts).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The free list points outside of the backing vector
+    OutOfBounds {
+        /// Offending link
+        index: usize,
+    },
+    /// The free list points to an occupied slot
+    OccupiedInFreeList {
+        /// Index of the slot
+        index: usize,
+    },
+    /// The free list visits the slot twice
+    Cycle {
+        /// Index of the slot
+        index: usize,
+    },
+    /// A vacant slot is not reachable from the free list
+    Unlinked {
+        /// Index of the slot
+        index: usize,
+    },
+    /// The cached element count does not match the number of occupied slots
+    WrongCount {
+        /// Cached count
+        stored: usize,
+        /// Number of occupied slots
+        actual: usize,
+    },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ValidationError::OutOfBounds { index } =>
+                write!(f, "free list points out of bounds to {}", index),
+            ValidationError::OccupiedInFreeList { index } =>
+                write!(f, "occupied slot {} is in the free list", index),
+            ValidationError::Cycle { index } =>
+                write!(f, "free list has a cycle at slot {}", index),
+            ValidationError::Unlinked { index } =>
+                write!(f, "vacant slot {} is not in the free list", index),
+            ValidationError::WrongCount { stored, actual } =>
+                write!(f, "element count is {}, but {} slots are occupied", stored, actual),
         }
     }
 }
 
+impl std::error::Error for ValidationError {}
+
+#[cfg(not(feature = "allocator_api"))]
 impl<V> Default for CompactMap<V> {
     fn default() -> CompactMap<V> {
         CompactMap::new()
     }
 }
 
+#[cfg(feature = "allocator_api")]
+impl<V, A: Allocator + Default> Default for CompactMap<V, A> {
+    fn default() -> CompactMap<V, A> {
+        CompactMap::new_in(A::default())
+    }
+}
 
-impl<V> Hash for CompactMap<V>
-where
-    V: Hash,
-{
-    fn hash<H>(&self, state: &mut H)
-    where
-        H: Hasher,
-    {
-        for i in 0..(self.data.len()) {
-            if let Entry::Occupied(ref j) = self.data[i] {
-                state.write_usize(i);
-                j.hash(state);
+
+impl_any_alloc! {
+    impl[V: Hash] (Hash) for CompactMap {
+        fn hash<H>(&self, state: &mut H)
+        where
+            H: Hasher,
+        {
+            for i in 0..(self.data.len()) {
+                if let Entry::Occupied(ref j) = self.data[i] {
+                    state.write_usize(i);
+                    j.hash(state);
+                }
             }
         }
     }
@@ -423,9 +3622,11 @@ where
 
 // [Partial]Eq impls are based on onces from VecMap
 
-impl<V: PartialEq> PartialEq for CompactMap<V> {
-    fn eq(&self, other: &Self) -> bool {
-        self.iter().eq(other.iter())
+impl_any_alloc! {
+    impl[V: PartialEq] (PartialEq) for CompactMap {
+        fn eq(&self, other: &Self) -> bool {
+            self.iter().eq(other.iter())
+        }
     }
 }
 
@@ -458,7 +3659,9 @@ macro_rules! iterate_for_ord_and_eq {
     }
 }
 
-impl<V: Eq> Eq for CompactMap<V> {}
+impl_any_alloc! {
+    impl[V: Eq] (Eq) for CompactMap {}
+}
 
 // We are greater then them iif { { we have i'th slot
 // filled in and they don't } or { data in i'th slot compares
@@ -502,105 +3705,194 @@ where
     }
 }
 
-impl<V> FromIterator<V> for CompactMap<V> {
-    fn from_iter<I>(iter: I) -> CompactMap<V>
-    where
-        I: IntoIterator<Item = V>,
-    {
+impl<V> From<HashMap<usize, V>> for CompactMap<V> {
+    fn from(m: HashMap<usize, V>) -> CompactMap<V> {
         let mut c = CompactMap::new();
-        
-        let it = iter.into_iter();
-        
-        reasonable_reserve(&mut c.data, it.size_hint());
-        
-        for i in it {
-            c.insert(i);
+        if let Some(&max_key) = m.keys().max() {
+            c.data.reserve_exact(max_key + 1);
+        }
+        for (k, v) in m {
+            c.put_unindexed(k, v);
         }
+        c.reindex();
         c
     }
 }
 
-impl<'a, V> FromIterator<&'a V> for CompactMap<V>
-where
-    V: Copy,
-{
-    #[allow(map_clone)]
-    fn from_iter<I>(iter: I) -> CompactMap<V>
-    where
-        I: IntoIterator<Item = &'a V>,
-    {
-        FromIterator::<V>::from_iter(iter.into_iter().map(|&value| value))
+impl<V> From<BTreeMap<usize, V>> for CompactMap<V> {
+    fn from(m: BTreeMap<usize, V>) -> CompactMap<V> {
+        let mut c = CompactMap::new();
+        if let Some(&max_key) = m.keys().next_back() {
+            c.data.reserve_exact(max_key + 1);
+        }
+        for (k, v) in m {
+            c.put_unindexed(k, v);
+        }
+        c.reindex();
+        c
     }
 }
 
-fn reasonable_reserve<T>(v: &mut Vec<T>, (rmin, mbrmax) : (usize, Option<usize>)) {
-    use std::cmp::{min,max};
-    if let Some(rmax) = mbrmax {
-        if rmin == rmax {
-            v.reserve_exact(rmin);
-        } else {
-            let reasonable_reserve = max(rmin, min(rmax, 4096));
-            v.reserve(reasonable_reserve);
-        }
-    } else {
-        v.reserve(rmin);
+impl<V> From<CompactMap<V>> for HashMap<usize, V> {
+    fn from(m: CompactMap<V>) -> HashMap<usize, V> {
+        m.into_hash_map()
+    }
+}
+
+impl<V> From<CompactMap<V>> for BTreeMap<usize, V> {
+    fn from(m: CompactMap<V>) -> BTreeMap<usize, V> {
+        m.into_btree_map()
+    }
+}
+
+/// Element `k` of the vector becomes the value at key `k`; `None`s become vacant slots,
+/// except trailing ones, which are dropped.
+impl<V> From<Vec<Option<V>>> for CompactMap<V> {
+    fn from(v: Vec<Option<V>>) -> CompactMap<V> {
+        CompactMap::from_entries(v.into_iter().map(|x| match x {
+            Some(x) => Entry::Occupied(x),
+            None => Entry::Empty(SENTINEL),
+        }).collect())
+    }
+}
+
+/// The value at key `k` ends up at index `k`, vacant slots become `None`s.
+impl<V> From<CompactMap<V>> for Vec<Option<V>> {
+    fn from(m: CompactMap<V>) -> Vec<Option<V>> {
+        m.data.into_iter().map(|e| match e {
+            Entry::Occupied(x) => Some(x),
+            Entry::Empty(_) => None,
+        }).collect()
+    }
+}
+
+impl<V: PartialEq, S: BuildHasher> PartialEq<HashMap<usize, V, S>> for CompactMap<V> {
+    fn eq(&self, other: &HashMap<usize, V, S>) -> bool {
+        self.len() == other.len() && self.iter().all(|(k, v)| other.get(&k) == Some(v))
+    }
+}
+
+impl<V: PartialEq, S: BuildHasher> PartialEq<CompactMap<V>> for HashMap<usize, V, S> {
+    fn eq(&self, other: &CompactMap<V>) -> bool {
+        other == self
+    }
+}
+
+impl<V: PartialEq> PartialEq<BTreeMap<usize, V>> for CompactMap<V> {
+    fn eq(&self, other: &BTreeMap<usize, V>) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter().map(|(&k, v)| (k, v)))
+    }
+}
+
+impl<V: PartialEq> PartialEq<CompactMap<V>> for BTreeMap<usize, V> {
+    fn eq(&self, other: &CompactMap<V>) -> bool {
+        other == self
     }
 }
 
-impl<V> Extend<V> for CompactMap<V> {
-    fn extend<I>(&mut self, iter: I)
+impl<V> FromIterator<V> for CompactMap<V> {
+    fn from_iter<I>(iter: I) -> CompactMap<V>
     where
         I: IntoIterator<Item = V>,
     {
+        let mut c = CompactMap::new();
+        
         let it = iter.into_iter();
         
-        reasonable_reserve(&mut self.data, it.size_hint());
+        c.reasonable_reserve(it.size_hint());
         
         for i in it {
-            self.insert(i);
+            c.insert(i);
         }
+        c
     }
 }
-impl<'a, V> Extend<&'a V> for CompactMap<V>
+
+impl<'a, V> FromIterator<&'a V> for CompactMap<V>
 where
     V: Copy,
 {
-    #[allow(map_clone)]
-    fn extend<I>(&mut self, iter: I)
+    fn from_iter<I>(iter: I) -> CompactMap<V>
     where
         I: IntoIterator<Item = &'a V>,
     {
-        self.extend(iter.into_iter().map(|&value| value));
+        FromIterator::<V>::from_iter(iter.into_iter().copied())
+    }
+}
+
+
+impl_any_alloc! {
+    impl[V] (Extend<V>) for CompactMap {
+        fn extend<I>(&mut self, iter: I)
+        where
+            I: IntoIterator<Item = V>,
+        {
+            let it = iter.into_iter();
+            
+            self.reasonable_reserve(it.size_hint());
+            
+            for i in it {
+                self.insert(i);
+            }
+        }
+    }
+}
+impl_any_alloc! {
+    impl['a, V: Copy] (Extend<&'a V>) for CompactMap {
+        fn extend<I>(&mut self, iter: I)
+        where
+            I: IntoIterator<Item = &'a V>,
+        {
+            self.extend(iter.into_iter().copied());
+        }
     }
 }
 
 // Debug, Index and IntexMut mostly borrowed from VecMap
-impl<V> Index<usize> for CompactMap<V> {
-    type Output = V;
-    #[inline]
-    fn index(&self, i: usize) -> &V {
-        self.get(i).expect("key not present")
+impl_any_alloc! {
+    impl[V] (Index<usize>) for CompactMap {
+        type Output = V;
+        #[inline]
+        fn index(&self, i: usize) -> &V {
+            self.get(i).expect("key not present")
+        }
     }
 }
-impl<'a, V> Index<&'a usize> for CompactMap<V> {
-    type Output = V;
-    fn index(&self, i: &usize) -> &V {
-        self.get(*i).expect("key not present")
+impl_any_alloc! {
+    impl['a, V] (Index<&'a usize>) for CompactMap {
+        type Output = V;
+        fn index(&self, i: &usize) -> &V {
+            self.get(*i).expect("key not present")
+        }
     }
 }
-impl<V> IndexMut<usize> for CompactMap<V> {
-    fn index_mut(&mut self, i: usize) -> &mut V {
-        self.get_mut(i).expect("key not present")
+impl_any_alloc! {
+    impl[V] (IndexMut<usize>) for CompactMap {
+        fn index_mut(&mut self, i: usize) -> &mut V {
+            self.get_mut(i).expect("key not present")
+        }
     }
 }
-impl<'a, V> IndexMut<&'a usize> for CompactMap<V> {
-    fn index_mut(&mut self, i: &usize) -> &mut V {
-        self.get_mut(*i).expect("key not present")
+impl_any_alloc! {
+    impl['a, V] (IndexMut<&'a usize>) for CompactMap {
+        fn index_mut(&mut self, i: &usize) -> &mut V {
+            self.get_mut(*i).expect("key not present")
+        }
     }
 }
-impl<V: fmt::Debug> fmt::Debug for CompactMap<V> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_map().entries(self).finish()
+impl_any_alloc! {
+    impl[V: fmt::Debug] (fmt::Debug) for CompactMap {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.debug_map().entries(self).finish()
+        }
+    }
+}
+
+impl_any_alloc! {
+    impl[V: fmt::Display] (fmt::Display) for CompactMap {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            display_entries(f, self)
+        }
     }
 }
 
@@ -630,6 +3922,78 @@ macro_rules! generate_iterator {
     };
 }
 
+/// Runs `$body` for each remaining entry (bound to `$item`) from the front,
+/// advancing the iterator. `$body` may `return` early.
+macro_rules! generate_scan {
+    ($self_:ident, mut, $item:ident => $body:block) => {
+        generate_scan!($self_ ; & mut Entry::Occupied(ref mut x), x, $item => $body);
+    };
+    ($self_:ident, const, $item:ident => $body:block) => {
+        generate_scan!($self_ ; &     Entry::Occupied(ref     x), x, $item => $body);
+    };
+    ($self_:ident, plain, $item:ident => $body:block) => {
+        generate_scan!($self_ ;       Entry::Occupied(        x), x, $item => $body);
+    };
+    ($self_:ident ; $pp:pat, $x:ident, $item:ident => $body:block) => {
+        while let Some(a) = $self_.iter.next() {
+            $self_.counter+=1;
+            if let $pp = a {
+                let $item = ($self_.counter-1, $x);
+                $body
+            }
+        }
+    };
+}
+
+/// Overrides of searching `Iterator` methods scanning the backing slice directly.
+macro_rules! generate_searches {
+    ($kind:tt, $item_ty:ty) => {
+        fn any<F>(&mut self, mut f: F) -> bool
+            where F: FnMut($item_ty) -> bool
+        {
+            generate_scan!(self, $kind, item => { if f(item) { return true; } });
+            false
+        }
+        fn all<F>(&mut self, mut f: F) -> bool
+            where F: FnMut($item_ty) -> bool
+        {
+            generate_scan!(self, $kind, item => { if !f(item) { return false; } });
+            true
+        }
+        fn find<P>(&mut self, mut predicate: P) -> Option<$item_ty>
+            where P: FnMut(&$item_ty) -> bool
+        {
+            generate_scan!(self, $kind, item => { if predicate(&item) { return Some(item); } });
+            None
+        }
+        fn position<P>(&mut self, mut predicate: P) -> Option<usize>
+            where P: FnMut($item_ty) -> bool
+        {
+            let mut n = 0;
+            generate_scan!(self, $kind, item => {
+                if predicate(item) { return Some(n); }
+                n += 1;
+            });
+            None
+        }
+    };
+}
+
+/// Skips `n` occupied entries without building items for them, then yields the next one.
+macro_rules! generate_nth {
+    ($self_:ident, $n:ident) => {
+        let mut n = $n;
+        while n > 0 {
+            $self_.counter+=1;
+            match $self_.iter.next() {
+                Some(a) => if a.is_occupied() { n -= 1; },
+                None => return None,
+            }
+        }
+        return $self_.next();
+    };
+}
+
 macro_rules! generate_rev_iterator {
     ($self_:ident, mut) => {
         generate_rev_iterator!($self_ ; & mut Entry::Occupied(ref mut x), x);
@@ -642,7 +4006,7 @@ macro_rules! generate_rev_iterator {
     };
     ($self_:ident ; $pp:pat, $x:ident) => {
         loop {
-            if $self_.counter == 0 { return None; }
+            if $self_.counter_back == $self_.counter { return None; }
             let e = $self_.iter.next_back();
             $self_.counter_back-=1;
             if let Some(a) = e {
@@ -672,13 +4036,55 @@ impl<'a, V> Clone for Iter<'a, V> {
         }
     }
 }
+impl<'a, V> Iter<'a, V> {
+    /// Returns the entry `next` would return, without advancing the iterator.
+    /// O(number of vacant slots before it).
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map: CompactMap<char> = "abc".chars().collect();
+    /// map.remove(0);
+    /// let mut it = map.iter();
+    /// assert_eq!(it.peek(), Some((1, &'b')));
+    /// assert_eq!(it.peek_key(), Some(1));
+    /// assert_eq!(it.next(), Some((1, &'b')));
+    /// assert_eq!(it.peek_key(), Some(2));
+    /// ```
+    pub fn peek(&self) -> Option<(usize, &'a V)> {
+        self.clone().next()
+    }
+
+    /// Returns the key `next` would return, without advancing the iterator.
+    pub fn peek_key(&self) -> Option<usize> {
+        self.peek().map(|e| e.0)
+    }
+}
 impl<'a, V> Iterator for Iter<'a, V> {
     type Item = (usize, &'a V);
 
-    #[allow(match_ref_pats)]
     fn next(&mut self) -> Option<(usize, &'a V)> {
         generate_iterator!(self, const);
     }
+    fn nth(&mut self, n: usize) -> Option<(usize, &'a V)> {
+        generate_nth!(self, n);
+    }
+    fn last(mut self) -> Option<(usize, &'a V)> {
+        self.next_back()
+    }
+    generate_searches!(const, (usize, &'a V));
+    fn fold<B, F>(self, init: B, mut f: F) -> B
+        where F: FnMut(B, (usize, &'a V)) -> B
+    {
+        let mut i = self.counter;
+        self.iter.fold(init, |acc, e| {
+            i += 1;
+            match *e {
+                Entry::Occupied(ref v) => f(acc, (i - 1, v)),
+                Entry::Empty(_) => acc,
+            }
+        })
+    }
     fn size_hint(&self) -> (usize, Option<usize>) {
         (0, self.iter.size_hint().1)
     }
@@ -688,31 +4094,94 @@ impl<'a, V> DoubleEndedIterator for Iter<'a, V>  {
         generate_rev_iterator!(self, const);
     }
 }
-impl<'a, V> IntoIterator for &'a CompactMap<V> {
-    type Item = (usize, &'a V);
-    type IntoIter = Iter<'a, V>;
-    fn into_iter(self) -> Iter<'a, V> {
-        Iter {
-            iter: self.data.iter(),
-            counter: 0,
-            counter_back: self.data.len(),
+impl<'a, V> FusedIterator for Iter<'a, V> {}
+impl_any_alloc! {
+    impl['a, V] (IntoIterator) for &'a CompactMap {
+        type Item = (usize, &'a V);
+        type IntoIter = Iter<'a, V>;
+        fn into_iter(self) -> Iter<'a, V> {
+            Iter {
+                iter: self.data.iter(),
+                counter: 0,
+                counter_back: self.data.len(),
+            }
         }
     }
 }
 
 /// An iterator over the key-value pairs of a map, with the
 /// values being mutable.
+///
+/// It is not `Clone`, as that would alias the mutable references.
+/// Use [`split_at`](#method.split_at) to get two disjoint iterators instead.
 pub struct IterMut<'a, V: 'a> {
     iter: slice::IterMut<'a, Entry<V>>,
     counter: usize,
     counter_back: usize,
 }
+impl<'a, V: 'a> IterMut<'a, V> {
+    /// Splits the remaining iteration into two disjoint iterators:
+    /// one over keys below `mid` and one over keys from `mid` onwards.
+    /// `mid` is clamped to the remaining key range, so one of
+    /// the halves may be empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use compactmap::CompactMap;
+    ///
+    /// let mut map: CompactMap<u32> = (0..6).collect();
+    /// {
+    ///     let (lo, hi) = map.iter_mut().split_at(4);
+    ///     for ((_, a), (_, b)) in lo.zip(hi) {
+    ///         *a += 10;
+    ///         *b += 20;
+    ///     }
+    /// }
+    /// assert_eq!(map.values().cloned().collect::<Vec<_>>(), [10, 11, 2, 3, 24, 25]);
+    /// ```
+    pub fn split_at(self, mid: usize) -> (IterMut<'a, V>, IterMut<'a, V>) {
+        let mid = mid.max(self.counter).min(self.counter_back);
+        let (lo, hi) = self.iter.into_slice().split_at_mut(mid - self.counter);
+        (
+            IterMut {
+                iter: lo.iter_mut(),
+                counter: self.counter,
+                counter_back: mid,
+            },
+            IterMut {
+                iter: hi.iter_mut(),
+                counter: mid,
+                counter_back: self.counter_back,
+            },
+        )
+    }
+}
 impl<'a, V: 'a> Iterator for IterMut<'a, V> {
     type Item = (usize, &'a mut V);
 
     fn next<'b>(&'b mut self) -> Option<(usize, &'a mut V)> {
         generate_iterator!(self, mut);
     }
+    fn nth(&mut self, n: usize) -> Option<(usize, &'a mut V)> {
+        generate_nth!(self, n);
+    }
+    fn last(mut self) -> Option<(usize, &'a mut V)> {
+        self.next_back()
+    }
+    generate_searches!(mut, (usize, &'a mut V));
+    fn fold<B, F>(self, init: B, mut f: F) -> B
+        where F: FnMut(B, (usize, &'a mut V)) -> B
+    {
+        let mut i = self.counter;
+        self.iter.fold(init, |acc, e| {
+            i += 1;
+            match *e {
+                Entry::Occupied(ref mut v) => f(acc, (i - 1, v)),
+                Entry::Empty(_) => acc,
+            }
+        })
+    }
     fn size_hint(&self) -> (usize, Option<usize>) {
         (0, self.iter.size_hint().1)
     }
@@ -722,53 +4191,189 @@ impl<'a, V: 'a> DoubleEndedIterator for IterMut<'a, V> {
         generate_rev_iterator!(self, mut);
     }
 }
+impl<'a, V: 'a> FusedIterator for IterMut<'a, V> {}
 
-impl<'a, V: 'a> IntoIterator for &'a mut CompactMap<V> {
-    type Item = (usize, &'a mut V);
-    type IntoIter = IterMut<'a, V>;
-    fn into_iter(self) -> IterMut<'a, V> {
-        let cb = self.data.len();
-        IterMut {
-            iter: self.data.iter_mut(),
-            counter: 0,
-            counter_back: cb,
+impl_any_alloc! {
+    impl['a, V: 'a] (IntoIterator) for &'a mut CompactMap {
+        type Item = (usize, &'a mut V);
+        type IntoIter = IterMut<'a, V>;
+        fn into_iter(self) -> IterMut<'a, V> {
+            let cb = self.data.len();
+            IterMut {
+                iter: self.data.iter_mut(),
+                counter: 0,
+                counter_back: cb,
+            }
         }
     }
 }
 
 /// A consuming iterator over the key-value pairs of a map.
+#[cfg(not(feature = "allocator_api"))]
+#[derive(Clone)]
 pub struct IntoIter<V> {
     iter: vec::IntoIter<Entry<V>>,
     counter: usize,
     counter_back: usize,
 }
-impl<V> Iterator for IntoIter<V> {
-    type Item = (usize, V);
 
-    fn next(&mut self) -> Option<(usize, V)> {
-        generate_iterator!(self, plain);
+/// A consuming iterator over the key-value pairs of a map.
+#[cfg(feature = "allocator_api")]
+#[derive(Clone)]
+pub struct IntoIter<V, A: Allocator = Global> {
+    iter: vec::IntoIter<Entry<V>, A>,
+    counter: usize,
+    counter_back: usize,
+}
+impl_any_alloc! {
+    impl[V] (Iterator) for IntoIter<V> {
+        type Item = (usize, V);
+
+        fn next(&mut self) -> Option<(usize, V)> {
+            generate_iterator!(self, plain);
+        }
+        fn nth(&mut self, n: usize) -> Option<(usize, V)> {
+            generate_nth!(self, n);
+        }
+        fn last(mut self) -> Option<(usize, V)> {
+            self.next_back()
+        }
+        generate_searches!(plain, (usize, V));
+        fn fold<B, F>(self, init: B, mut f: F) -> B
+            where F: FnMut(B, (usize, V)) -> B
+        {
+            let mut i = self.counter;
+            self.iter.fold(init, |acc, e| {
+                i += 1;
+                match e {
+                    Entry::Occupied(v) => f(acc, (i - 1, v)),
+                    Entry::Empty(_) => acc,
+                }
+            })
+        }
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            (0, self.iter.size_hint().1)
+        }
     }
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        (0, self.iter.size_hint().1)
+}
+impl_any_alloc! {
+    impl[V] (DoubleEndedIterator) for IntoIter<V> {
+        fn next_back(&mut self) -> Option<(usize, V)> {
+            generate_rev_iterator!(self, plain);
+        }
     }
 }
-impl<V> DoubleEndedIterator for IntoIter<V> {
-    fn next_back(&mut self) -> Option<(usize, V)> {
-        generate_rev_iterator!(self, plain);
+impl_any_alloc! {
+    impl[V] (FusedIterator) for IntoIter<V> {}
+}
+impl_any_alloc! {
+    impl[V] (IntoIterator) for CompactMap {
+        type Item = (usize, V);
+        type IntoIter = with_alloc!(IntoIter<V>);
+        fn into_iter(self) -> with_alloc!(IntoIter<V>) {
+            let cb = self.data.len();
+            IntoIter {
+                iter: self.data.into_iter(),
+                counter: 0,
+                counter_back: cb,
+            }
+        }
     }
 }
-impl<V> IntoIterator for CompactMap<V> {
-    type Item = (usize, V);
-    type IntoIter = IntoIter<V>;
-    fn into_iter(self) -> IntoIter<V> {
-        let cb = self.data.len();
-        IntoIter {
-            iter: self.data.into_iter(),
-            counter: 0,
-            counter_back: cb,
+
+/// A consuming iterator over the keys of a map.
+#[cfg(not(feature = "allocator_api"))]
+#[derive(Clone)]
+pub struct IntoKeys<V> {
+    iter: IntoIter<V>,
+}
+
+/// A consuming iterator over the keys of a map.
+#[cfg(feature = "allocator_api")]
+#[derive(Clone)]
+pub struct IntoKeys<V, A: Allocator = Global> {
+    iter: IntoIter<V, A>,
+}
+impl_any_alloc! {
+    impl[V] (Iterator) for IntoKeys<V> {
+        type Item = usize;
+
+        fn next(&mut self) -> Option<usize> {
+            self.iter.next().map(|e| e.0)
+        }
+        fn nth(&mut self, n: usize) -> Option<usize> {
+            self.iter.nth(n).map(|e| e.0)
+        }
+        fn last(self) -> Option<usize> {
+            self.iter.last().map(|e| e.0)
+        }
+        fn fold<B, F>(self, init: B, mut f: F) -> B
+            where F: FnMut(B, usize) -> B
+        {
+            self.iter.fold(init, |acc, e| f(acc, e.0))
+        }
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            self.iter.size_hint()
+        }
+    }
+}
+impl_any_alloc! {
+    impl[V] (DoubleEndedIterator) for IntoKeys<V> {
+        fn next_back(&mut self) -> Option<usize> {
+            self.iter.next_back().map(|e| e.0)
+        }
+    }
+}
+impl_any_alloc! {
+    impl[V] (FusedIterator) for IntoKeys<V> {}
+}
+
+/// A consuming iterator over the values of a map.
+#[cfg(not(feature = "allocator_api"))]
+#[derive(Clone)]
+pub struct IntoValues<V> {
+    iter: IntoIter<V>,
+}
+
+/// A consuming iterator over the values of a map.
+#[cfg(feature = "allocator_api")]
+#[derive(Clone)]
+pub struct IntoValues<V, A: Allocator = Global> {
+    iter: IntoIter<V, A>,
+}
+impl_any_alloc! {
+    impl[V] (Iterator) for IntoValues<V> {
+        type Item = V;
+
+        fn next(&mut self) -> Option<V> {
+            self.iter.next().map(|e| e.1)
+        }
+        fn nth(&mut self, n: usize) -> Option<V> {
+            self.iter.nth(n).map(|e| e.1)
+        }
+        fn last(self) -> Option<V> {
+            self.iter.last().map(|e| e.1)
+        }
+        fn fold<B, F>(self, init: B, mut f: F) -> B
+            where F: FnMut(B, V) -> B
+        {
+            self.iter.fold(init, |acc, e| f(acc, e.1))
+        }
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            self.iter.size_hint()
+        }
+    }
+}
+impl_any_alloc! {
+    impl[V] (DoubleEndedIterator) for IntoValues<V> {
+        fn next_back(&mut self) -> Option<V> {
+            self.iter.next_back().map(|e| e.1)
         }
     }
 }
+impl_any_alloc! {
+    impl[V] (FusedIterator) for IntoValues<V> {}
+}
 
 
 /// An iterator over the keys of a map.
@@ -787,6 +4392,17 @@ impl<'a, V> Iterator for Keys<'a, V> {
     fn next(&mut self) -> Option<usize> {
         self.iter.next().map(|e| e.0)
     }
+    fn nth(&mut self, n: usize) -> Option<usize> {
+        self.iter.nth(n).map(|e| e.0)
+    }
+    fn last(self) -> Option<usize> {
+        self.iter.last().map(|e| e.0)
+    }
+    fn fold<B, F>(self, init: B, mut f: F) -> B
+        where F: FnMut(B, usize) -> B
+    {
+        self.iter.fold(init, |acc, e| f(acc, e.0))
+    }
     fn size_hint(&self) -> (usize, Option<usize>) {
         self.iter.size_hint()
     }
@@ -796,6 +4412,7 @@ impl<'a, V> DoubleEndedIterator for Keys<'a, V> {
         self.iter.next_back().map(|e| e.0)
     }
 }
+impl<'a, V> FusedIterator for Keys<'a, V> {}
 
 /// An iterator over the values of a map.
 pub struct Values<'a, V: 'a> {
@@ -813,73 +4430,316 @@ impl<'a, V> Iterator for Values<'a, V> {
     fn next(&mut self) -> Option<&'a V> {
         self.iter.next().map(|e| e.1)
     }
+    fn nth(&mut self, n: usize) -> Option<&'a V> {
+        self.iter.nth(n).map(|e| e.1)
+    }
+    fn last(self) -> Option<&'a V> {
+        self.iter.last().map(|e| e.1)
+    }
+    fn fold<B, F>(self, init: B, mut f: F) -> B
+        where F: FnMut(B, &'a V) -> B
+    {
+        self.iter.fold(init, |acc, e| f(acc, e.1))
+    }
     fn size_hint(&self) -> (usize, Option<usize>) {
         self.iter.size_hint()
     }
-}
-impl<'a, V> DoubleEndedIterator for Values<'a, V> {
-    fn next_back(&mut self) -> Option<&'a V> {
-        self.iter.next_back().map(|e| e.1)
+}
+impl<'a, V> DoubleEndedIterator for Values<'a, V> {
+    fn next_back(&mut self) -> Option<&'a V> {
+        self.iter.next_back().map(|e| e.1)
+    }
+}
+impl<'a, V> FusedIterator for Values<'a, V> {}
+
+/// An iterator over the values of a map.
+pub struct ValuesMut<'a, V: 'a> {
+    iter_mut: IterMut<'a, V>,
+}
+impl<'a, V> Iterator for ValuesMut<'a, V> {
+    type Item = &'a mut V;
+
+    fn next(&mut self) -> Option<&'a mut V> {
+        self.iter_mut.next().map(|e| e.1)
+    }
+    fn nth(&mut self, n: usize) -> Option<&'a mut V> {
+        self.iter_mut.nth(n).map(|e| e.1)
+    }
+    fn last(self) -> Option<&'a mut V> {
+        self.iter_mut.last().map(|e| e.1)
+    }
+    fn fold<B, F>(self, init: B, mut f: F) -> B
+        where F: FnMut(B, &'a mut V) -> B
+    {
+        self.iter_mut.fold(init, |acc, e| f(acc, e.1))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter_mut.size_hint()
+    }
+}
+impl<'a, V> DoubleEndedIterator for ValuesMut<'a, V> {
+    fn next_back(&mut self) -> Option<&'a mut V> {
+        self.iter_mut.next_back().map(|e| e.1)
+    }
+}
+impl<'a, V> FusedIterator for ValuesMut<'a, V> {}
+
+type DrainFilter<V> = fn((usize, Entry<V>)) -> Option<(usize, V)>;
+
+/// A draining iterator over the key-value pairs of a map.
+#[cfg(not(feature = "allocator_api"))]
+pub struct Drain<'a, V: 'a> {
+    iter: std::iter::FilterMap<std::iter::Enumerate<std::vec::Drain<'a, Entry<V>>>, DrainFilter<V>>
+}
+
+/// A draining iterator over the key-value pairs of a map.
+#[cfg(feature = "allocator_api")]
+pub struct Drain<'a, V: 'a, A: Allocator + 'a = Global> {
+    iter: std::iter::FilterMap<std::iter::Enumerate<std::vec::Drain<'a, Entry<V>, A>>, DrainFilter<V>>
+}
+
+impl_any_alloc! {
+    impl['a, V] (Iterator) for Drain<'a, V> {
+        type Item = (usize, V);
+
+        fn next(&mut self) -> Option<(usize, V)> { self.iter.next() }
+        fn size_hint(&self) -> (usize, Option<usize>) { self.iter.size_hint() }
+    }
+}
+impl_any_alloc! {
+    impl['a, V] (DoubleEndedIterator) for Drain<'a, V> {
+        fn next_back(&mut self) -> Option<(usize, V)> { self.iter.next_back() }
+    }
+}
+impl_any_alloc! {
+    impl['a, V] (FusedIterator) for Drain<'a, V> {}
+}
+
+/// A draining iterator over the key-value pairs of a map that owns the entries.
+/// Created by [`CompactMap::drain_owned`](struct.CompactMap.html#method.drain_owned).
+pub struct DrainOwned<V> {
+    inner: IntoIter<V>,
+}
+
+impl<V> Iterator for DrainOwned<V> {
+    type Item = (usize, V);
+
+    fn next(&mut self) -> Option<(usize, V)> { self.inner.next() }
+    fn size_hint(&self) -> (usize, Option<usize>) { self.inner.size_hint() }
+}
+impl<V> DoubleEndedIterator for DrainOwned<V> {
+    fn next_back(&mut self) -> Option<(usize, V)> { self.inner.next_back() }
+}
+impl<V> FusedIterator for DrainOwned<V> {}
+
+/// A draining iterator over the key-value pairs of a map within a key range.
+pub struct DrainRange<'a, V: 'a> {
+    map: &'a mut CompactMap<V>,
+    counter: usize,
+    counter_back: usize,
+}
+impl<'a, V> DrainRange<'a, V> {
+    /// Takes the value out and links its slot into the free list right away,
+    /// so the map stays consistent even if the iterator is leaked.
+    fn take(&mut self, i: usize) -> Option<V> {
+        if let Entry::Empty(_) = self.map.data[i] {
+            return None;
+        }
+        let empty_entry = Entry::Empty(self.map.free_head);
+        match mem::replace(&mut self.map.data[i], empty_entry) {
+            Entry::Occupied(v) => {
+                self.map.free_head = i;
+                self.map.occupied -= 1;
+                Some(v)
+            }
+            Entry::Empty(_) => unreachable!(),
+        }
+    }
+}
+impl<'a, V> Iterator for DrainRange<'a, V> {
+    type Item = (usize, V);
+
+    fn next(&mut self) -> Option<(usize, V)> {
+        while self.counter < self.counter_back {
+            let i = self.counter;
+            self.counter += 1;
+            if let Some(v) = self.take(i) {
+                return Some((i, v));
+            }
+        }
+        None
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.counter_back - self.counter))
+    }
+}
+impl<'a, V> DoubleEndedIterator for DrainRange<'a, V> {
+    fn next_back(&mut self) -> Option<(usize, V)> {
+        while self.counter < self.counter_back {
+            self.counter_back -= 1;
+            let i = self.counter_back;
+            if let Some(v) = self.take(i) {
+                return Some((i, v));
+            }
+        }
+        None
+    }
+}
+impl<'a, V> FusedIterator for DrainRange<'a, V> {}
+impl<'a, V> Drop for DrainRange<'a, V> {
+    fn drop(&mut self) {
+        let end = self.counter_back;
+        for _ in self.by_ref() {}
+        if end == self.map.data.len() && self.map.truncate_vacant_tail() {
+            self.map.reindex();
+        }
+        self.map.debug_validate();
+    }
+}
+
+
+/// A cursor over the occupied entries of a map, created by
+/// [`cursor_at`](struct.CompactMap.html#method.cursor_at).
+///
+/// Besides the entries there is an "end" position: moving forward from the
+/// last entry or backward from the first one gets there, and moving from
+/// it wraps around to the first or the last entry.
+pub struct Cursor<'a, V: 'a> {
+    map: &'a CompactMap<V>,
+    pos: usize,
+}
+impl<'a, V> Clone for Cursor<'a, V> {
+    fn clone(&self) -> Cursor<'a, V> {
+        Cursor {
+            map: self.map,
+            pos: self.pos,
+        }
+    }
+}
+impl<'a, V> Cursor<'a, V> {
+    /// Returns the key of the current entry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cursor is at the end.
+    pub fn key(&self) -> usize {
+        assert!(!self.is_at_end(), "cursor is at the end");
+        self.pos
+    }
+
+    /// Returns the value of the current entry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cursor is at the end.
+    pub fn value(&self) -> &'a V {
+        match self.map.data.get(self.pos) {
+            Some(Entry::Occupied(v)) => v,
+            _ => panic!("cursor is at the end"),
+        }
+    }
+
+    /// Moves to the next occupied entry.
+    pub fn move_next(&mut self) {
+        self.pos = self.map.next_occupied(self.pos.wrapping_add(1));
+    }
+
+    /// Moves to the previous occupied entry.
+    pub fn move_prev(&mut self) {
+        self.pos = self.map.prev_occupied(self.pos);
+    }
+
+    /// Checks if the cursor is past the entries rather than at one of them.
+    pub fn is_at_end(&self) -> bool {
+        self.pos == SENTINEL
     }
 }
 
-/// An iterator over the values of a map.
-pub struct ValuesMut<'a, V: 'a> {
-    iter_mut: IterMut<'a, V>,
+/// A cursor over the occupied entries of a map that can modify them, created by
+/// [`cursor_at_mut`](struct.CompactMap.html#method.cursor_at_mut).
+///
+/// Moves the same way as [`Cursor`](struct.Cursor.html).
+pub struct CursorMut<'a, V: 'a> {
+    map: &'a mut CompactMap<V>,
+    pos: usize,
 }
-impl<'a, V> Iterator for ValuesMut<'a, V> {
-    type Item = &'a mut V;
+impl<'a, V> CursorMut<'a, V> {
+    /// Returns the key of the current entry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cursor is at the end.
+    pub fn key(&self) -> usize {
+        assert!(!self.is_at_end(), "cursor is at the end");
+        self.pos
+    }
 
-    fn next(&mut self) -> Option<&'a mut V> {
-        self.iter_mut.next().map(|e| e.1)
+    /// Returns the value of the current entry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cursor is at the end.
+    pub fn value(&self) -> &V {
+        match self.map.data.get(self.pos) {
+            Some(Entry::Occupied(v)) => v,
+            _ => panic!("cursor is at the end"),
+        }
     }
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        self.iter_mut.size_hint()
+
+    /// Returns the value of the current entry, mutably.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cursor is at the end.
+    pub fn value_mut(&mut self) -> &mut V {
+        match self.map.data.get_mut(self.pos) {
+            Some(Entry::Occupied(v)) => v,
+            _ => panic!("cursor is at the end"),
+        }
     }
-}
-impl<'a, V> DoubleEndedIterator for ValuesMut<'a, V> {
-    fn next_back(&mut self) -> Option<&'a mut V> {
-        self.iter_mut.next_back().map(|e| e.1)
+
+    /// Removes the current entry like [`remove`](struct.CompactMap.html#method.remove)
+    /// and moves to the next one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cursor is at the end.
+    pub fn remove_current(&mut self) -> V {
+        let i = self.key();
+        let v = self.map.remove(i).expect("cursor points to a vacant slot");
+        self.pos = self.map.next_occupied(i + 1);
+        v
     }
-}
 
-/// A draining iterator over the key-value pairs of a map.
-pub struct Drain<'a, V: 'a> {
-    iter: std::iter::FilterMap<
-        std::iter::Enumerate<std::vec::Drain<'a, Entry<V>>>,
-        fn((usize, Entry<V>)) -> Option<(usize, V)>
-        >
-}
+    /// Moves to the next occupied entry.
+    pub fn move_next(&mut self) {
+        self.pos = self.map.next_occupied(self.pos.wrapping_add(1));
+    }
 
-impl<'a, V> Iterator for Drain<'a, V> {
-    type Item = (usize, V);
+    /// Moves to the previous occupied entry.
+    pub fn move_prev(&mut self) {
+        self.pos = self.map.prev_occupied(self.pos);
+    }
 
-    fn next(&mut self) -> Option<(usize, V)> { self.iter.next() }
-    fn size_hint(&self) -> (usize, Option<usize>) { self.iter.size_hint() }
-}
-impl<'a, V> DoubleEndedIterator for Drain<'a, V> {
-    fn next_back(&mut self) -> Option<(usize, V)> { self.iter.next_back() }
+    /// Checks if the cursor is past the entries rather than at one of them.
+    pub fn is_at_end(&self) -> bool {
+        self.pos == SENTINEL
+    }
 }
 
-
 #[cfg(feature = "serde")]
 mod serdizer {
     extern crate serde;
 
     use super::CompactMap;
-    use super::Entry;
 
-    use super::SENTINEL;
     use self::serde::ser::SerializeMap;
 
     impl<V: serde::Serialize> serde::Serialize for CompactMap<V> {
         fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
-            #[cfg(feature = "serde_ser_len")]
-            let len = Some(self.len_slow());
-            #[cfg(not(feature = "serde_ser_len"))]
-            let len = None;
-
-            let mut map = s.serialize_map(len)?;
+            // Length is O(1), so always provide it: formats like postcard
+            // and bincode can't serialize maps of unknown length.
+            let mut map = s.serialize_map(Some(self.len()))?;
             for (k, v) in self {
                 map.serialize_entry(&k, v)?;
             }
@@ -894,13 +4754,22 @@ mod serdizer {
 
     use self::serde::de::{Deserialize, Deserializer, Visitor, MapAccess};
 
+    use self::serde::de::Error;
+
+    /// Key limit of the `Deserialize` impl
+    #[cfg(feature = "serde_safe")]
+    const DEFAULT_KEY_LIMIT: usize = 1 << 20;
+    #[cfg(not(feature = "serde_safe"))]
+    const DEFAULT_KEY_LIMIT: usize = usize::MAX;
+
     struct MyMapVisitor<V> {
         marker: PhantomData<fn() -> CompactMap<V>>,
+        limit: usize,
     }
 
     impl<V> MyMapVisitor<V> {
-        fn new() -> Self {
-            MyMapVisitor { marker: PhantomData }
+        fn new(limit: usize) -> Self {
+            MyMapVisitor { marker: PhantomData, limit }
         }
     }
 
@@ -921,13 +4790,12 @@ mod serdizer {
         {
             let mut map = CompactMap::with_capacity(access.size_hint().unwrap_or(0));
 
-            while let Some((key, value)) = access.next_entry()? {
-
-                // because of Vec::resize_default is unstable
-                while map.data.len() <= key {
-                    map.data.push(Entry::Empty(SENTINEL));
+            while let Some((key, value)) = access.next_entry::<usize, V>()? {
+                if key >= self.limit {
+                    return Err(M::Error::custom(format_args!(
+                        "key {} is too large, the limit is {}", key, self.limit)));
                 }
-                map.data[key] = Entry::Occupied(value);
+                map.put_unindexed(key, value);
             }
             map.reindex();
 
@@ -946,19 +4814,652 @@ mod serdizer {
         {
             // Instantiate our Visitor and ask the Deserializer to drive
             // it over the input data, resulting in an instance of MyMap.
-            deserializer.deserialize_map(MyMapVisitor::new())
+            deserializer.deserialize_map(MyMapVisitor::new(DEFAULT_KEY_LIMIT))
+        }
+    }
+
+    impl<V> CompactMap<V> {
+        /// Deserializes the map like its `Deserialize` impl does, but fails
+        /// on keys `>= limit` instead of allocating space for them.
+        /// Use it on untrusted input, where a single large key could otherwise
+        /// make the map allocate a huge vector.
+        ///
+        /// ```
+        /// # extern crate compactmap;
+        /// # extern crate serde_json;
+        /// use compactmap::CompactMap;
+        ///
+        /// # fn main() {
+        /// let json = r#"{"1":"a","999999999":"b"}"#;
+        /// let mut de = serde_json::Deserializer::from_str(json);
+        /// assert!(CompactMap::<String>::deserialize_with_limit(&mut de, 1000).is_err());
+        ///
+        /// let mut de = serde_json::Deserializer::from_str(r#"{"1":"a"}"#);
+        /// let map = CompactMap::<String>::deserialize_with_limit(&mut de, 1000).unwrap();
+        /// assert_eq!(map[1], "a");
+        /// # }
+        /// ```
+        pub fn deserialize_with_limit<'de, D>(deserializer: D, limit: usize) -> Result<Self, D::Error>
+        where
+            V: Deserialize<'de>,
+            D: Deserializer<'de>,
+        {
+            deserializer.deserialize_map(MyMapVisitor::new(limit))
+        }
+    }
+}
+
+/// Alternative serde format: a sequence where position is the key,
+/// vacant slots are `null`s (`None`s) and occupied slots are their values.
+///
+/// Use with `#[serde(with = "compactmap::serde_seq")]`.
+///
+/// ```
+/// # extern crate compactmap;
+/// # extern crate serde_json;
+/// use compactmap::CompactMap;
+///
+/// # fn main() {
+/// let mut map = CompactMap::new();
+/// map.insert("a");
+/// map.insert("b");
+/// map.remove(0);
+///
+/// let mut buf = vec![];
+/// compactmap::serde_seq::serialize(&map, &mut serde_json::Serializer::new(&mut buf)).unwrap();
+/// assert_eq!(buf, br#"[null,"b"]"#);
+///
+/// let json = br#"[null,"b",null,"d",null]"#;
+/// let map: CompactMap<String> =
+///     compactmap::serde_seq::deserialize(&mut serde_json::Deserializer::from_slice(json)).unwrap();
+/// assert_eq!(map.keys().collect::<Vec<_>>(), [1, 3]);
+/// assert!(map.capacity() >= 5);
+/// # }
+/// ```
+#[cfg(feature = "serde")]
+pub mod serde_seq {
+    extern crate serde;
+
+    use super::{CompactMap, Entry};
+
+    use std::fmt;
+    use std::marker::PhantomData;
+
+    use self::serde::ser::{Serialize, Serializer, SerializeSeq};
+    use self::serde::de::{Deserialize, Deserializer, Visitor, SeqAccess};
+
+    /// Serializes the map as a sequence with `None`s in vacant slots.
+    pub fn serialize<V, S>(map: &CompactMap<V>, s: S) -> Result<S::Ok, S::Error>
+    where
+        V: Serialize,
+        S: Serializer,
+    {
+        let mut seq = s.serialize_seq(Some(map.data.len()))?;
+        for entry in &map.data {
+            match *entry {
+                Entry::Occupied(ref v) => seq.serialize_element(&Some(v))?,
+                Entry::Empty(_) => seq.serialize_element(&None::<&V>)?,
+            }
+        }
+        seq.end()
+    }
+
+    struct SeqVisitor<V> {
+        marker: PhantomData<fn() -> CompactMap<V>>,
+    }
+
+    impl<'de, V> Visitor<'de> for SeqVisitor<V>
+    where
+        V: Deserialize<'de>,
+    {
+        type Value = CompactMap<V>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a sequence of optional values")
+        }
+
+        fn visit_seq<A>(self, mut access: A) -> Result<Self::Value, A::Error>
+        where
+            A: SeqAccess<'de>,
+        {
+            let mut data = Vec::with_capacity(access.size_hint().unwrap_or(0));
+            while let Some(x) = access.next_element()? {
+                data.push(match x {
+                    Some(v) => Entry::Occupied(v),
+                    None => Entry::Empty(super::SENTINEL),
+                });
+            }
+            Ok(CompactMap::from_entries(data))
+        }
+    }
+
+    /// Deserializes the map from a sequence written by [`serialize`](fn.serialize.html),
+    /// keeping the keys. Trailing `None`s are dropped.
+    pub fn deserialize<'de, V, D>(deserializer: D) -> Result<CompactMap<V>, D::Error>
+    where
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(SeqVisitor { marker: PhantomData })
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary_impls {
+    extern crate arbitrary;
+
+    use super::CompactMap;
+    use self::arbitrary::{Arbitrary, Result, Unstructured};
+
+    enum Op<V> {
+        Insert(V),
+        Remove(usize),
+    }
+
+    impl<'a, V: Arbitrary<'a>> Arbitrary<'a> for Op<V> {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            if u.arbitrary()? {
+                Ok(Op::Insert(u.arbitrary()?))
+            } else {
+                Ok(Op::Remove(u.arbitrary::<u16>()? as usize))
+            }
+        }
+    }
+
+    // Replaying operations instead of generating raw slots
+    // keeps the free list well-formed.
+    impl<'a, V: Arbitrary<'a>> Arbitrary<'a> for CompactMap<V> {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            let mut map = CompactMap::new();
+            for op in u.arbitrary_iter::<Op<V>>()? {
+                match op? {
+                    Op::Insert(v) => {
+                        map.insert(v);
+                    }
+                    Op::Remove(k) => {
+                        if !map.data.is_empty() {
+                            let k = k % map.data.len();
+                            map.remove(k);
+                        }
+                    }
+                }
+            }
+            Ok(map)
+        }
+    }
+}
+
+#[cfg(feature = "schemars")]
+mod schemars_impls {
+    extern crate schemars;
+
+    use super::CompactMap;
+    use self::schemars::JsonSchema;
+    use self::schemars::gen::SchemaGenerator;
+    use self::schemars::schema::{InstanceType, Metadata, ObjectValidation, Schema, SchemaObject, StringValidation};
+
+    // JSON object keys are strings, so the key constraint is a pattern
+    // matching the way serde_json writes integer keys.
+    impl<V: JsonSchema> JsonSchema for CompactMap<V> {
+        fn schema_name() -> String {
+            format!("CompactMap_of_{}", V::schema_name())
+        }
+
+        fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+            let key = SchemaObject {
+                string: Some(Box::new(StringValidation {
+                    pattern: Some("^(0|[1-9][0-9]*)$".to_owned()),
+                    ..Default::default()
+                })),
+                ..Default::default()
+            };
+            SchemaObject {
+                metadata: Some(Box::new(Metadata {
+                    description: Some("Keys are non-negative integers assigned by the map".to_owned()),
+                    ..Default::default()
+                })),
+                instance_type: Some(InstanceType::Object.into()),
+                object: Some(Box::new(ObjectValidation {
+                    additional_properties: Some(Box::new(gen.subschema_for::<V>())),
+                    property_names: Some(Box::new(key.into())),
+                    ..Default::default()
+                })),
+                ..Default::default()
+            }.into()
+        }
+    }
+}
+
+#[cfg(feature = "bincode2")]
+mod bincode_impls {
+    extern crate bincode2 as bincode;
+
+    use std::mem::size_of;
+
+    use super::{CompactMap, Entry};
+    use self::bincode::{BorrowDecode, Decode, Encode};
+    use self::bincode::de::{BorrowDecoder, Decoder};
+    use self::bincode::enc::Encoder;
+    use self::bincode::error::{DecodeError, EncodeError};
+
+    // Same format as `BTreeMap<usize, V>`, which is also what
+    // bincode's serde support produces for the `Serialize` impl.
+    impl<V: Encode> Encode for CompactMap<V> {
+        fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+            self.len().encode(encoder)?;
+            for (k, v) in self {
+                k.encode(encoder)?;
+                v.encode(encoder)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Vacant slots allocated for a large key count against the decoder's
+    /// byte limit, if one is configured.
+    fn decode_entries<V, D, F>(decoder: &mut D, mut decode_value: F) -> Result<CompactMap<V>, DecodeError>
+        where D: Decoder, F: FnMut(&mut D) -> Result<V, DecodeError>
+    {
+        let len = usize::decode(decoder)?;
+        decoder.claim_container_read::<(usize, V)>(len)?;
+
+        let mut map = CompactMap::new();
+        for _ in 0..len {
+            decoder.unclaim_bytes_read(size_of::<(usize, V)>());
+            let key = usize::decode(decoder)?;
+            if key >= map.data.len() {
+                decoder.claim_container_read::<Entry<V>>(key - map.data.len() + 1)?;
+            }
+            let value = decode_value(decoder)?;
+            map.put_unindexed(key, value);
+        }
+        map.reindex();
+        Ok(map)
+    }
+
+    impl<Context, V: Decode<Context>> Decode<Context> for CompactMap<V> {
+        fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+            decode_entries(decoder, V::decode)
+        }
+    }
+
+    impl<'de, Context, V: BorrowDecode<'de, Context>> BorrowDecode<'de, Context> for CompactMap<V> {
+        fn borrow_decode<D: BorrowDecoder<'de, Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+            decode_entries(decoder, V::borrow_decode)
+        }
+    }
+}
+
+#[cfg(feature = "rand")]
+mod rand_impls {
+    extern crate rand;
+
+    use super::CompactMap;
+    use self::rand::Rng;
+
+    impl<V> CompactMap<V> {
+        /// Picks an entry uniformly at random, or returns `None` if the map is empty.
+        ///
+        /// Tries random slots until it hits an occupied one, so the expected
+        /// number of tries is `1 / density`, where density is
+        /// `len() / (len() + count_holes())`. Fast for dense maps,
+        /// slow for maps with mostly vacant slots.
+        ///
+        /// ```
+        /// extern crate compactmap;
+        /// extern crate rand;
+        /// # fn main() {
+        /// use compactmap::CompactMap;
+        ///
+        /// let mut map: CompactMap<_> = vec!["a", "b", "c"].into_iter().collect();
+        /// map.remove(1);
+        /// let (k, v) = map.sample_entry(&mut rand::thread_rng()).unwrap();
+        /// assert!(k == 0 && *v == "a" || k == 2 && *v == "c");
+        /// # }
+        /// ```
+        pub fn sample_entry<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<(usize, &V)> {
+            if self.is_empty() {
+                return None;
+            }
+            loop {
+                let i = rng.gen_range(0..self.data.len());
+                if let Some(v) = self.get(i) {
+                    return Some((i, v));
+                }
+            }
+        }
+
+        /// Same as [`sample_entry`](#method.sample_entry), but returns only the key.
+        pub fn sample_key<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<usize> {
+            self.sample_entry(rng).map(|(k, _)| k)
+        }
+    }
+}
+
+#[cfg(feature = "proptest")]
+mod proptest_impls {
+    use proptest;
+    use proptest::collection::SizeRange;
+    use proptest::strategy::{NewTree, Strategy, ValueTree};
+    use proptest::test_runner::TestRunner;
+
+    use super::CompactMap;
+
+    /// Creates a [`CompactMapStrategy`](struct.CompactMapStrategy.html) generating
+    /// maps with values from `value_strategy`.
+    pub fn compactmap_strategy<S: Strategy>(value_strategy: S) -> CompactMapStrategy<S> {
+        CompactMapStrategy {
+            value_strategy,
+            size: SizeRange::new(0..=100),
+            hole_probability: 0.25,
+        }
+    }
+
+    /// Proptest strategy generating `CompactMap`s with holes.
+    ///
+    /// Number of slots (vacant or occupied) is taken from `size_range`, `0..=100` by default.
+    /// Each slot is vacant with `hole_probability`, 0.25 by default.
+    /// Shrinking first vacates slots one by one, then shrinks the values.
+    #[derive(Clone, Debug)]
+    pub struct CompactMapStrategy<S> {
+        /// Strategy for the values
+        pub value_strategy: S,
+        size: SizeRange,
+        hole_probability: f64,
+    }
+
+    impl<S> CompactMapStrategy<S> {
+        /// Sets range of the number of slots
+        pub fn size_range<R: Into<SizeRange>>(mut self, size: R) -> Self {
+            self.size = size.into();
+            self
+        }
+
+        /// Sets probability of each slot to be vacant
+        pub fn hole_probability(mut self, p: f64) -> Self {
+            assert!((0.0..=1.0).contains(&p), "hole probability must be within [0.0, 1.0]");
+            self.hole_probability = p;
+            self
+        }
+    }
+
+    impl<S: Strategy> Strategy for CompactMapStrategy<S> {
+        type Tree = CompactMapValueTree<S::Tree>;
+        type Value = CompactMap<S::Value>;
+
+        fn new_tree(&self, runner: &mut TestRunner) -> NewTree<Self> {
+            let (start, end) = self.size.start_end_incl();
+            let len = (start..=end).new_tree(runner)?.current();
+            let hole = proptest::bool::weighted(self.hole_probability);
+            let mut slots = Vec::with_capacity(len);
+            for _ in 0..len {
+                if hole.new_tree(runner)?.current() {
+                    slots.push(None);
+                } else {
+                    slots.push(Some(self.value_strategy.new_tree(runner)?));
+                }
+            }
+            Ok(CompactMapValueTree {
+                slots,
+                shrinker: 0,
+                vacating: true,
+                prev_shrinker: None,
+            })
+        }
+    }
+
+    /// `ValueTree` of [`CompactMapStrategy`](struct.CompactMapStrategy.html)
+    pub struct CompactMapValueTree<T> {
+        slots: Vec<Option<T>>,
+        shrinker: usize,
+        vacating: bool,
+        prev_shrinker: Option<(usize, Option<T>)>,
+    }
+
+    impl<T: ValueTree> ValueTree for CompactMapValueTree<T> {
+        type Value = CompactMap<T::Value>;
+
+        fn current(&self) -> CompactMap<T::Value> {
+            let mut map = CompactMap::with_capacity(self.slots.len());
+            for (i, slot) in self.slots.iter().enumerate() {
+                if let Some(ref t) = *slot {
+                    map.put_unindexed(i, t.current());
+                }
+            }
+            map.reindex();
+            map
+        }
+
+        fn simplify(&mut self) -> bool {
+            if self.vacating {
+                while self.shrinker < self.slots.len() {
+                    let i = self.shrinker;
+                    self.shrinker += 1;
+                    if self.slots[i].is_some() {
+                        self.prev_shrinker = Some((i, self.slots[i].take()));
+                        return true;
+                    }
+                }
+                self.vacating = false;
+                self.shrinker = 0;
+            }
+            while self.shrinker < self.slots.len() {
+                let i = self.shrinker;
+                if let Some(ref mut t) = self.slots[i] {
+                    if t.simplify() {
+                        self.prev_shrinker = Some((i, None));
+                        return true;
+                    }
+                }
+                self.shrinker += 1;
+            }
+            false
+        }
+
+        fn complicate(&mut self) -> bool {
+            match self.prev_shrinker.take() {
+                None => false,
+                Some((i, Some(vacated))) => {
+                    self.slots[i] = Some(vacated);
+                    true
+                }
+                Some((i, None)) => {
+                    if let Some(ref mut t) = self.slots[i] {
+                        if t.complicate() {
+                            self.prev_shrinker = Some((i, None));
+                            return true;
+                        }
+                    }
+                    false
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "proptest")]
+pub use proptest_impls::{compactmap_strategy, CompactMapStrategy, CompactMapValueTree};
+
+#[cfg(feature = "rkyv")]
+mod rkyv_impls {
+    use rkyv::{Archive, Archived, Deserialize, Fallible, Serialize};
+    use rkyv::ser::{ScratchSpace, Serializer};
+    use rkyv::vec::{ArchivedVec, VecResolver};
+
+    use super::{ArchivedEntry, CompactMap, Entry};
+
+    /// Archived `CompactMap`, readable directly from a byte buffer.
+    ///
+    /// Free list links are stored as is, but they are not used for reading.
+    /// The free list gets rebuilt when deserializing back to `CompactMap`.
+    ///
+    /// With `rkyv_validation` feature it implements `CheckBytes`, so untrusted
+    /// buffers can be read with `rkyv::check_archived_root`.
+    #[repr(C)]
+    pub struct ArchivedCompactMap<V: Archive> {
+        data: ArchivedVec<ArchivedEntry<V>>,
+        free_head: Archived<usize>,
+        occupied: Archived<usize>,
+    }
+
+    impl<V: Archive> ArchivedCompactMap<V> {
+        /// Returns a reference to the archived value corresponding to the key.
+        pub fn get(&self, i: usize) -> Option<&Archived<V>> {
+            self.data.get(i).and_then(|entry| match *entry {
+                ArchivedEntry::Empty(_) => None,
+                ArchivedEntry::Occupied(ref v) => Some(v),
+            })
+        }
+
+        /// Returns an iterator visiting all key-value pairs in ascending order of the keys.
+        pub fn iter(&self) -> impl Iterator<Item = (usize, &Archived<V>)> + '_ {
+            self.data.iter().enumerate().filter_map(|(i, entry)| match *entry {
+                ArchivedEntry::Empty(_) => None,
+                ArchivedEntry::Occupied(ref v) => Some((i, v)),
+            })
+        }
+
+        /// Returns the number of elements in the map.
+        pub fn len(&self) -> usize {
+            self.occupied as usize
+        }
+
+        /// Returns true if the map contains no elements.
+        pub fn is_empty(&self) -> bool {
+            self.occupied == 0
+        }
+    }
+
+    /// The resolver for an archived `CompactMap`
+    pub struct CompactMapResolver {
+        data: VecResolver,
+    }
+
+    impl<V: Archive> Archive for CompactMap<V> {
+        type Archived = ArchivedCompactMap<V>;
+        type Resolver = CompactMapResolver;
+
+        unsafe fn resolve(&self, pos: usize, resolver: CompactMapResolver, out: *mut Self::Archived) {
+            let (fp, fo) = rkyv::out_field!(out.data);
+            self.data.resolve(pos + fp, resolver.data, fo);
+            let (fp, fo) = rkyv::out_field!(out.free_head);
+            self.free_head.resolve(pos + fp, (), fo);
+            let (fp, fo) = rkyv::out_field!(out.occupied);
+            self.occupied.resolve(pos + fp, (), fo);
+        }
+    }
+
+    impl<V, S> Serialize<S> for CompactMap<V>
+    where
+        V: Serialize<S>,
+        S: ScratchSpace + Serializer + ?Sized,
+    {
+        fn serialize(&self, serializer: &mut S) -> Result<CompactMapResolver, S::Error> {
+            Ok(CompactMapResolver {
+                data: self.data.serialize(serializer)?,
+            })
+        }
+    }
+
+    impl<V, D> Deserialize<CompactMap<V>, D> for ArchivedCompactMap<V>
+    where
+        V: Archive,
+        D: Fallible + ?Sized,
+        ArchivedVec<ArchivedEntry<V>>: Deserialize<Vec<Entry<V>>, D>,
+    {
+        fn deserialize(&self, deserializer: &mut D) -> Result<CompactMap<V>, D::Error> {
+            // Archived links may be narrower than `usize`, so relink vacant slots
+            Ok(CompactMap::from_entries(self.data.deserialize(deserializer)?))
+        }
+    }
+
+    #[cfg(feature = "rkyv_validation")]
+    mod validation {
+        use std::{error, fmt, ptr};
+
+        use rkyv::Archive;
+        use rkyv::bytecheck::{CheckBytes, StructCheckError};
+        use rkyv::validation::ArchiveContext;
+        use rkyv::vec::ArchivedVec;
+
+        use super::ArchivedCompactMap;
+        use super::super::ArchivedEntry;
+
+        /// Stored element count does not match the occupied slots.
+        #[derive(Debug)]
+        struct LenMismatch {
+            stored: usize,
+            actual: usize,
+        }
+
+        impl fmt::Display for LenMismatch {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "length is {} but {} slots are occupied", self.stored, self.actual)
+            }
+        }
+
+        impl error::Error for LenMismatch {}
+
+        impl<V, C> CheckBytes<C> for ArchivedCompactMap<V>
+        where
+            V: Archive,
+            C: ArchiveContext + ?Sized,
+            ArchivedVec<ArchivedEntry<V>>: CheckBytes<C>,
+            <ArchivedVec<ArchivedEntry<V>> as CheckBytes<C>>::Error: error::Error + 'static,
+        {
+            type Error = StructCheckError;
+
+            unsafe fn check_bytes<'a>(value: *const Self, context: &mut C) -> Result<&'a Self, StructCheckError> {
+                let data = ArchivedVec::check_bytes(ptr::addr_of!((*value).data), context)
+                    .map_err(|e| StructCheckError { field_name: "data", inner: Box::new(e) })?;
+                // `free_head` and `occupied` are plain integers, any bit pattern is valid
+                let actual = data.iter().filter(|e| match **e {
+                    ArchivedEntry::Empty(_) => false,
+                    ArchivedEntry::Occupied(_) => true,
+                }).count();
+                let stored = (*value).occupied as usize;
+                if stored != actual {
+                    return Err(StructCheckError {
+                        field_name: "occupied",
+                        inner: Box::new(LenMismatch { stored, actual }),
+                    });
+                }
+                Ok(&*value)
+            }
         }
     }
 }
 
+#[cfg(feature = "rkyv")]
+pub use rkyv_impls::{ArchivedCompactMap, CompactMapResolver};
+
 /// Special version of `CompactMap` that helps you not to confuse those `usize` tokens
 /// with something else
 #[macro_use]
 pub mod wrapped;
 
+/// Version of `CompactMap` that does not reuse keys of removed entries
+pub mod arena;
+pub use arena::ArenaMap;
+
+/// Read-only version of `CompactMap` for sharing without locks
+pub mod frozen;
+pub use frozen::ImmutableCompactMap;
+
+/// Map with versioned keys, compatible with the core API of the `slotmap` crate
+pub mod slotmap;
+
+/// Version of `CompactMap` with `NonZeroUsize` keys
+pub mod nz;
+
+/// `CompactMap` wrapped in a `RwLock` for use from multiple threads
+#[cfg(feature = "sync")]
+pub mod sync;
+
 
 #[cfg(test)]
 mod test;
 
-#[cfg(test)]
+// Too slow under Miri
+#[cfg(all(test, not(miri)))]
 mod quickcheck;
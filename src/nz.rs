@@ -0,0 +1,1152 @@
+use ::std::num::NonZeroUsize;
+use ::std::iter::FromIterator;
+use ::std::ops::{Index, IndexMut, RangeBounds};
+use ::std::fmt;
+use ::std::hash::{BuildHasher, Hash};
+use ::std::collections::{BTreeMap, HashMap};
+
+use super::{Values, ValuesMut};
+use super::wrapped::CompactMapKey;
+
+/// Version of `CompactMap` with `NonZeroUsize` keys starting from 1.
+///
+/// Thanks to the niche optimization `Option<NonZeroUsize>` takes no more
+/// space than `usize`, so it can serve as a nullable key.
+/// Key `k` is stored at index `k - 1` of the underlying map,
+/// which is available via [`unwrapped`](#method.unwrapped).
+///
+/// ```
+/// use compactmap::nz::CompactMap;
+///
+/// let mut map = CompactMap::new();
+/// let a = map.insert("a");
+/// assert_eq!(a.get(), 1);
+/// assert_eq!(map[a], "a");
+///
+/// let mut slot: Option<_> = None;
+/// assert_eq!(std::mem::size_of_val(&slot), std::mem::size_of::<usize>());
+/// slot = Some(map.insert("b"));
+/// assert_eq!(map.remove(slot.unwrap()), Some("b"));
+/// ```
+///
+/// This is a thin layer over
+/// [`wrapped::CompactMap<NonZeroUsize, V>`](../wrapped/struct.CompactMap.html),
+/// which it converts to and from with `From`, and it offers the same methods.
+/// `Debug` prints the 1-based keys. Serialized forms (serde, bincode) are
+/// those of the underlying map, so they use the shifted-down keys.
+/// Cursors, diffs, freezing and raw parts are only available on the
+/// underlying map. For proptest, map the output of
+/// [`wrapped::compactmap_strategy::<NonZeroUsize, _>`](../wrapped/fn.compactmap_strategy.html)
+/// with `CompactMap::from`.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CompactMap<V> {
+    inner: super::wrapped::CompactMap<NonZeroUsize, V>,
+}
+
+impl<V> CompactMap<V> {
+    #[inline]
+    fn wrap(inner: super::wrapped::CompactMap<NonZeroUsize, V>) -> Self {
+        CompactMap { inner }
+    }
+
+    /// Extract underlying map, with keys shifted down by one
+    pub fn into_unwrapped(self) -> super::CompactMap<V> {
+        self.inner.into_unwrapped()
+    }
+
+    /// Wrap the map, shifting its keys up by one
+    pub fn from_unwrapped(s: super::CompactMap<V>) -> Self {
+        CompactMap::wrap(super::wrapped::CompactMap::from_unwrapped(s))
+    }
+
+    /// Temporarily use the underlying map, with keys shifted down by one
+    pub fn unwrapped(&self) -> &super::CompactMap<V> {
+        self.inner.unwrapped()
+    }
+
+    /// Temporarily use the underlying map, with keys shifted down by one
+    pub fn unwrapped_mut(&mut self) -> &mut super::CompactMap<V> {
+        self.inner.unwrapped_mut()
+    }
+
+    /// Returns true if the map contains a value for the specified key.
+    pub fn contains_key(&self, k: NonZeroUsize) -> bool {
+        self.get(k).is_some()
+    }
+
+    /// See [`super::CompactMap::validate`](../struct.CompactMap.html#method.validate)
+    pub fn validate(&self) -> Result<(), super::ValidationError> {
+        self.unwrapped().validate()
+    }
+
+    /// See [`super::CompactMap::debug_stats`](../struct.CompactMap.html#method.debug_stats)
+    pub fn debug_stats(&self) -> impl fmt::Debug + '_
+        where V: fmt::Debug
+    {
+        self.unwrapped().debug_stats()
+    }
+
+    /// See [`super::CompactMap::partition`](../struct.CompactMap.html#method.partition)
+    pub fn partition<F>(self, mut f: F) -> (CompactMap<V>, CompactMap<V>)
+        where F: FnMut(NonZeroUsize, &V) -> bool
+    {
+        let (yes, no) = self.into_unwrapped().partition(|i, v| f(NonZeroUsize::from_index(i), v));
+        (CompactMap::from_unwrapped(yes), CompactMap::from_unwrapped(no))
+    }
+
+    /// See [`super::CompactMap::partition_ref`](../struct.CompactMap.html#method.partition_ref)
+    pub fn partition_ref<F>(&self, mut f: F) -> (CompactMap<V>, CompactMap<V>)
+        where F: FnMut(NonZeroUsize, &V) -> bool, V: Clone
+    {
+        let (yes, no) = self.unwrapped().partition_ref(|i, v| f(NonZeroUsize::from_index(i), v));
+        (CompactMap::from_unwrapped(yes), CompactMap::from_unwrapped(no))
+    }
+
+    /// See [`super::CompactMap::new`](../struct.CompactMap.html#method.new)
+    pub fn new() -> Self {
+        CompactMap::wrap(super::wrapped::CompactMap::new())
+    }
+
+    /// See [`super::CompactMap::with_capacity`](../struct.CompactMap.html#method.with_capacity)
+    pub fn with_capacity(capacity: usize) -> Self {
+        CompactMap::wrap(super::wrapped::CompactMap::with_capacity(capacity))
+    }
+
+    /// See [`super::CompactMap::from_fn`](../struct.CompactMap.html#method.from_fn)
+    pub fn from_fn<F>(len: usize, f: F) -> Self
+        where F: FnMut(NonZeroUsize) -> V
+    {
+        CompactMap::wrap(super::wrapped::CompactMap::from_fn(len, f))
+    }
+
+    /// See [`super::CompactMap::try_from_fn`](../struct.CompactMap.html#method.try_from_fn)
+    pub fn try_from_fn<F, E>(len: usize, f: F) -> Result<Self, E>
+        where F: FnMut(NonZeroUsize) -> Result<V, E>
+    {
+        super::wrapped::CompactMap::try_from_fn(len, f).map(CompactMap::wrap)
+    }
+
+    /// See [`super::CompactMap::from_keyed_pairs`](../struct.CompactMap.html#method.from_keyed_pairs)
+    pub fn from_keyed_pairs<I>(iter: I) -> Self
+        where I: IntoIterator<Item = (NonZeroUsize, V)>
+    {
+        CompactMap::wrap(super::wrapped::CompactMap::from_keyed_pairs(iter))
+    }
+
+    /// See [`super::CompactMap::capacity`](../struct.CompactMap.html#method.capacity)
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    /// See [`super::CompactMap::reserve`](../struct.CompactMap.html#method.reserve)
+    pub fn reserve(&mut self, len: usize) {
+        self.inner.reserve(len)
+    }
+
+    /// See [`super::CompactMap::reserve_exact`](../struct.CompactMap.html#method.reserve_exact)
+    pub fn reserve_exact(&mut self, len: usize) {
+        self.inner.reserve_exact(len)
+    }
+
+    /// See [`super::CompactMap::clear`](../struct.CompactMap.html#method.clear)
+    pub fn clear(&mut self) {
+        self.inner.clear()
+    }
+
+    /// See [`super::CompactMap::is_empty_slow`](../struct.CompactMap.html#method.is_empty_slow)
+    #[deprecated(since = "0.4.0", note = "use is_empty()")]
+    #[allow(deprecated)]
+    pub fn is_empty_slow(&self) -> bool {
+        self.inner.is_empty_slow()
+    }
+
+    /// See [`super::CompactMap::is_empty`](../struct.CompactMap.html#method.is_empty)
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// See [`super::CompactMap::len`](../struct.CompactMap.html#method.len)
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// See [`super::CompactMap::occupied_count`](../struct.CompactMap.html#method.occupied_count)
+    #[inline]
+    pub fn occupied_count(&self) -> usize {
+        self.inner.occupied_count()
+    }
+
+    /// See [`super::CompactMap::free_count`](../struct.CompactMap.html#method.free_count)
+    #[inline]
+    pub fn free_count(&self) -> usize {
+        self.inner.free_count()
+    }
+
+    /// See [`super::CompactMap::count_holes`](../struct.CompactMap.html#method.count_holes)
+    pub fn count_holes(&self) -> usize {
+        self.inner.count_holes()
+    }
+
+    /// See [`super::CompactMap::iter_holes`](../struct.CompactMap.html#method.iter_holes)
+    pub fn iter_holes(&self) -> impl Iterator<Item = NonZeroUsize> + '_ {
+        self.inner.iter_holes()
+    }
+
+    /// See [`super::CompactMap::free_list_vec`](../struct.CompactMap.html#method.free_list_vec)
+    pub fn free_list_vec(&self) -> Vec<NonZeroUsize> {
+        self.inner.free_list_vec()
+    }
+
+    /// See [`super::CompactMap::free_list_len`](../struct.CompactMap.html#method.free_list_len)
+    pub fn free_list_len(&self) -> usize {
+        self.inner.free_list_len()
+    }
+
+    /// See [`super::CompactMap::is_dense`](../struct.CompactMap.html#method.is_dense)
+    pub fn is_dense(&self) -> bool {
+        self.inner.is_dense()
+    }
+
+    /// See [`super::CompactMap::dense_len`](../struct.CompactMap.html#method.dense_len)
+    pub fn dense_len(&self) -> Option<usize> {
+        self.inner.dense_len()
+    }
+
+    /// See [`super::CompactMap::assert_dense`](../struct.CompactMap.html#method.assert_dense)
+    pub fn assert_dense(&self) {
+        self.inner.assert_dense()
+    }
+
+    /// See [`super::CompactMap::insert`](../struct.CompactMap.html#method.insert)
+    pub fn insert(&mut self, v: V) -> NonZeroUsize {
+        self.inner.insert(v)
+    }
+
+    /// See [`super::CompactMap::insert_with`](../struct.CompactMap.html#method.insert_with)
+    pub fn insert_with<F>(&mut self, f: F) -> NonZeroUsize
+        where F: FnOnce(NonZeroUsize) -> V
+    {
+        self.inner.insert_with(f)
+    }
+
+    /// See [`super::CompactMap::insert_with_key`](../struct.CompactMap.html#method.insert_with_key)
+    pub fn insert_with_key<F>(&mut self, f: F) -> NonZeroUsize
+        where F: FnOnce(NonZeroUsize) -> V
+    {
+        self.inner.insert_with_key(f)
+    }
+
+    /// See [`super::CompactMap::allocate_with`](../struct.CompactMap.html#method.allocate_with)
+    pub fn allocate_with<F>(&mut self, f: F) -> NonZeroUsize
+        where F: FnOnce(NonZeroUsize) -> V
+    {
+        self.inner.allocate_with(f)
+    }
+
+    /// See [`super::CompactMap::try_allocate_with`](../struct.CompactMap.html#method.try_allocate_with)
+    pub fn try_allocate_with<F, E>(&mut self, f: F) -> Result<NonZeroUsize, E>
+        where F: FnOnce(NonZeroUsize) -> Result<V, E>
+    {
+        self.inner.try_allocate_with(f)
+    }
+
+    /// See [`super::CompactMap::try_insert`](../struct.CompactMap.html#method.try_insert)
+    pub fn try_insert(&mut self, v: V) -> Result<NonZeroUsize, V> {
+        self.inner.try_insert(v)
+    }
+
+    /// See [`super::CompactMap::bulk_insert`](../struct.CompactMap.html#method.bulk_insert)
+    pub fn bulk_insert<I>(&mut self, iter: I) -> Vec<NonZeroUsize>
+        where I: IntoIterator<Item = V>
+    {
+        self.inner.bulk_insert(iter)
+    }
+
+    /// See [`super::CompactMap::bulk_insert_array`](../struct.CompactMap.html#method.bulk_insert_array)
+    pub fn bulk_insert_array<const N: usize>(&mut self, values: [V; N]) -> [NonZeroUsize; N] {
+        self.inner.bulk_insert_array(values)
+    }
+
+    /// See [`super::CompactMap::insert_returning_ref`](../struct.CompactMap.html#method.insert_returning_ref)
+    pub fn insert_returning_ref(&mut self, v: V) -> (NonZeroUsize, &mut V) {
+        self.inner.insert_returning_ref(v)
+    }
+
+    /// See [`super::CompactMap::remove`](../struct.CompactMap.html#method.remove)
+    pub fn remove(&mut self, i: NonZeroUsize) -> Option<V> {
+        self.inner.remove(i)
+    }
+
+    /// See [`super::CompactMap::remove_if`](../struct.CompactMap.html#method.remove_if)
+    pub fn remove_if<F>(&mut self, i: NonZeroUsize, predicate: F) -> Option<V>
+        where F: FnOnce(&V) -> bool
+    {
+        self.inner.remove_if(i, predicate)
+    }
+
+    /// See [`super::CompactMap::remove_if_mut`](../struct.CompactMap.html#method.remove_if_mut)
+    pub fn remove_if_mut<F>(&mut self, i: NonZeroUsize, predicate: F) -> Option<V>
+        where F: FnOnce(&mut V) -> bool
+    {
+        self.inner.remove_if_mut(i, predicate)
+    }
+
+    /// See [`super::CompactMap::pop_first`](../struct.CompactMap.html#method.pop_first)
+    pub fn pop_first(&mut self) -> Option<(NonZeroUsize, V)> {
+        self.inner.pop_first()
+    }
+
+    /// See [`super::CompactMap::pop_last`](../struct.CompactMap.html#method.pop_last)
+    pub fn pop_last(&mut self) -> Option<(NonZeroUsize, V)> {
+        self.inner.pop_last()
+    }
+
+    /// See [`super::CompactMap::remove_many`](../struct.CompactMap.html#method.remove_many)
+    pub fn remove_many<I>(&mut self, keys: I) -> usize
+        where I: IntoIterator<Item = NonZeroUsize>
+    {
+        self.inner.remove_many(keys)
+    }
+
+    /// See [`super::CompactMap::remove_many_collect`](../struct.CompactMap.html#method.remove_many_collect)
+    pub fn remove_many_collect<I>(&mut self, keys: I) -> Vec<V>
+        where I: IntoIterator<Item = NonZeroUsize>
+    {
+        self.inner.remove_many_collect(keys)
+    }
+
+    /// See [`super::CompactMap::retain`](../struct.CompactMap.html#method.retain)
+    pub fn retain<F>(&mut self, f: F)
+        where F: FnMut(NonZeroUsize, &mut V) -> bool
+    {
+        self.inner.retain(f)
+    }
+
+    /// See [`super::CompactMap::retain_and_count`](../struct.CompactMap.html#method.retain_and_count)
+    pub fn retain_and_count<F>(&mut self, f: F) -> usize
+        where F: FnMut(NonZeroUsize, &mut V) -> bool
+    {
+        self.inner.retain_and_count(f)
+    }
+
+    /// See [`super::CompactMap::retain_mut`](../struct.CompactMap.html#method.retain_mut)
+    pub fn retain_mut<F>(&mut self, f: F)
+        where F: FnMut(NonZeroUsize, &mut V) -> bool
+    {
+        self.inner.retain_mut(f)
+    }
+
+    /// See [`super::CompactMap::remove_entry`](../struct.CompactMap.html#method.remove_entry)
+    pub fn remove_entry(&mut self, i: NonZeroUsize) -> Option<(NonZeroUsize, V)> {
+        self.inner.remove_entry(i)
+    }
+
+    /// See [`super::CompactMap::swap_remove`](../struct.CompactMap.html#method.swap_remove)
+    pub fn swap_remove(&mut self, i: NonZeroUsize) -> Option<(V, Option<super::Relocation<NonZeroUsize>>)> {
+        self.inner.swap_remove(i)
+    }
+
+    /// See [`super::CompactMap::get_or_insert`](../struct.CompactMap.html#method.get_or_insert)
+    pub fn get_or_insert(&mut self, i: NonZeroUsize, default: V) -> &mut V {
+        self.inner.get_or_insert(i, default)
+    }
+
+    /// See [`super::CompactMap::get_or_insert_with`](../struct.CompactMap.html#method.get_or_insert_with)
+    pub fn get_or_insert_with<F>(&mut self, i: NonZeroUsize, f: F) -> &mut V
+        where F: FnOnce() -> V
+    {
+        self.inner.get_or_insert_with(i, f)
+    }
+
+    /// See [`super::CompactMap::get_or_insert_default`](../struct.CompactMap.html#method.get_or_insert_default)
+    pub fn get_or_insert_default(&mut self, i: NonZeroUsize) -> &mut V
+        where V: Default
+    {
+        self.inner.get_or_insert_default(i)
+    }
+
+    /// See [`super::CompactMap::modify_or_insert`](../struct.CompactMap.html#method.modify_or_insert)
+    pub fn modify_or_insert<M, F>(&mut self, i: NonZeroUsize, modify: M, insert: F) -> &mut V
+        where M: FnOnce(&mut V), F: FnOnce() -> V
+    {
+        self.inner.modify_or_insert(i, modify, insert)
+    }
+
+    /// See [`super::CompactMap::get_or_insert_with_key`](../struct.CompactMap.html#method.get_or_insert_with_key)
+    pub fn get_or_insert_with_key<F>(&mut self, i: NonZeroUsize, f: F) -> &mut V
+        where F: FnOnce(NonZeroUsize) -> V
+    {
+        self.inner.get_or_insert_with_key(i, f)
+    }
+
+    /// See [`super::CompactMap::get`](../struct.CompactMap.html#method.get)
+    pub fn get(&self, i: NonZeroUsize) -> Option<&V> {
+        self.inner.get(i)
+    }
+
+    /// See [`super::CompactMap::get_mut`](../struct.CompactMap.html#method.get_mut)
+    pub fn get_mut(&mut self, i: NonZeroUsize) -> Option<&mut V> {
+        self.inner.get_mut(i)
+    }
+
+    /// See [`super::CompactMap::raw_entry`](../struct.CompactMap.html#method.raw_entry)
+    pub fn raw_entry(&self, i: NonZeroUsize) -> Option<&super::Entry<V>> {
+        self.inner.raw_entry(i)
+    }
+
+    /// See [`super::CompactMap::raw_iter`](../struct.CompactMap.html#method.raw_iter)
+    pub fn raw_iter(&self) -> ::std::slice::Iter<'_, super::Entry<V>> {
+        self.inner.raw_iter()
+    }
+
+    /// See [`super::CompactMap::raw_iter_mut`](../struct.CompactMap.html#method.raw_iter_mut)
+    pub fn raw_iter_mut(&mut self) -> impl DoubleEndedIterator<Item = Option<&mut V>> + ExactSizeIterator + '_ {
+        self.inner.raw_iter_mut()
+    }
+
+    /// See [`super::CompactMap::key_windows`](../struct.CompactMap.html#method.key_windows)
+    pub fn key_windows(&self, size: usize) -> ::std::slice::Windows<'_, super::Entry<V>> {
+        self.inner.key_windows(size)
+    }
+
+    /// See [`super::CompactMap::raw_entry_mut`](../struct.CompactMap.html#method.raw_entry_mut)
+    pub fn raw_entry_mut(&mut self, i: NonZeroUsize) -> Option<&mut super::Entry<V>> {
+        self.inner.raw_entry_mut(i)
+    }
+
+    /// See [`super::CompactMap::get_multiple`](../struct.CompactMap.html#method.get_multiple)
+    pub fn get_multiple<const N: usize>(&self, keys: [NonZeroUsize; N]) -> [Option<&V>; N] {
+        self.inner.get_multiple(keys)
+    }
+
+    /// See [`super::CompactMap::get_pair_mut`](../struct.CompactMap.html#method.get_pair_mut)
+    pub fn get_pair_mut(&mut self, a: NonZeroUsize, b: NonZeroUsize) -> Option<(&mut V, &mut V)> {
+        self.inner.get_pair_mut(a, b)
+    }
+
+    /// See [`super::CompactMap::modify`](../struct.CompactMap.html#method.modify)
+    pub fn modify<F>(&mut self, i: NonZeroUsize, f: F) -> Option<&mut V>
+        where F: FnOnce(&mut V)
+    {
+        self.inner.modify(i, f)
+    }
+
+    /// See [`super::CompactMap::update`](../struct.CompactMap.html#method.update)
+    pub fn update<F, R>(&mut self, i: NonZeroUsize, f: F) -> Option<R>
+        where F: FnOnce(&mut V) -> R
+    {
+        self.inner.update(i, f)
+    }
+
+    /// See [`super::CompactMap::replace`](../struct.CompactMap.html#method.replace)
+    pub fn replace(&mut self, i: NonZeroUsize, new_value: V) -> Option<V> {
+        self.inner.replace(i, new_value)
+    }
+
+    /// See [`super::CompactMap::move_entry`](../struct.CompactMap.html#method.move_entry)
+    pub fn move_entry(&mut self, from: NonZeroUsize, to: NonZeroUsize) -> Result<(), super::MoveError<NonZeroUsize>> {
+        self.inner.move_entry(from, to)
+    }
+
+    /// See [`super::CompactMap::relocate_entry`](../struct.CompactMap.html#method.relocate_entry)
+    pub fn relocate_entry(&mut self, from: NonZeroUsize, to: NonZeroUsize) {
+        self.inner.relocate_entry(from, to)
+    }
+
+    /// See [`super::CompactMap::find`](../struct.CompactMap.html#method.find)
+    pub fn find<F>(&self, f: F) -> Option<(NonZeroUsize, &V)>
+        where F: FnMut(&V) -> bool
+    {
+        self.inner.find(f)
+    }
+
+    /// See [`super::CompactMap::find_key`](../struct.CompactMap.html#method.find_key)
+    pub fn find_key<F>(&self, f: F) -> Option<NonZeroUsize>
+        where F: FnMut(&V) -> bool
+    {
+        self.inner.find_key(f)
+    }
+
+    /// See [`super::CompactMap::find_mut`](../struct.CompactMap.html#method.find_mut)
+    pub fn find_mut<F>(&mut self, f: F) -> Option<(NonZeroUsize, &mut V)>
+        where F: FnMut(&V) -> bool
+    {
+        self.inner.find_mut(f)
+    }
+
+    /// See [`super::CompactMap::contains_value`](../struct.CompactMap.html#method.contains_value)
+    pub fn contains_value(&self, value: &V) -> bool
+        where V: PartialEq
+    {
+        self.inner.contains_value(value)
+    }
+
+    /// See [`super::CompactMap::contains_value_by`](../struct.CompactMap.html#method.contains_value_by)
+    pub fn contains_value_by<F>(&self, f: F) -> bool
+        where F: FnMut(&V) -> bool
+    {
+        self.inner.contains_value_by(f)
+    }
+
+    /// See [`super::CompactMap::iter`](../struct.CompactMap.html#method.iter)
+    pub fn iter(&self) -> Iter<'_, V> {
+        self.inner.iter()
+    }
+
+    /// See [`super::CompactMap::iter_from`](../struct.CompactMap.html#method.iter_from)
+    pub fn iter_from(&self, start: NonZeroUsize) -> Iter<'_, V> {
+        self.inner.iter_from(start)
+    }
+
+    /// See [`super::CompactMap::iter_mut`](../struct.CompactMap.html#method.iter_mut)
+    pub fn iter_mut(&mut self) -> IterMut<'_, V> {
+        self.inner.iter_mut()
+    }
+
+    /// See [`super::CompactMap::iter_from_mut`](../struct.CompactMap.html#method.iter_from_mut)
+    pub fn iter_from_mut(&mut self, start: NonZeroUsize) -> IterMut<'_, V> {
+        self.inner.iter_from_mut(start)
+    }
+
+    /// See [`super::CompactMap::range`](../struct.CompactMap.html#method.range)
+    pub fn range<R: RangeBounds<NonZeroUsize>>(&self, range: R) -> Iter<'_, V> {
+        self.inner.range(range)
+    }
+
+    /// See [`super::CompactMap::range_mut`](../struct.CompactMap.html#method.range_mut)
+    pub fn range_mut<R: RangeBounds<NonZeroUsize>>(&mut self, range: R) -> IterMut<'_, V> {
+        self.inner.range_mut(range)
+    }
+
+    /// See [`super::CompactMap::into_keys`](../struct.CompactMap.html#method.into_keys)
+    pub fn into_keys(self) -> IntoKeys<V> {
+        self.inner.into_keys()
+    }
+
+    /// See [`super::CompactMap::into_values`](../struct.CompactMap.html#method.into_values)
+    pub fn into_values(self) -> IntoValues<V> {
+        self.inner.into_values()
+    }
+
+    /// See [`super::CompactMap::keys`](../struct.CompactMap.html#method.keys)
+    pub fn keys(&self) -> Keys<'_, V> {
+        self.inner.keys()
+    }
+
+    /// See [`super::CompactMap::values`](../struct.CompactMap.html#method.values)
+    pub fn values(&self) -> Values<'_, V> {
+        self.inner.values()
+    }
+
+    /// See [`super::CompactMap::values_mut`](../struct.CompactMap.html#method.values_mut)
+    pub fn values_mut(&mut self) -> ValuesMut<'_, V> {
+        self.inner.values_mut()
+    }
+
+    /// See [`super::CompactMap::windows_of_size`](../struct.CompactMap.html#method.windows_of_size)
+    pub fn windows_of_size(&self, size: usize) -> impl Iterator<Item = Vec<(NonZeroUsize, &V)>> + '_ {
+        self.inner.windows_of_size(size)
+    }
+
+    /// See [`super::CompactMap::len_slow`](../struct.CompactMap.html#method.len_slow)
+    #[deprecated(since = "0.4.0", note = "use len()")]
+    #[allow(deprecated)]
+    pub fn len_slow(&self) -> usize {
+        self.inner.len_slow()
+    }
+
+    /// See [`super::CompactMap::shrink_to_fit`](../struct.CompactMap.html#method.shrink_to_fit)
+    pub fn shrink_to_fit(&mut self) {
+        self.inner.shrink_to_fit()
+    }
+
+    /// See [`super::CompactMap::shrink_free_list`](../struct.CompactMap.html#method.shrink_free_list)
+    pub fn shrink_free_list(&mut self) {
+        self.inner.shrink_free_list()
+    }
+
+    /// See [`super::CompactMap::max_occupied_key`](../struct.CompactMap.html#method.max_occupied_key)
+    pub fn max_occupied_key(&self) -> Option<NonZeroUsize> {
+        self.inner.max_occupied_key()
+    }
+
+    /// See [`super::CompactMap::fingerprint`](../struct.CompactMap.html#method.fingerprint)
+    pub fn fingerprint(&self) -> u64
+        where V: Hash
+    {
+        self.inner.fingerprint()
+    }
+
+    /// See [`super::CompactMap::fingerprint_with`](../struct.CompactMap.html#method.fingerprint_with)
+    pub fn fingerprint_with<S: BuildHasher>(&self, hash_builder: &S) -> u64
+        where V: Hash
+    {
+        self.inner.fingerprint_with(hash_builder)
+    }
+
+    /// See [`super::CompactMap::stats`](../struct.CompactMap.html#method.stats)
+    pub fn stats(&self) -> super::MapStats {
+        self.inner.stats()
+    }
+
+    /// See [`super::CompactMap::trailing_holes`](../struct.CompactMap.html#method.trailing_holes)
+    pub fn trailing_holes(&self) -> usize {
+        self.inner.trailing_holes()
+    }
+
+    /// See [`super::CompactMap::compact`](../struct.CompactMap.html#method.compact)
+    pub fn compact(&mut self) -> Vec<Option<NonZeroUsize>> {
+        self.inner.compact()
+    }
+
+    /// See [`super::CompactMap::compact_and_remap`](../struct.CompactMap.html#method.compact_and_remap)
+    pub fn compact_and_remap(&self) -> (CompactMap<V>, Vec<Option<NonZeroUsize>>)
+        where V: Clone
+    {
+        let (m, remap) = self.inner.compact_and_remap();
+        (CompactMap::wrap(m), remap)
+    }
+
+    /// See [`super::CompactMap::into_compact_and_remap`](../struct.CompactMap.html#method.into_compact_and_remap)
+    pub fn into_compact_and_remap(self) -> (CompactMap<V>, Vec<Option<NonZeroUsize>>) {
+        let (m, remap) = self.inner.into_compact_and_remap();
+        (CompactMap::wrap(m), remap)
+    }
+
+    /// See [`super::CompactMap::truncate`](../struct.CompactMap.html#method.truncate)
+    pub fn truncate(&mut self, bound: NonZeroUsize) {
+        self.inner.truncate(bound)
+    }
+
+    /// See [`super::CompactMap::split_off`](../struct.CompactMap.html#method.split_off)
+    pub fn split_off(&mut self, at: NonZeroUsize) -> CompactMap<V> {
+        CompactMap::wrap(self.inner.split_off(at))
+    }
+
+    /// See [`super::CompactMap::fill_to`](../struct.CompactMap.html#method.fill_to)
+    pub fn fill_to<F>(&mut self, bound: NonZeroUsize, f: F) -> Vec<NonZeroUsize>
+        where F: FnMut() -> V
+    {
+        self.inner.fill_to(bound, f)
+    }
+
+    /// See [`super::CompactMap::fill_gaps_with`](../struct.CompactMap.html#method.fill_gaps_with)
+    pub fn fill_gaps_with<F>(&mut self, f: F)
+        where F: FnMut(NonZeroUsize) -> V
+    {
+        self.inner.fill_gaps_with(f)
+    }
+
+    /// See [`super::CompactMap::fill_to_default`](../struct.CompactMap.html#method.fill_to_default)
+    pub fn fill_to_default(&mut self, bound: NonZeroUsize) -> Vec<NonZeroUsize>
+        where V: Default
+    {
+        self.inner.fill_to_default(bound)
+    }
+
+    /// See [`super::CompactMap::drain`](../struct.CompactMap.html#method.drain)
+    pub fn drain(&mut self) -> Drain<'_, V> {
+        self.inner.drain()
+    }
+
+    /// See [`super::CompactMap::drain_owned`](../struct.CompactMap.html#method.drain_owned)
+    pub fn drain_owned(&mut self) -> DrainOwned<V> {
+        self.inner.drain_owned()
+    }
+
+    /// See [`super::CompactMap::take_all`](../struct.CompactMap.html#method.take_all)
+    pub fn take_all(&mut self) -> Vec<V> {
+        self.inner.take_all()
+    }
+
+    /// See [`super::CompactMap::into_hash_map`](../struct.CompactMap.html#method.into_hash_map)
+    pub fn into_hash_map(self) -> HashMap<NonZeroUsize, V> {
+        self.inner.into_hash_map()
+    }
+
+    /// See [`super::CompactMap::into_btree_map`](../struct.CompactMap.html#method.into_btree_map)
+    pub fn into_btree_map(self) -> BTreeMap<NonZeroUsize, V> {
+        self.inner.into_btree_map()
+    }
+
+    /// See [`super::CompactMap::to_hash_map`](../struct.CompactMap.html#method.to_hash_map)
+    pub fn to_hash_map(&self) -> HashMap<NonZeroUsize, V>
+        where V: Clone
+    {
+        self.inner.to_hash_map()
+    }
+
+    /// See [`super::CompactMap::to_btree_map`](../struct.CompactMap.html#method.to_btree_map)
+    pub fn to_btree_map(&self) -> BTreeMap<NonZeroUsize, V>
+        where V: Clone
+    {
+        self.inner.to_btree_map()
+    }
+
+    /// See [`super::CompactMap::drain_range`](../struct.CompactMap.html#method.drain_range)
+    pub fn drain_range<R: RangeBounds<NonZeroUsize>>(&mut self, range: R) -> DrainRange<'_, V> {
+        self.inner.drain_range(range)
+    }
+
+    /// See [`super::CompactMap::map_values`](../struct.CompactMap.html#method.map_values)
+    pub fn map_values<U, F>(self, f: F) -> CompactMap<U>
+        where F: FnMut(V) -> U
+    {
+        CompactMap::wrap(self.inner.map_values(f))
+    }
+
+    /// See [`super::CompactMap::map_values_ref`](../struct.CompactMap.html#method.map_values_ref)
+    pub fn map_values_ref<U, F>(&self, f: F) -> CompactMap<U>
+        where F: FnMut(&V) -> U
+    {
+        CompactMap::wrap(self.inner.map_values_ref(f))
+    }
+
+    /// See [`super::CompactMap::try_map_values`](../struct.CompactMap.html#method.try_map_values)
+    pub fn try_map_values<U, E, F>(self, f: F) -> Result<CompactMap<U>, E>
+        where F: FnMut(V) -> Result<U, E>
+    {
+        self.inner.try_map_values(f).map(CompactMap::wrap)
+    }
+
+    /// See [`super::CompactMap::try_map_values_ref`](../struct.CompactMap.html#method.try_map_values_ref)
+    pub fn try_map_values_ref<U, E, F>(&self, f: F) -> Result<CompactMap<U>, E>
+        where F: FnMut(&V) -> Result<U, E>
+    {
+        self.inner.try_map_values_ref(f).map(CompactMap::wrap)
+    }
+
+    /// See [`super::CompactMap::filter_map`](../struct.CompactMap.html#method.filter_map)
+    pub fn filter_map<U, F>(self, f: F) -> CompactMap<U>
+        where F: FnMut(NonZeroUsize, V) -> Option<U>
+    {
+        CompactMap::wrap(self.inner.filter_map(f))
+    }
+
+    /// See [`super::CompactMap::filter_map_ref`](../struct.CompactMap.html#method.filter_map_ref)
+    pub fn filter_map_ref<U, F>(&self, f: F) -> CompactMap<U>
+        where F: FnMut(NonZeroUsize, &V) -> Option<U>
+    {
+        CompactMap::wrap(self.inner.filter_map_ref(f))
+    }
+
+    /// See [`super::CompactMap::clone_with_filter`](../struct.CompactMap.html#method.clone_with_filter)
+    pub fn clone_with_filter<F>(&self, f: F) -> CompactMap<V>
+        where V: Clone, F: FnMut(NonZeroUsize, &V) -> bool
+    {
+        CompactMap::wrap(self.inner.clone_with_filter(f))
+    }
+
+    /// See [`super::CompactMap::group_by`](../struct.CompactMap.html#method.group_by)
+    pub fn group_by<K2, F>(&self, f: F) -> HashMap<K2, Vec<NonZeroUsize>>
+        where K2: Hash + Eq, F: FnMut(&V) -> K2
+    {
+        self.inner.group_by(f)
+    }
+
+    /// See [`super::CompactMap::group_by_values`](../struct.CompactMap.html#method.group_by_values)
+    pub fn group_by_values<K2, F>(&self, f: F) -> HashMap<K2, Vec<&V>>
+        where K2: Hash + Eq, F: FnMut(&V) -> K2
+    {
+        self.inner.group_by_values(f)
+    }
+
+    /// See [`super::CompactMap::max_value`](../struct.CompactMap.html#method.max_value)
+    pub fn max_value(&self) -> Option<&V>
+        where V: Ord
+    {
+        self.inner.max_value()
+    }
+
+    /// See [`super::CompactMap::min_value`](../struct.CompactMap.html#method.min_value)
+    pub fn min_value(&self) -> Option<&V>
+        where V: Ord
+    {
+        self.inner.min_value()
+    }
+
+    /// See [`super::CompactMap::max_value_key`](../struct.CompactMap.html#method.max_value_key)
+    pub fn max_value_key(&self) -> Option<(NonZeroUsize, &V)>
+        where V: Ord
+    {
+        self.inner.max_value_key()
+    }
+
+    /// See [`super::CompactMap::min_value_key`](../struct.CompactMap.html#method.min_value_key)
+    pub fn min_value_key(&self) -> Option<(NonZeroUsize, &V)>
+        where V: Ord
+    {
+        self.inner.min_value_key()
+    }
+
+    /// See [`super::CompactMap::max_by_key`](../struct.CompactMap.html#method.max_by_key)
+    pub fn max_by_key<K2, F>(&self, f: F) -> Option<(NonZeroUsize, &V)>
+        where K2: Ord, F: FnMut(&V) -> K2
+    {
+        self.inner.max_by_key(f)
+    }
+
+    /// See [`super::CompactMap::min_by_key`](../struct.CompactMap.html#method.min_by_key)
+    pub fn min_by_key<K2, F>(&self, f: F) -> Option<(NonZeroUsize, &V)>
+        where K2: Ord, F: FnMut(&V) -> K2
+    {
+        self.inner.min_by_key(f)
+    }
+
+    /// See [`super::CompactMap::sum`](../struct.CompactMap.html#method.sum)
+    pub fn sum<'a, S>(&'a self) -> S
+        where S: ::std::iter::Sum<&'a V>
+    {
+        self.inner.sum()
+    }
+
+    /// See [`super::CompactMap::product`](../struct.CompactMap.html#method.product)
+    pub fn product<'a, P>(&'a self) -> P
+        where P: ::std::iter::Product<&'a V>
+    {
+        self.inner.product()
+    }
+
+    /// See [`super::CompactMap::sum_by`](../struct.CompactMap.html#method.sum_by)
+    pub fn sum_by<S, F>(&self, f: F) -> S
+        where S: ::std::iter::Sum<S>, F: FnMut(&V) -> S
+    {
+        self.inner.sum_by(f)
+    }
+
+    /// See [`super::CompactMap::count_where`](../struct.CompactMap.html#method.count_where)
+    pub fn count_where<F>(&self, f: F) -> usize
+        where F: FnMut(&V) -> bool
+    {
+        self.inner.count_where(f)
+    }
+
+    /// See [`super::CompactMap::zip_map`](../struct.CompactMap.html#method.zip_map)
+    pub fn zip_map<U, R, F>(&self, other: &CompactMap<U>, f: F) -> CompactMap<R>
+        where F: FnMut(&V, &U) -> R
+    {
+        CompactMap::wrap(self.inner.zip_map(&other.inner, f))
+    }
+
+    /// See [`super::CompactMap::zip_outer`](../struct.CompactMap.html#method.zip_outer)
+    pub fn zip_outer<U, R, F>(&self, other: &CompactMap<U>, f: F) -> CompactMap<R>
+        where F: FnMut(Option<&V>, Option<&U>) -> R
+    {
+        CompactMap::wrap(self.inner.zip_outer(&other.inner, f))
+    }
+
+    /// See [`super::CompactMap::merge`](../struct.CompactMap.html#method.merge)
+    pub fn merge(self, other: CompactMap<V>) -> CompactMap<V> {
+        CompactMap::wrap(self.inner.merge(other.inner))
+    }
+
+    /// See [`super::CompactMap::merge_preserving_keys`](../struct.CompactMap.html#method.merge_preserving_keys)
+    pub fn merge_preserving_keys(self, other: CompactMap<V>)
+        -> Result<CompactMap<V>, super::MergeConflict<CompactMap<V>, NonZeroUsize>>
+    {
+        self.inner.merge_preserving_keys(other.inner).map(CompactMap::wrap).map_err(|e| super::MergeConflict {
+            key: e.key,
+            ours: CompactMap::wrap(e.ours),
+            theirs: CompactMap::wrap(e.theirs),
+        })
+    }
+
+    /// See [`super::CompactMap::merge_with`](../struct.CompactMap.html#method.merge_with)
+    pub fn merge_with<F>(self, other: CompactMap<V>, f: F) -> CompactMap<V>
+        where F: FnMut(V, V) -> V
+    {
+        CompactMap::wrap(self.inner.merge_with(other.inner, f))
+    }
+
+    /// See [`super::CompactMap::append`](../struct.CompactMap.html#method.append)
+    pub fn append(&mut self, other: &mut CompactMap<V>) {
+        self.inner.append(&mut other.inner)
+    }
+
+    /// See [`super::CompactMap::append_preserving_keys`](../struct.CompactMap.html#method.append_preserving_keys)
+    pub fn append_preserving_keys(&mut self, other: &mut CompactMap<V>)
+        -> Result<(), super::ConflictKey<NonZeroUsize>>
+    {
+        self.inner.append_preserving_keys(&mut other.inner)
+    }
+
+    /// See [`super::CompactMap::extend_keyed`](../struct.CompactMap.html#method.extend_keyed)
+    pub fn extend_keyed<I>(&mut self, iter: I)
+        where I: IntoIterator<Item = (NonZeroUsize, V)>
+    {
+        self.inner.extend_keyed(iter)
+    }
+
+    /// See [`super::CompactMap::try_extend_keyed`](../struct.CompactMap.html#method.try_extend_keyed)
+    pub fn try_extend_keyed<I>(&mut self, iter: I) -> Result<(), super::ExtendConflict<V, NonZeroUsize>>
+        where I: IntoIterator<Item = (NonZeroUsize, V)>
+    {
+        self.inner.try_extend_keyed(iter)
+    }
+}
+
+impl<V: Copy + CompactMapKey> CompactMap<V> {
+    /// See [`super::CompactMap::invert`](../struct.CompactMap.html#method.invert)
+    pub fn invert(&self) -> Result<super::wrapped::CompactMap<V, NonZeroUsize>, super::InvertError<NonZeroUsize, V>> {
+        self.inner.invert()
+    }
+}
+
+impl<A, B> CompactMap<(A, B)> {
+    /// See [`super::CompactMap::unzip`](../struct.CompactMap.html#method.unzip)
+    pub fn unzip(self) -> (CompactMap<A>, CompactMap<B>) {
+        let (a, b) = self.inner.unzip();
+        (CompactMap::wrap(a), CompactMap::wrap(b))
+    }
+
+    /// See [`super::CompactMap::unzip_ref`](../struct.CompactMap.html#method.unzip_ref)
+    pub fn unzip_ref(&self) -> (CompactMap<A>, CompactMap<B>)
+        where A: Clone, B: Clone
+    {
+        let (a, b) = self.inner.unzip_ref();
+        (CompactMap::wrap(a), CompactMap::wrap(b))
+    }
+}
+
+impl<V> Default for CompactMap<V> {
+    fn default() -> Self {
+        CompactMap::new()
+    }
+}
+
+impl<V: Clone> Clone for CompactMap<V> {
+    fn clone(&self) -> Self {
+        CompactMap::from_unwrapped(self.unwrapped().clone())
+    }
+}
+
+impl<V> From<super::wrapped::CompactMap<NonZeroUsize, V>> for CompactMap<V> {
+    fn from(m: super::wrapped::CompactMap<NonZeroUsize, V>) -> Self {
+        CompactMap::wrap(m)
+    }
+}
+
+impl<V> From<CompactMap<V>> for super::wrapped::CompactMap<NonZeroUsize, V> {
+    fn from(m: CompactMap<V>) -> Self {
+        m.inner
+    }
+}
+
+impl<V> FromIterator<V> for CompactMap<V> {
+    fn from_iter<I: IntoIterator<Item = V>>(iter: I) -> CompactMap<V> {
+        CompactMap::wrap(iter.into_iter().collect())
+    }
+}
+
+impl<'a, V: Copy> FromIterator<&'a V> for CompactMap<V> {
+    fn from_iter<I: IntoIterator<Item = &'a V>>(iter: I) -> CompactMap<V> {
+        CompactMap::wrap(iter.into_iter().collect())
+    }
+}
+
+impl<V> Extend<V> for CompactMap<V> {
+    fn extend<I: IntoIterator<Item = V>>(&mut self, iter: I) {
+        self.inner.extend(iter)
+    }
+}
+
+impl<'a, V: Copy> Extend<&'a V> for CompactMap<V> {
+    fn extend<I: IntoIterator<Item = &'a V>>(&mut self, iter: I) {
+        self.inner.extend(iter)
+    }
+}
+
+impl<V> Index<NonZeroUsize> for CompactMap<V> {
+    type Output = V;
+
+    #[inline]
+    fn index(&self, k: NonZeroUsize) -> &V {
+        &self.inner[k]
+    }
+}
+
+impl<V> Index<&NonZeroUsize> for CompactMap<V> {
+    type Output = V;
+
+    #[inline]
+    fn index(&self, k: &NonZeroUsize) -> &V {
+        &self.inner[k]
+    }
+}
+
+impl<V> IndexMut<NonZeroUsize> for CompactMap<V> {
+    #[inline]
+    fn index_mut(&mut self, k: NonZeroUsize) -> &mut V {
+        &mut self.inner[k]
+    }
+}
+
+impl<V> IndexMut<&NonZeroUsize> for CompactMap<V> {
+    #[inline]
+    fn index_mut(&mut self, k: &NonZeroUsize) -> &mut V {
+        &mut self.inner[k]
+    }
+}
+
+impl<V: fmt::Debug> fmt::Debug for CompactMap<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<V: fmt::Display> fmt::Display for CompactMap<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl<'a, V> IntoIterator for &'a CompactMap<V> {
+    type Item = (NonZeroUsize, &'a V);
+    type IntoIter = Iter<'a, V>;
+    fn into_iter(self) -> Iter<'a, V> {
+        self.iter()
+    }
+}
+
+impl<'a, V> IntoIterator for &'a mut CompactMap<V> {
+    type Item = (NonZeroUsize, &'a mut V);
+    type IntoIter = IterMut<'a, V>;
+    fn into_iter(self) -> IterMut<'a, V> {
+        self.iter_mut()
+    }
+}
+
+impl<V> IntoIterator for CompactMap<V> {
+    type Item = (NonZeroUsize, V);
+    type IntoIter = IntoIter<V>;
+    fn into_iter(self) -> IntoIter<V> {
+        self.inner.into_iter()
+    }
+}
+
+/// An iterator over the key-value pairs of a map.
+pub type Iter<'a, V> = super::wrapped::Iter<'a, NonZeroUsize, V>;
+/// An iterator over the key-value pairs of a map, with the
+/// values being mutable.
+pub type IterMut<'a, V> = super::wrapped::IterMut<'a, NonZeroUsize, V>;
+/// A consuming iterator over the key-value pairs of a map.
+pub type IntoIter<V> = super::wrapped::IntoIter<NonZeroUsize, V>;
+/// An iterator over the keys of a map.
+pub type Keys<'a, V> = super::wrapped::Keys<'a, NonZeroUsize, V>;
+/// A consuming iterator over the keys of a map.
+pub type IntoKeys<V> = super::wrapped::IntoKeys<NonZeroUsize, V>;
+/// A consuming iterator over the values of a map.
+pub type IntoValues<V> = super::wrapped::IntoValues<NonZeroUsize, V>;
+/// A draining iterator over the key-value pairs of a map.
+pub type Drain<'a, V> = super::wrapped::Drain<'a, NonZeroUsize, V>;
+/// An owning draining iterator over the key-value pairs of a map.
+pub type DrainOwned<V> = super::wrapped::DrainOwned<NonZeroUsize, V>;
+/// A draining iterator over a key range of a map.
+pub type DrainRange<'a, V> = super::wrapped::DrainRange<'a, NonZeroUsize, V>;
+
+#[cfg(feature = "serde")]
+mod serdizer {
+    extern crate serde;
+
+    use super::CompactMap;
+
+    impl<V: serde::Serialize> serde::Serialize for CompactMap<V> {
+        fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+            self.inner.serialize(s)
+        }
+    }
+
+    impl<'de, V: serde::Deserialize<'de>> serde::Deserialize<'de> for CompactMap<V> {
+        fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            Ok(CompactMap::wrap(serde::Deserialize::deserialize(deserializer)?))
+        }
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary_impls {
+    extern crate arbitrary;
+
+    use super::CompactMap;
+    use self::arbitrary::{Arbitrary, Result, Unstructured};
+
+    impl<'a, V: Arbitrary<'a>> Arbitrary<'a> for CompactMap<V> {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            Ok(CompactMap::wrap(Arbitrary::arbitrary(u)?))
+        }
+    }
+}
+
+#[cfg(feature = "schemars")]
+mod schemars_impls {
+    extern crate schemars;
+
+    use super::CompactMap;
+    use self::schemars::JsonSchema;
+    use self::schemars::gen::SchemaGenerator;
+    use self::schemars::schema::Schema;
+
+    // Same serde format as the unwrapped map
+    impl<V: JsonSchema> JsonSchema for CompactMap<V> {
+        fn schema_name() -> String {
+            super::super::CompactMap::<V>::schema_name()
+        }
+
+        fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+            super::super::CompactMap::<V>::json_schema(gen)
+        }
+    }
+}
+
+#[cfg(feature = "bincode2")]
+mod bincode_impls {
+    extern crate bincode2 as bincode;
+
+    use super::CompactMap;
+    use self::bincode::{BorrowDecode, Decode, Encode};
+    use self::bincode::de::{BorrowDecoder, Decoder};
+    use self::bincode::enc::Encoder;
+    use self::bincode::error::{DecodeError, EncodeError};
+
+    impl<V: Encode> Encode for CompactMap<V> {
+        fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+            self.inner.encode(encoder)
+        }
+    }
+
+    impl<Context, V: Decode<Context>> Decode<Context> for CompactMap<V> {
+        fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+            Ok(CompactMap::wrap(Decode::decode(decoder)?))
+        }
+    }
+
+    impl<'de, Context, V: BorrowDecode<'de, Context>> BorrowDecode<'de, Context> for CompactMap<V> {
+        fn borrow_decode<D: BorrowDecoder<'de, Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+            Ok(CompactMap::wrap(BorrowDecode::borrow_decode(decoder)?))
+        }
+    }
+}
+
+#[cfg(feature = "rand")]
+mod rand_impls {
+    extern crate rand;
+
+    use ::std::num::NonZeroUsize;
+    use super::CompactMap;
+    use self::rand::Rng;
+
+    impl<V> CompactMap<V> {
+        /// See [`super::CompactMap::sample_entry`](../struct.CompactMap.html#method.sample_entry)
+        pub fn sample_entry<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<(NonZeroUsize, &V)> {
+            self.inner.sample_entry(rng)
+        }
+
+        /// See [`super::CompactMap::sample_key`](../struct.CompactMap.html#method.sample_key)
+        pub fn sample_key<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<NonZeroUsize> {
+            self.inner.sample_key(rng)
+        }
+    }
+}
@@ -2,8 +2,10 @@ extern crate quickcheck;
 extern crate slab;
 #[cfg(feature = "serde")]
 extern crate serde_json;
-#[cfg(all(feature="serde", feature = "serde_ser_len"))]
+#[cfg(feature = "serde")]
 extern crate bincode;
+#[cfg(feature = "bincode2")]
+extern crate bincode2;
 
 // Check against slab
 
@@ -14,8 +16,12 @@ enum Action {
     ShrinkToFit,
     #[cfg(feature = "serde")]
     SerdeJson,
-    #[cfg(all(feature="serde", feature = "serde_ser_len"))]
+    #[cfg(feature = "serde")]
+    SerdeJsonSeq,
+    #[cfg(feature = "serde")]
     SerdeBincode,
+    #[cfg(feature = "bincode2")]
+    Bincode2,
 }
 
 type ActionSequence = Vec<Action>;
@@ -28,15 +34,22 @@ impl quickcheck::Arbitrary for Action {
         {if g.gen_weighted_bool(100) {
             return Action::SerdeJson
         }}
-        #[cfg(all(feature="serde", feature = "serde_ser_len"))]
+        #[cfg(feature = "serde")]
+        {if g.gen_weighted_bool(100) {
+            return Action::SerdeJsonSeq
+        }}
+        #[cfg(feature = "serde")]
         {if g.gen_weighted_bool(100) {
             return Action::SerdeBincode
         }}
+        #[cfg(feature = "bincode2")]
+        {if g.gen_weighted_bool(100) {
+            return Action::Bincode2
+        }}
         
         if g.gen_weighted_bool(100) {
             Action::ShrinkToFit
-        } else
-        if g.gen_weighted_bool(2) {
+        } else if g.gen_weighted_bool(2) {
             Action::Insert(g.gen_range(0, 50))
         } else {
             Action::Remove(g.gen_range(0, 50))
@@ -70,7 +83,7 @@ fn check(s: ActionSequence) -> bool {
                         return false
                     }
                 } else {
-                    if cm.remove(n) != None { 
+                    if cm.remove(n).is_some() { 
                         println!("rm5 n={}", n);
                         return false
                     }
@@ -85,17 +98,33 @@ fn check(s: ActionSequence) -> bool {
                 let s = serde_json::to_string(&cm).unwrap();
                 cm = serde_json::from_str(&s).unwrap();
             },
-            #[cfg(all(feature="serde", feature = "serde_ser_len"))]
+            #[cfg(feature = "serde")]
+            Action::SerdeJsonSeq => {
+                let mut s = vec![];
+                super::serde_seq::serialize(&cm, &mut serde_json::Serializer::new(&mut s)).unwrap();
+                cm = super::serde_seq::deserialize(&mut serde_json::Deserializer::from_slice(&s)).unwrap();
+            },
+            #[cfg(feature = "serde")]
             Action::SerdeBincode => {
                 let s = bincode::serialize(&cm, bincode::Infinite).unwrap();
                 cm = bincode::deserialize(&s).unwrap();
             },
+            #[cfg(feature = "bincode2")]
+            Action::Bincode2 => {
+                let config = bincode2::config::standard();
+                let s = bincode2::encode_to_vec(&cm, config).unwrap();
+                cm = bincode2::decode_from_slice(&s, config).unwrap().0;
+            },
+        }
+        if cm.free_list_len() + cm.iter().count() != cm.data.len() {
+            println!("free list {:?}, len {}", cm.free_list_vec(), cm.data.len());
+            return false;
         }
     }
     
     
-    if cm.len_slow() != slab.len() {
-        println!("len {} {}",cm.len_slow(), slab.len());
+    if cm.len() != slab.len() || cm.iter().count() != cm.len() {
+        println!("len {} {} {}",cm.len(), cm.iter().count(), slab.len());
         return false;
     }
     
@@ -0,0 +1,348 @@
+use ::std::ops::{Index, IndexMut};
+use ::std::iter::FusedIterator;
+use ::std::fmt;
+
+use super::{CompactMap, Values, ValuesMut};
+
+/// Versioned key of a [`SlotMap`](struct.SlotMap.html): slot index and
+/// the generation of the slot at the time of insertion.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Key {
+    idx: u32,
+    gen: u32,
+}
+
+impl Key {
+    /// Creates a key that is never valid in any map.
+    pub fn null() -> Key {
+        Key { idx: u32::MAX, gen: 0 }
+    }
+
+    /// Checks if the key was created by [`null`](#method.null).
+    pub fn is_null(&self) -> bool {
+        self.idx == u32::MAX
+    }
+
+    /// Index of the slot this key refers to
+    pub fn index(&self) -> usize {
+        self.idx as usize
+    }
+
+    /// Generation of the slot this key refers to
+    pub fn generation(&self) -> u32 {
+        self.gen
+    }
+}
+
+impl Default for Key {
+    fn default() -> Key {
+        Key::null()
+    }
+}
+
+/// Map with versioned keys, modelled after the `slotmap` crate's `SlotMap`.
+///
+/// Slots are reused like in `CompactMap`, but every removal bumps the slot's
+/// generation, so keys of removed entries do not give access to newer ones.
+///
+/// ```
+/// use compactmap::slotmap::SlotMap;
+///
+/// let mut sm = SlotMap::new();
+/// let a = sm.insert("a");
+/// assert_eq!(sm.remove(a), Some("a"));
+/// let b = sm.insert("b");
+/// assert_eq!(a.index(), b.index());
+/// assert_eq!(sm.get(a), None);
+/// assert_eq!(sm[b], "b");
+/// ```
+#[derive(Clone)]
+pub struct SlotMap<V> {
+    inner: CompactMap<V>,
+    gens: Vec<u32>,
+}
+
+impl<V> SlotMap<V> {
+    /// Creates an empty `SlotMap`.
+    pub fn new() -> SlotMap<V> {
+        SlotMap {
+            inner: CompactMap::new(),
+            gens: Vec::new(),
+        }
+    }
+
+    /// Creates an empty `SlotMap` with space for at least `capacity`
+    /// elements before resizing.
+    pub fn with_capacity(capacity: usize) -> SlotMap<V> {
+        SlotMap {
+            inner: CompactMap::with_capacity(capacity),
+            gens: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Returns the number of elements in the map.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Returns true if the map contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Returns the number of elements the map can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    fn key(&self, i: usize) -> Key {
+        Key { idx: i as u32, gen: self.gens[i] }
+    }
+
+    fn slot(&self, key: Key) -> Option<usize> {
+        let i = key.idx as usize;
+        match self.gens.get(i) {
+            Some(&gen) if gen == key.gen && self.inner.get(i).is_some() => Some(i),
+            _ => None,
+        }
+    }
+
+    /// Inserts a value into the map and returns its key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of slots would overflow `u32`.
+    pub fn insert(&mut self, v: V) -> Key {
+        self.insert_with_key(|_| v)
+    }
+
+    /// Inserts a value built from its future key and returns the key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of slots would overflow `u32`.
+    pub fn insert_with_key<F>(&mut self, f: F) -> Key
+        where F: FnOnce(Key) -> V
+    {
+        let gens = &mut self.gens;
+        let i = self.inner.insert_with(|i| {
+            assert!(i < u32::MAX as usize, "SlotMap number of slots overflow");
+            if i == gens.len() {
+                gens.push(0);
+            }
+            f(Key { idx: i as u32, gen: gens[i] })
+        });
+        self.key(i)
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key
+    /// is still valid.
+    pub fn remove(&mut self, key: Key) -> Option<V> {
+        let i = self.slot(key)?;
+        self.gens[i] = self.gens[i].wrapping_add(1);
+        self.inner.remove(i)
+    }
+
+    /// Retains only the elements for which `f` returns `true`.
+    pub fn retain<F>(&mut self, mut f: F)
+        where F: FnMut(Key, &mut V) -> bool
+    {
+        let gens = &self.gens;
+        let doomed = self.inner.iter_mut()
+            .filter_map(|(i, v)| {
+                if f(Key { idx: i as u32, gen: gens[i] }, v) { None } else { Some(i) }
+            })
+            .collect::<Vec<_>>();
+        for i in doomed {
+            self.gens[i] = self.gens[i].wrapping_add(1);
+            self.inner.remove(i);
+        }
+    }
+
+    /// Removes all values. Keys handed out before stay invalid.
+    pub fn clear(&mut self) {
+        let keys = self.inner.keys().collect::<Vec<_>>();
+        for i in keys {
+            self.gens[i] = self.gens[i].wrapping_add(1);
+        }
+        self.inner.clear();
+    }
+
+    /// Returns true if the key is valid.
+    pub fn contains_key(&self, key: Key) -> bool {
+        self.slot(key).is_some()
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get(&self, key: Key) -> Option<&V> {
+        self.slot(key).and_then(move |i| self.inner.get(i))
+    }
+
+    /// Returns a mutable reference to the value corresponding to the key.
+    pub fn get_mut(&mut self, key: Key) -> Option<&mut V> {
+        match self.slot(key) {
+            Some(i) => self.inner.get_mut(i),
+            None => None,
+        }
+    }
+
+    /// Returns an iterator visiting all key-value pairs in ascending order of the indexes.
+    pub fn iter(&self) -> Iter<'_, V> {
+        Iter {
+            inner: self.inner.iter(),
+            gens: &self.gens,
+        }
+    }
+
+    /// Returns an iterator visiting all key-value pairs in ascending order of the indexes,
+    /// with mutable references to the values.
+    pub fn iter_mut(&mut self) -> IterMut<'_, V> {
+        IterMut {
+            inner: self.inner.iter_mut(),
+            gens: &self.gens,
+        }
+    }
+
+    /// Returns an iterator visiting all keys in ascending order of the indexes.
+    pub fn keys(&self) -> Keys<'_, V> {
+        Keys { inner: self.iter() }
+    }
+
+    /// Returns an iterator visiting all values in ascending order of the indexes.
+    pub fn values(&self) -> Values<'_, V> {
+        self.inner.values()
+    }
+
+    /// Returns an iterator visiting all values in ascending order of the indexes,
+    /// with mutable references.
+    pub fn values_mut(&mut self) -> ValuesMut<'_, V> {
+        self.inner.values_mut()
+    }
+}
+
+impl<V> Default for SlotMap<V> {
+    fn default() -> SlotMap<V> {
+        SlotMap::new()
+    }
+}
+
+impl<V> Index<Key> for SlotMap<V> {
+    type Output = V;
+
+    fn index(&self, key: Key) -> &V {
+        self.get(key).expect("invalid SlotMap key used")
+    }
+}
+
+impl<V> IndexMut<Key> for SlotMap<V> {
+    fn index_mut(&mut self, key: Key) -> &mut V {
+        self.get_mut(key).expect("invalid SlotMap key used")
+    }
+}
+
+impl<V: fmt::Debug> fmt::Debug for SlotMap<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<'a, V> IntoIterator for &'a SlotMap<V> {
+    type Item = (Key, &'a V);
+    type IntoIter = Iter<'a, V>;
+
+    fn into_iter(self) -> Iter<'a, V> {
+        self.iter()
+    }
+}
+
+impl<'a, V> IntoIterator for &'a mut SlotMap<V> {
+    type Item = (Key, &'a mut V);
+    type IntoIter = IterMut<'a, V>;
+
+    fn into_iter(self) -> IterMut<'a, V> {
+        self.iter_mut()
+    }
+}
+
+/// An iterator over the key-value pairs of a `SlotMap`.
+pub struct Iter<'a, V: 'a> {
+    inner: super::Iter<'a, V>,
+    gens: &'a [u32],
+}
+impl<'a, V> Clone for Iter<'a, V> {
+    fn clone(&self) -> Iter<'a, V> {
+        Iter {
+            inner: self.inner.clone(),
+            gens: self.gens,
+        }
+    }
+}
+impl<'a, V> Iterator for Iter<'a, V> {
+    type Item = (Key, &'a V);
+
+    fn next(&mut self) -> Option<(Key, &'a V)> {
+        let gens = self.gens;
+        self.inner.next().map(|(i, v)| (Key { idx: i as u32, gen: gens[i] }, v))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+impl<'a, V> DoubleEndedIterator for Iter<'a, V> {
+    fn next_back(&mut self) -> Option<(Key, &'a V)> {
+        let gens = self.gens;
+        self.inner.next_back().map(|(i, v)| (Key { idx: i as u32, gen: gens[i] }, v))
+    }
+}
+impl<'a, V> FusedIterator for Iter<'a, V> {}
+
+/// An iterator over the key-value pairs of a `SlotMap`, with the
+/// values being mutable.
+pub struct IterMut<'a, V: 'a> {
+    inner: super::IterMut<'a, V>,
+    gens: &'a [u32],
+}
+impl<'a, V> Iterator for IterMut<'a, V> {
+    type Item = (Key, &'a mut V);
+
+    fn next(&mut self) -> Option<(Key, &'a mut V)> {
+        let gens = self.gens;
+        self.inner.next().map(|(i, v)| (Key { idx: i as u32, gen: gens[i] }, v))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+impl<'a, V> DoubleEndedIterator for IterMut<'a, V> {
+    fn next_back(&mut self) -> Option<(Key, &'a mut V)> {
+        let gens = self.gens;
+        self.inner.next_back().map(|(i, v)| (Key { idx: i as u32, gen: gens[i] }, v))
+    }
+}
+impl<'a, V> FusedIterator for IterMut<'a, V> {}
+
+/// An iterator over the keys of a `SlotMap`.
+pub struct Keys<'a, V: 'a> {
+    inner: Iter<'a, V>,
+}
+impl<'a, V> Clone for Keys<'a, V> {
+    fn clone(&self) -> Keys<'a, V> {
+        Keys { inner: self.inner.clone() }
+    }
+}
+impl<'a, V> Iterator for Keys<'a, V> {
+    type Item = Key;
+
+    fn next(&mut self) -> Option<Key> {
+        self.inner.next().map(|(k, _)| k)
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+impl<'a, V> DoubleEndedIterator for Keys<'a, V> {
+    fn next_back(&mut self) -> Option<Key> {
+        self.inner.next_back().map(|(k, _)| k)
+    }
+}
+impl<'a, V> FusedIterator for Keys<'a, V> {}
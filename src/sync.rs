@@ -0,0 +1,121 @@
+use ::std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use ::std::fmt;
+
+use super::CompactMap;
+
+/// `CompactMap` behind a `RwLock`, for sharing between threads.
+///
+/// Single operations take the lock for their duration only. Use
+/// [`batch_read`](#method.batch_read) and [`batch_write`](#method.batch_write)
+/// to hold the lock for a whole transaction.
+///
+/// A panic while the write lock is held poisons the lock, making all
+/// further calls panic as well.
+///
+/// ```
+/// use compactmap::sync::RwCompactMap;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let map = Arc::new(RwCompactMap::new());
+/// let handles = (0..4).map(|i| {
+///     let map = map.clone();
+///     thread::spawn(move || map.write_insert(i))
+/// }).collect::<Vec<_>>();
+/// for h in handles {
+///     let key = h.join().unwrap();
+///     assert!(map.read_get(key).is_some());
+/// }
+/// assert_eq!(map.batch_read(|m| m.values().sum::<i32>()), 6);
+/// ```
+pub struct RwCompactMap<V> {
+    lock: RwLock<CompactMap<V>>,
+}
+
+impl<V> RwCompactMap<V> {
+    /// Creates an empty map.
+    pub fn new() -> RwCompactMap<V> {
+        RwCompactMap::from_map(CompactMap::new())
+    }
+
+    /// Puts an existing map behind the lock.
+    pub fn from_map(map: CompactMap<V>) -> RwCompactMap<V> {
+        RwCompactMap { lock: RwLock::new(map) }
+    }
+
+    /// Takes the map out of the lock.
+    pub fn into_inner(self) -> CompactMap<V> {
+        self.lock.into_inner().expect("RwCompactMap lock poisoned")
+    }
+
+    /// Takes the read lock, blocking until it is available.
+    pub fn read(&self) -> RwLockReadGuard<'_, CompactMap<V>> {
+        self.lock.read().expect("RwCompactMap lock poisoned")
+    }
+
+    /// Takes the write lock, blocking until it is available.
+    pub fn write(&self) -> RwLockWriteGuard<'_, CompactMap<V>> {
+        self.lock.write().expect("RwCompactMap lock poisoned")
+    }
+
+    /// Runs `f` with the read lock held and returns its result.
+    pub fn batch_read<F, R>(&self, f: F) -> R
+        where F: FnOnce(&CompactMap<V>) -> R
+    {
+        f(&self.read())
+    }
+
+    /// Runs `f` with the write lock held and returns its result.
+    pub fn batch_write<F, R>(&self, f: F) -> R
+        where F: FnOnce(&mut CompactMap<V>) -> R
+    {
+        f(&mut self.write())
+    }
+
+    /// Returns a copy of the value at the key.
+    pub fn read_get(&self, i: usize) -> Option<V>
+        where V: Clone
+    {
+        self.read().get(i).cloned()
+    }
+
+    /// Returns the number of elements in the map.
+    pub fn read_len(&self) -> usize {
+        self.read().len()
+    }
+
+    /// Returns a copy of the whole map.
+    pub fn snapshot(&self) -> CompactMap<V>
+        where V: Clone
+    {
+        self.read().clone()
+    }
+
+    /// Inserts a value into the map and returns its key.
+    pub fn write_insert(&self, v: V) -> usize {
+        self.write().insert(v)
+    }
+
+    /// Removes a key from the map, returning the value if there was one.
+    pub fn write_remove(&self, i: usize) -> Option<V> {
+        self.write().remove(i)
+    }
+}
+
+impl<V> Default for RwCompactMap<V> {
+    fn default() -> RwCompactMap<V> {
+        RwCompactMap::new()
+    }
+}
+
+impl<V> From<CompactMap<V>> for RwCompactMap<V> {
+    fn from(map: CompactMap<V>) -> RwCompactMap<V> {
+        RwCompactMap::from_map(map)
+    }
+}
+
+impl<V: fmt::Debug> fmt::Debug for RwCompactMap<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.lock.fmt(f)
+    }
+}
@@ -1,8 +1,15 @@
 #![allow(unused_variables)]
 
-use super::CompactMap;
+use super::{CompactMap, Entry};
 use std::cmp::Ordering;
 
+/// Builds a map with a possibly broken free list, bypassing the checks
+/// of `from_raw_parts`.
+fn corrupted<V>(data: Vec<Entry<V>>, free_head: usize) -> CompactMap<V> {
+    let occupied = data.iter().filter(|e| e.is_occupied()).count();
+    CompactMap { data, free_head, occupied }
+}
+
 
 #[test]
 fn it_works() {
@@ -275,3 +282,2850 @@ fn try_wrapped() {
     let q : Mom = m.insert(45);
     m.remove(q);
 }
+
+#[cfg(feature = "allocator_api")]
+#[test]
+fn custom_allocator() {
+    use std::alloc::{AllocError, Allocator, Global, Layout};
+    use std::cell::Cell;
+    use std::ptr::NonNull;
+
+    #[derive(Clone, Copy)]
+    struct Counting<'a>(&'a Cell<usize>);
+    unsafe impl<'a> Allocator for Counting<'a> {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            self.0.set(self.0.get() + 1);
+            Global.allocate(layout)
+        }
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    let allocs = Cell::new(0);
+    let mut m = CompactMap::with_capacity_in(4, Counting(&allocs));
+    assert_eq!(allocs.get(), 1);
+    let a = m.insert("a");
+    let b = m.insert("b");
+    m.remove(a);
+    assert_eq!(m.insert("c"), a);
+    assert_eq!(m.iter().collect::<Vec<_>>(), [(0, &"c"), (1, &"b")]);
+    assert_eq!(m.len(), 2);
+    assert_eq!(allocs.get(), 1);
+    m.remove(b);
+    m.shrink_to_fit();
+    assert_eq!(m.max_occupied_key(), Some(0));
+    m.insert("d");
+    assert_eq!(m.drain().collect::<Vec<_>>(), [(0, "c"), (1, "d")]);
+    m.insert("e");
+    let it = m.into_iter();
+    assert_eq!(it.clone().collect::<Vec<_>>(), [(0, "e")]);
+    assert_eq!(it.rev().next(), Some((0, "e")));
+
+    let mut w: super::wrapped::CompactMap<u32, u8, _> = super::wrapped::CompactMap::new_in(Counting(&allocs));
+    let k = w.insert(7);
+    w[k] += 1;
+    assert_eq!(w.get(k), Some(&8));
+    assert_eq!(format!("{:?}", w), "{0: 8}");
+    w.insert(9);
+    assert_eq!(w.drain().collect::<Vec<_>>(), [(0, 8), (1, 9)]);
+    w.insert(1);
+    assert_eq!(w.into_values().collect::<Vec<_>>(), [1]);
+}
+
+/// Key type with room for two keys only
+#[derive(Debug, PartialEq)]
+struct Tiny(usize);
+
+impl super::wrapped::__sealed::Sealed for Tiny {}
+
+impl super::wrapped::CompactMapKey for Tiny {
+    const MAX: usize = 1;
+
+    fn from_index(i: usize) -> Tiny {
+        assert!(i <= 1, "key {} does not fit into Tiny", i);
+        Tiny(i)
+    }
+
+    fn into_index(self) -> usize {
+        self.0
+    }
+}
+
+#[test]
+fn wrapped_insert_key_overflow() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use super::wrapped::CompactMap;
+
+    let mut m: CompactMap<Tiny, u8> = CompactMap::new();
+    assert_eq!(m.insert(0), Tiny(0));
+    assert_eq!(m.insert_with(|k| k.0 as u8), Tiny(1));
+
+    assert!(catch_unwind(AssertUnwindSafe(|| m.insert(2))).is_err());
+    let mut called = false;
+    assert!(catch_unwind(AssertUnwindSafe(|| m.insert_with(|_| { called = true; 2 }))).is_err());
+    assert!(!called);
+    assert_eq!(m.len(), 2);
+    assert_eq!(m.iter().map(|(_, &v)| v).collect::<Vec<_>>(), [0, 1]);
+
+    m.remove(Tiny(0));
+    assert_eq!(m.insert(3), Tiny(0));
+}
+
+#[test]
+fn raw_parts() {
+    let mut m: CompactMap<u64> = CompactMap::new();
+    for i in 0..6 {
+        m.insert(i * 11);
+    }
+    m.remove(1);
+    m.remove(4);
+    m.remove(2);
+
+    let (data, free_head) = m.clone().into_raw_parts();
+    assert_eq!(data.len(), 6);
+    assert_eq!(free_head, 2);
+
+    let mut m2 = CompactMap::from_raw_parts(data, free_head).unwrap();
+    assert_eq!(m, m2);
+    assert_eq!(m2.insert(22), 2);
+    assert_eq!(m2.insert(44), 4);
+    assert_eq!(m2.insert(11), 1);
+    assert_eq!(m2.insert(66), 6);
+    assert_eq!(m2.len(), 7);
+}
+
+#[test]
+fn from_std_maps() {
+    use std::collections::{BTreeMap, HashMap};
+
+    let mut hm = HashMap::new();
+    hm.insert(0, "a");
+    hm.insert(1000, "b");
+    hm.insert(3, "c");
+    let mut m: CompactMap<&str> = hm.clone().into();
+    assert_eq!(m.len(), 3);
+    assert_eq!(m[0], "a");
+    assert_eq!(m[3], "c");
+    assert_eq!(m[1000], "b");
+    assert_eq!(m.clone().into_hash_map(), hm);
+
+    let bm: BTreeMap<usize, &str> = hm.into_iter().collect();
+    assert_eq!(CompactMap::from(bm.clone()), m);
+    assert_eq!(m.clone().into_btree_map(), bm);
+
+    let mut keys = vec![];
+    for _ in 0..998 {
+        keys.push(m.insert("x"));
+    }
+    keys.sort();
+    assert_eq!(keys, (1..1000).filter(|&x| x != 3).collect::<Vec<_>>());
+    assert_eq!(m.insert("y"), 1001);
+}
+
+#[test]
+fn map_values() {
+    let mut m: CompactMap<u64> = CompactMap::new();
+    for i in 0..6 {
+        m.insert(i);
+    }
+    m.remove(1);
+    m.remove(4);
+
+    let m2 = m.map_values_ref(|&x| x * 10);
+    assert_eq!(m.keys().collect::<Vec<_>>(), m2.keys().collect::<Vec<_>>());
+    assert_eq!(m2.values().cloned().collect::<Vec<_>>(), vec![0, 20, 30, 50]);
+
+    let mut m3 = m.map_values(|x| x.to_string());
+    assert_eq!(m2.keys().collect::<Vec<_>>(), m3.keys().collect::<Vec<_>>());
+    assert_eq!(m3[5], "5");
+    assert_eq!(m3.insert("x".to_string()), 4);
+    assert_eq!(m3.insert("y".to_string()), 1);
+}
+
+#[test]
+fn try_map_values() {
+    let mut m: CompactMap<&str> = CompactMap::new();
+    for s in &["0", "1", "2", "x", "4"] {
+        m.insert(*s);
+    }
+    let mut seen = 0;
+    assert!(m.try_map_values_ref(|s| { seen += 1; s.parse::<u32>() }).is_err());
+    assert_eq!(seen, 4);
+
+    m.remove(3);
+    m.remove(1);
+    let mut parsed = m.try_map_values_ref(|s| s.parse::<u32>()).unwrap();
+    assert_eq!(parsed.iter().collect::<Vec<_>>(), [(0, &0), (2, &2), (4, &4)]);
+    assert_eq!(parsed.insert(10), 1);
+    assert_eq!(parsed.insert(30), 3);
+
+    let r: Result<CompactMap<u32>, ()> = m.try_map_values(|s| Ok(s.len() as u32));
+    assert_eq!(r.unwrap().keys().collect::<Vec<_>>(), [0, 2, 4]);
+
+    let w: super::wrapped::CompactMap<Mom, &str> = vec!["7", "y"].into_iter().collect();
+    assert!(w.try_map_values_ref(|s| s.parse::<u8>()).is_err());
+    let w = w.try_map_values(|s| if s == "y" { Ok(0) } else { s.parse::<u8>() }).unwrap();
+    assert_eq!(w[Mom(0)], 7);
+}
+
+#[test]
+fn filter_map() {
+    let mut m: CompactMap<u64> = CompactMap::new();
+    for i in 0..8 {
+        m.insert(i);
+    }
+    m.remove(3);
+
+    let m2 = m.filter_map_ref(|k, &v| if k % 2 == 0 { Some(v + 100) } else { None });
+    assert_eq!(m2.iter().collect::<Vec<_>>(), [(0, &100), (2, &102), (4, &104), (6, &106)]);
+
+    let mut m3 = m.filter_map(|k, v| if k > 4 { Some(v) } else { None });
+    assert_eq!(m3.keys().collect::<Vec<_>>(), [5, 6, 7]);
+    let mut reused: Vec<_> = (0..5).map(|_| m3.insert(0)).collect();
+    reused.sort();
+    assert_eq!(reused, [0, 1, 2, 3, 4]);
+    assert_eq!(m3.insert(0), 8);
+}
+
+#[test]
+fn partition() {
+    let mut m: CompactMap<u64> = CompactMap::new();
+    for i in 0..8 {
+        m.insert(i * 10);
+    }
+    m.remove(5);
+
+    let (a, b) = m.partition_ref(|k, _| k < 3);
+    assert_eq!(a.keys().collect::<Vec<_>>(), [0, 1, 2]);
+    assert_eq!(b.keys().collect::<Vec<_>>(), [3, 4, 6, 7]);
+
+    let (mut a, mut b) = m.partition(|_, &v| v % 20 == 0);
+    assert_eq!(a.iter().collect::<Vec<_>>(), [(0, &0), (2, &20), (4, &40), (6, &60)]);
+    assert_eq!(b.iter().collect::<Vec<_>>(), [(1, &10), (3, &30), (7, &70)]);
+    let k = a.insert(0);
+    assert!(k < 8 && k % 2 == 1);
+    let k = b.insert(0);
+    assert!(k < 8 && [0, 2, 4, 5, 6].contains(&k));
+}
+
+#[test]
+fn merge() {
+    let mut a: CompactMap<u64> = (0..4).collect();
+    a.remove(1);
+    let mut b: CompactMap<u64> = (10..16).collect();
+    b.remove(0);
+    b.remove(2);
+    b.remove(3);
+    let c: CompactMap<u64> = (100..101).collect();
+
+    let m = a.clone().merge(b.clone());
+    assert_eq!(m.iter().collect::<Vec<_>>(), [(0, &0), (1, &11), (2, &2), (3, &3), (4, &14), (5, &15)]);
+
+    let mut m = a.clone().merge_preserving_keys(b).unwrap();
+    assert_eq!(m.iter().collect::<Vec<_>>(), [(0, &0), (1, &11), (2, &2), (3, &3), (4, &14), (5, &15)]);
+    assert_eq!(m.insert(99), 6);
+
+    let mut d = c.clone();
+    d.insert(101);
+    d.insert(102);
+    d.remove(1);
+    let err = a.clone().merge_preserving_keys(d.clone()).unwrap_err();
+    assert_eq!(err.key, 0);
+    assert_eq!(err.ours, a);
+    assert_eq!(err.theirs, d);
+    assert_eq!(err.to_string(), "both maps have an entry with key 0");
+
+    let mut m = a.merge_with(c, |x, y| x + y + 1);
+    assert_eq!(m.iter().collect::<Vec<_>>(), [(0, &101), (2, &2), (3, &3)]);
+    assert_eq!(m.insert(99), 1);
+}
+
+#[cfg(feature = "rkyv")]
+#[test]
+fn rkyv_roundtrip() {
+    use rkyv;
+    use rkyv::Deserialize;
+
+    let mut m: CompactMap<u64> = (0..6).map(|x| x * 11).collect();
+    m.remove(1);
+    m.remove(4);
+
+    let bytes = rkyv::to_bytes::<_, 256>(&m).unwrap();
+    let archived = unsafe { rkyv::archived_root::<CompactMap<u64>>(&bytes) };
+    assert_eq!(archived.get(0), Some(&0));
+    assert_eq!(archived.get(1), None);
+    assert_eq!(archived.get(5), Some(&55));
+    assert_eq!(archived.get(6), None);
+    assert_eq!(archived.len(), 4);
+    assert_eq!(archived.iter().map(|(k, _)| k).collect::<Vec<_>>(), [0, 2, 3, 5]);
+
+    let mut m2: CompactMap<u64> = archived.deserialize(&mut rkyv::Infallible).unwrap();
+    assert_eq!(m, m2);
+    let mut reused = vec![m2.insert(1), m2.insert(4)];
+    reused.sort();
+    assert_eq!(reused, [1, 4]);
+    assert_eq!(m2.insert(6), 6);
+}
+
+#[cfg(feature = "rkyv_validation")]
+#[test]
+fn rkyv_check_archived_root() {
+    use rkyv;
+    use rkyv::Deserialize;
+
+    let mut m: CompactMap<String> = (0..4).map(|x| x.to_string()).collect();
+    m.remove(2);
+
+    let mut bytes = rkyv::to_bytes::<_, 256>(&m).unwrap();
+    {
+        let archived = rkyv::check_archived_root::<CompactMap<String>>(&bytes).unwrap();
+        assert_eq!(archived.len(), 3);
+        assert!(!archived.is_empty());
+        assert_eq!(archived.get(3).map(|s| s.as_str()), Some("3"));
+        let m2: CompactMap<String> = archived.deserialize(&mut rkyv::Infallible).unwrap();
+        assert_eq!(m, m2);
+    }
+
+    // The element count is stored last
+    let n = bytes.len();
+    bytes[n - 4] = 9;
+    match rkyv::check_archived_root::<CompactMap<String>>(&bytes) {
+        Err(e) => assert!(e.to_string().contains("length is 9 but 3 slots are occupied")),
+        Ok(_) => panic!("corrupted length was accepted"),
+    }
+}
+
+#[cfg(feature = "proptest")]
+use proptest;
+
+#[cfg(feature = "proptest")]
+proptest::proptest! {
+    #[test]
+    fn proptest_drain_len(mut map in super::compactmap_strategy(0..100u32)) {
+        let len = map.len();
+        proptest::prop_assert_eq!(len, map.drain().count());
+    }
+
+    #[test]
+    fn proptest_wrapped(map in super::wrapped::compactmap_strategy::<Mom, _>(0..100u32)) {
+        let keys: Vec<usize> = map.keys().map(Into::into).collect();
+        proptest::prop_assert_eq!(keys, map.unwrapped().keys().collect::<Vec<_>>());
+    }
+}
+
+#[cfg(feature = "proptest")]
+#[test]
+fn proptest_shrinks_to_fewer_entries() {
+    use proptest::strategy::{Strategy, ValueTree};
+    use proptest::test_runner::TestRunner;
+
+    let strategy = super::compactmap_strategy(0..100u32)
+        .size_range(20..30)
+        .hole_probability(0.5);
+    let mut runner = TestRunner::deterministic();
+    let mut tree = strategy.new_tree(&mut runner).unwrap();
+    let mut prev = tree.current().len();
+    while tree.simplify() {
+        let len = tree.current().len();
+        assert!(len <= prev);
+        prev = len;
+    }
+    assert!(tree.current().is_empty());
+}
+
+#[test]
+fn iter_back_first() {
+    let mut m: CompactMap<u64> = (0..4).collect();
+    m.remove(2);
+    assert_eq!(m.iter().rev().collect::<Vec<_>>(), [(3, &3), (1, &1), (0, &0)]);
+    assert_eq!(m.iter_mut().next_back(), Some((3, &mut 3)));
+    assert_eq!(m.clone().into_iter().rev().map(|(k, _)| k).collect::<Vec<_>>(), [3, 1, 0]);
+    assert_eq!(CompactMap::<u64>::new().iter().next_back(), None);
+}
+
+#[test]
+fn into_keys_values() {
+    let mut m: CompactMap<u64> = (10..15).collect();
+    m.remove(1);
+    m.remove(3);
+    assert_eq!(m.clone().into_keys().collect::<Vec<_>>(), [0, 2, 4]);
+    assert_eq!(m.clone().into_keys().rev().collect::<Vec<_>>(), [4, 2, 0]);
+    assert_eq!(m.clone().into_values().collect::<Vec<_>>(), [10, 12, 14]);
+    assert_eq!(m.clone().into_values().rev().collect::<Vec<_>>(), [14, 12, 10]);
+
+    let w: super::wrapped::CompactMap<Mom, u64> = super::wrapped::CompactMap::from_unwrapped(m.clone());
+    assert_eq!(w.into_keys().collect::<Vec<_>>(), [Mom(0), Mom(2), Mom(4)]);
+    let w: super::wrapped::CompactMap<Mom, u64> = super::wrapped::CompactMap::from_unwrapped(m);
+    assert_eq!(w.into_values().collect::<Vec<_>>(), [10, 12, 14]);
+}
+
+#[test]
+fn validate() {
+    use super::{Entry, ValidationError};
+
+    let mut m: CompactMap<u64> = (0..5).collect();
+    m.remove(1);
+    m.remove(3);
+    assert_eq!(m.validate(), Ok(()));
+
+    let corrupt = |data: Vec<Entry<u64>>, head| {
+        let err = CompactMap::from_raw_parts(data.clone(), head).map(|_| ());
+        assert_eq!(err, corrupted(data, head).validate());
+        err
+    };
+    use super::Entry::{Empty, Occupied};
+    assert_eq!(corrupt(vec![Occupied(0), Empty(usize::MAX)], 5),
+               Err(ValidationError::OutOfBounds { index: 5 }));
+    assert_eq!(corrupt(vec![Occupied(0), Empty(7)], 1),
+               Err(ValidationError::OutOfBounds { index: 7 }));
+    assert_eq!(corrupt(vec![Occupied(0), Empty(0)], 1),
+               Err(ValidationError::OccupiedInFreeList { index: 0 }));
+    assert_eq!(corrupt(vec![Empty(1), Empty(0)], 1),
+               Err(ValidationError::Cycle { index: 1 }));
+    assert_eq!(corrupt(vec![Empty(usize::MAX), Empty(usize::MAX)], 1),
+               Err(ValidationError::Unlinked { index: 0 }));
+    assert_eq!(corrupt(vec![Empty(usize::MAX), Occupied(1)], usize::MAX),
+               Err(ValidationError::Unlinked { index: 0 }));
+}
+
+#[test]
+fn counts() {
+    let mut m: CompactMap<u64> = CompactMap::with_capacity(16);
+    assert!(m.is_empty());
+    assert_eq!((m.len(), m.free_count()), (0, 0));
+    for i in 0..6 {
+        m.insert(i);
+    }
+    m.remove(1);
+    m.remove(3);
+    m.remove(99);
+    assert_eq!((m.len(), m.occupied_count(), m.free_count()), (4, 4, 2));
+    m.remove(5);
+    assert_eq!((m.len(), m.free_count()), (3, 2));
+    m.insert(7);
+    assert_eq!((m.len(), m.free_count()), (4, 1));
+    m.shrink_to_fit();
+    assert_eq!((m.len(), m.free_count()), (4, 1));
+    assert_eq!(m.drain().count(), 4);
+    assert!(m.is_empty());
+    assert_eq!(m.free_count(), 0);
+
+    #[allow(deprecated)]
+    {
+        assert!(m.is_empty_slow());
+        m.insert(1);
+        assert_eq!(m.len_slow(), 1);
+    }
+}
+
+#[test]
+fn remove_entry() {
+    let mut m: CompactMap<u64> = (10..13).collect();
+    assert_eq!(m.remove_entry(1), Some((1, 11)));
+    assert_eq!(m.remove_entry(1), None);
+    assert_eq!(m.remove_entry(100), None);
+    assert_eq!(m.len(), 2);
+
+    let mut w: super::wrapped::CompactMap<Mom, u64> = super::wrapped::CompactMap::from_unwrapped(m);
+    assert_eq!(w.remove_entry(Mom(2)), Some((Mom(2), 12)));
+    assert_eq!(w.remove_entry(Mom(2)), None);
+}
+
+#[test]
+fn insert_with_key() {
+    let mut m: CompactMap<(usize, u64)> = CompactMap::new();
+    for i in 0..5 {
+        let k = m.insert_with_key(|k| (k, i));
+        assert_eq!(m[k].0, k);
+    }
+    m.remove(2);
+    let k = m.insert_with_key(|k| (k, 99));
+    assert_eq!((k, m[k]), (2, (2, 99)));
+
+    let mut w: super::wrapped::CompactMap<Mom, (Mom, u64)> = super::wrapped::CompactMap::new();
+    let k = w.insert_with_key(|k| (k, 1));
+    assert_eq!(w[k].0, k);
+}
+
+#[test]
+fn get_or_insert() {
+    let mut m: CompactMap<u64> = (0..6).collect();
+    m.remove(1);
+    m.remove(3);
+    m.remove(4);
+
+    assert_eq!(*m.get_or_insert(0, 100), 0);
+    assert_eq!(*m.get_or_insert_with(2, || panic!("occupied")), 2);
+    assert_eq!(*m.get_or_insert(3, 33), 33);
+    assert_eq!(*m.get_or_insert_with(9, || 99), 99);
+    assert_eq!(m.len(), 5);
+    assert_eq!(m.keys().collect::<Vec<_>>(), [0, 2, 3, 5, 9]);
+
+    let mut reused: Vec<_> = (0..4).map(|_| m.insert(0)).collect();
+    reused.sort();
+    assert_eq!(reused, [4, 6, 7, 8]);
+    assert_eq!(m.insert(0), 1);
+    assert_eq!(m.insert(0), 10);
+
+    let mut w: super::wrapped::CompactMap<Mom, u64> = super::wrapped::CompactMap::new();
+    *w.get_or_insert(Mom(1), 0) += 1;
+    *w.get_or_insert_with(Mom(1), || 0) += 1;
+    assert_eq!(w[Mom(1)], 2);
+}
+
+#[test]
+fn modify_update_replace() {
+    let mut m: CompactMap<u64> = (0..3).collect();
+    m.remove(1);
+
+    assert_eq!(m.modify(2, |v| *v *= 10), Some(&mut 20));
+    assert_eq!(m.modify(1, |_| panic!("vacant")), None);
+    assert_eq!(m.update(2, |v| *v + 1), Some(21));
+    assert_eq!(m.update(1, |_| -> u64 { panic!("vacant") }), None);
+    assert_eq!(m.replace(0, 7), Some(0));
+    assert_eq!(m.replace(1, 7), None);
+    assert_eq!(m.replace(5, 7), None);
+    assert_eq!(m.into_iter().collect::<Vec<_>>(), [(0, 7), (2, 20)]);
+}
+
+#[test]
+fn size_hints() {
+    let mut m: CompactMap<u64> = (0..5).collect();
+    m.remove(1);
+    assert_eq!(m.iter().size_hint(), (0, Some(5)));
+    let mut it = m.iter();
+    it.next();
+    it.next_back();
+    assert_eq!(it.size_hint(), (0, Some(3)));
+    assert_eq!(m.keys().size_hint(), (0, Some(5)));
+    assert_eq!(m.values_mut().size_hint(), (0, Some(5)));
+
+    let mut w: super::wrapped::CompactMap<Mom, u64> = super::wrapped::CompactMap::new();
+    w.insert(1);
+    w.insert(2);
+    assert_eq!(w.iter().size_hint(), (0, Some(2)));
+    assert_eq!(w.iter_mut().size_hint(), (0, Some(2)));
+    assert_eq!(w.into_iter().size_hint(), (0, Some(2)));
+}
+
+#[test]
+fn into_iter_clone() {
+    let mut m: CompactMap<String> = (0..4).map(|x| x.to_string()).collect();
+    m.remove(2);
+    let mut it = m.into_iter();
+    it.next();
+    let checkpoint = it.clone();
+    assert_eq!(it.collect::<Vec<_>>(), [(1, "1".to_string()), (3, "3".to_string())]);
+    assert_eq!(checkpoint.clone().next_back(), Some((3, "3".to_string())));
+    assert_eq!(checkpoint.count(), 2);
+}
+
+#[test]
+fn iter_mut_split_at() {
+    let mut m: CompactMap<u64> = (0..6).collect();
+    m.remove(3);
+    {
+        let mut it = m.iter_mut();
+        it.next();
+        it.next_back();
+        let (lo, hi) = it.split_at(3);
+        assert_eq!(lo.map(|(k, _)| k).collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(hi.map(|(k, _)| k).collect::<Vec<_>>(), [4]);
+    }
+    {
+        let (lo, hi) = m.iter_mut().split_at(100);
+        assert_eq!(lo.count(), 5);
+        assert_eq!(hi.count(), 0);
+    }
+    {
+        let mut it = m.iter_mut();
+        it.next();
+        let (lo, mut hi) = it.split_at(0);
+        assert_eq!(lo.count(), 0);
+        assert_eq!(hi.next_back().map(|(k, _)| k), Some(5));
+        assert_eq!(hi.next().map(|(k, _)| k), Some(1));
+    }
+}
+
+#[test]
+fn iter_from() {
+    let mut m: CompactMap<u64> = (0..10).collect();
+    m.remove(5);
+    m.remove(6);
+
+    assert_eq!(m.iter_from(5).map(|(k, _)| k).collect::<Vec<_>>(), [7, 8, 9]);
+    assert_eq!(m.iter_from(0).count(), 8);
+    assert_eq!(m.iter_from(10).next(), None);
+    assert_eq!(m.iter_from(usize::MAX).next_back(), None);
+    assert_eq!(m.iter_from(3).rev().map(|(k, _)| k).collect::<Vec<_>>(), [9, 8, 7, 4, 3]);
+
+    for (_, v) in m.iter_from_mut(8) {
+        *v = 0;
+    }
+    assert_eq!(m.values().cloned().collect::<Vec<_>>(), [0, 1, 2, 3, 4, 7, 0, 0]);
+
+    let mut w: super::wrapped::CompactMap<Mom, u64> = super::wrapped::CompactMap::new();
+    w.insert(1);
+    w.insert(2);
+    w.insert(3);
+    assert_eq!(w.iter_from(Mom(1)).map(|(k, _)| k.0).collect::<Vec<_>>(), [1, 2]);
+    assert_eq!(w.iter_from_mut(Mom(2)).count(), 1);
+}
+
+#[test]
+fn range() {
+    let mut m: CompactMap<u64> = (0..10).collect();
+    m.remove(4);
+
+    fn keys<'a, I: Iterator<Item = (usize, &'a u64)>>(it: I) -> Vec<usize> {
+        it.map(|(k, _)| k).collect()
+    }
+    assert_eq!(keys(m.range(2..6)), [2, 3, 5]);
+    assert_eq!(keys(m.range(8..)), [8, 9]);
+    assert_eq!(keys(m.range(..=1)), [0, 1]);
+    assert_eq!(keys(m.range(7..100)), [7, 8, 9]);
+    let (hi, lo) = (6, 3);
+    assert!(keys(m.range(hi..lo)).is_empty());
+    assert!(keys(m.range(20..30)).is_empty());
+    assert_eq!(keys(m.range(..)), keys(m.iter()));
+    assert_eq!(keys(m.range(3..7).rev()), [6, 5, 3]);
+
+    for (_, v) in m.range_mut(..3) {
+        *v += 100;
+    }
+    assert_eq!(m[2], 102);
+    assert_eq!(m[3], 3);
+
+    let mut w: super::wrapped::CompactMap<Mom, u64> = super::wrapped::CompactMap::new();
+    for x in 0..5 {
+        w.insert(x);
+    }
+    assert_eq!(w.range(Mom(1)..Mom(3)).map(|(_, v)| *v).collect::<Vec<_>>(), [1, 2]);
+    assert_eq!(w.range_mut(Mom(3)..).count(), 2);
+}
+
+#[test]
+fn truncate() {
+    let mut m: CompactMap<u64> = (0..8).collect();
+    m.remove(1);
+    m.remove(5);
+    m.remove(6);
+    m.truncate(3);
+    assert_eq!(m.len(), 2);
+    assert!(m.capacity() >= 8);
+    assert_eq!(m.iter().collect::<Vec<_>>(), [(0, &0), (2, &2)]);
+    for k in 3..8 {
+        assert_eq!(m.get(k), None);
+    }
+    assert_eq!(m.insert(10), 1);
+    assert_eq!(m.insert(11), 3);
+
+    m.truncate(100);
+    assert_eq!(m.len(), 4);
+    m.truncate(0);
+    assert!(m.is_empty());
+    assert_eq!(m.insert(0), 0);
+
+    let mut w: super::wrapped::CompactMap<Mom, u64> = super::wrapped::CompactMap::new();
+    w.insert(1);
+    w.insert(2);
+    w.truncate(Mom(1));
+    assert_eq!(w.len(), 1);
+    assert_eq!(w.get(Mom(1)), None);
+}
+
+#[test]
+fn fill_to() {
+    let mut m: CompactMap<u64> = (0..6).collect();
+    m.remove(1);
+    m.remove(4);
+    assert_eq!(m.fill_to(3, || 9), [1]);
+    assert_eq!(m.len(), 5);
+    assert_eq!(m.insert(7), 4);
+
+    let mut n = 100;
+    assert_eq!(m.fill_to(8, || { n += 1; n }), [6, 7]);
+    assert_eq!(m.values().cloned().collect::<Vec<_>>(), [0, 9, 2, 3, 7, 5, 101, 102]);
+    assert!(m.fill_to(4, || panic!("nothing to fill")).is_empty());
+
+    let mut d: CompactMap<u64> = CompactMap::new();
+    assert_eq!(d.fill_to_default(3), [0, 1, 2]);
+    assert_eq!(d.insert(1), 3);
+
+    let mut w: super::wrapped::CompactMap<Mom, u64> = super::wrapped::CompactMap::new();
+    assert_eq!(w.fill_to_default(Mom(2)), [Mom(0), Mom(1)]);
+}
+
+#[test]
+fn fill_to_panicking() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    let mut m: CompactMap<u32> = (0..4).collect();
+    m.remove(0);
+    m.remove(2);
+    let mut n = 0;
+    let r = catch_unwind(AssertUnwindSafe(|| {
+        m.fill_to(6, || { n += 1; if n == 3 { panic!("boom") } else { n } });
+    }));
+    assert!(r.is_err());
+    assert_eq!(m.validate(), Ok(()));
+    assert_eq!(m.iter().collect::<Vec<_>>(), [(0, &1), (1, &1), (2, &2), (3, &3)]);
+    assert_eq!(m.insert(7), 4);
+    assert_eq!(m.len(), 5);
+}
+
+#[test]
+fn drain_range() {
+    let mut m: CompactMap<u64> = (0..10).collect();
+    m.remove(3);
+    {
+        let mut d = m.drain_range(2..6);
+        assert_eq!(d.next(), Some((2, 2)));
+        assert_eq!(d.next_back(), Some((5, 5)));
+    }
+    assert_eq!(m.len(), 6);
+    assert_eq!(m.keys().collect::<Vec<_>>(), [0, 1, 6, 7, 8, 9]);
+    assert!(m.capacity() >= 10);
+
+    // tail range trims trailing vacant slots, even when dropped early
+    m.remove(5);
+    m.drain_range(7..).next();
+    assert_eq!(m.keys().collect::<Vec<_>>(), [0, 1, 6]);
+    let mut reused: Vec<_> = (0..5).map(|_| m.insert(0)).collect();
+    reused.sort();
+    assert_eq!(reused, [2, 3, 4, 5, 7]);
+
+    assert_eq!(m.drain_range(20..).count(), 0);
+    assert_eq!(m.drain_range(..).count(), 8);
+    assert!(m.is_empty());
+    assert_eq!(m.insert(0), 0);
+
+    // leaking the iterator keeps the map consistent
+    let mut m: CompactMap<u64> = (0..4).collect();
+    {
+        let mut d = m.drain_range(1..3);
+        d.next();
+        ::std::mem::forget(d);
+    }
+    assert_eq!(m.validate(), Ok(()));
+    assert_eq!(m.len(), 3);
+
+    let mut w: super::wrapped::CompactMap<Mom, u64> = super::wrapped::CompactMap::new();
+    w.insert(1);
+    w.insert(2);
+    assert_eq!(w.drain_range(Mom(1)..).collect::<Vec<_>>(), [(Mom(1), 2)]);
+    assert_eq!(w.len(), 1);
+}
+
+#[test]
+fn extend_keyed() {
+    let mut m: CompactMap<&str> = CompactMap::from_keyed_pairs(vec![(5, "f"), (1, "b"), (3, "d")]);
+    assert_eq!(m.len(), 3);
+    assert_eq!(m.keys().collect::<Vec<_>>(), [1, 3, 5]);
+
+    m.extend_keyed(vec![(3, "D"), (7, "h")]);
+    assert_eq!(m.len(), 4);
+    assert_eq!(m[3], "D");
+
+    let mut reused: Vec<_> = (0..4).map(|_| m.insert("")).collect();
+    reused.sort();
+    assert_eq!(reused, [0, 2, 4, 6]);
+    assert_eq!(m.insert(""), 8);
+
+    // Tuple values still go through FromIterator<V> and Extend<V>
+    let mut t: CompactMap<_> = vec![(1usize, "a")].into_iter().collect();
+    t.extend(vec![(3usize, "x")]);
+    assert_eq!(t.iter().collect::<Vec<_>>(), [(0, &(1, "a")), (1, &(3, "x"))]);
+
+    let mut m: CompactMap<&str> = CompactMap::new();
+    let e = m.try_extend_keyed(vec![(1, "a"), (4, "b"), (1, "c"), (9, "d")]).unwrap_err();
+    assert_eq!((e.key, e.theirs), (1, "c"));
+    assert_eq!(m.keys().collect::<Vec<_>>(), [1, 4]);
+    assert_eq!(m[1], "a");
+    assert_eq!(m.validate(), Ok(()));
+
+    let mut w: super::wrapped::CompactMap<Mom, u64> = super::wrapped::CompactMap::from_keyed_pairs(vec![(Mom(2), 20)]);
+    w.extend_keyed(vec![(Mom(0), 0)]);
+    assert_eq!(w.keys().collect::<Vec<_>>(), [Mom(0), Mom(2)]);
+    let e = w.try_extend_keyed(vec![(Mom(2), 21)]).unwrap_err();
+    assert_eq!((e.key, e.theirs), (Mom(2), 21));
+    assert_eq!(w[Mom(2)], 20);
+}
+
+#[test]
+fn append() {
+    let mut a: CompactMap<u64> = (0..3).collect();
+    a.remove(1);
+    let mut b: CompactMap<u64> = (10..12).collect();
+    let mut empty = CompactMap::new();
+
+    a.append(&mut empty);
+    assert_eq!(a.len(), 2);
+    a.append(&mut b);
+    assert_eq!(a.iter().collect::<Vec<_>>(), [(0, &0), (1, &10), (2, &2), (3, &11)]);
+    assert!(b.is_empty());
+    assert!(b.capacity() >= 2);
+    empty.append(&mut a);
+    assert_eq!(empty.len(), 4);
+    assert!(a.is_empty());
+}
+
+#[test]
+fn append_preserving_keys() {
+    let mut a: CompactMap<u64> = CompactMap::from_keyed_pairs(vec![(0, 0), (4, 4)]);
+    let mut b: CompactMap<u64> = CompactMap::from_keyed_pairs(vec![(2, 2), (7, 7)]);
+    let mut empty = CompactMap::new();
+
+    a.append_preserving_keys(&mut empty).unwrap();
+    a.append_preserving_keys(&mut b).unwrap();
+    assert_eq!(a.keys().collect::<Vec<_>>(), [0, 2, 4, 7]);
+    assert!(b.is_empty());
+    assert_eq!(a.insert(1), 6);
+
+    let mut c: CompactMap<u64> = CompactMap::from_keyed_pairs(vec![(3, 3), (4, 40), (7, 70)]);
+    assert_eq!(a.append_preserving_keys(&mut c), Err(super::ConflictKey { key: 4 }));
+    assert_eq!(c.len(), 3);
+    assert_eq!(a[4], 4);
+    assert_eq!(a.get(3), None);
+
+    empty.append_preserving_keys(&mut c).unwrap();
+    assert_eq!(empty.keys().collect::<Vec<_>>(), [3, 4, 7]);
+
+    let mut w: super::wrapped::CompactMap<Mom, u64> = super::wrapped::CompactMap::from_keyed_pairs(vec![(Mom(1), 1)]);
+    let mut x: super::wrapped::CompactMap<Mom, u64> = super::wrapped::CompactMap::from_keyed_pairs(vec![(Mom(1), 2)]);
+    assert_eq!(w.append_preserving_keys(&mut x).unwrap_err().key, Mom(1));
+    w.append(&mut x);
+    assert_eq!(w.len(), 2);
+}
+
+#[test]
+fn dense() {
+    let mut m: CompactMap<u64> = CompactMap::new();
+    assert_eq!(m.dense_len(), Some(0));
+    m.extend(0..4);
+    m.assert_dense();
+    m.remove(3);
+    assert_eq!(m.dense_len(), Some(3));
+    m.remove(1);
+    assert!(!m.is_dense());
+    m.insert(1);
+    assert_eq!(m.dense_len(), Some(3));
+    m.remove(0);
+    m.remove(2);
+    assert!(!m.is_dense());
+    m.shrink_to_fit();
+    assert!(!m.is_dense());
+}
+
+#[test]
+#[should_panic(expected = "not dense")]
+fn assert_dense_panics() {
+    let mut m: CompactMap<u64> = (0..3).collect();
+    m.remove(0);
+    m.assert_dense();
+}
+
+#[test]
+fn get_pair_mut() {
+    let mut m: CompactMap<u64> = (0..5).collect();
+    m.remove(2);
+    {
+        let (a, b) = m.get_pair_mut(1, 3).unwrap();
+        *a += 10;
+        *b += 30;
+    }
+    {
+        let (a, b) = m.get_pair_mut(4, 0).unwrap();
+        *a += 40;
+        *b += 100;
+    }
+    assert_eq!(m.values().cloned().collect::<Vec<_>>(), [100, 11, 33, 44]);
+    assert!(m.get_pair_mut(1, 1).is_none());
+    assert!(m.get_pair_mut(1, 2).is_none());
+    assert!(m.get_pair_mut(2, 1).is_none());
+    assert!(m.get_pair_mut(0, 5).is_none());
+    assert!(m.get_pair_mut(usize::MAX, 0).is_none());
+
+    let mut w: super::wrapped::CompactMap<Mom, u64> = (0..2).collect();
+    {
+        let (a, b) = w.get_pair_mut(Mom(0), Mom(1)).unwrap();
+        *a = *b;
+    }
+    assert_eq!(w[Mom(0)], 1);
+}
+
+mod tokens {
+    declare_compactmap_token!(pub PubToken);
+    declare_compactmap_token!(pub(crate) CrateToken);
+    declare_compactmap_token!(pub(super) SuperToken);
+}
+
+#[test]
+fn token_visibility() {
+    use self::tokens::{CrateToken, PubToken, SuperToken};
+
+    let mut w: super::wrapped::CompactMap<PubToken, u64> = super::wrapped::CompactMap::new();
+    let k = w.insert(5);
+    assert_eq!(k, PubToken(0));
+    assert_eq!(k.0, 0);
+    assert_eq!(usize::from(CrateToken(2)), 2);
+    assert_eq!(SuperToken::from(7), SuperToken(7));
+}
+
+#[cfg(feature = "serde")]
+mod serde_tokens {
+    extern crate serde_derive;
+    extern crate serde_json;
+
+    declare_compactmap_token!(#[derive(serde_derive::Serialize, serde_derive::Deserialize)] pub SerdeToken);
+
+    #[test]
+    fn token_derives() {
+        let s = serde_json::to_string(&SerdeToken(42)).unwrap();
+        assert_eq!(s, "42");
+        let t: SerdeToken = serde_json::from_str(&s).unwrap();
+        assert_eq!(t, SerdeToken(42));
+    }
+}
+
+#[cfg(feature = "serde")]
+mod serde_formats {
+    extern crate serde_derive;
+    extern crate serde_json;
+
+    use super::super::CompactMap;
+
+    #[derive(serde_derive::Serialize, serde_derive::Deserialize)]
+    struct Doc {
+        object: CompactMap<String>,
+        #[serde(with = "super::super::serde_seq")]
+        array: CompactMap<String>,
+    }
+
+    #[test]
+    fn roundtrip_both_formats() {
+        let mut m = CompactMap::new();
+        for s in &["a", "b", "c", "d"] {
+            m.insert(s.to_string());
+        }
+        m.remove(0);
+        m.remove(2);
+
+        let doc = Doc { object: m.clone(), array: m };
+        let s = serde_json::to_string(&doc).unwrap();
+        assert_eq!(s, r#"{"object":{"1":"b","3":"d"},"array":[null,"b",null,"d"]}"#);
+
+        let back: Doc = serde_json::from_str(&s).unwrap();
+        assert_eq!(back.object, back.array);
+        assert_eq!(back.array.keys().collect::<Vec<_>>(), [1, 3]);
+        assert_eq!(back.array[3], "d");
+    }
+
+    #[test]
+    fn serde_postcard_compat() {
+        extern crate postcard;
+
+        let mut m: CompactMap<String> = CompactMap::new();
+        for s in &["a", "b", "c", "d"] {
+            m.insert(s.to_string());
+        }
+        m.remove(1);
+        let bytes = postcard::to_allocvec(&m).unwrap();
+        let back: CompactMap<String> = postcard::from_bytes(&bytes).unwrap();
+        assert_eq!(back, m);
+        assert_eq!(back.keys().collect::<Vec<_>>(), [0, 2, 3]);
+
+        let doc = Doc { object: m.clone(), array: m };
+        let bytes = postcard::to_allocvec(&doc).unwrap();
+        let back: Doc = postcard::from_bytes(&bytes).unwrap();
+        assert_eq!(back.object, back.array);
+    }
+
+    #[test]
+    fn key_limit() {
+        let json = r#"{"2":"b","7":"c"}"#;
+        let limited = |limit| {
+            let mut de = serde_json::Deserializer::from_str(json);
+            CompactMap::<String>::deserialize_with_limit(&mut de, limit)
+        };
+        assert_eq!(limited(8).unwrap().keys().collect::<Vec<_>>(), [2, 7]);
+        let err = limited(7).unwrap_err();
+        assert!(err.to_string().contains("key 7 is too large"));
+
+        let huge = r#"{"1048576":"x"}"#;
+        let res: Result<CompactMap<String>, _> = serde_json::from_str(huge);
+        assert_eq!(res.is_err(), cfg!(feature = "serde_safe"));
+    }
+}
+
+#[test]
+fn find() {
+    let mut m: CompactMap<u64> = CompactMap::new();
+    assert_eq!(m.find(|_| true), None);
+    assert_eq!(m.find_key(|_| true), None);
+    assert_eq!(m.find_mut(|_| true), None);
+
+    m.extend(vec![3, 8, 5, 8]);
+    m.remove(0);
+    assert_eq!(m.find(|&v| v == 8), Some((1, &8)));
+    assert_eq!(m.find_key(|&v| v < 8), Some(2));
+    assert_eq!(m.find(|&v| v == 3), None);
+    *m.find_mut(|&v| v == 8).unwrap().1 = 80;
+    assert_eq!(m.get(1), Some(&80));
+    assert_eq!(m.find(|&v| v == 8), Some((3, &8)));
+
+    let w: super::wrapped::CompactMap<Mom, u64> = vec![1, 2].into_iter().collect();
+    assert_eq!(w.find(|&v| v == 2), Some((Mom(1), &2)));
+    assert_eq!(w.find_key(|&v| v == 1), Some(Mom(0)));
+}
+
+#[test]
+fn contains_value() {
+    let mut m: CompactMap<u64> = CompactMap::new();
+    assert!(!m.contains_value(&0));
+    assert!(!m.contains_value_by(|_| true));
+
+    m.extend(vec![1, 7, 7, 2]);
+    assert!(m.contains_value(&7));
+    assert!(!m.contains_value(&3));
+    let mut calls = 0;
+    assert!(m.contains_value_by(|&v| { calls += 1; v == 7 }));
+    assert_eq!(calls, 2);
+    m.remove(0);
+    assert!(!m.contains_value(&1));
+
+    let w: super::wrapped::CompactMap<Mom, u64> = vec![5].into_iter().collect();
+    assert!(w.contains_value(&5));
+    assert!(!w.contains_value_by(|&v| v > 5));
+}
+
+#[test]
+fn remove_many() {
+    let mut m: CompactMap<u64> = (0..8).collect();
+    assert_eq!(m.remove_many(vec![]), 0);
+    assert_eq!(m.remove_many(vec![6, 2, 7, 2, 100]), 3);
+    assert_eq!(m.keys().collect::<Vec<_>>(), [0, 1, 3, 4, 5]);
+    assert_eq!(m.insert(20), 2);
+    assert_eq!(m.insert(60), 6);
+
+    assert_eq!(m.remove_many_collect(vec![5, 0, 4, 9, 0]), [0, 4, 5]);
+    assert_eq!(m.len(), 4);
+    assert_eq!(m.remove_many_collect(m.keys().collect::<Vec<_>>()), [1, 20, 3, 60]);
+    assert!(m.is_empty());
+    assert_eq!(m.insert(0), 0);
+
+    let mut w: super::wrapped::CompactMap<Mom, u64> = (0..3).collect();
+    assert_eq!(w.remove_many(vec![Mom(0), Mom(2)]), 2);
+    assert_eq!(w.remove_many_collect(vec![Mom(1)]), [1]);
+}
+
+#[test]
+fn eq_std_maps() {
+    use std::collections::{BTreeMap, HashMap};
+
+    let mut m: CompactMap<&str> = vec!["a", "b", "c"].into_iter().collect();
+    m.remove(1);
+    let mut hm: HashMap<usize, &str> = vec![(2, "c"), (0, "a")].into_iter().collect();
+    let mut bm: BTreeMap<usize, &str> = hm.clone().into_iter().collect();
+
+    assert_eq!(m, hm);
+    assert_eq!(hm, m);
+    assert_eq!(m, bm);
+    assert_eq!(bm, m);
+    assert_eq!(CompactMap::<&str>::new(), HashMap::new());
+    assert_eq!(CompactMap::<&str>::new(), BTreeMap::new());
+
+    hm.insert(2, "x");
+    bm.insert(2, "x");
+    assert_ne!(m, hm);
+    assert_ne!(bm, m);
+
+    hm.insert(2, "c");
+    bm.insert(2, "c");
+    hm.insert(1, "b");
+    bm.insert(1, "b");
+    assert_ne!(m, hm);
+    assert_ne!(m, bm);
+
+    hm.remove(&1);
+    bm.remove(&1);
+    hm.remove(&0);
+    bm.remove(&0);
+    hm.insert(5, "a");
+    bm.insert(5, "a");
+    assert_ne!(hm, m);
+    assert_ne!(m, bm);
+}
+
+#[test]
+fn zip() {
+    let a: CompactMap<u64> = CompactMap::from_keyed_pairs(vec![(0, 1), (2, 3), (3, 4), (6, 7)]);
+    let b: CompactMap<&str> = CompactMap::from_keyed_pairs(vec![(1, "b"), (2, "c"), (3, "d")]);
+
+    let inner = a.zip_map(&b, |&n, &s| (n, s));
+    assert_eq!(inner.into_iter().collect::<Vec<_>>(), [(2, (3, "c")), (3, (4, "d"))]);
+
+    let outer = a.zip_outer(&b, |n, s| (n.cloned(), s.cloned()));
+    assert_eq!(outer.len(), 5);
+    assert_eq!(outer.iter().collect::<Vec<_>>(), [
+        (0, &(Some(1), None)),
+        (1, &(None, Some("b"))),
+        (2, &(Some(3), Some("c"))),
+        (3, &(Some(4), Some("d"))),
+        (6, &(Some(7), None)),
+    ]);
+
+    let empty: CompactMap<u8> = CompactMap::new();
+    assert!(a.zip_map(&empty, |_, _| ()).is_empty());
+    assert_eq!(empty.zip_outer(&a, |_, x| *x.unwrap()).values().cloned().collect::<Vec<_>>(), [1, 3, 4, 7]);
+
+    let w: super::wrapped::CompactMap<Mom, u64> = (0..2).collect();
+    assert_eq!(w.zip_map(&w, |x, y| x + y)[Mom(1)], 2);
+    assert_eq!(w.zip_outer(&w, |x, _| *x.unwrap()).len(), 2);
+}
+
+#[test]
+fn unzip() {
+    let mut m: CompactMap<(u64, String)> = (0..6).map(|x| (x, x.to_string())).collect();
+    m.remove(1);
+    m.remove(4);
+
+    let (l, r) = m.unzip_ref();
+    for (k, v) in &m {
+        assert_eq!(l[k], v.0);
+        assert_eq!(r[k], v.1);
+    }
+    assert_eq!(l.len(), 4);
+    assert_eq!(r.len(), 4);
+
+    let (mut l, mut r) = m.clone().unzip();
+    assert_eq!(l.keys().collect::<Vec<_>>(), m.keys().collect::<Vec<_>>());
+    assert_eq!(r.values().cloned().collect::<Vec<_>>(), ["0", "2", "3", "5"]);
+    for _ in 0..3 {
+        let k = m.insert((0, String::new()));
+        assert_eq!(l.insert(0), k);
+        assert_eq!(r.insert(String::new()), k);
+    }
+
+    let w: super::wrapped::CompactMap<Mom, (u8, u16)> = vec![(1, 2)].into_iter().collect();
+    let (a, b) = w.unzip();
+    assert_eq!((a[Mom(0)], b[Mom(0)]), (1, 2));
+}
+
+#[test]
+fn insert_returning_ref() {
+    let mut m: CompactMap<(usize, u64)> = CompactMap::new();
+    m.insert((0, 0));
+    m.insert((1, 0));
+    m.remove(0);
+    {
+        let (k, v) = m.insert_returning_ref((0, 0));
+        assert_eq!(k, 0);
+        v.0 = k;
+        v.1 = 42;
+    }
+    assert_eq!(m[0], (0, 42));
+    assert_eq!(m.insert_returning_ref((9, 9)).0, 2);
+
+    let mut w: super::wrapped::CompactMap<Mom, u64> = super::wrapped::CompactMap::new();
+    {
+        let (k, v) = w.insert_returning_ref(1);
+        *v += 1;
+        assert_eq!(k, Mom(0));
+    }
+    assert_eq!(w[Mom(0)], 2);
+}
+
+#[test]
+fn holes() {
+    let mut m: CompactMap<u64> = CompactMap::new();
+    assert_eq!(m.iter_holes().count(), 0);
+    m.extend(0..8);
+    m.remove(5);
+    m.remove(0);
+    m.remove(6);
+    assert_eq!(m.iter_holes().collect::<Vec<_>>(), [0, 5, 6]);
+    assert_eq!(m.count_holes(), 3);
+    m.remove(7);
+    assert_eq!(m.iter_holes().collect::<Vec<_>>(), [0]);
+    m.shrink_to_fit();
+    assert_eq!(m.iter_holes().collect::<Vec<_>>(), [0]);
+    assert_eq!(m.count_holes(), 1);
+
+    let mut w: super::wrapped::CompactMap<Mom, u64> = (0..3).collect();
+    w.remove(Mom(1));
+    assert_eq!(w.iter_holes().collect::<Vec<_>>(), [Mom(1)]);
+    assert_eq!(w.count_holes(), 1);
+}
+
+#[test]
+fn compact_and_remap() {
+    let mut m: CompactMap<u64> = (0..7).map(|x| x * 10).collect();
+    m.remove(0);
+    m.remove(3);
+    m.remove(4);
+
+    let (c, remap) = m.compact_and_remap();
+    assert_eq!(remap.len(), 7);
+    assert_eq!(remap.iter().filter(|k| k.is_some()).count(), m.len());
+    for (old, v) in &m {
+        assert_eq!(c[remap[old].unwrap()], *v);
+    }
+    assert_eq!(c.dense_len(), Some(4));
+    assert_eq!(m.len(), 4);
+
+    let (c2, remap2) = m.into_compact_and_remap();
+    assert_eq!(c2, c);
+    assert_eq!(remap2, remap);
+
+    let (e, r) = CompactMap::<u64>::new().into_compact_and_remap();
+    assert!(e.is_empty());
+    assert!(r.is_empty());
+
+    let mut w: super::wrapped::CompactMap<Mom, u64> = (0..3).collect();
+    w.remove(Mom(0));
+    let (wc, wr) = w.compact_and_remap();
+    assert_eq!(wr, [None, Some(Mom(0)), Some(Mom(1))]);
+    assert_eq!(wc[Mom(0)], 1);
+}
+
+#[test]
+fn invert() {
+    let mut m: CompactMap<usize> = vec![7, 3, 0, 9].into_iter().collect();
+    m.remove(2);
+    let inv = m.invert().unwrap();
+    assert_eq!(inv.len(), 3);
+    for (k, &v) in &m {
+        assert_eq!(inv[v], k);
+    }
+    assert_eq!(inv.invert().unwrap(), m);
+    assert!(CompactMap::<usize>::new().invert().unwrap().is_empty());
+
+    m.insert(3);
+    assert_eq!(m.invert(), Err(super::InvertError { duplicate_value: 3, key1: 1, key2: 2 }));
+
+    declare_compactmap_token!(Dad);
+    let w: super::wrapped::CompactMap<Mom, Dad> = vec![Dad(4), Dad(1)].into_iter().collect();
+    let winv: super::wrapped::CompactMap<Dad, Mom> = w.invert().unwrap();
+    assert_eq!(winv[Dad(4)], Mom(0));
+    assert_eq!(winv.invert().unwrap()[Mom(1)], Dad(1));
+
+    let w: super::wrapped::CompactMap<Mom, u32> = vec![5u32, 2, 5].into_iter().collect();
+    let e = w.invert().unwrap_err();
+    assert_eq!((e.duplicate_value, e.key1, e.key2), (5u32, Mom(0), Mom(2)));
+    let w: super::wrapped::CompactMap<Mom, u32> = vec![5u32, 2].into_iter().collect();
+    let winv: super::wrapped::CompactMap<u32, Mom> = w.invert().unwrap();
+    assert_eq!(winv.keys().collect::<Vec<u32>>(), [2, 5]);
+    assert_eq!(winv[5u32], Mom(0));
+
+    let n: super::nz::CompactMap<u32> = vec![3u32, 1].into_iter().collect();
+    let ninv = n.invert().unwrap();
+    assert_eq!(ninv[3u32], ::std::num::NonZeroUsize::new(1).unwrap());
+}
+
+#[test]
+fn raw_entry() {
+    use super::Entry;
+
+    let mut m: CompactMap<u64> = (0..4).collect();
+    m.remove(1);
+    m.remove(2);
+    match m.raw_entry(1) {
+        Some(&Entry::Empty(next)) => assert_eq!(next, usize::MAX),
+        _ => panic!(),
+    }
+    match m.raw_entry(2) {
+        Some(&Entry::Empty(next)) => assert_eq!(next, 1),
+        _ => panic!(),
+    }
+    assert!(m.raw_entry(4).is_none());
+    assert!(!m.raw_entry(1).unwrap().is_occupied());
+    assert_eq!(m.raw_entry(2).unwrap().next_free(), Some(1));
+    assert_eq!(m.raw_entry(3).and_then(Entry::value), Some(&3));
+    assert_eq!(m.raw_entry(3).unwrap().next_free(), None);
+
+    // Move the value from slot 0 to vacant slot 1, relinking the free list
+    *m.raw_entry_mut(1).unwrap() = Entry::Occupied(10);
+    *m.raw_entry_mut(0).unwrap() = Entry::Empty(usize::MAX);
+    assert!(m.validate().is_err());
+    *m.raw_entry_mut(2).unwrap() = Entry::Empty(0);
+    assert_eq!(m.validate(), Ok(()));
+    assert_eq!(m.iter().collect::<Vec<_>>(), [(1, &10), (3, &3)]);
+    assert_eq!(m.insert(20), 2);
+    assert_eq!(m.insert(0), 0);
+
+    let w: super::wrapped::CompactMap<Mom, u64> = (0..1).collect();
+    assert!(w.raw_entry(Mom(0)).is_some());
+}
+
+#[test]
+fn wrapped_rev() {
+    let mut w: super::wrapped::CompactMap<Mom, u64> = (0..6).collect();
+    w.remove(Mom(2));
+    w.remove(Mom(4));
+
+    let fwd = w.iter().map(|(k, &v)| (k, v)).collect::<Vec<_>>();
+    let mut rev = w.iter().rev().map(|(k, &v)| (k, v)).collect::<Vec<_>>();
+    rev.reverse();
+    assert_eq!(fwd, rev);
+    assert_eq!(w.keys().rev().collect::<Vec<_>>(), [Mom(5), Mom(3), Mom(1), Mom(0)]);
+    assert_eq!(w.iter_mut().next_back().map(|(k, _)| k), Some(Mom(5)));
+
+    let mut it = w.iter();
+    assert_eq!(it.by_ref().count(), 4);
+    assert_eq!(it.next(), None);
+    assert_eq!(it.next_back(), None);
+
+    let mut drain = w.drain();
+    assert_eq!(drain.next_back(), Some((Mom(5), 5)));
+    assert_eq!(drain.next(), Some((Mom(0), 0)));
+}
+
+#[test]
+fn wrapped_iter_clone() {
+    struct NoClone(u64);
+    let mut w: super::wrapped::CompactMap<Mom, NoClone> = (0..4).map(NoClone).collect();
+    w.remove(Mom(1));
+
+    let mut keys = w.keys();
+    keys.next();
+    assert_eq!(keys.clone().collect::<Vec<_>>(), [Mom(2), Mom(3)]);
+    let it = w.iter();
+    assert_eq!(it.clone().map(|(_, v)| v.0).sum::<u64>(), 5);
+
+    let w = || (0..3).collect::<super::wrapped::CompactMap<Mom, u64>>();
+    let mut it = w().into_iter();
+    it.next();
+    assert_eq!(it.clone().collect::<Vec<_>>(), [(Mom(1), 1), (Mom(2), 2)]);
+    assert_eq!(it.collect::<Vec<_>>(), [(Mom(1), 1), (Mom(2), 2)]);
+    let keys = w().into_keys();
+    assert_eq!(keys.clone().count(), keys.count());
+    let values = w().into_values();
+    assert_eq!(values.clone().collect::<Vec<_>>(), [0, 1, 2]);
+}
+
+#[test]
+fn arena_never_reuses() {
+    use super::ArenaMap;
+
+    let mut a = ArenaMap::new();
+    for i in 0..4 {
+        assert_eq!(a.insert(i * 10), i);
+    }
+    assert_eq!(a.remove(1), Some(10));
+    assert_eq!(a.remove(3), Some(30));
+    assert_eq!(a.remove(3), None);
+    assert_eq!(a.insert(40), 4);
+    assert_eq!(a.remove(4), Some(40));
+    assert_eq!(a.remove(2), Some(20));
+    assert_eq!(a.next_key(), 5);
+    assert_eq!(a.insert(50), 5);
+    assert_eq!(a.iter().collect::<Vec<_>>(), [(0, &0), (5, &50)]);
+    assert_eq!(a.len(), 2);
+    a[5] += 1;
+    a.shrink_to_fit();
+    assert_eq!(a.insert(60), 6);
+    assert_eq!(a.as_map().validate(), Ok(()));
+
+    let mut m = a.clone().into_reusing();
+    assert_eq!(m.values().cloned().collect::<Vec<_>>(), [0, 51, 60]);
+    let reused = m.insert(1);
+    assert!((1..5).contains(&reused));
+
+    a.clear();
+    assert!(a.is_empty());
+    assert_eq!(a.insert(0), 0);
+}
+
+#[test]
+fn pop_first_last() {
+    let mut m: CompactMap<u64> = (0..5).collect();
+    assert_eq!(m.pop_last(), Some((4, 4)));
+
+    m.insert(40);
+    m.insert(50);
+    m.remove(5);
+    m.remove(0);
+    assert_eq!(m.pop_last(), Some((4, 40)));
+    assert_eq!(m.pop_first(), Some((1, 1)));
+    assert_eq!(m.len(), 2);
+
+    let mut m: CompactMap<u64> = (0..6).collect();
+    m.remove(2);
+    let mut keys = vec![];
+    while let Some((k, _)) = m.pop_last() {
+        keys.push(k);
+    }
+    assert_eq!(keys, [5, 4, 3, 1, 0]);
+    assert!(m.is_empty());
+    assert_eq!(m.pop_first(), None);
+
+    let mut trailing: CompactMap<u64> = CompactMap::new();
+    trailing.fill_to_default(3);
+    trailing.remove(2);
+    trailing.remove(0);
+    assert_eq!(trailing.pop_last(), Some((1, 0)));
+    assert_eq!(trailing.pop_last(), None);
+
+    let mut w: super::wrapped::CompactMap<Mom, u64> = (0..3).collect();
+    assert_eq!(w.pop_first(), Some((Mom(0), 0)));
+    assert_eq!(w.pop_last(), Some((Mom(2), 2)));
+}
+
+#[test]
+fn split_off() {
+    let mut m: CompactMap<u64> = (0..10).collect();
+    m.remove(2);
+    m.remove(6);
+    m.remove(8);
+    let orig = m.clone();
+
+    let tail = m.split_off(5);
+    assert_eq!(
+        m.iter().chain(tail.iter()).collect::<Vec<_>>(),
+        orig.iter().collect::<Vec<_>>()
+    );
+    assert_eq!(m.len() + tail.len(), orig.len());
+    assert_eq!(tail.keys().collect::<Vec<_>>(), [5, 7, 9]);
+
+    let mut tail = tail;
+    assert_eq!(tail.insert(100), 8);
+    assert_eq!(m.insert(20), 2);
+    assert_eq!(m.insert(50), 5);
+
+    assert!(m.split_off(100).is_empty());
+    let all = m.split_off(0);
+    assert!(m.is_empty());
+    assert_eq!(all.len(), 6);
+
+    let mut w: super::wrapped::CompactMap<Mom, u64> = (0..4).collect();
+    let wt = w.split_off(Mom(3));
+    assert_eq!(wt.keys().collect::<Vec<_>>(), [Mom(3)]);
+    assert_eq!(w.len(), 3);
+}
+
+#[test]
+fn slotmap() {
+    use super::slotmap::{Key, SlotMap};
+
+    let mut sm = SlotMap::new();
+    let a = sm.insert(1u64);
+    let b = sm.insert(2);
+    let c = sm.insert_with_key(|k| k.index() as u64 * 100);
+    assert_eq!(sm[c], 200);
+    assert_eq!(sm.len(), 3);
+
+    assert_eq!(sm.remove(b), Some(2));
+    assert_eq!(sm.remove(b), None);
+    let d = sm.insert(4);
+    assert_eq!(d.index(), b.index());
+    assert_ne!(d, b);
+    assert_eq!(sm.get(b), None);
+    assert!(sm.get_mut(b).is_none());
+    assert!(sm.contains_key(d));
+
+    assert_eq!(sm.remove(c), Some(200));
+    let e = sm.insert(5);
+    assert_eq!(e.index(), c.index());
+    assert_eq!(sm.get(c), None);
+    assert_eq!(e.generation(), 1);
+
+    *sm.get_mut(a).unwrap() += 10;
+    assert_eq!(sm.iter().map(|(_, &v)| v).collect::<Vec<_>>(), [11, 4, 5]);
+    assert_eq!(sm.keys().collect::<Vec<_>>(), [a, d, e]);
+    for (_, v) in &mut sm {
+        *v += 1;
+    }
+    assert_eq!(sm.values().cloned().collect::<Vec<_>>(), [12, 5, 6]);
+
+    sm.retain(|_, v| *v % 2 == 0);
+    assert_eq!(sm.keys().collect::<Vec<_>>(), [a, e]);
+    assert!(!sm.contains_key(d));
+
+    sm.clear();
+    assert!(sm.is_empty());
+    let f = sm.insert(7);
+    assert!(!sm.contains_key(a) && !sm.contains_key(e));
+    assert!(sm.contains_key(f));
+    assert!(!sm.contains_key(Key::null()));
+    assert!(Key::default().is_null());
+}
+
+#[test]
+fn cursor() {
+    let mut m: CompactMap<u64> = (0..8).collect();
+    m.remove(0);
+    m.remove(3);
+    m.remove(4);
+
+    let mut c = m.cursor_at(3);
+    assert_eq!(c.key(), 5);
+    let mut keys = vec![];
+    while !c.is_at_end() {
+        keys.push(c.key());
+        c.move_next();
+    }
+    assert_eq!(keys, [5, 6, 7]);
+    c.move_next();
+    assert_eq!(c.key(), 1);
+    c.move_prev();
+    assert!(c.is_at_end());
+    c.move_prev();
+    assert_eq!(*c.value(), 7);
+    assert!(m.cursor_at(100).is_at_end());
+
+    {
+        let mut c = m.cursor_at_mut(6);
+        assert_eq!(c.remove_current(), 6);
+        assert_eq!(c.key(), 7);
+        assert_eq!(c.remove_current(), 7);
+        assert!(c.is_at_end());
+        c.move_prev();
+        assert_eq!(c.key(), 5);
+        *c.value_mut() = 50;
+    }
+    assert_eq!(m.iter().collect::<Vec<_>>(), [(1, &1), (2, &2), (5, &50)]);
+    assert!(m.validate().is_ok());
+
+    let empty: CompactMap<u64> = CompactMap::new();
+    let mut c = empty.cursor_at(0);
+    c.move_next();
+    c.move_prev();
+    assert!(c.is_at_end());
+}
+
+#[test]
+#[should_panic]
+fn cursor_at_end_panics() {
+    let m: CompactMap<u64> = CompactMap::new();
+    m.cursor_at(0).key();
+}
+
+#[test]
+fn shrink_free_list() {
+    use super::Entry;
+
+    let mut m: CompactMap<u64> = (0..10).collect();
+    assert_eq!(m.trailing_holes(), 0);
+    let cap = m.capacity();
+    m.remove(1);
+    m.remove(4);
+    m.remove(5);
+    m.truncate(6);
+    // bulk operations strip the vacant tail themselves
+    assert_eq!(m.trailing_holes(), 0);
+    assert_eq!(m.free_count(), 1);
+    m.shrink_free_list();
+    assert_eq!(m.free_count(), 1);
+    assert_eq!(m.capacity(), cap);
+    assert_eq!(m.insert(10), 1);
+    assert_eq!(m.insert(40), 4);
+    assert_eq!(m.len(), 5);
+
+    let data = vec![Entry::Occupied(1u64), Entry::Empty(2), Entry::Empty(usize::MAX), Entry::Empty(1)];
+    let mut m = corrupted(data.clone(), 3);
+    assert_eq!(m.trailing_holes(), 3);
+    m.shrink_free_list();
+    assert_eq!(m.trailing_holes(), 0);
+    assert_eq!(m.max_occupied_key(), Some(0));
+    assert_eq!(m.free_count(), 0);
+    assert_eq!(m.validate(), Ok(()));
+
+    // from_raw_parts strips them itself
+    let mut m = CompactMap::from_raw_parts(data, 3).unwrap();
+    assert_eq!(m.trailing_holes(), 0);
+    assert_eq!(m.max_occupied_key(), Some(0));
+    assert_eq!(m.insert(2), 1);
+}
+
+#[test]
+fn max_occupied_key() {
+    let mut m: CompactMap<u64> = CompactMap::new();
+    assert_eq!(m.max_occupied_key(), None);
+    m.extend(0..6);
+    assert_eq!(m.max_occupied_key(), Some(5));
+    m.remove(3);
+    m.remove(4);
+    assert_eq!(m.max_occupied_key(), Some(5));
+    m.remove(5);
+    assert_eq!(m.max_occupied_key(), Some(2));
+    m.insert(30);
+    assert_eq!(m.max_occupied_key(), Some(3));
+    assert_eq!(m.pop_last(), Some((3, 30)));
+    assert_eq!(m.max_occupied_key(), Some(2));
+    m.pop_last();
+    assert_eq!(m.max_occupied_key(), Some(1));
+
+    let w: super::wrapped::CompactMap<Mom, u64> = (0..2).collect();
+    assert_eq!(w.max_occupied_key(), Some(Mom(1)));
+}
+
+#[test]
+fn from_fn() {
+    let mut m = CompactMap::from_fn(4, |k| k as u64 + 1);
+    assert_eq!(m.len(), 4);
+    assert_eq!(m.free_count(), 0);
+    assert_eq!(m.capacity(), 4);
+    assert_eq!(m.iter().collect::<Vec<_>>(), [(0, &1), (1, &2), (2, &3), (3, &4)]);
+    assert_eq!(m.insert(5), 4);
+    assert!(CompactMap::from_fn(0, |k| k).is_empty());
+
+    let mut calls = 0;
+    let r = CompactMap::try_from_fn(5, |k| {
+        calls += 1;
+        if k == 2 { Err(k) } else { Ok(k) }
+    });
+    assert_eq!(r.err(), Some(2));
+    assert_eq!(calls, 3);
+    let ok: Result<CompactMap<usize>, ()> = CompactMap::try_from_fn(3, Ok);
+    assert_eq!(ok.unwrap().values().sum::<usize>(), 3);
+
+    let w = super::wrapped::CompactMap::from_fn(3, |k: Mom| k.0 * 2);
+    assert_eq!(w[Mom(2)], 4);
+    let w: Result<super::wrapped::CompactMap<Mom, usize>, ()> =
+        super::wrapped::CompactMap::try_from_fn(2, |k: Mom| Ok(k.0));
+    assert_eq!(w.unwrap().len(), 2);
+}
+
+#[test]
+fn remove_trims_trailing_holes() {
+    let mut m: CompactMap<u64> = (0..5).collect();
+    m.remove(3);
+    m.remove(2);
+    m.remove(4);
+    assert_eq!(m.len() + m.free_count(), 2);
+    assert_eq!(m.trailing_holes(), 0);
+    assert_eq!(m.insert(2), 2);
+
+    let mut m: CompactMap<u64> = (0..8).collect();
+    for &k in &[1, 5, 3, 6, 2] {
+        m.remove(k);
+    }
+    assert_eq!(m.remove(7), Some(7));
+    assert_eq!(m.iter_holes().collect::<Vec<_>>(), [1, 2, 3]);
+    assert!(m.validate().is_ok());
+    assert_eq!(m.remove(4), Some(4));
+    assert_eq!(m.len() + m.free_count(), 1);
+    assert_eq!(m.remove(0), Some(0));
+    assert_eq!(m.len() + m.free_count(), 0);
+    assert_eq!(m.insert(9), 0);
+}
+
+#[test]
+fn nth() {
+    let mut m: CompactMap<u64> = (0..10).collect();
+    for &k in &[0, 3, 4, 8] {
+        m.remove(k);
+    }
+    let keys = m.keys().collect::<Vec<_>>();
+    for n in 0..keys.len() + 2 {
+        assert_eq!(m.iter().nth(n).map(|e| e.0), keys.get(n).cloned());
+        assert_eq!(m.keys().nth(n), keys.get(n).cloned());
+        assert_eq!(m.values().nth(n).map(|&v| v as usize), keys.get(n).cloned());
+        assert_eq!(m.iter_mut().nth(n).map(|e| e.0), keys.get(n).cloned());
+        assert_eq!(m.values_mut().nth(n).map(|v| *v as usize), keys.get(n).cloned());
+        assert_eq!(m.clone().into_iter().nth(n).map(|e| e.0), keys.get(n).cloned());
+        assert_eq!(m.clone().into_keys().nth(n), keys.get(n).cloned());
+        assert_eq!(m.clone().into_values().nth(n).map(|v| v as usize), keys.get(n).cloned());
+    }
+
+    let mut it = m.iter();
+    assert_eq!(it.nth(1), Some((2, &2)));
+    assert_eq!(it.next_back(), Some((9, &9)));
+    assert_eq!(it.nth(1), Some((6, &6)));
+    let zero = 0;
+    assert_eq!(it.nth(zero), Some((7, &7)));
+    assert_eq!(it.nth(zero), None);
+    assert_eq!(it.next_back(), None);
+
+    let mut it = m.iter();
+    assert_eq!(it.nth(100), None);
+    assert_eq!(it.next_back(), None);
+
+    let w: super::wrapped::CompactMap<Mom, u64> = (0..4).collect();
+    assert_eq!(w.iter().nth(2), Some((Mom(2), &2)));
+    assert_eq!(w.keys().nth(3), Some(Mom(3)));
+}
+
+#[test]
+fn last() {
+    let mut m: CompactMap<u64> = (0..6).collect();
+    m.remove(1);
+    m.truncate(5);
+    m.remove(3);
+    assert_eq!(m.iter().last(), Some((4, &4)));
+    assert_eq!(m.iter_mut().last().map(|e| e.0), Some(4));
+    assert_eq!(m.keys().last(), Some(4));
+    assert_eq!(m.values().last(), Some(&4));
+    assert_eq!(m.values_mut().last(), Some(&mut 4));
+    assert_eq!(m.clone().into_iter().last(), Some((4, 4)));
+    assert_eq!(m.clone().into_keys().last(), Some(4));
+    assert_eq!(m.clone().into_values().last(), Some(4));
+
+    let mut it = m.iter();
+    it.next_back();
+    assert_eq!(it.last(), Some((2, &2)));
+    let mut it = m.iter();
+    it.nth(2);
+    assert_eq!(it.last(), None);
+    assert_eq!(CompactMap::<u64>::new().into_iter().last(), None);
+
+    let w: super::wrapped::CompactMap<Mom, u64> = (0..3).collect();
+    assert_eq!(w.iter().last(), Some((Mom(2), &2)));
+    assert_eq!(w.into_keys().last(), Some(Mom(2)));
+}
+
+#[test]
+fn nz_keys() {
+    use std::num::NonZeroUsize;
+    use super::nz;
+
+    let mut m = nz::CompactMap::new();
+    let a = m.insert(10u64);
+    let b = m.insert(20);
+    let c = m.insert_with(|k| k.get() as u64 * 100);
+    assert_eq!((a.get(), b.get(), c.get()), (1, 2, 3));
+    assert_eq!(m[c], 300);
+    assert_eq!(m.unwrapped()[0], 10);
+
+    assert_eq!(m.remove(b), Some(20));
+    assert!(!m.contains_key(b));
+    assert_eq!(m.get(NonZeroUsize::new(100).unwrap()), None);
+    assert_eq!(m.insert(40), b);
+
+    m[a] += 1;
+    for (_, v) in &mut m {
+        *v += 1;
+    }
+    assert_eq!(m.iter().map(|(k, &v)| (k.get(), v)).collect::<Vec<_>>(), [(1, 12), (2, 41), (3, 301)]);
+    assert_eq!(m.keys().rev().map(|k| k.get()).collect::<Vec<_>>(), [3, 2, 1]);
+    assert_eq!(format!("{:?}", m), "{1: 12, 2: 41, 3: 301}");
+    assert_eq!(m.pop_last().map(|e| e.0), Some(c));
+
+    let m2: nz::CompactMap<u64> = vec![12, 41].into_iter().collect();
+    assert!(m == m2);
+    assert_eq!(m.into_iter().map(|(k, _)| k.get()).collect::<Vec<_>>(), [1, 2]);
+}
+
+#[test]
+fn nz_forwarded_api() {
+    use std::num::NonZeroUsize;
+    use super::nz;
+    use super::wrapped;
+
+    let k = |i| NonZeroUsize::new(i).unwrap();
+    let mut m: nz::CompactMap<u32> = (1..=6).collect();
+    m.retain(|key, v| key.get() % 3 != 0 && *v < 6);
+    assert_eq!(m.keys().map(|x| x.get()).collect::<Vec<_>>(), [1, 2, 4, 5]);
+    assert_eq!(m.range(k(2)..k(5)).map(|(_, &v)| v).collect::<Vec<_>>(), [2, 4]);
+    assert_eq!(m.iter_from(k(4)).count(), 2);
+    assert_eq!(*m.get_or_insert(k(3), 30), 30);
+    assert_eq!(*m.get_or_insert_with(k(3), || 0), 30);
+    assert_eq!(m.max_occupied_key(), Some(k(5)));
+    assert_eq!(m.to_string(), "{1: 1, 2: 2, 3: 30, 4: 4, 5: 5}");
+
+    let e = m.try_extend_keyed(vec![(k(7), 7), (k(2), 20)]).unwrap_err();
+    assert_eq!((e.key, e.theirs), (k(2), 20));
+    assert_eq!(m[k(7)], 7);
+
+    let copy = m.clone();
+    let ranged: Vec<_> = m.drain_range(k(4)..).map(|(x, _)| x.get()).collect();
+    assert_eq!(ranged, [4, 5, 7]);
+    let drained: Vec<_> = m.drain().map(|(x, v)| (x.get(), v)).collect();
+    assert_eq!(drained, [(1, 1), (2, 2), (3, 30)]);
+    assert!(m.is_empty());
+    assert_eq!(copy.len(), 6);
+
+    let (small, big) = copy.partition(|_, &v| v < 5);
+    assert_eq!(small.keys().map(|x| x.get()).collect::<Vec<_>>(), [1, 2, 4]);
+    let w: wrapped::CompactMap<NonZeroUsize, u32> = big.into();
+    assert_eq!(w.get(k(3)), Some(&30));
+
+    let it = nz::CompactMap::from(w).into_iter();
+    assert_eq!(it.clone().count(), 3);
+    assert_eq!(it.map(|(x, _)| x.get()).collect::<Vec<_>>(), [3, 5, 7]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn nz_serde() {
+    extern crate serde_json;
+    use super::nz;
+
+    let mut m: nz::CompactMap<u8> = vec![1, 2, 3].into_iter().collect();
+    let two = m.keys().nth(1).unwrap();
+    m.remove(two);
+    let s = serde_json::to_string(&m).unwrap();
+    assert_eq!(s, serde_json::to_string(m.unwrapped()).unwrap());
+    let back: nz::CompactMap<u8> = serde_json::from_str(&s).unwrap();
+    assert!(back == m);
+    assert_eq!(format!("{:?}", back), "{1: 1, 3: 3}");
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn rw_compactmap() {
+    use std::sync::Arc;
+    use std::thread;
+    use super::sync::RwCompactMap;
+
+    let map: Arc<RwCompactMap<u64>> = Arc::new((0..4).collect::<CompactMap<u64>>().into());
+    assert_eq!(map.write_remove(1), Some(1));
+    let threads = (0..8).map(|i| {
+        let map = map.clone();
+        thread::spawn(move || {
+            let k = map.write_insert(100 + i);
+            assert_eq!(map.read_get(k), Some(100 + i));
+            k
+        })
+    }).collect::<Vec<_>>();
+    let mut keys = threads.into_iter().map(|t| t.join().unwrap()).collect::<Vec<_>>();
+    keys.sort();
+    assert_eq!(keys, [1, 4, 5, 6, 7, 8, 9, 10]);
+    assert_eq!(map.read_len(), 11);
+
+    let removed = map.batch_write(|m| m.remove_many(vec![4, 5, 6]));
+    assert_eq!(removed, 3);
+    let snap = map.snapshot();
+    assert_eq!(snap.len(), 8);
+    assert_eq!(map.batch_read(|m| m.keys().max()), Some(10));
+
+    let map = Arc::try_unwrap(map).unwrap();
+    assert_eq!(map.into_inner(), snap);
+}
+
+#[test]
+fn stats() {
+    use super::MapStats;
+
+    let mut m: CompactMap<u64> = CompactMap::new();
+    assert_eq!(m.stats(), MapStats { occupied: 0, capacity: 0, holes: 0, free_list_len: 0 });
+    m.extend(0..8);
+    m.remove(2);
+    m.remove(5);
+    m.truncate(7);
+    let st = m.stats();
+    assert_eq!((st.occupied, st.holes, st.free_list_len), (5, 2, 2));
+    assert!(st.capacity >= 8);
+    assert_eq!(
+        format!("{:?}", m.debug_stats()),
+        format!("CompactMap {{ len: 5, capacity: {}, holes: 2, data: {:?} }}", st.capacity, m)
+    );
+
+    let (data, _) = m.into_raw_parts();
+    let broken = corrupted(data, 2);
+    assert_eq!(broken.stats().free_list_len, 1);
+
+    let w: super::wrapped::CompactMap<Mom, u64> = (0..3).collect();
+    assert_eq!(w.stats().occupied, 3);
+}
+
+#[test]
+fn retain() {
+    let mut m: CompactMap<u64> = (0..10).collect();
+    m.remove(3);
+    let mut seen = vec![];
+    m.retain(|k, v| {
+        seen.push(k);
+        *v += 100;
+        *v % 3 != 0
+    });
+    assert_eq!(seen, [0, 1, 2, 4, 5, 6, 7, 8, 9]);
+    assert_eq!(m.iter().collect::<Vec<_>>(), [(0, &100), (1, &101), (4, &104), (6, &106), (7, &107), (9, &109)]);
+    assert_eq!(m.trailing_holes(), 0);
+    assert!(m.validate().is_ok());
+
+    m.retain_mut(|_, v| {
+        *v -= 100;
+        true
+    });
+    assert_eq!(m.values().cloned().collect::<Vec<_>>(), [0, 1, 4, 6, 7, 9]);
+    m.retain(|k, _| k < 9);
+    assert_eq!(m.trailing_holes(), 0);
+    m.retain(|_, _| false);
+    assert!(m.is_empty());
+    assert_eq!(m.insert(1), 0);
+
+    let mut w: super::wrapped::CompactMap<Mom, u64> = (0..4).collect();
+    w.retain_mut(|k, v| {
+        *v *= 2;
+        k != Mom(1)
+    });
+    assert_eq!(w.iter().map(|(k, &v)| (k, v)).collect::<Vec<_>>(), [(Mom(0), 0), (Mom(2), 4), (Mom(3), 6)]);
+}
+
+#[test]
+fn fold() {
+    let mut m: CompactMap<u64> = (0..10).collect();
+    for &k in &[0, 4, 5, 9] {
+        m.remove(k);
+    }
+    let keys = |m: &CompactMap<u64>| m.keys().fold(vec![], |mut acc, k| { acc.push(k); acc });
+    assert_eq!(keys(&m), [1, 2, 3, 6, 7, 8]);
+    assert_eq!(m.iter().map(|(_, v)| *v).sum::<u64>(), 27);
+    assert_eq!(m.values().sum::<u64>(), 27);
+
+    let mut it = m.iter();
+    it.next();
+    it.next_back();
+    assert_eq!(it.fold(vec![], |mut acc, (k, &v)| { acc.push((k, v)); acc }), [(2, 2), (3, 3), (6, 6), (7, 7)]);
+
+    m.iter_mut().for_each(|(k, v)| *v += k as u64);
+    m.values_mut().for_each(|v| *v += 1);
+    assert_eq!(m.clone().into_values().fold(0, |a, v| a.max(v)), 17);
+    assert_eq!(m.clone().into_keys().fold(0, |a, k| a * 10 + k), 123678);
+    let mut it = m.into_iter();
+    it.next();
+    assert_eq!(it.fold(vec![], |mut acc, (k, _)| { acc.push(k); acc }), [2, 3, 6, 7, 8]);
+
+    let w: super::wrapped::CompactMap<Mom, u64> = (0..4).collect();
+    assert_eq!(w.iter().fold(0, |a, (k, &v)| a + k.0 as u64 + v), 12);
+    assert_eq!(w.keys().fold(vec![], |mut acc, k| { acc.push(k); acc }), [Mom(0), Mom(1), Mom(2), Mom(3)]);
+}
+
+#[test]
+fn iter_peek() {
+    let mut m: CompactMap<u64> = (0..6).collect();
+    m.remove(1);
+    m.remove(2);
+    m.remove(5);
+    let mut it = m.iter();
+    let mut seen = vec![];
+    while let Some(k) = it.peek_key() {
+        assert_eq!(it.peek(), it.clone().next());
+        assert_eq!(it.next().map(|e| e.0), Some(k));
+        seen.push(k);
+    }
+    assert_eq!(seen, [0, 3, 4]);
+    assert_eq!(it.peek(), None);
+
+    let mut it = m.iter();
+    it.next_back();
+    it.next_back();
+    assert_eq!(it.peek_key(), Some(0));
+    it.next();
+    assert_eq!(it.peek_key(), None);
+
+    let w: super::wrapped::CompactMap<Mom, u64> = (0..2).collect();
+    let mut it = w.iter();
+    it.next();
+    assert_eq!(it.peek(), Some((Mom(1), &1)));
+    assert_eq!(it.peek_key(), Some(Mom(1)));
+}
+
+#[test]
+fn iter_searches() {
+    let mut m: CompactMap<u64> = (0..10).collect();
+    for &k in &[0, 3, 4, 8] {
+        m.remove(k);
+    }
+
+    let mut it = m.iter();
+    assert_eq!(it.find(|e| *e.1 > 4), Some((5, &5)));
+    assert_eq!(it.next(), Some((6, &6)));
+    assert_eq!(it.position(|e| e.0 == 9), Some(1));
+    assert_eq!(it.next(), None);
+
+    let mut it = m.iter();
+    assert!(it.any(|(k, _)| k == 2));
+    assert_eq!(it.next(), Some((5, &5)));
+    assert!(it.all(|(_, &v)| v > 4));
+    assert!(!m.iter().any(|(k, _)| k == 3));
+    assert!(!m.iter().all(|(k, _)| k < 9));
+    assert_eq!(m.iter().position(|(k, _)| k == 3), None);
+
+    let mut it = m.iter_mut();
+    if let Some((_, v)) = it.find(|e| e.0 == 6) {
+        *v = 60;
+    }
+    assert!(it.all(|(k, _)| k > 6));
+    assert_eq!(m[6], 60);
+
+    let mut it = m.clone().into_iter();
+    assert_eq!(it.position(|(_, v)| v == 60), Some(3));
+    assert_eq!(it.find(|e| e.1 % 2 == 1), Some((7, 7)));
+    assert!(it.any(|(k, _)| k == 9));
+    assert!(it.all(|_| false));
+
+    let mut it = m.iter();
+    it.next_back();
+    assert_eq!(it.position(|(k, _)| k == 9), None);
+}
+
+#[test]
+fn move_entry() {
+    let mut m: CompactMap<&str> = vec!["a", "b", "c", "d"].into_iter().collect();
+    m.remove(1);
+    let handle = 0;
+
+    m.move_entry(handle, 1).unwrap();
+    assert_eq!(m.get(handle), None);
+    assert_eq!(m[1], "a");
+    assert_eq!(m.validate(), Ok(()));
+
+    m.move_entry(3, 3).unwrap();
+    assert_eq!(m.move_entry(0, 3), Err(::MoveError::VacantSource { key: 0 }));
+    assert_eq!(m.move_entry(1, 2), Err(::MoveError::OccupiedTarget { key: 2 }));
+    assert_eq!(m.keys().collect::<Vec<_>>(), [1, 2, 3]);
+
+    m.relocate_entry(3, 6);
+    assert_eq!(m.get(3), None);
+    assert_eq!(m[6], "d");
+    assert_eq!(m.len(), 3);
+    assert_eq!(m.validate(), Ok(()));
+    assert_eq!(m.insert("e"), 5);
+
+    let mut w: super::wrapped::CompactMap<Mom, &str> = vec!["x"].into_iter().collect();
+    assert_eq!(w.move_entry(Mom(1), Mom(0)), Err(::MoveError::VacantSource { key: Mom(1) }));
+    w.relocate_entry(Mom(0), Mom(2));
+    assert_eq!(w.get(Mom(2)), Some(&"x"));
+    assert_eq!(w.get(Mom(0)), None);
+}
+
+#[test]
+#[should_panic(expected = "already occupied")]
+fn relocate_entry_occupied() {
+    let mut m: CompactMap<u8> = (0..2).collect();
+    m.relocate_entry(0, 1);
+}
+
+#[test]
+fn compact_in_place() {
+    use std::collections::HashMap;
+
+    let mut m: CompactMap<u32> = (0..6).collect();
+    m.remove(1);
+    m.remove(2);
+    m.remove(4);
+    let cap = m.capacity();
+    assert_eq!(m.compact(), [Some(0), None, None, Some(1), None, Some(2)]);
+    assert_eq!(m.iter().map(|(k, &v)| (k, v)).collect::<Vec<_>>(), [(0, 0), (1, 3), (2, 5)]);
+    assert_eq!(m.capacity(), cap);
+    assert_eq!(m.validate(), Ok(()));
+    assert_eq!(m.insert(6), 3);
+
+    let mut e: CompactMap<u32> = CompactMap::new();
+    assert!(e.compact().is_empty());
+
+    // nodes refer to each other by key, labels live outside the map
+    let mut nodes: super::wrapped::CompactMap<Mom, Option<Mom>> = super::wrapped::CompactMap::new();
+    let mut labels: HashMap<Mom, String> = HashMap::new();
+    let mut prev = None;
+    for i in 0..5 {
+        let k = nodes.insert(prev);
+        labels.insert(k, format!("node{}", i));
+        prev = Some(k);
+    }
+    for &k in &[Mom(0), Mom(2)] {
+        nodes.remove(k);
+        labels.remove(&k);
+    }
+    for (_, link) in nodes.iter_mut() {
+        *link = link.filter(|l| labels.contains_key(l));
+    }
+
+    let remap = nodes.compact();
+    assert_eq!(remap, [None, Some(Mom(0)), None, Some(Mom(1)), Some(Mom(2))]);
+    labels = labels.into_iter().map(|(k, s)| (remap[usize::from(k)].unwrap(), s)).collect();
+    for (_, link) in nodes.iter_mut() {
+        *link = link.and_then(|l| remap[usize::from(l)]);
+    }
+
+    assert_eq!(nodes.len(), labels.len());
+    assert_eq!(labels[&Mom(2)], "node4");
+    assert_eq!(nodes[Mom(2)], Some(Mom(1)));
+    assert_eq!(labels[&nodes[Mom(2)].unwrap()], "node3");
+    assert_eq!(nodes[Mom(1)], None);
+    assert_eq!(labels[&Mom(0)], "node1");
+}
+
+#[test]
+fn display_format() {
+    use std::fmt;
+
+    let mut m: CompactMap<String> = vec!["hello", "big", "wide", "world"]
+        .into_iter().map(String::from).collect();
+    m.remove(1);
+    m.remove(2);
+    assert_eq!(m.to_string(), "{0: hello, 3: world}");
+    assert_eq!(format!("{}", m.display()), "{0: hello, 3: world}");
+    assert_eq!(format!("{:#}", m), "{\n    0: hello,\n    3: world,\n}");
+    assert_eq!(format!("{:?}", m), r#"{0: "hello", 3: "world"}"#);
+
+    m.remove(0);
+    assert_eq!(m.to_string(), "{3: world}");
+    m.clear();
+    assert_eq!(m.to_string(), "{}");
+    assert_eq!(format!("{:#}", m.display()), "{}");
+
+    declare_compactmap_token!(Node);
+    impl fmt::Display for Node {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "n{}", self.0)
+        }
+    }
+    let mut w: super::wrapped::CompactMap<Node, f32> = vec![0.5, 1.0, 1.5].into_iter().collect();
+    w.remove(Node(1));
+    assert_eq!(w.to_string(), "{n0: 0.5, n2: 1.5}");
+    assert_eq!(format!("{:#}", w), "{\n    n0: 0.5,\n    n2: 1.5,\n}");
+}
+
+#[test]
+fn fingerprint() {
+    use std::collections::hash_map::RandomState;
+
+    let mut a: CompactMap<u32> = (0..5).collect();
+    a.remove(1);
+    a.remove(3);
+    let mut b = CompactMap::new();
+    for &(k, v) in &[(4, 4), (0, 0), (2, 2)] {
+        b.get_or_insert(k, v);
+    }
+    assert_eq!(a, b);
+    assert_eq!(a.fingerprint(), b.fingerprint());
+    assert_eq!(a.fingerprint(), a.clone().fingerprint());
+
+    b[2] = 3;
+    assert_ne!(a.fingerprint(), b.fingerprint());
+    b[2] = 2;
+    a.relocate_entry(4, 3);
+    assert_ne!(a.fingerprint(), b.fingerprint());
+
+    assert_eq!(CompactMap::<u32>::new().fingerprint(), 0);
+    let s = RandomState::new();
+    assert_eq!(b.fingerprint_with(&s), b.clone().fingerprint_with(&s));
+
+    let w: super::wrapped::CompactMap<Mom, u32> = super::wrapped::CompactMap::from_unwrapped(b);
+    let mut w2: super::wrapped::CompactMap<Mom, u32> = super::wrapped::CompactMap::new();
+    w2.extend_keyed(vec![(Mom(2), 2), (Mom(4), 4), (Mom(0), 0)]);
+    assert_eq!(w.fingerprint(), w2.fingerprint());
+}
+
+#[test]
+fn clone_with_filter() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let mut m: CompactMap<Rc<Cell<u32>>> = (0..6).map(|i| Rc::new(Cell::new(i))).collect();
+    m.remove(2);
+    let all = m.clone_with_filter(|_, _| true);
+    assert_eq!(all, m.clone());
+    assert_eq!(all.validate(), Ok(()));
+
+    let mut some = m.clone_with_filter(|k, v| k < 4 && v.get() != 1);
+    assert_eq!(some.keys().collect::<Vec<_>>(), [0, 3]);
+    assert_eq!(some.validate(), Ok(()));
+    // only kept values were cloned
+    assert_eq!(Rc::strong_count(&m[1]), 2);
+    assert_eq!(Rc::strong_count(&m[3]), 3);
+    let mut keys = vec![some.insert(Rc::new(Cell::new(9))), some.insert(Rc::new(Cell::new(9)))];
+    keys.sort();
+    assert_eq!(keys, [1, 2]);
+
+    let w: super::wrapped::CompactMap<Mom, u32> = (0..4).collect();
+    let odd = w.clone_with_filter(|k, _| k.0 % 2 == 1);
+    assert_eq!(odd.keys().collect::<Vec<_>>(), [Mom(1), Mom(3)]);
+}
+
+#[test]
+fn take_all() {
+    let mut m: CompactMap<u32> = (0..8).map(|x| x * 10).collect();
+    for &k in &[0, 3, 6] {
+        m.remove(k);
+    }
+    let len = m.len();
+    let cap = m.capacity();
+    let values = m.take_all();
+    assert_eq!(values.len(), len);
+    assert_eq!(values, [10, 20, 40, 50, 70]);
+    assert!(m.is_empty());
+    assert_eq!(m.capacity(), cap);
+    assert_eq!(m.validate(), Ok(()));
+    assert_eq!(m.insert(1), 0);
+    assert!(CompactMap::<u32>::new().take_all().is_empty());
+
+    let mut w: super::wrapped::CompactMap<Mom, u32> = (0..3).collect();
+    w.remove(Mom(1));
+    assert_eq!(w.take_all(), [0, 2]);
+    assert!(w.is_empty());
+}
+
+#[test]
+fn vec_of_options() {
+    let mut m: CompactMap<char> = "abcdef".chars().collect();
+    m.remove(1);
+    m.remove(4);
+    let v: Vec<Option<char>> = m.clone().into();
+    assert_eq!(v, [Some('a'), None, Some('c'), Some('d'), None, Some('f')]);
+    let back = CompactMap::from(v.clone());
+    assert_eq!(back, m);
+    assert_eq!(back.validate(), Ok(()));
+    assert_eq!(Vec::from(back), v);
+
+    let v = vec![None, Some(1), None, None];
+    let mut m = CompactMap::from(v.clone());
+    assert_eq!(m.len(), 1);
+    assert_eq!(m.validate(), Ok(()));
+    assert_eq!(m.max_occupied_key(), Some(1));
+    assert_eq!(Vec::from(m.clone()), &v[..2]);
+    m.insert(2);
+    m.insert(3);
+    m.insert(4);
+    assert_eq!(Vec::from(m), [Some(2), Some(1), Some(3), Some(4)]);
+
+    assert!(CompactMap::<u8>::from(Vec::new()).is_empty());
+    assert!(Vec::from(CompactMap::<u8>::new()).is_empty());
+}
+
+#[test]
+fn free_list_vec() {
+    let mut m: CompactMap<u64> = (0..8).collect();
+    assert!(m.free_list_vec().is_empty());
+    for &k in &[4, 1, 6] {
+        m.remove(k);
+    }
+    assert_eq!(m.free_list_vec(), [6, 1, 4]);
+    assert_eq!(m.free_list_len(), 3);
+    assert_eq!(m.free_list_len() + m.iter().count(), 8);
+    m.insert(10);
+    assert_eq!(m.free_list_vec(), [1, 4]);
+
+    // a cycle through slots 1 and 4
+    let (mut data, head) = m.into_raw_parts();
+    data[4] = super::Entry::Empty(1);
+    let broken = corrupted(data, head);
+    assert_eq!(broken.free_list_len(), 8);
+
+    let mut w: super::wrapped::CompactMap<Mom, u64> = (0..3).collect();
+    w.remove(Mom(0));
+    assert_eq!(w.free_list_vec(), [Mom(0)]);
+    assert_eq!(w.free_list_len(), 1);
+}
+
+#[test]
+fn bulk_insert() {
+    let mut m: CompactMap<u32> = (0..6).collect();
+    for &k in &[1, 4, 2] {
+        m.remove(k);
+    }
+    let mut seq = m.clone();
+    let expected = (10..15).map(|v| seq.insert(v)).collect::<Vec<_>>();
+    assert_eq!(m.bulk_insert(10..15), expected);
+    assert_eq!(m, seq);
+    assert_eq!(m.validate(), Ok(()));
+    assert_eq!(m.bulk_insert(vec![20, 21]), [8, 9]);
+    assert!(m.bulk_insert(None).is_empty());
+    assert_eq!(m.len(), 10);
+
+    let mut m: CompactMap<u32> = (0..3).collect();
+    m.remove(0);
+    assert_eq!(m.bulk_insert_array([7, 8]), [0, 3]);
+    assert!(m.bulk_insert_array::<0>([]).is_empty());
+    assert_eq!(m.validate(), Ok(()));
+
+    let mut w: super::wrapped::CompactMap<Mom, u32> = super::wrapped::CompactMap::new();
+    assert_eq!(w.bulk_insert(vec![1, 2]), [Mom(0), Mom(1)]);
+    assert_eq!(w.bulk_insert_array([3]), [Mom(2)]);
+}
+
+#[test]
+fn bulk_insert_panicking_iterator() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    let mut m: CompactMap<u32> = CompactMap::new();
+    let r = catch_unwind(AssertUnwindSafe(|| {
+        m.bulk_insert((0..5).map(|x| if x == 3 { panic!("boom") } else { x }));
+    }));
+    assert!(r.is_err());
+    assert_eq!(m.len(), 3);
+    assert_eq!(m.validate(), Ok(()));
+}
+
+#[test]
+fn group_by() {
+    let mut m: CompactMap<&str> = vec!["apple", "banana", "cherry", "avocado", "blueberry", "apricot"]
+        .into_iter().collect();
+    m.remove(2);
+    let groups = m.group_by(|s| s.as_bytes()[0]);
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[&b'a'], [0, 3, 5]);
+    assert_eq!(groups[&b'b'], [1, 4]);
+    assert_eq!(groups.values().map(Vec::len).sum::<usize>(), m.len());
+
+    let by_len = m.group_by_values(|s| s.len());
+    assert_eq!(by_len[&7], [&"avocado", &"apricot"]);
+    assert_eq!(by_len.values().map(Vec::len).sum::<usize>(), m.len());
+    assert!(CompactMap::<u8>::new().group_by(|&x| x).is_empty());
+
+    let w: super::wrapped::CompactMap<Mom, u32> = (0..5).collect();
+    let parity = w.group_by(|x| x % 2);
+    assert_eq!(parity[&1], [Mom(1), Mom(3)]);
+    assert_eq!(w.group_by_values(|x| x % 2)[&0], [&0, &2, &4]);
+}
+
+#[test]
+fn diff() {
+    let mut a: CompactMap<u32> = (0..8).collect();
+    a.remove(2);
+    a.remove(5);
+    let mut b = a.clone();
+    b.remove(0);
+    b.remove(7);
+    b[3] = 30;
+    b.get_or_insert(5, 50);
+    b.get_or_insert(10, 100);
+
+    let d = a.diff(&b);
+    assert_eq!(d.added().collect::<Vec<_>>(), [(5, &50), (10, &100)]);
+    assert_eq!(d.removed().collect::<Vec<_>>(), [0, 7]);
+    assert_eq!(d.changed().collect::<Vec<_>>(), [(3, &30)]);
+
+    let mut replica = a.clone();
+    replica.apply_diff(d);
+    assert_eq!(replica, b);
+    assert_eq!(replica.validate(), Ok(()));
+    assert_eq!(replica.free_list_len(), b.free_count());
+
+    // and back
+    let mut replica = b.clone();
+    replica.apply_diff(b.diff(&a));
+    assert_eq!(replica, a);
+    assert_eq!(replica.trailing_holes(), 0);
+    assert_eq!(replica.validate(), Ok(()));
+
+    let same = a.diff(&a);
+    assert_eq!(same.added().count() + same.removed().count() + same.changed().count(), 0);
+}
+
+#[test]
+fn freeze_thaw() {
+    fn assert_sync<T: Sync>(_: &T) {}
+
+    let mut m: CompactMap<String> = vec!["hello", "big", "world"].into_iter().map(String::from).collect();
+    m.remove(1);
+    let ptr = m.get(2).unwrap() as *const String;
+    let frozen = m.clone().freeze();
+    assert_sync(&frozen);
+    assert_eq!(frozen.len(), 2);
+    assert!(!frozen.is_empty());
+    assert!(frozen.contains_key(0));
+    assert!(!frozen.contains_key(1));
+    assert_eq!(frozen[2], "world");
+    assert_eq!(frozen.keys().collect::<Vec<_>>(), [0, 2]);
+    assert_eq!(frozen.values().cloned().collect::<Vec<_>>(), ["hello", "world"]);
+    assert_eq!((&frozen).into_iter().count(), 2);
+    assert_eq!(frozen.to_string(), "{0: hello, 2: world}");
+    assert_eq!(format!("{:?}", frozen), format!("{:?}", m));
+    assert_eq!(frozen.as_map(), &m);
+
+    let frozen = m.freeze();
+    let mut m = frozen.thaw();
+    assert_eq!(m.get(2).unwrap() as *const String, ptr);
+    assert_eq!(m.insert("again".to_string()), 1);
+}
+
+#[cfg(feature = "schemars")]
+#[test]
+fn json_schema() {
+    extern crate schemars;
+    extern crate serde_json;
+
+    let schema = serde_json::to_value(schemars::schema_for!(CompactMap<String>)).unwrap();
+    assert_eq!(schema, serde_json::json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "CompactMap_of_String",
+        "description": "Keys are non-negative integers assigned by the map",
+        "type": "object",
+        "additionalProperties": { "type": "string" },
+        "propertyNames": { "pattern": "^(0|[1-9][0-9]*)$" },
+    }));
+
+    let wrapped = schemars::schema_for!(super::wrapped::CompactMap<Mom, String>);
+    assert_eq!(serde_json::to_value(wrapped).unwrap(), schema);
+
+    #[derive(schemars::JsonSchema)]
+    #[allow(dead_code)]
+    struct Point { x: i32 }
+    let schema = serde_json::to_value(schemars::schema_for!(CompactMap<Point>)).unwrap();
+    assert_eq!(schema["additionalProperties"], serde_json::json!({ "$ref": "#/definitions/Point" }));
+    assert_eq!(schema["definitions"]["Point"]["required"], serde_json::json!(["x"]));
+}
+
+#[test]
+fn compact_map_key() {
+    use super::wrapped::CompactMapKey;
+
+    assert_eq!(<Mom as CompactMapKey>::MAX, usize::MAX);
+    assert_eq!(Mom::from_index(3), Mom(3));
+    assert_eq!(Mom(4).into_index(), 4);
+
+    let mut m: super::wrapped::CompactMap<u32, &str> = super::wrapped::CompactMap::new();
+    let a: u32 = m.insert("a");
+    m.insert("b");
+    m.remove(a);
+    assert_eq!(m.insert("c"), 0u32);
+    assert_eq!(m.keys().collect::<Vec<u32>>(), [0, 1]);
+    assert_eq!(<u32 as CompactMapKey>::MAX, u32::MAX as usize);
+
+    let mut m: super::wrapped::CompactMap<usize, &str> = super::wrapped::CompactMap::new();
+    assert_eq!(m.insert("a"), 0usize);
+}
+
+#[cfg(target_pointer_width = "64")]
+#[test]
+#[should_panic(expected = "does not fit into u32")]
+fn compact_map_key_u32_overflow() {
+    use super::wrapped::CompactMapKey;
+
+    u32::from_index(u32::MAX as usize + 1);
+}
+
+#[test]
+fn swap_remove() {
+    use super::Relocation;
+
+    // particles referring to each other by key
+    let mut m: CompactMap<(char, Option<usize>)> = CompactMap::new();
+    let a = m.insert(('a', None));
+    let b = m.insert(('b', Some(a)));
+    let c = m.insert(('c', Some(b)));
+    let d = m.insert(('d', Some(b)));
+
+    let (removed, reloc) = m.swap_remove(a).unwrap();
+    assert_eq!(removed, ('a', None));
+    assert_eq!(reloc, Some(Relocation { old_key: d, new_key: a }));
+    assert_eq!(m.get(d), None);
+    assert_eq!(m[a], ('d', Some(b)));
+    assert!(m.is_dense());
+    assert_eq!(m.validate(), Ok(()));
+
+    assert_eq!(m.swap_remove(c), Some((('c', Some(b)), None)));
+    assert_eq!(m.swap_remove(c), None);
+    assert_eq!(m.keys().collect::<Vec<_>>(), [0, 1]);
+
+    // holes below the removed key stay where they are
+    let mut m: CompactMap<u32> = (0..5).collect();
+    m.remove(1);
+    assert_eq!(m.swap_remove(2), Some((2, Some(Relocation { old_key: 4, new_key: 2 }))));
+    assert_eq!(m.iter().map(|(k, &v)| (k, v)).collect::<Vec<_>>(), [(0, 0), (2, 4), (3, 3)]);
+    assert_eq!(m.validate(), Ok(()));
+    assert_eq!(m.insert(9), 1);
+    assert_eq!(m.insert(9), 4);
+
+    let mut w: super::wrapped::CompactMap<Mom, u32> = (0..3).collect();
+    assert_eq!(w.swap_remove(Mom(1)), Some((1, Some(Relocation { old_key: Mom(2), new_key: Mom(1) }))));
+    assert_eq!(w[Mom(1)], 2);
+}
+
+#[cfg(feature = "bincode2")]
+#[test]
+fn bincode2() {
+    extern crate bincode2;
+    use std::collections::BTreeMap;
+
+    let mut m: CompactMap<String> = vec!["a", "b", "c", "d"].into_iter().map(String::from).collect();
+    m.remove(1);
+    m.remove(0);
+    let bytes = bincode2::encode_to_vec(&m, bincode2::config::standard()).unwrap();
+    let tree: BTreeMap<usize, String> = m.clone().into();
+    assert_eq!(bytes, bincode2::encode_to_vec(&tree, bincode2::config::standard()).unwrap());
+
+    let (back, read): (CompactMap<String>, _) = bincode2::decode_from_slice(&bytes, bincode2::config::standard()).unwrap();
+    assert_eq!(read, bytes.len());
+    assert_eq!(back, m);
+    assert_eq!(back.validate(), Ok(()));
+    let (borrowed, _): (CompactMap<&str>, _) = bincode2::borrow_decode_from_slice(&bytes, bincode2::config::standard()).unwrap();
+    assert_eq!(borrowed.iter().collect::<Vec<_>>(), [(2, &"c"), (3, &"d")]);
+
+    // a large key would allocate a lot of vacant slots
+    let mut huge = BTreeMap::new();
+    huge.insert(1usize << 30, 1u8);
+    let bytes = bincode2::encode_to_vec(&huge, bincode2::config::standard()).unwrap();
+    let limited = bincode2::config::standard().with_limit::<1000>();
+    assert!(bincode2::decode_from_slice::<CompactMap<u8>, _>(&bytes, limited).is_err());
+
+    let w: super::wrapped::CompactMap<Mom, String> = super::wrapped::CompactMap::from_unwrapped(m);
+    let bytes = bincode2::encode_to_vec(&w, bincode2::config::standard()).unwrap();
+    let (back, _): (super::wrapped::CompactMap<Mom, String>, _) =
+        bincode2::decode_from_slice(&bytes, bincode2::config::standard()).unwrap();
+    assert_eq!(back[Mom(3)], "d");
+}
+
+#[test]
+fn drain_owned() {
+    let mut m: CompactMap<String> = (0..5).map(|i| i.to_string()).collect();
+    m.remove(1);
+    m.remove(3);
+    let mut it = m.drain_owned();
+    assert!(m.is_empty());
+    assert_eq!(m.validate(), Ok(()));
+    assert_eq!(m.insert("x".to_string()), 0);
+    drop(m);
+
+    assert_eq!(it.next_back(), Some((4, "4".to_string())));
+    assert_eq!(it.collect::<Vec<_>>(), [(0, "0".to_string()), (2, "2".to_string())]);
+
+    // usable across a point where the map is borrowed mutably again
+    let mut m: CompactMap<u32> = (0..3).collect();
+    let drained = m.drain_owned();
+    for (_, v) in drained {
+        m.insert(v * 10);
+    }
+    assert_eq!(m.values().cloned().collect::<Vec<_>>(), [0, 10, 20]);
+
+    let mut w: super::wrapped::CompactMap<Mom, u32> = (0..3).collect();
+    assert_eq!(w.drain_owned().map(|(k, _)| k).collect::<Vec<_>>(), [Mom(0), Mom(1), Mom(2)]);
+    assert!(w.is_empty());
+}
+
+#[test]
+fn try_insert() {
+    let mut m: CompactMap<u32> = CompactMap::with_capacity(8);
+    let n = m.capacity();
+    assert!(n >= 8);
+    for i in 0..n {
+        assert_eq!(m.try_insert(i as u32), Ok(i));
+    }
+    assert_eq!(m.try_insert(100), Err(100));
+    assert_eq!(m.len(), n);
+    assert_eq!(m.capacity(), n);
+
+    m.remove(3);
+    m.remove(5);
+    assert_eq!(m.try_insert(101), Ok(5));
+    assert_eq!(m.try_insert(102), Ok(3));
+    assert_eq!(m.try_insert(103), Err(103));
+    assert_eq!(m.validate(), Ok(()));
+
+    let mut m: CompactMap<u32> = CompactMap::new();
+    assert_eq!(m.try_insert(1), Err(1));
+
+    let mut w: super::wrapped::CompactMap<Mom, u32> = super::wrapped::CompactMap::with_capacity(1);
+    assert_eq!(w.try_insert(1), Ok(Mom(0)));
+}
+
+#[cfg(feature = "rand")]
+#[test]
+fn sample_entry() {
+    extern crate rand;
+
+    let mut rng = <rand::rngs::StdRng as rand::SeedableRng>::seed_from_u64(7);
+    let mut m: CompactMap<u32> = (0..10).collect();
+    for k in 1..9 {
+        m.remove(k);
+    }
+    let mut hits = [0; 10];
+    for _ in 0..1000 {
+        let (k, &v) = m.sample_entry(&mut rng).unwrap();
+        assert_eq!(k as u32, v);
+        hits[k] += 1;
+    }
+    assert_eq!(hits[0] + hits[9], 1000);
+    assert!(hits[0] > 400 && hits[9] > 400);
+
+    m.clear();
+    assert_eq!(m.sample_entry(&mut rng), None);
+    assert_eq!(m.sample_key(&mut rng), None);
+
+    let w: super::wrapped::CompactMap<Mom, u32> = (0..3).collect();
+    assert!(w.sample_key(&mut rng).unwrap().0 < 3);
+}
+
+#[test]
+fn try_allocate_with() {
+    let mut m: CompactMap<(usize, u32)> = (0..4).map(|i| (i, i as u32)).collect();
+    m.remove(1);
+    let before = m.clone();
+
+    assert_eq!(m.try_allocate_with(|_| Err::<(usize, u32), _>("fail")), Err("fail"));
+    assert_eq!(m, before);
+    assert_eq!(m.free_list_vec(), [1]);
+
+    let k = m.try_allocate_with(|k| Ok::<_, ()>((k, 10))).unwrap();
+    assert_eq!((k, m[k]), (1, (1, 10)));
+    let k = m.allocate_with(|k| (k, 11));
+    assert_eq!((k, m[k]), (4, (4, 11)));
+
+    let mut w: super::wrapped::CompactMap<Mom, Mom> = super::wrapped::CompactMap::new();
+    assert_eq!(w.try_allocate_with(|_| Err::<Mom, _>(())), Err(()));
+    assert!(w.is_empty());
+    let k = w.try_allocate_with(Ok::<Mom, ()>).unwrap();
+    assert_eq!(w[k], k);
+}
+
+mod marker_traits {
+    extern crate static_assertions;
+
+    use std::rc::Rc;
+    use super::Mom;
+    use super::super::{wrapped, CompactMap, ImmutableCompactMap};
+
+    static_assertions::assert_impl_all!(CompactMap<u32>: Send, Sync);
+    static_assertions::assert_not_impl_any!(CompactMap<Rc<u32>>: Send, Sync);
+    static_assertions::assert_impl_all!(wrapped::CompactMap<Mom, u32>: Send, Sync);
+    static_assertions::assert_not_impl_any!(wrapped::CompactMap<Mom, Rc<u32>>: Send, Sync);
+    static_assertions::assert_impl_all!(ImmutableCompactMap<u32>: Send, Sync);
+}
+
+#[test]
+fn get_or_insert_with_key() {
+    let mut m: CompactMap<(usize, u32)> = (0..3).map(|i| (i, 0)).collect();
+    m.remove(1);
+    let mut calls = vec![];
+    for &k in &[0, 1, 2, 1, 5, 5] {
+        m.get_or_insert_with_key(k, |k| { calls.push(k); (k, 0) }).1 += 1;
+    }
+    assert_eq!(calls, [1, 5]);
+    assert_eq!(m.iter().map(|(k, &v)| (k, v)).collect::<Vec<_>>(),
+               [(0, (0, 1)), (1, (1, 2)), (2, (2, 1)), (5, (5, 2))]);
+
+    let mut w: super::wrapped::CompactMap<Mom, Mom> = super::wrapped::CompactMap::new();
+    assert_eq!(*w.get_or_insert_with_key(Mom(3), |k| k), Mom(3));
+    assert_eq!(*w.get_or_insert_with_key(Mom(3), |_| panic!("occupied")), Mom(3));
+}
+
+#[test]
+fn retain_and_count() {
+    let mut m: CompactMap<u32> = (0..10).collect();
+    m.remove(4);
+    assert_eq!(m.retain_and_count(|k, _| k % 3 != 0), 4);
+    assert_eq!(m.keys().collect::<Vec<_>>(), [1, 2, 5, 7, 8]);
+    assert_eq!(m.retain_and_count(|_, _| true), 0);
+    assert_eq!(m.retain_and_count(|_, _| false), 5);
+    assert!(m.is_empty());
+    assert_eq!(m.retain_and_count(|_, _| false), 0);
+
+    let mut w: super::wrapped::CompactMap<Mom, u32> = (0..4).collect();
+    assert_eq!(w.retain_and_count(|k, _| k != Mom(2)), 1);
+    assert_eq!(w.len(), 3);
+}
+
+#[test]
+fn raw_iter_skip_holes() {
+    use super::{skip_holes, skip_holes_mut};
+
+    let mut m: CompactMap<u32> = (0..6).collect();
+    m.remove(0);
+    m.remove(3);
+    assert_eq!(m.raw_iter().len(), 6);
+    assert_eq!(skip_holes(m.raw_iter()).collect::<Vec<_>>(), m.iter().collect::<Vec<_>>());
+    assert_eq!(skip_holes(m.raw_iter()).map(|(_, &v)| v).sum::<u32>(), 12);
+
+    for (k, v) in skip_holes_mut(m.raw_iter_mut()) {
+        *v += k as u32;
+    }
+    assert_eq!(m.values().cloned().collect::<Vec<_>>(), [2, 4, 8, 10]);
+    assert_eq!(m.validate(), Ok(()));
+
+    let empty: CompactMap<u32> = CompactMap::new();
+    assert_eq!(skip_holes(empty.raw_iter()).count(), 0);
+
+    let w: super::wrapped::CompactMap<Mom, u32> = (0..3).collect();
+    assert_eq!(skip_holes(w.raw_iter()).count(), 3);
+}
+
+#[test]
+fn get_multiple() {
+    let mut m: CompactMap<u32> = (10..14).collect();
+    m.remove(2);
+    assert_eq!(m.get_multiple([3, 2, 0, 3, 99]), [Some(&13), None, Some(&10), Some(&13), None]);
+    assert_eq!(m.get_multiple([]), [None::<&u32>; 0]);
+
+    let w: super::wrapped::CompactMap<Mom, u32> = (0..2).collect();
+    assert_eq!(w.get_multiple([Mom(1), Mom(2)]), [Some(&1), None]);
+}
+
+#[test]
+fn fill_gaps_with() {
+    let mut m: CompactMap<u32> = CompactMap::new();
+    for i in 0..5 {
+        m.insert(i * 100);
+    }
+    m.remove(1);
+    m.remove(3);
+    let mut seen = vec![];
+    m.fill_gaps_with(|i| { seen.push(i); i as u32 });
+    assert_eq!(seen, [1, 3]);
+    assert!(m.is_dense());
+    assert_eq!(m.len(), 5);
+    assert_eq!(m.values().cloned().collect::<Vec<_>>(), [0, 1, 200, 3, 400]);
+    assert_eq!(m.insert(5), 5);
+
+    m.remove(5);
+    m.fill_gaps_with(|_| panic!("no gaps"));
+
+    let mut w: super::wrapped::CompactMap<Mom, Mom> = (0..3).map(Mom).collect();
+    w.remove(Mom(0));
+    w.fill_gaps_with(|k| k);
+    assert_eq!(w[Mom(0)], Mom(0));
+    assert!(w.is_dense());
+}
+
+#[test]
+fn fill_gaps_with_panicking() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    let mut m: CompactMap<usize> = (0..6).collect();
+    for &k in &[1, 2, 4] {
+        m.remove(k);
+    }
+    let r = catch_unwind(AssertUnwindSafe(|| {
+        m.fill_gaps_with(|k| if k == 2 { panic!("boom") } else { k * 10 });
+    }));
+    assert!(r.is_err());
+    assert_eq!(m.validate(), Ok(()));
+    assert_eq!(m.len(), 4);
+    assert_eq!(m[1], 10);
+    let mut reused = vec![m.insert(0), m.insert(0)];
+    reused.sort();
+    assert_eq!(reused, [2, 4]);
+    assert_eq!(m.insert(0), 6);
+}
+
+#[test]
+fn remove_if() {
+    let mut m: CompactMap<u32> = (0..4).collect();
+    assert_eq!(m.remove_if(1, |&v| v == 0), None);
+    assert_eq!(m.remove_if(1, |&v| v == 1), Some(1));
+    assert_eq!(m.remove_if(1, |_| panic!("vacant")), None);
+    assert_eq!(m.remove_if(9, |_| panic!("out of bounds")), None);
+
+    assert_eq!(m.remove_if_mut(2, |v| { *v += 10; false }), None);
+    assert_eq!(m[2], 12);
+    assert_eq!(m.remove_if_mut(3, |v| { *v += 10; true }), Some(13));
+    assert_eq!(m.keys().collect::<Vec<_>>(), [0, 2]);
+    assert_eq!(m.validate(), Ok(()));
+
+    let mut w: super::wrapped::CompactMap<Mom, u32> = (0..2).collect();
+    assert_eq!(w.remove_if(Mom(0), |_| true), Some(0));
+    assert_eq!(w.remove_if_mut(Mom(1), |_| false), None);
+    assert_eq!(w.len(), 1);
+}
+
+#[test]
+fn to_std_maps() {
+    use std::collections::{BTreeMap, HashMap};
+
+    let mut m: CompactMap<String> = ["a", "b", "c", "d"].iter().map(|s| s.to_string()).collect();
+    m.remove(1);
+    let hm: HashMap<usize, String> = m.to_hash_map();
+    let bm: BTreeMap<usize, String> = m.to_btree_map();
+    assert_eq!(hm.len(), 3);
+    assert_eq!(bm.keys().cloned().collect::<Vec<_>>(), [0, 2, 3]);
+    assert_eq!(CompactMap::from(hm.clone()), m);
+    assert_eq!(CompactMap::from(bm.clone()), m);
+    assert_eq!(m.clone().into_hash_map(), hm);
+    assert_eq!(m.into_btree_map(), bm);
+
+    let w: super::wrapped::CompactMap<Mom, u32> = (0..3).collect();
+    let hm: HashMap<Mom, u32> = w.to_hash_map();
+    assert_eq!(hm[&Mom(2)], 2);
+    let bm: BTreeMap<Mom, u32> = w.to_btree_map();
+    let back: super::wrapped::CompactMap<Mom, u32> = super::wrapped::CompactMap::from_keyed_pairs(bm.clone());
+    assert!(back == w);
+    assert_eq!(back.into_hash_map(), hm);
+    assert_eq!(w.into_btree_map(), bm);
+}
+
+#[test]
+fn min_max_value() {
+    let mut m: CompactMap<i32> = vec![5, -2, 9, 9, -2, 0].into_iter().collect();
+    m.remove(5);
+    assert_eq!(m.max_value(), Some(&9));
+    assert_eq!(m.min_value(), Some(&-2));
+    assert_eq!(m.max_value_key(), Some((3, &9)));
+    assert_eq!(m.min_value_key(), Some((1, &-2)));
+    assert_eq!(m.max_by_key(|v| -v), Some((4, &-2)));
+    assert_eq!(m.min_by_key(|v| v.abs()), Some((1, &-2)));
+
+    m.clear();
+    assert_eq!(m.max_value(), None);
+    assert_eq!(m.min_value_key(), None);
+    assert_eq!(m.max_by_key(|&v| v), None);
+
+    let w: super::wrapped::CompactMap<Mom, u32> = vec![4, 8, 1].into_iter().collect();
+    assert_eq!(w.max_value_key(), Some((Mom(1), &8)));
+    assert_eq!(w.min_by_key(|&v| v), Some((Mom(2), &1)));
+}
+
+#[test]
+fn windows() {
+    use super::skip_holes;
+
+    let mut m: CompactMap<u32> = (0..6).collect();
+    m.remove(1);
+    m.remove(4);
+    let keys = |size| m.windows_of_size(size)
+        .map(|w| w.into_iter().map(|(k, _)| k).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    assert_eq!(keys(1), [[0], [2], [3], [5]]);
+    assert_eq!(keys(3), [[0, 2, 3], [2, 3, 5]]);
+    assert_eq!(keys(4), [[0, 2, 3, 5]]);
+    assert!(keys(5).is_empty());
+
+    let occupied: Vec<usize> = m.key_windows(3).map(|w| skip_holes(w).count()).collect();
+    assert_eq!(occupied, [2, 2, 2, 2]);
+    assert_eq!(m.key_windows(7).count(), 0);
+
+    let w: super::wrapped::CompactMap<Mom, u32> = (0..3).collect();
+    let windows: Vec<Vec<(Mom, &u32)>> = w.windows_of_size(2).collect();
+    assert_eq!(windows, [[(Mom(0), &0), (Mom(1), &1)], [(Mom(1), &1), (Mom(2), &2)]]);
+}
+
+#[test]
+#[should_panic(expected = "window size must be non-zero")]
+fn windows_of_size_zero() {
+    let m: CompactMap<u32> = (0..3).collect();
+    let _ = m.windows_of_size(0);
+}
+
+#[test]
+fn sum_product() {
+    let mut ints: CompactMap<i64> = vec![3, -4, 5, 6].into_iter().collect();
+    ints.remove(2);
+    assert_eq!(ints.sum::<i64>(), ints.values().sum::<i64>());
+    assert_eq!(ints.sum::<i64>(), 5);
+    assert_eq!(ints.product::<i64>(), -72);
+
+    let floats: CompactMap<f64> = vec![0.5, 1.5, 2.0].into_iter().collect();
+    assert_eq!(floats.sum::<f64>(), floats.values().sum::<f64>());
+    assert_eq!(floats.product::<f64>(), 1.5);
+
+    let bytes: CompactMap<u8> = (1..4).collect();
+    assert_eq!(bytes.sum::<u8>(), bytes.values().sum::<u8>());
+    assert_eq!(bytes.sum_by(|&b| b as u32 * 100), 600);
+    assert_eq!(bytes.count_where(|&b| b > 1), 2);
+
+    let empty: CompactMap<u16> = CompactMap::new();
+    assert_eq!(empty.sum::<u16>(), 0);
+    assert_eq!(empty.product::<u16>(), 1);
+    assert_eq!(empty.count_where(|_| true), 0);
+
+    let w: super::wrapped::CompactMap<Mom, u32> = (1..4).collect();
+    assert_eq!(w.sum::<u32>(), 6);
+    assert_eq!(w.product::<u32>(), 6);
+    assert_eq!(w.sum_by(|&v| v as u64), 6);
+    assert_eq!(w.count_where(|&v| v != 2), 2);
+}
+
+#[test]
+fn get_or_insert_default() {
+    let mut m: CompactMap<u32> = CompactMap::new();
+    for &k in &[3, 1, 3, 3] {
+        *m.get_or_insert_default(k) += 1;
+    }
+    assert_eq!(m.iter().collect::<Vec<_>>(), [(1, &1), (3, &3)]);
+
+    assert_eq!(*m.modify_or_insert(1, |v| *v *= 10, || panic!("occupied")), 10);
+    assert_eq!(*m.modify_or_insert(2, |_| panic!("vacant"), || 7), 7);
+    assert_eq!(*m.modify_or_insert(2, |v| *v += 1, || 0), 8);
+    assert_eq!(m.len(), 3);
+    assert_eq!(m.insert(0), 0);
+
+    let mut w: super::wrapped::CompactMap<Mom, u32> = super::wrapped::CompactMap::new();
+    *w.get_or_insert_default(Mom(2)) += 5;
+    w.modify_or_insert(Mom(2), |v| *v += 1, || 0);
+    w.modify_or_insert(Mom(0), |v| *v += 1, || 9);
+    assert_eq!((w[Mom(0)], w[Mom(2)]), (9, 6));
+}
@@ -1,8 +1,54 @@
 use ::std::marker::PhantomData;
-use ::std::convert::From;
 use ::std::iter::FromIterator;
-use ::std::ops::{Index, IndexMut};
+use ::std::ops::{Bound, Index, IndexMut, RangeBounds};
 use ::std::fmt;
+use ::std::iter::FusedIterator;
+use ::std::hash::{BuildHasher, Hash};
+use ::std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "allocator_api")]
+use ::std::alloc::{Allocator, Global};
+
+/// Same as `impl_any_alloc!` in the crate root, for the wrapper.
+macro_rules! impl_any_alloc_wrapped {
+    ($(#[$attr:meta])* impl[$($g:tt)*] ($($tr:tt)*) for CompactMap { $($body:tt)* }) => {
+        #[cfg(not(feature = "allocator_api"))]
+        $(#[$attr])*
+        impl<$($g)*> $($tr)* for CompactMap<K, V> { $($body)* }
+        #[cfg(feature = "allocator_api")]
+        $(#[$attr])*
+        impl<$($g)*, A: Allocator> $($tr)* for CompactMap<K, V, A> { $($body)* }
+    };
+    ($(#[$attr:meta])* impl[$($g:tt)*] ($($tr:tt)*) for &$lt:lifetime CompactMap { $($body:tt)* }) => {
+        #[cfg(not(feature = "allocator_api"))]
+        $(#[$attr])*
+        impl<$($g)*> $($tr)* for &$lt CompactMap<K, V> { $($body)* }
+        #[cfg(feature = "allocator_api")]
+        $(#[$attr])*
+        impl<$($g)*, A: Allocator> $($tr)* for &$lt CompactMap<K, V, A> { $($body)* }
+    };
+    ($(#[$attr:meta])* impl[$($g:tt)*] ($($tr:tt)*) for &$lt:lifetime mut CompactMap { $($body:tt)* }) => {
+        #[cfg(not(feature = "allocator_api"))]
+        $(#[$attr])*
+        impl<$($g)*> $($tr)* for &$lt mut CompactMap<K, V> { $($body)* }
+        #[cfg(feature = "allocator_api")]
+        $(#[$attr])*
+        impl<$($g)*, A: Allocator> $($tr)* for &$lt mut CompactMap<K, V, A> { $($body)* }
+    };
+    ($(#[$attr:meta])* impl[$($g:tt)*] ($($tr:tt)*) for $ty:ident<$($p:tt),+> { $($body:tt)* }) => {
+        #[cfg(not(feature = "allocator_api"))]
+        $(#[$attr])*
+        impl<$($g)*> $($tr)* for $ty<$($p),+> { $($body)* }
+        #[cfg(feature = "allocator_api")]
+        $(#[$attr])*
+        impl<$($g)*, A: Allocator> $($tr)* for $ty<$($p),+, A> { $($body)* }
+    };
+    ($($body:tt)*) => {
+        #[cfg(not(feature = "allocator_api"))]
+        impl<K: CompactMapKey, V> CompactMap<K, V> { $($body)* }
+        #[cfg(feature = "allocator_api")]
+        impl<K: CompactMapKey, V, A: Allocator> CompactMap<K, V, A> { $($body)* }
+    };
+}
 
 /// Special version of `CompactMap` that uses your usize-equivalent types as keys
 /// You are expected to use newtype-style structs like `struct MyToken(usize);` for this
@@ -26,13 +72,104 @@ use ::std::fmt;
 ///   m2.remove(q); // expected type `main::Lol`, found type `main::Mom`
 /// }
 /// ```
+#[cfg(not(feature = "allocator_api"))]
 #[derive(Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
-pub struct CompactMap<K : Into<usize> + From<usize>, V> {
+pub struct CompactMap<K: CompactMapKey, V> {
     inner: super::CompactMap<V>,
     _pd: PhantomData<K>,
 }
 
-impl<K:Into<usize> + From<usize>, V> CompactMap<K,V> {
+/// Special version of `CompactMap` that uses your usize-equivalent types as keys.
+///
+/// This is the `allocator_api` version, see
+/// [`super::CompactMap::new_in`](../struct.CompactMap.html#method.new_in).
+#[cfg(feature = "allocator_api")]
+pub struct CompactMap<K: CompactMapKey, V, A: Allocator = Global> {
+    inner: super::CompactMap<V, A>,
+    _pd: PhantomData<K>,
+}
+
+#[cfg(feature = "allocator_api")]
+impl<K: CompactMapKey, V, A: Allocator> CompactMap<K, V, A> {
+    /// See [`super::CompactMap::new_in`](../struct.CompactMap.html#method.new_in)
+    pub fn new_in(alloc: A) -> Self {
+        CompactMap::from_unwrapped(super::CompactMap::new_in(alloc))
+    }
+
+    /// See [`super::CompactMap::with_capacity_in`](../struct.CompactMap.html#method.with_capacity_in)
+    pub fn with_capacity_in(capacity: usize, alloc: A) -> Self {
+        CompactMap::from_unwrapped(super::CompactMap::with_capacity_in(capacity, alloc))
+    }
+
+    /// See [`super::CompactMap::allocator`](../struct.CompactMap.html#method.allocator)
+    pub fn allocator(&self) -> &A {
+        self.inner.allocator()
+    }
+
+    /// Extract underlying unwrapped map
+    pub fn into_unwrapped(self) -> super::CompactMap<V, A> {
+        self.inner
+    }
+
+    /// Wrap the map. You are responsible that it is the correct one
+    pub fn from_unwrapped(s: super::CompactMap<V, A>) -> Self {
+        CompactMap {
+            inner : s,
+            _pd : Default::default(),
+        }
+    }
+
+    /// Temporarily use the map without the safety wrapper
+    pub fn unwrapped(&self) -> &super::CompactMap<V, A> {
+        &self.inner
+    }
+
+    /// Temporarily use the map without the safety wrapper
+    pub fn unwrapped_mut(&mut self) -> &mut super::CompactMap<V, A> {
+        &mut self.inner
+    }
+}
+
+#[cfg(feature = "allocator_api")]
+impl<K: CompactMapKey, V> Default for CompactMap<K, V> {
+    fn default() -> Self {
+        CompactMap::new()
+    }
+}
+
+#[cfg(feature = "allocator_api")]
+impl<K: CompactMapKey + PartialOrd, V: PartialOrd> PartialOrd for CompactMap<K, V> {
+    fn partial_cmp(&self, other: &Self) -> Option<::std::cmp::Ordering> {
+        self.inner.partial_cmp(&other.inner)
+    }
+}
+
+#[cfg(feature = "allocator_api")]
+impl<K: CompactMapKey + Ord, V: Ord> Ord for CompactMap<K, V> {
+    fn cmp(&self, other: &Self) -> ::std::cmp::Ordering {
+        self.inner.cmp(&other.inner)
+    }
+}
+
+#[cfg(feature = "allocator_api")]
+impl<K: CompactMapKey + Hash, V: Hash, A: Allocator> Hash for CompactMap<K, V, A> {
+    fn hash<H: ::std::hash::Hasher>(&self, state: &mut H) {
+        self.inner.hash(state)
+    }
+}
+
+#[cfg(feature = "allocator_api")]
+impl<K: CompactMapKey + PartialEq, V: PartialEq, A: Allocator> PartialEq for CompactMap<K, V, A> {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner
+    }
+}
+
+#[cfg(feature = "allocator_api")]
+impl<K: CompactMapKey + Eq, V: Eq, A: Allocator> Eq for CompactMap<K, V, A> {}
+
+#[cfg(not(feature = "allocator_api"))]
+impl<K: CompactMapKey, V> CompactMap<K,V> {
     /// Extract underlying unwrapped map
     pub fn into_unwrapped(self) -> super::CompactMap<V> {
         self.inner
@@ -58,8 +195,7 @@ impl<K:Into<usize> + From<usize>, V> CompactMap<K,V> {
 }
 
 // Forwarded content
-impl<K:Into<usize> + From<usize>, V> CompactMap<K,V> {
-    
+impl<K: CompactMapKey, V> CompactMap<K,V> {
     /// See [`super::CompactMap::new`](../struct.CompactMap.html#method.new)
     pub fn new() -> Self {
         CompactMap {
@@ -76,6 +212,30 @@ impl<K:Into<usize> + From<usize>, V> CompactMap<K,V> {
         }
     }
 
+    /// See [`super::CompactMap::from_fn`](../struct.CompactMap.html#method.from_fn)
+    pub fn from_fn<F>(len: usize, mut f: F) -> Self
+        where F: FnMut(K) -> V
+    {
+        CompactMap::from_unwrapped(super::CompactMap::from_fn(len, |k| f(K::from_index(k))))
+    }
+
+    /// See [`super::CompactMap::try_from_fn`](../struct.CompactMap.html#method.try_from_fn)
+    pub fn try_from_fn<F, E>(len: usize, mut f: F) -> Result<Self, E>
+        where F: FnMut(K) -> Result<V, E>
+    {
+        super::CompactMap::try_from_fn(len, |k| f(K::from_index(k))).map(CompactMap::from_unwrapped)
+    }
+
+    /// See [`super::CompactMap::from_keyed_pairs`](../struct.CompactMap.html#method.from_keyed_pairs)
+    pub fn from_keyed_pairs<I>(iter: I) -> Self
+        where I: IntoIterator<Item = (K, V)>
+    {
+        let it = iter.into_iter().map(|(k, v)| (k.into_index(), v));
+        CompactMap::from_unwrapped(super::CompactMap::from_keyed_pairs(it))
+    }
+}
+
+impl_any_alloc_wrapped! {
     /// See [`super::CompactMap::capacity`](../struct.CompactMap.html#method.capacity)
     #[inline]
     pub fn capacity(&self) -> usize {
@@ -100,83 +260,475 @@ impl<K:Into<usize> + From<usize>, V> CompactMap<K,V> {
     }
 
     /// See [`super::CompactMap::is_empty_slow`](../struct.CompactMap.html#method.is_empty_slow)
+    #[deprecated(since = "0.4.0", note = "use is_empty()")]
+    #[allow(deprecated)]
     pub fn is_empty_slow(&self) -> bool {
         self.inner.is_empty_slow()
     }
 
+    /// See [`super::CompactMap::is_empty`](../struct.CompactMap.html#method.is_empty)
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// See [`super::CompactMap::len`](../struct.CompactMap.html#method.len)
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// See [`super::CompactMap::occupied_count`](../struct.CompactMap.html#method.occupied_count)
+    #[inline]
+    pub fn occupied_count(&self) -> usize {
+        self.inner.occupied_count()
+    }
+
+    /// See [`super::CompactMap::free_count`](../struct.CompactMap.html#method.free_count)
+    #[inline]
+    pub fn free_count(&self) -> usize {
+        self.inner.free_count()
+    }
+
+    /// See [`super::CompactMap::count_holes`](../struct.CompactMap.html#method.count_holes)
+    pub fn count_holes(&self) -> usize {
+        self.inner.count_holes()
+    }
+
+    /// See [`super::CompactMap::iter_holes`](../struct.CompactMap.html#method.iter_holes)
+    pub fn iter_holes(&self) -> impl Iterator<Item = K> + '_ {
+        self.inner.iter_holes().map(K::from_index)
+    }
+
+    /// See [`super::CompactMap::free_list_vec`](../struct.CompactMap.html#method.free_list_vec)
+    pub fn free_list_vec(&self) -> Vec<K> {
+        self.inner.free_list_vec().into_iter().map(K::from_index).collect()
+    }
+
+    /// See [`super::CompactMap::free_list_len`](../struct.CompactMap.html#method.free_list_len)
+    pub fn free_list_len(&self) -> usize {
+        self.inner.free_list_len()
+    }
+
+    /// See [`super::CompactMap::is_dense`](../struct.CompactMap.html#method.is_dense)
+    pub fn is_dense(&self) -> bool {
+        self.inner.is_dense()
+    }
+
+    /// See [`super::CompactMap::dense_len`](../struct.CompactMap.html#method.dense_len)
+    pub fn dense_len(&self) -> Option<usize> {
+        self.inner.dense_len()
+    }
+
+    /// See [`super::CompactMap::assert_dense`](../struct.CompactMap.html#method.assert_dense)
+    pub fn assert_dense(&self) {
+        self.inner.assert_dense()
+    }
+
+    /// Panics if the key the next insertion would use does not fit into `K`,
+    /// before anything is stored under it.
+    fn check_next_key(&self) {
+        let next = if self.inner.free_head == super::SENTINEL {
+            self.inner.data.len()
+        } else {
+            self.inner.free_head
+        };
+        assert!(next <= K::MAX, "key {} does not fit into the key type", next);
+    }
+
     /// See [`super::CompactMap::insert`](../struct.CompactMap.html#method.insert)
+    ///
+    /// # Panics
+    ///
+    /// Panics without inserting if all keys representable by `K` are taken.
     pub fn insert(&mut self, v: V) -> K {
-        From::from(self.inner.insert(v))
+        self.check_next_key();
+        K::from_index(self.inner.insert(v))
     }
     
     /// See [`super::CompactMap::insert_with`](../struct.CompactMap.html#method.insert_with)
+    ///
+    /// # Panics
+    ///
+    /// Panics without calling `f` if all keys representable by `K` are taken.
     pub fn insert_with<F>(&mut self, f: F) -> K
         where F: FnOnce(K) -> V
     {
-        From::from(self.inner.insert_with(|key|f(key.into())))
+        self.check_next_key();
+        K::from_index(self.inner.insert_with(|key|f(K::from_index(key))))
+    }
+
+    /// See [`super::CompactMap::insert_with_key`](../struct.CompactMap.html#method.insert_with_key)
+    pub fn insert_with_key<F>(&mut self, f: F) -> K
+        where F: FnOnce(K) -> V
+    {
+        self.insert_with(f)
+    }
+
+    /// See [`super::CompactMap::allocate_with`](../struct.CompactMap.html#method.allocate_with)
+    pub fn allocate_with<F>(&mut self, f: F) -> K
+        where F: FnOnce(K) -> V
+    {
+        self.insert_with(f)
+    }
+
+    /// See [`super::CompactMap::try_allocate_with`](../struct.CompactMap.html#method.try_allocate_with)
+    pub fn try_allocate_with<F, E>(&mut self, f: F) -> Result<K, E>
+        where F: FnOnce(K) -> Result<V, E>
+    {
+        self.inner.try_allocate_with(|key| f(K::from_index(key))).map(K::from_index)
+    }
+
+    /// See [`super::CompactMap::try_insert`](../struct.CompactMap.html#method.try_insert)
+    pub fn try_insert(&mut self, v: V) -> Result<K, V> {
+        self.inner.try_insert(v).map(K::from_index)
+    }
+
+    /// See [`super::CompactMap::bulk_insert`](../struct.CompactMap.html#method.bulk_insert)
+    pub fn bulk_insert<I>(&mut self, iter: I) -> Vec<K>
+        where I: IntoIterator<Item = V>
+    {
+        self.inner.bulk_insert(iter).into_iter().map(K::from_index).collect()
+    }
+
+    /// See [`super::CompactMap::bulk_insert_array`](../struct.CompactMap.html#method.bulk_insert_array)
+    pub fn bulk_insert_array<const N: usize>(&mut self, values: [V; N]) -> [K; N] {
+        self.inner.bulk_insert_array(values).map(K::from_index)
+    }
+
+    /// See [`super::CompactMap::insert_returning_ref`](../struct.CompactMap.html#method.insert_returning_ref)
+    pub fn insert_returning_ref(&mut self, v: V) -> (K, &mut V) {
+        let (k, v) = self.inner.insert_returning_ref(v);
+        (K::from_index(k), v)
     }
 
     /// See [`super::CompactMap::remove`](../struct.CompactMap.html#method.remove)
     pub fn remove(&mut self, i: K) -> Option<V> {
-        self.inner.remove(i.into())
+        self.inner.remove(i.into_index())
+    }
+    
+    /// See [`super::CompactMap::remove_if`](../struct.CompactMap.html#method.remove_if)
+    pub fn remove_if<F>(&mut self, i: K, predicate: F) -> Option<V>
+        where F: FnOnce(&V) -> bool
+    {
+        self.inner.remove_if(i.into_index(), predicate)
+    }
+
+    /// See [`super::CompactMap::remove_if_mut`](../struct.CompactMap.html#method.remove_if_mut)
+    pub fn remove_if_mut<F>(&mut self, i: K, predicate: F) -> Option<V>
+        where F: FnOnce(&mut V) -> bool
+    {
+        self.inner.remove_if_mut(i.into_index(), predicate)
+    }
+
+    /// See [`super::CompactMap::pop_first`](../struct.CompactMap.html#method.pop_first)
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        self.inner.pop_first().map(|(k, v)| (K::from_index(k), v))
+    }
+
+    /// See [`super::CompactMap::pop_last`](../struct.CompactMap.html#method.pop_last)
+    pub fn pop_last(&mut self) -> Option<(K, V)> {
+        self.inner.pop_last().map(|(k, v)| (K::from_index(k), v))
+    }
+
+    /// See [`super::CompactMap::remove_many`](../struct.CompactMap.html#method.remove_many)
+    pub fn remove_many<I>(&mut self, keys: I) -> usize
+        where I: IntoIterator<Item = K>
+    {
+        self.inner.remove_many(keys.into_iter().map(K::into_index))
+    }
+
+    /// See [`super::CompactMap::remove_many_collect`](../struct.CompactMap.html#method.remove_many_collect)
+    pub fn remove_many_collect<I>(&mut self, keys: I) -> Vec<V>
+        where I: IntoIterator<Item = K>
+    {
+        self.inner.remove_many_collect(keys.into_iter().map(K::into_index))
+    }
+
+    /// See [`super::CompactMap::retain`](../struct.CompactMap.html#method.retain)
+    pub fn retain<F>(&mut self, mut f: F)
+        where F: FnMut(K, &mut V) -> bool
+    {
+        self.inner.retain(|k, v| f(K::from_index(k), v))
+    }
+
+    /// See [`super::CompactMap::retain_and_count`](../struct.CompactMap.html#method.retain_and_count)
+    pub fn retain_and_count<F>(&mut self, mut f: F) -> usize
+        where F: FnMut(K, &mut V) -> bool
+    {
+        self.inner.retain_and_count(|k, v| f(K::from_index(k), v))
+    }
+
+    /// See [`super::CompactMap::retain_mut`](../struct.CompactMap.html#method.retain_mut)
+    pub fn retain_mut<F>(&mut self, f: F)
+        where F: FnMut(K, &mut V) -> bool
+    {
+        self.retain(f)
+    }
+
+    /// See [`super::CompactMap::remove_entry`](../struct.CompactMap.html#method.remove_entry)
+    pub fn remove_entry(&mut self, i: K) -> Option<(K, V)> {
+        self.inner.remove_entry(i.into_index()).map(|(k, v)| (K::from_index(k), v))
+    }
+    
+    /// See [`super::CompactMap::swap_remove`](../struct.CompactMap.html#method.swap_remove)
+    pub fn swap_remove(&mut self, i: K) -> Option<(V, Option<super::Relocation<K>>)> {
+        self.inner.swap_remove(i.into_index()).map(|(v, r)| (v, r.map(|r| super::Relocation {
+            old_key: K::from_index(r.old_key),
+            new_key: K::from_index(r.new_key),
+        })))
+    }
+
+    /// See [`super::CompactMap::get_or_insert`](../struct.CompactMap.html#method.get_or_insert)
+    pub fn get_or_insert(&mut self, i: K, default: V) -> &mut V {
+        self.inner.get_or_insert(i.into_index(), default)
+    }
+
+    /// See [`super::CompactMap::get_or_insert_with`](../struct.CompactMap.html#method.get_or_insert_with)
+    pub fn get_or_insert_with<F>(&mut self, i: K, f: F) -> &mut V
+        where F: FnOnce() -> V
+    {
+        self.inner.get_or_insert_with(i.into_index(), f)
+    }
+
+    /// See [`super::CompactMap::get_or_insert_default`](../struct.CompactMap.html#method.get_or_insert_default)
+    pub fn get_or_insert_default(&mut self, i: K) -> &mut V
+        where V: Default
+    {
+        self.inner.get_or_insert_default(i.into_index())
+    }
+
+    /// See [`super::CompactMap::modify_or_insert`](../struct.CompactMap.html#method.modify_or_insert)
+    pub fn modify_or_insert<M, F>(&mut self, i: K, modify: M, insert: F) -> &mut V
+        where M: FnOnce(&mut V), F: FnOnce() -> V
+    {
+        self.inner.modify_or_insert(i.into_index(), modify, insert)
+    }
+
+    /// See [`super::CompactMap::get_or_insert_with_key`](../struct.CompactMap.html#method.get_or_insert_with_key)
+    pub fn get_or_insert_with_key<F>(&mut self, i: K, f: F) -> &mut V
+        where F: FnOnce(K) -> V
+    {
+        self.inner.get_or_insert_with_key(i.into_index(), |key| f(K::from_index(key)))
     }
     
     /// See [`super::CompactMap::get`](../struct.CompactMap.html#method.get)
     pub fn get(&self, i: K) -> Option<&V> {
-        self.inner.get(i.into())
+        self.inner.get(i.into_index())
     }
     
     /// See [`super::CompactMap::get_mut`](../struct.CompactMap.html#method.get_mut)
     pub fn get_mut(&mut self, i: K) -> Option<&mut V> {
-        self.inner.get_mut(i.into())
+        self.inner.get_mut(i.into_index())
+    }
+
+    /// See [`super::CompactMap::raw_entry`](../struct.CompactMap.html#method.raw_entry)
+    pub fn raw_entry(&self, i: K) -> Option<&super::Entry<V>> {
+        self.inner.raw_entry(i.into_index())
+    }
+
+    /// See [`super::CompactMap::raw_iter`](../struct.CompactMap.html#method.raw_iter)
+    pub fn raw_iter(&self) -> ::std::slice::Iter<'_, super::Entry<V>> {
+        self.inner.raw_iter()
+    }
+
+    /// See [`super::CompactMap::raw_iter_mut`](../struct.CompactMap.html#method.raw_iter_mut)
+    pub fn raw_iter_mut(&mut self) -> impl DoubleEndedIterator<Item = Option<&mut V>> + ExactSizeIterator + '_ {
+        self.inner.raw_iter_mut()
+    }
+
+    /// See [`super::CompactMap::key_windows`](../struct.CompactMap.html#method.key_windows)
+    pub fn key_windows(&self, size: usize) -> ::std::slice::Windows<'_, super::Entry<V>> {
+        self.inner.key_windows(size)
+    }
+
+    /// See [`super::CompactMap::raw_entry_mut`](../struct.CompactMap.html#method.raw_entry_mut)
+    pub fn raw_entry_mut(&mut self, i: K) -> Option<&mut super::Entry<V>> {
+        self.inner.raw_entry_mut(i.into_index())
+    }
+
+    /// See [`super::CompactMap::get_multiple`](../struct.CompactMap.html#method.get_multiple)
+    pub fn get_multiple<const N: usize>(&self, keys: [K; N]) -> [Option<&V>; N] {
+        self.inner.get_multiple(keys.map(K::into_index))
+    }
+
+    /// See [`super::CompactMap::get_pair_mut`](../struct.CompactMap.html#method.get_pair_mut)
+    pub fn get_pair_mut(&mut self, a: K, b: K) -> Option<(&mut V, &mut V)> {
+        self.inner.get_pair_mut(a.into_index(), b.into_index())
+    }
+
+    /// See [`super::CompactMap::modify`](../struct.CompactMap.html#method.modify)
+    pub fn modify<F>(&mut self, i: K, f: F) -> Option<&mut V>
+        where F: FnOnce(&mut V)
+    {
+        self.inner.modify(i.into_index(), f)
+    }
+
+    /// See [`super::CompactMap::update`](../struct.CompactMap.html#method.update)
+    pub fn update<F, R>(&mut self, i: K, f: F) -> Option<R>
+        where F: FnOnce(&mut V) -> R
+    {
+        self.inner.update(i.into_index(), f)
+    }
+
+    /// See [`super::CompactMap::replace`](../struct.CompactMap.html#method.replace)
+    pub fn replace(&mut self, i: K, new_value: V) -> Option<V> {
+        self.inner.replace(i.into_index(), new_value)
+    }
+
+    /// See [`super::CompactMap::move_entry`](../struct.CompactMap.html#method.move_entry)
+    pub fn move_entry(&mut self, from: K, to: K) -> Result<(), super::MoveError<K>> {
+        self.inner.move_entry(from.into_index(), to.into_index()).map_err(|e| match e {
+            super::MoveError::VacantSource { key } =>
+                super::MoveError::VacantSource { key: K::from_index(key) },
+            super::MoveError::OccupiedTarget { key } =>
+                super::MoveError::OccupiedTarget { key: K::from_index(key) },
+        })
+    }
+
+    /// See [`super::CompactMap::relocate_entry`](../struct.CompactMap.html#method.relocate_entry)
+    pub fn relocate_entry(&mut self, from: K, to: K) {
+        self.inner.relocate_entry(from.into_index(), to.into_index())
+    }
+
+    /// See [`super::CompactMap::find`](../struct.CompactMap.html#method.find)
+    pub fn find<F>(&self, f: F) -> Option<(K, &V)>
+        where F: FnMut(&V) -> bool
+    {
+        self.inner.find(f).map(|(k, v)| (K::from_index(k), v))
+    }
+
+    /// See [`super::CompactMap::find_key`](../struct.CompactMap.html#method.find_key)
+    pub fn find_key<F>(&self, f: F) -> Option<K>
+        where F: FnMut(&V) -> bool
+    {
+        self.inner.find_key(f).map(K::from_index)
+    }
+
+    /// See [`super::CompactMap::find_mut`](../struct.CompactMap.html#method.find_mut)
+    pub fn find_mut<F>(&mut self, f: F) -> Option<(K, &mut V)>
+        where F: FnMut(&V) -> bool
+    {
+        self.inner.find_mut(f).map(|(k, v)| (K::from_index(k), v))
+    }
+
+    /// See [`super::CompactMap::contains_value`](../struct.CompactMap.html#method.contains_value)
+    pub fn contains_value(&self, value: &V) -> bool
+        where V: PartialEq
+    {
+        self.inner.contains_value(value)
+    }
+
+    /// See [`super::CompactMap::contains_value_by`](../struct.CompactMap.html#method.contains_value_by)
+    pub fn contains_value_by<F>(&self, f: F) -> bool
+        where F: FnMut(&V) -> bool
+    {
+        self.inner.contains_value_by(f)
     }
 
     /// Returns an iterator visiting all key-value pairs in unspecified order.
     /// The iterator's element type is `(K, &'r V)`.
-    pub fn iter(&self) -> Iter<K, V> {
+    pub fn iter(&self) -> Iter<'_, K, V> {
         Iter {
             inner: self.inner.iter(),
              _pd: Default::default(),
         }
     }
 
+    /// See [`super::CompactMap::iter_from`](../struct.CompactMap.html#method.iter_from)
+    pub fn iter_from(&self, start: K) -> Iter<'_, K, V> {
+        Iter {
+            inner: self.inner.iter_from(start.into_index()),
+            _pd: Default::default(),
+        }
+    }
+
     /// Returns an iterator visiting all key-value pairs in unspecified order,
     /// with mutable references to the values.
     /// The iterator's element type is `(K, &'r mut V)`
-    pub fn iter_mut(&mut self) -> IterMut<K, V> {
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
         IterMut {
             inner: self.inner.iter_mut(),
              _pd: Default::default(),
         }
     }
 
+    /// See [`super::CompactMap::iter_from_mut`](../struct.CompactMap.html#method.iter_from_mut)
+    pub fn iter_from_mut(&mut self, start: K) -> IterMut<'_, K, V> {
+        IterMut {
+            inner: self.inner.iter_from_mut(start.into_index()),
+            _pd: Default::default(),
+        }
+    }
+
+    /// See [`super::CompactMap::range`](../struct.CompactMap.html#method.range)
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Iter<'_, K, V>
+        where K: Clone
+    {
+        Iter {
+            inner: self.inner.range(key_bounds(&range)),
+            _pd: Default::default(),
+        }
+    }
+
+    /// See [`super::CompactMap::range_mut`](../struct.CompactMap.html#method.range_mut)
+    pub fn range_mut<R: RangeBounds<K>>(&mut self, range: R) -> IterMut<'_, K, V>
+        where K: Clone
+    {
+        IterMut {
+            inner: self.inner.range_mut(key_bounds(&range)),
+            _pd: Default::default(),
+        }
+    }
+
     /// Returns an iterator visiting all key-value pairs in unspecified order,
     /// the keys, consuming the original `CompactMap`.
     /// The iterator's element type is `(K, V)`.
-    pub fn into_iter(self) -> IntoIter<K, V> {
+    #[allow(clippy::should_implement_trait)]
+    pub fn into_iter(self) -> with_alloc!(IntoIter<K, V>) {
         IntoIter {
             inner: self.inner.into_iter(),
              _pd: Default::default(),
         }
     }
 
+    /// Returns an iterator visiting all keys in ascending order,
+    /// consuming the original `CompactMap`.
+    /// The iterator's element type is `K`.
+    pub fn into_keys(self) -> with_alloc!(IntoKeys<K, V>) {
+        IntoKeys { inner: self.inner.into_keys(), _pd: Default::default() }
+    }
+
+    /// See [`super::CompactMap::into_values`](../struct.CompactMap.html#method.into_values)
+    pub fn into_values(self) -> with_alloc!(IntoValues<K, V>) {
+        IntoValues { inner: self.inner.into_values(), _pd: Default::default() }
+    }
+
     /// Returns an iterator visiting all keys in some order.
     /// The iterator's element type is `K`.
-    pub fn keys(&self) -> Keys<K, V> {
+    pub fn keys(&self) -> Keys<'_, K, V> {
         Keys { inner: self.inner.keys(), _pd: Default::default() }
     }
     
     /// See [`super::CompactMap::values`](../struct.CompactMap.html#method.values)
-    pub fn values(&self) -> super::Values<V> {
+    pub fn values(&self) -> super::Values<'_, V> {
         self.inner.values()
     }
     
     /// See [`super::CompactMap::values_mut`](../struct.CompactMap.html#method.values_mut)
-    pub fn values_mut(&mut self) -> super::ValuesMut<V> {
+    pub fn values_mut(&mut self) -> super::ValuesMut<'_, V> {
         self.inner.values_mut()
     }
+
+    /// See [`super::CompactMap::windows_of_size`](../struct.CompactMap.html#method.windows_of_size)
+    pub fn windows_of_size(&self, size: usize) -> impl Iterator<Item = Vec<(K, &V)>> + '_ {
+        self.inner.windows_of_size(size)
+            .map(|w| w.into_iter().map(|(k, v)| (K::from_index(k), v)).collect())
+    }
     
     /// See [`super::CompactMap::len_slow`](../struct.CompactMap.html#method.len_slow)
+    #[deprecated(since = "0.4.0", note = "use len()")]
+    #[allow(deprecated)]
     pub fn len_slow(&self) -> usize {
         self.inner.len_slow()
     }
@@ -186,118 +738,585 @@ impl<K:Into<usize> + From<usize>, V> CompactMap<K,V> {
         self.inner.shrink_to_fit()
     }
 
-    /// Returns an iterator visiting all key-value pairs in ascending order of
-    /// the keys, emptying (but not consuming) the original `CompactMap`.
-    /// The iterator's element type is `(K, V)`. Keeps the allocated memory for reuse.
-    pub fn drain(&mut self) -> Drain<K, V> {
-        Drain { 
-            inner: self.inner.drain(),
-            _pd : Default::default(),
-        }
+    /// See [`super::CompactMap::shrink_free_list`](../struct.CompactMap.html#method.shrink_free_list)
+    pub fn shrink_free_list(&mut self) {
+        self.inner.shrink_free_list()
     }
-}
 
+    /// See [`super::CompactMap::max_occupied_key`](../struct.CompactMap.html#method.max_occupied_key)
+    pub fn max_occupied_key(&self) -> Option<K> {
+        self.inner.max_occupied_key().map(K::from_index)
+    }
 
-impl<K:Into<usize> + From<usize>, V> FromIterator<V> for CompactMap<K, V> {
-    fn from_iter<I>(iter: I) -> CompactMap<K, V>
-    where
-        I: IntoIterator<Item = V>,
+    /// See [`super::CompactMap::fingerprint`](../struct.CompactMap.html#method.fingerprint)
+    pub fn fingerprint(&self) -> u64
+        where V: Hash
     {
-        CompactMap::from_unwrapped(super::CompactMap::from_iter(iter))
+        self.inner.fingerprint()
     }
-}
 
-impl<'a, K:Into<usize> + From<usize>, V> FromIterator<&'a V> for CompactMap<K, V>
-where
-    V: Copy,
-{
-    #[allow(map_clone)]
-    fn from_iter<I>(iter: I) -> CompactMap<K, V>
-    where
-        I: IntoIterator<Item = &'a V>,
+    /// See [`super::CompactMap::fingerprint_with`](../struct.CompactMap.html#method.fingerprint_with)
+    pub fn fingerprint_with<S: BuildHasher>(&self, hash_builder: &S) -> u64
+        where V: Hash
     {
-        FromIterator::<V>::from_iter(iter.into_iter().map(|&value| value))
+        self.inner.fingerprint_with(hash_builder)
     }
-}
 
-impl<K:Into<usize> + From<usize>, V> Extend<V> for CompactMap<K,V> {
-    fn extend<I>(&mut self, iter: I)
-    where
-        I: IntoIterator<Item = V>,
-    {
-        self.inner.extend(iter)
+    /// See [`super::CompactMap::stats`](../struct.CompactMap.html#method.stats)
+    pub fn stats(&self) -> super::MapStats {
+        self.inner.stats()
     }
-}
-impl<'a,K:Into<usize> + From<usize>, V> Extend<&'a V> for CompactMap<K,V>
-where
-    V: Copy,
-{
-    #[allow(map_clone)]
-    fn extend<I>(&mut self, iter: I)
-    where
-        I: IntoIterator<Item = &'a V>,
-    {
-        self.extend(iter.into_iter().map(|&value| value));
+
+    /// See [`super::CompactMap::trailing_holes`](../struct.CompactMap.html#method.trailing_holes)
+    pub fn trailing_holes(&self) -> usize {
+        self.inner.trailing_holes()
     }
-}
 
-impl<V,K:Into<usize> + From<usize>> Index<K> for CompactMap<K,V> {
-    type Output = V;
-    #[inline]
-    fn index(&self, i: K) -> &V {
-        self.inner.index(i.into())
+    /// See [`super::CompactMap::compact`](../struct.CompactMap.html#method.compact)
+    pub fn compact(&mut self) -> Vec<Option<K>> {
+        self.inner.compact().into_iter().map(|k| k.map(K::from_index)).collect()
     }
-}
-impl<'a,K:Copy + Into<usize> + From<usize>, V> Index<&'a K> for CompactMap<K,V> {
-    type Output = V;
-    fn index(&self, i: &K) -> &V {
-        let idx : usize = (*i).into();
-        self.inner.index(&idx)
+
+    /// See [`super::CompactMap::compact_and_remap`](../struct.CompactMap.html#method.compact_and_remap)
+    pub fn compact_and_remap(&self) -> (CompactMap<K, V>, Vec<Option<K>>)
+        where V: Clone
+    {
+        let (m, remap) = self.inner.compact_and_remap();
+        (CompactMap::from_unwrapped(m), remap.into_iter().map(|k| k.map(K::from_index)).collect())
+    }
+
+    /// See [`super::CompactMap::into_compact_and_remap`](../struct.CompactMap.html#method.into_compact_and_remap)
+    pub fn into_compact_and_remap(self) -> (CompactMap<K, V>, Vec<Option<K>>) {
+        let (m, remap) = self.inner.into_compact_and_remap();
+        (CompactMap::from_unwrapped(m), remap.into_iter().map(|k| k.map(K::from_index)).collect())
+    }
+
+    /// See [`super::CompactMap::truncate`](../struct.CompactMap.html#method.truncate)
+    pub fn truncate(&mut self, bound: K) {
+        self.inner.truncate(bound.into_index())
+    }
+}
+
+impl<K: CompactMapKey, V> CompactMap<K,V> {
+    /// See [`super::CompactMap::split_off`](../struct.CompactMap.html#method.split_off)
+    pub fn split_off(&mut self, at: K) -> CompactMap<K, V> {
+        CompactMap::from_unwrapped(self.inner.split_off(at.into_index()))
+    }
+}
+
+impl_any_alloc_wrapped! {
+    /// See [`super::CompactMap::fill_to`](../struct.CompactMap.html#method.fill_to)
+    pub fn fill_to<F>(&mut self, bound: K, f: F) -> Vec<K>
+        where F: FnMut() -> V
+    {
+        self.inner.fill_to(bound.into_index(), f).into_iter().map(K::from_index).collect()
+    }
+
+    /// See [`super::CompactMap::fill_gaps_with`](../struct.CompactMap.html#method.fill_gaps_with)
+    pub fn fill_gaps_with<F>(&mut self, mut f: F)
+        where F: FnMut(K) -> V
+    {
+        self.inner.fill_gaps_with(|i| f(K::from_index(i)))
+    }
+
+    /// See [`super::CompactMap::fill_to_default`](../struct.CompactMap.html#method.fill_to_default)
+    pub fn fill_to_default(&mut self, bound: K) -> Vec<K>
+        where V: Default
+    {
+        self.inner.fill_to_default(bound.into_index()).into_iter().map(K::from_index).collect()
+    }
+
+    /// Returns an iterator visiting all key-value pairs in ascending order of
+    /// the keys, emptying (but not consuming) the original `CompactMap`.
+    /// The iterator's element type is `(K, V)`. Keeps the allocated memory for reuse.
+    pub fn drain(&mut self) -> with_alloc!(Drain<'_, K, V>) {
+        Drain { 
+            inner: self.inner.drain(),
+            _pd : Default::default(),
+        }
+    }
+}
+
+impl<K: CompactMapKey, V> CompactMap<K,V> {
+    /// See [`super::CompactMap::drain_owned`](../struct.CompactMap.html#method.drain_owned)
+    pub fn drain_owned(&mut self) -> DrainOwned<K, V> {
+        DrainOwned {
+            inner: self.inner.drain_owned(),
+            _pd: PhantomData,
+        }
+    }
+}
+
+impl_any_alloc_wrapped! {
+    /// See [`super::CompactMap::take_all`](../struct.CompactMap.html#method.take_all)
+    pub fn take_all(&mut self) -> Vec<V> {
+        self.inner.take_all()
     }
 }
-impl<K:Into<usize> + From<usize>,V> IndexMut<K> for CompactMap<K,V> {
-    fn index_mut(&mut self, i: K) -> &mut V {
-        self.inner.index_mut(i.into())
+
+impl<K: CompactMapKey, V> CompactMap<K,V> {
+    /// See [`super::CompactMap::into_hash_map`](../struct.CompactMap.html#method.into_hash_map)
+    pub fn into_hash_map(self) -> HashMap<K, V>
+        where K: Hash + Eq
+    {
+        self.into_iter().collect()
+    }
+
+    /// See [`super::CompactMap::into_btree_map`](../struct.CompactMap.html#method.into_btree_map)
+    pub fn into_btree_map(self) -> BTreeMap<K, V>
+        where K: Ord
+    {
+        self.into_iter().collect()
+    }
+}
+
+impl_any_alloc_wrapped! {
+    /// See [`super::CompactMap::to_hash_map`](../struct.CompactMap.html#method.to_hash_map)
+    pub fn to_hash_map(&self) -> HashMap<K, V>
+        where K: Hash + Eq, V: Clone
+    {
+        self.iter().map(|(k, v)| (k, v.clone())).collect()
+    }
+
+    /// See [`super::CompactMap::to_btree_map`](../struct.CompactMap.html#method.to_btree_map)
+    pub fn to_btree_map(&self) -> BTreeMap<K, V>
+        where K: Ord, V: Clone
+    {
+        self.iter().map(|(k, v)| (k, v.clone())).collect()
+    }
+}
+
+impl<K: CompactMapKey, V> CompactMap<K,V> {
+    /// See [`super::CompactMap::drain_range`](../struct.CompactMap.html#method.drain_range)
+    pub fn drain_range<R: RangeBounds<K>>(&mut self, range: R) -> DrainRange<'_, K, V>
+        where K: Clone
+    {
+        DrainRange {
+            inner: self.inner.drain_range(key_bounds(&range)),
+            _pd: Default::default(),
+        }
+    }
+}
+
+impl_any_alloc_wrapped! {
+    /// See [`super::CompactMap::map_values`](../struct.CompactMap.html#method.map_values)
+    pub fn map_values<U, F>(self, f: F) -> CompactMap<K, U>
+        where F: FnMut(V) -> U
+    {
+        CompactMap::from_unwrapped(self.inner.map_values(f))
+    }
+
+    /// See [`super::CompactMap::map_values_ref`](../struct.CompactMap.html#method.map_values_ref)
+    pub fn map_values_ref<U, F>(&self, f: F) -> CompactMap<K, U>
+        where F: FnMut(&V) -> U
+    {
+        CompactMap::from_unwrapped(self.inner.map_values_ref(f))
+    }
+
+    /// See [`super::CompactMap::try_map_values`](../struct.CompactMap.html#method.try_map_values)
+    pub fn try_map_values<U, E, F>(self, f: F) -> Result<CompactMap<K, U>, E>
+        where F: FnMut(V) -> Result<U, E>
+    {
+        self.inner.try_map_values(f).map(CompactMap::from_unwrapped)
+    }
+
+    /// See [`super::CompactMap::try_map_values_ref`](../struct.CompactMap.html#method.try_map_values_ref)
+    pub fn try_map_values_ref<U, E, F>(&self, f: F) -> Result<CompactMap<K, U>, E>
+        where F: FnMut(&V) -> Result<U, E>
+    {
+        self.inner.try_map_values_ref(f).map(CompactMap::from_unwrapped)
     }
 }
-impl<'a,K:Copy + Into<usize> + From<usize>, V> IndexMut<&'a K> for CompactMap<K,V> {
-    fn index_mut(&mut self, i: &K) -> &mut V {
-        let idx : usize = (*i).into();
-        self.inner.index_mut(idx)
+
+impl<K: CompactMapKey, V> CompactMap<K,V> {
+    /// See [`super::CompactMap::filter_map`](../struct.CompactMap.html#method.filter_map)
+    pub fn filter_map<U, F>(self, mut f: F) -> CompactMap<K, U>
+        where F: FnMut(K, V) -> Option<U>
+    {
+        CompactMap::from_unwrapped(self.inner.filter_map(|k, v| f(K::from_index(k), v)))
+    }
+
+    /// See [`super::CompactMap::filter_map_ref`](../struct.CompactMap.html#method.filter_map_ref)
+    pub fn filter_map_ref<U, F>(&self, mut f: F) -> CompactMap<K, U>
+        where F: FnMut(K, &V) -> Option<U>
+    {
+        CompactMap::from_unwrapped(self.inner.filter_map_ref(|k, v| f(K::from_index(k), v)))
+    }
+
+    /// See [`super::CompactMap::clone_with_filter`](../struct.CompactMap.html#method.clone_with_filter)
+    pub fn clone_with_filter<F>(&self, mut f: F) -> CompactMap<K, V>
+        where V: Clone, F: FnMut(K, &V) -> bool
+    {
+        CompactMap::from_unwrapped(self.inner.clone_with_filter(|k, v| f(K::from_index(k), v)))
     }
 }
-impl<K:Into<usize> + From<usize>, V: fmt::Debug> fmt::Debug for CompactMap<K,V> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.inner.fmt(f)
+
+impl_any_alloc_wrapped! {
+    /// See [`super::CompactMap::group_by`](../struct.CompactMap.html#method.group_by)
+    pub fn group_by<K2, F>(&self, f: F) -> HashMap<K2, Vec<K>>
+        where K2: Hash + Eq, F: FnMut(&V) -> K2
+    {
+        self.inner.group_by(f).into_iter()
+            .map(|(g, keys)| (g, keys.into_iter().map(K::from_index).collect()))
+            .collect()
+    }
+
+    /// See [`super::CompactMap::group_by_values`](../struct.CompactMap.html#method.group_by_values)
+    pub fn group_by_values<K2, F>(&self, f: F) -> HashMap<K2, Vec<&V>>
+        where K2: Hash + Eq, F: FnMut(&V) -> K2
+    {
+        self.inner.group_by_values(f)
+    }
+
+    /// See [`super::CompactMap::max_value`](../struct.CompactMap.html#method.max_value)
+    pub fn max_value(&self) -> Option<&V>
+        where V: Ord
+    {
+        self.inner.max_value()
+    }
+
+    /// See [`super::CompactMap::min_value`](../struct.CompactMap.html#method.min_value)
+    pub fn min_value(&self) -> Option<&V>
+        where V: Ord
+    {
+        self.inner.min_value()
+    }
+
+    /// See [`super::CompactMap::max_value_key`](../struct.CompactMap.html#method.max_value_key)
+    pub fn max_value_key(&self) -> Option<(K, &V)>
+        where V: Ord
+    {
+        self.inner.max_value_key().map(|(k, v)| (K::from_index(k), v))
+    }
+
+    /// See [`super::CompactMap::min_value_key`](../struct.CompactMap.html#method.min_value_key)
+    pub fn min_value_key(&self) -> Option<(K, &V)>
+        where V: Ord
+    {
+        self.inner.min_value_key().map(|(k, v)| (K::from_index(k), v))
+    }
+
+    /// See [`super::CompactMap::max_by_key`](../struct.CompactMap.html#method.max_by_key)
+    pub fn max_by_key<K2, F>(&self, f: F) -> Option<(K, &V)>
+        where K2: Ord, F: FnMut(&V) -> K2
+    {
+        self.inner.max_by_key(f).map(|(k, v)| (K::from_index(k), v))
+    }
+
+    /// See [`super::CompactMap::min_by_key`](../struct.CompactMap.html#method.min_by_key)
+    pub fn min_by_key<K2, F>(&self, f: F) -> Option<(K, &V)>
+        where K2: Ord, F: FnMut(&V) -> K2
+    {
+        self.inner.min_by_key(f).map(|(k, v)| (K::from_index(k), v))
+    }
+
+    /// See [`super::CompactMap::sum`](../struct.CompactMap.html#method.sum)
+    pub fn sum<'a, S>(&'a self) -> S
+        where S: ::std::iter::Sum<&'a V>
+    {
+        self.inner.sum()
+    }
+
+    /// See [`super::CompactMap::product`](../struct.CompactMap.html#method.product)
+    pub fn product<'a, P>(&'a self) -> P
+        where P: ::std::iter::Product<&'a V>
+    {
+        self.inner.product()
+    }
+
+    /// See [`super::CompactMap::sum_by`](../struct.CompactMap.html#method.sum_by)
+    pub fn sum_by<S, F>(&self, f: F) -> S
+        where S: ::std::iter::Sum<S>, F: FnMut(&V) -> S
+    {
+        self.inner.sum_by(f)
+    }
+
+    /// See [`super::CompactMap::count_where`](../struct.CompactMap.html#method.count_where)
+    pub fn count_where<F>(&self, f: F) -> usize
+        where F: FnMut(&V) -> bool
+    {
+        self.inner.count_where(f)
     }
 }
 
+impl<K: CompactMapKey, V> CompactMap<K,V> {
+    /// See [`super::CompactMap::zip_map`](../struct.CompactMap.html#method.zip_map)
+    pub fn zip_map<U, R, F>(&self, other: &CompactMap<K, U>, f: F) -> CompactMap<K, R>
+        where F: FnMut(&V, &U) -> R
+    {
+        CompactMap::from_unwrapped(self.inner.zip_map(&other.inner, f))
+    }
+
+    /// See [`super::CompactMap::zip_outer`](../struct.CompactMap.html#method.zip_outer)
+    pub fn zip_outer<U, R, F>(&self, other: &CompactMap<K, U>, f: F) -> CompactMap<K, R>
+        where F: FnMut(Option<&V>, Option<&U>) -> R
+    {
+        CompactMap::from_unwrapped(self.inner.zip_outer(&other.inner, f))
+    }
+
+    /// See [`super::CompactMap::merge`](../struct.CompactMap.html#method.merge)
+    pub fn merge(self, other: CompactMap<K, V>) -> CompactMap<K, V> {
+        CompactMap::from_unwrapped(self.inner.merge(other.inner))
+    }
+
+    /// See [`super::CompactMap::merge_preserving_keys`](../struct.CompactMap.html#method.merge_preserving_keys)
+    pub fn merge_preserving_keys(self, other: CompactMap<K, V>)
+        -> Result<CompactMap<K, V>, super::MergeConflict<CompactMap<K, V>, K>>
+    {
+        match self.inner.merge_preserving_keys(other.inner) {
+            Ok(m) => Ok(CompactMap::from_unwrapped(m)),
+            Err(e) => Err(super::MergeConflict {
+                key: K::from_index(e.key),
+                ours: CompactMap::from_unwrapped(e.ours),
+                theirs: CompactMap::from_unwrapped(e.theirs),
+            }),
+        }
+    }
+
+    /// See [`super::CompactMap::merge_with`](../struct.CompactMap.html#method.merge_with)
+    pub fn merge_with<F>(self, other: CompactMap<K, V>, f: F) -> CompactMap<K, V>
+        where F: FnMut(V, V) -> V
+    {
+        CompactMap::from_unwrapped(self.inner.merge_with(other.inner, f))
+    }
+}
+
+impl_any_alloc_wrapped! {
+    /// See [`super::CompactMap::append`](../struct.CompactMap.html#method.append)
+    pub fn append(&mut self, other: &mut CompactMap<K, V>) {
+        self.inner.append(&mut other.inner)
+    }
+
+    /// See [`super::CompactMap::append_preserving_keys`](../struct.CompactMap.html#method.append_preserving_keys)
+    pub fn append_preserving_keys(&mut self, other: &mut CompactMap<K, V>)
+        -> Result<(), super::ConflictKey<K>>
+    {
+        self.inner.append_preserving_keys(&mut other.inner).map_err(|e| super::ConflictKey {
+            key: K::from_index(e.key),
+        })
+    }
+
+    /// See [`super::CompactMap::extend_keyed`](../struct.CompactMap.html#method.extend_keyed)
+    pub fn extend_keyed<I>(&mut self, iter: I)
+        where I: IntoIterator<Item = (K, V)>
+    {
+        self.inner.extend_keyed(iter.into_iter().map(|(k, v)| (k.into_index(), v)))
+    }
+
+    /// See [`super::CompactMap::try_extend_keyed`](../struct.CompactMap.html#method.try_extend_keyed)
+    pub fn try_extend_keyed<I>(&mut self, iter: I) -> Result<(), super::ExtendConflict<V, K>>
+        where I: IntoIterator<Item = (K, V)>
+    {
+        match self.inner.try_extend_keyed(iter.into_iter().map(|(k, v)| (k.into_index(), v))) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(super::ExtendConflict {
+                key: K::from_index(e.key),
+                theirs: e.theirs,
+            }),
+        }
+    }
+}
+
+impl<K: CompactMapKey, V: Copy + CompactMapKey> CompactMap<K, V> {
+    /// See [`super::CompactMap::invert`](../struct.CompactMap.html#method.invert)
+    pub fn invert(&self) -> Result<CompactMap<V, K>, super::InvertError<K, V>> {
+        match self.inner.map_values_ref(|v| v.into_index()).invert() {
+            Ok(m) => Ok(CompactMap::from_unwrapped(m.map_values(K::from_index))),
+            Err(e) => Err(super::InvertError {
+                duplicate_value: V::from_index(e.duplicate_value),
+                key1: K::from_index(e.key1),
+                key2: K::from_index(e.key2),
+            }),
+        }
+    }
+}
+
+impl<K: CompactMapKey, A, B> CompactMap<K, (A, B)> {
+    /// See [`super::CompactMap::unzip`](../struct.CompactMap.html#method.unzip)
+    pub fn unzip(self) -> (CompactMap<K, A>, CompactMap<K, B>) {
+        let (a, b) = self.inner.unzip();
+        (CompactMap::from_unwrapped(a), CompactMap::from_unwrapped(b))
+    }
+
+    /// See [`super::CompactMap::unzip_ref`](../struct.CompactMap.html#method.unzip_ref)
+    pub fn unzip_ref(&self) -> (CompactMap<K, A>, CompactMap<K, B>)
+        where A: Clone, B: Clone
+    {
+        let (a, b) = self.inner.unzip_ref();
+        (CompactMap::from_unwrapped(a), CompactMap::from_unwrapped(b))
+    }
+}
+
+
+impl<K: CompactMapKey, V> FromIterator<V> for CompactMap<K, V> {
+    fn from_iter<I>(iter: I) -> CompactMap<K, V>
+    where
+        I: IntoIterator<Item = V>,
+    {
+        CompactMap::from_unwrapped(super::CompactMap::from_iter(iter))
+    }
+}
+
+impl<'a, K: CompactMapKey, V> FromIterator<&'a V> for CompactMap<K, V>
+where
+    V: Copy,
+{
+    fn from_iter<I>(iter: I) -> CompactMap<K, V>
+    where
+        I: IntoIterator<Item = &'a V>,
+    {
+        FromIterator::<V>::from_iter(iter.into_iter().copied())
+    }
+}
+
+impl_any_alloc_wrapped! {
+    impl[K: CompactMapKey, V] (Extend<V>) for CompactMap {
+        fn extend<I>(&mut self, iter: I)
+        where
+            I: IntoIterator<Item = V>,
+        {
+            self.inner.extend(iter)
+        }
+    }
+}
+
+impl_any_alloc_wrapped! {
+    impl['a, K: CompactMapKey, V: Copy] (Extend<&'a V>) for CompactMap {
+        fn extend<I>(&mut self, iter: I)
+        where
+            I: IntoIterator<Item = &'a V>,
+        {
+            self.extend(iter.into_iter().copied());
+        }
+    }
+}
+
+impl_any_alloc_wrapped! {
+    impl[K: CompactMapKey, V] (Index<K>) for CompactMap {
+        type Output = V;
+        #[inline]
+        fn index(&self, i: K) -> &V {
+            self.inner.index(i.into_index())
+        }
+    }
+}
+
+impl_any_alloc_wrapped! {
+    impl['a, K: Copy + CompactMapKey, V] (Index<&'a K>) for CompactMap {
+        type Output = V;
+        fn index(&self, i: &K) -> &V {
+            let idx : usize = (*i).into_index();
+            self.inner.index(&idx)
+        }
+    }
+}
+
+impl_any_alloc_wrapped! {
+    impl[K: CompactMapKey, V] (IndexMut<K>) for CompactMap {
+        fn index_mut(&mut self, i: K) -> &mut V {
+            self.inner.index_mut(i.into_index())
+        }
+    }
+}
+
+impl_any_alloc_wrapped! {
+    impl['a, K: Copy + CompactMapKey, V] (IndexMut<&'a K>) for CompactMap {
+        fn index_mut(&mut self, i: &K) -> &mut V {
+            let idx : usize = (*i).into_index();
+            self.inner.index_mut(idx)
+        }
+    }
+}
+
+impl_any_alloc_wrapped! {
+    impl[K: CompactMapKey, V: fmt::Debug] (fmt::Debug) for CompactMap {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            self.inner.fmt(f)
+        }
+    }
+}
+
+impl_any_alloc_wrapped! {
+    impl[K: CompactMapKey + fmt::Display, V: fmt::Display] (fmt::Display) for CompactMap {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            super::display_entries(f, self.iter())
+        }
+    }
+}
+
+
+fn key_bounds<K, R>(range: &R) -> (Bound<usize>, Bound<usize>)
+    where K: CompactMapKey + Clone, R: RangeBounds<K>
+{
+    let convert = |b: Bound<&K>| match b {
+        Bound::Included(k) => Bound::Included(k.clone().into_index()),
+        Bound::Excluded(k) => Bound::Excluded(k.clone().into_index()),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    (convert(range.start_bound()), convert(range.end_bound()))
+}
 
 /// An iterator over the key-value pairs of a map.
-#[derive(Clone)]
-pub struct Iter<'a, K: Into<usize> + From<usize>, V: 'a> {
+pub struct Iter<'a, K: CompactMapKey, V: 'a> {
     inner: super::Iter<'a, V>,
     _pd: PhantomData<K>,
 }
-impl<'a, K: Into<usize> + From<usize>, V> Iterator for Iter<'a, K, V> {
+impl<'a, K: CompactMapKey, V> Iterator for Iter<'a, K, V> {
     type Item = (K, &'a V);
 
     fn next(&mut self) -> Option<(K, &'a V)> {
-        self.inner.next().map(|(k,v)|(From::from(k),v))
+        self.inner.next().map(|(k,v)|(K::from_index(k),v))
+    }
+    fn nth(&mut self, n: usize) -> Option<(K, &'a V)> {
+        self.inner.nth(n).map(|(k,v)|(K::from_index(k),v))
+    }
+    fn last(self) -> Option<(K, &'a V)> {
+        self.inner.last().map(|(k,v)|(K::from_index(k),v))
+    }
+    fn fold<B, F>(self, init: B, mut f: F) -> B
+        where F: FnMut(B, (K, &'a V)) -> B
+    {
+        self.inner.fold(init, |acc, (k, v)| f(acc, (K::from_index(k), v)))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
     }
 }
-impl<'a, K: Into<usize> + From<usize>, V> DoubleEndedIterator for Iter<'a, K, V> {
+impl<'a, K: CompactMapKey, V> DoubleEndedIterator for Iter<'a, K, V> {
     fn next_back(&mut self) -> Option<(K, &'a V)> {
-        self.inner.next_back().map(|(k,v)|(From::from(k),v))
+        self.inner.next_back().map(|(k,v)|(K::from_index(k),v))
     }
 }
-impl<'a, K: Into<usize> + From<usize>, V> IntoIterator for &'a CompactMap<K,V> {
-    type Item = (K, &'a V);
-    type IntoIter = Iter<'a, K, V>;
-    fn into_iter(self) -> Iter<'a, K, V> {
+impl<'a, K: CompactMapKey, V> FusedIterator for Iter<'a, K, V> {}
+impl<'a, K: CompactMapKey, V> Iter<'a, K, V> {
+    /// See [`super::Iter::peek`](../struct.Iter.html#method.peek)
+    pub fn peek(&self) -> Option<(K, &'a V)> {
+        self.inner.peek().map(|(k, v)| (K::from_index(k), v))
+    }
+
+    /// See [`super::Iter::peek_key`](../struct.Iter.html#method.peek_key)
+    pub fn peek_key(&self) -> Option<K> {
+        self.inner.peek_key().map(K::from_index)
+    }
+}
+impl<'a, K: CompactMapKey, V> Clone for Iter<'a, K, V> {
+    fn clone(&self) -> Self {
         Iter {
-            inner: self.inner.iter(),
-            _pd: Default::default(),
+            inner: self.inner.clone(),
+            _pd: PhantomData,
+        }
+    }
+}
+
+impl_any_alloc_wrapped! {
+    impl['a, K: CompactMapKey, V] (IntoIterator) for &'a CompactMap {
+        type Item = (K, &'a V);
+        type IntoIter = Iter<'a, K, V>;
+        fn into_iter(self) -> Iter<'a, K, V> {
+            Iter {
+                inner: self.inner.iter(),
+                _pd: Default::default(),
+            }
         }
     }
 }
@@ -305,59 +1324,125 @@ impl<'a, K: Into<usize> + From<usize>, V> IntoIterator for &'a CompactMap<K,V> {
 
 /// An iterator over the key-value pairs of a map, with the
 /// values being mutable.
-pub struct IterMut<'a, K: Into<usize> + From<usize>, V: 'a> {
+pub struct IterMut<'a, K: CompactMapKey, V: 'a> {
     inner: super::IterMut<'a, V>,
     _pd: PhantomData<K>,
 }
-impl<'a, K: Into<usize> + From<usize>, V: 'a> Iterator for IterMut<'a, K, V> {
+impl<'a, K: CompactMapKey, V: 'a> Iterator for IterMut<'a, K, V> {
     type Item = (K, &'a mut V);
 
     fn next<'b>(&'b mut self) -> Option<(K, &'a mut V)> {
-        self.inner.next().map(|(k,v)|(From::from(k),v))
+        self.inner.next().map(|(k,v)|(K::from_index(k),v))
+    }
+    fn nth(&mut self, n: usize) -> Option<(K, &'a mut V)> {
+        self.inner.nth(n).map(|(k,v)|(K::from_index(k),v))
+    }
+    fn last(self) -> Option<(K, &'a mut V)> {
+        self.inner.last().map(|(k,v)|(K::from_index(k),v))
+    }
+    fn fold<B, F>(self, init: B, mut f: F) -> B
+        where F: FnMut(B, (K, &'a mut V)) -> B
+    {
+        self.inner.fold(init, |acc, (k, v)| f(acc, (K::from_index(k), v)))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
     }
 }
-impl<'a, K: Into<usize> + From<usize>, V: 'a> DoubleEndedIterator for IterMut<'a, K, V> {
+impl<'a, K: CompactMapKey, V: 'a> DoubleEndedIterator for IterMut<'a, K, V> {
     fn next_back<'b>(&'b mut self) -> Option<(K, &'a mut V)> {
-        self.inner.next_back().map(|(k,v)|(From::from(k),v))
+        self.inner.next_back().map(|(k,v)|(K::from_index(k),v))
     }
 }
+impl<'a, K: CompactMapKey, V: 'a> FusedIterator for IterMut<'a, K, V> {}
 
-impl<'a, K: Into<usize> + From<usize>, V: 'a> IntoIterator for &'a mut CompactMap<K, V> {
-    type Item = (K, &'a mut V);
-    type IntoIter = IterMut<'a, K, V>;
-    fn into_iter(self) -> IterMut<'a, K, V> {
-        IterMut {
-            inner: self.inner.iter_mut(),
-            _pd: Default::default(),
+impl_any_alloc_wrapped! {
+    impl['a, K: CompactMapKey, V: 'a] (IntoIterator) for &'a mut CompactMap {
+        type Item = (K, &'a mut V);
+        type IntoIter = IterMut<'a, K, V>;
+        fn into_iter(self) -> IterMut<'a, K, V> {
+            IterMut {
+                inner: self.inner.iter_mut(),
+                _pd: Default::default(),
+            }
         }
     }
 }
 
 
 /// A consuming iterator over the key-value pairs of a map.
-pub struct IntoIter<K : Into<usize> + From<usize>, V> {
+#[cfg(not(feature = "allocator_api"))]
+pub struct IntoIter<K: CompactMapKey, V> {
     inner: super::IntoIter<V>,
     _pd: PhantomData<K>,
 }
-impl<K: Into<usize> + From<usize>, V> Iterator for IntoIter<K,V> {
-    type Item = (K, V);
 
-    fn next(&mut self) -> Option<(K, V)> {
-        self.inner.next().map(|(k,v)|(From::from(k),v))
+/// A consuming iterator over the key-value pairs of a map.
+#[cfg(feature = "allocator_api")]
+pub struct IntoIter<K: CompactMapKey, V, A: Allocator = Global> {
+    inner: super::IntoIter<V, A>,
+    _pd: PhantomData<K>,
+}
+impl_any_alloc_wrapped! {
+    impl[K: CompactMapKey, V] (Iterator) for IntoIter<K, V> {
+        type Item = (K, V);
+
+        fn next(&mut self) -> Option<(K, V)> {
+            self.inner.next().map(|(k,v)|(K::from_index(k),v))
+        }
+        fn nth(&mut self, n: usize) -> Option<(K, V)> {
+            self.inner.nth(n).map(|(k,v)|(K::from_index(k),v))
+        }
+        fn last(self) -> Option<(K, V)> {
+            self.inner.last().map(|(k,v)|(K::from_index(k),v))
+        }
+        fn fold<B, F>(self, init: B, mut f: F) -> B
+            where F: FnMut(B, (K, V)) -> B
+        {
+            self.inner.fold(init, |acc, (k, v)| f(acc, (K::from_index(k), v)))
+        }
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            self.inner.size_hint()
+        }
     }
 }
-impl<K: Into<usize> + From<usize>, V> DoubleEndedIterator for IntoIter<K,V> {
-    fn next_back(&mut self) -> Option<(K, V)> {
-        self.inner.next_back().map(|(k,v)|(From::from(k),v))
+impl_any_alloc_wrapped! {
+    impl[K: CompactMapKey, V] (DoubleEndedIterator) for IntoIter<K, V> {
+        fn next_back(&mut self) -> Option<(K, V)> {
+            self.inner.next_back().map(|(k,v)|(K::from_index(k),v))
+        }
     }
 }
-impl<K: Into<usize> + From<usize>, V> IntoIterator for CompactMap<K,V> {
-    type Item = (K, V);
-    type IntoIter = IntoIter<K,V>;
-    fn into_iter(self) -> IntoIter<K,V> {
+impl_any_alloc_wrapped! {
+    impl[K: CompactMapKey, V] (FusedIterator) for IntoIter<K, V> {}
+}
+#[cfg(not(feature = "allocator_api"))]
+impl<K: CompactMapKey, V: Clone> Clone for IntoIter<K, V> {
+    fn clone(&self) -> Self {
         IntoIter {
-            inner: self.inner.into_iter(),
-            _pd: Default::default(),
+            inner: self.inner.clone(),
+            _pd: PhantomData,
+        }
+    }
+}
+#[cfg(feature = "allocator_api")]
+impl<K: CompactMapKey, V: Clone, A: Allocator + Clone> Clone for IntoIter<K, V, A> {
+    fn clone(&self) -> Self {
+        IntoIter {
+            inner: self.inner.clone(),
+            _pd: PhantomData,
+        }
+    }
+}
+impl_any_alloc_wrapped! {
+    impl[K: CompactMapKey, V] (IntoIterator) for CompactMap {
+        type Item = (K, V);
+        type IntoIter = with_alloc!(IntoIter<K, V>);
+        fn into_iter(self) -> with_alloc!(IntoIter<K, V>) {
+            IntoIter {
+                inner: self.inner.into_iter(),
+                _pd: Default::default(),
+            }
         }
     }
 }
@@ -365,50 +1450,350 @@ impl<K: Into<usize> + From<usize>, V> IntoIterator for CompactMap<K,V> {
 
 
 /// An iterator over the keys of a map.
-#[derive(Clone)]
-pub struct Keys<'a, K : Into<usize> + From<usize>, V: 'a> {
+pub struct Keys<'a, K: CompactMapKey, V: 'a> {
     inner: super::Keys<'a, V>,
     _pd: PhantomData<K>,
 }
-impl<'a, K : Into<usize> + From<usize>, V> Iterator for Keys<'a, K, V> {
+impl<'a, K: CompactMapKey, V> Iterator for Keys<'a, K, V> {
     type Item = K;
 
     fn next(&mut self) -> Option<K> {
-        self.inner.next().map(From::from)
+        self.inner.next().map(K::from_index)
+    }
+    fn nth(&mut self, n: usize) -> Option<K> {
+        self.inner.nth(n).map(K::from_index)
+    }
+    fn last(self) -> Option<K> {
+        self.inner.last().map(K::from_index)
+    }
+    fn fold<B, F>(self, init: B, mut f: F) -> B
+        where F: FnMut(B, K) -> B
+    {
+        self.inner.fold(init, |acc, k| f(acc, K::from_index(k)))
     }
     fn size_hint(&self) -> (usize, Option<usize>) {
         self.inner.size_hint()
     }
 }
-impl<'a, K : Into<usize> + From<usize>, V> DoubleEndedIterator for Keys<'a, K, V> {
+impl<'a, K: CompactMapKey, V> DoubleEndedIterator for Keys<'a, K, V> {
     fn next_back(&mut self) -> Option<K> {
-        self.inner.next_back().map(From::from)
+        self.inner.next_back().map(K::from_index)
+    }
+}
+impl<'a, K: CompactMapKey, V> FusedIterator for Keys<'a, K, V> {}
+impl<'a, K: CompactMapKey, V> Clone for Keys<'a, K, V> {
+    fn clone(&self) -> Self {
+        Keys {
+            inner: self.inner.clone(),
+            _pd: PhantomData,
+        }
+    }
+}
+
+
+/// A consuming iterator over the keys of a map.
+#[cfg(not(feature = "allocator_api"))]
+pub struct IntoKeys<K: CompactMapKey, V> {
+    inner: super::IntoKeys<V>,
+    _pd: PhantomData<K>,
+}
+
+/// A consuming iterator over the keys of a map.
+#[cfg(feature = "allocator_api")]
+pub struct IntoKeys<K: CompactMapKey, V, A: Allocator = Global> {
+    inner: super::IntoKeys<V, A>,
+    _pd: PhantomData<K>,
+}
+impl_any_alloc_wrapped! {
+    impl[K: CompactMapKey, V] (Iterator) for IntoKeys<K, V> {
+        type Item = K;
+
+        fn next(&mut self) -> Option<K> {
+            self.inner.next().map(K::from_index)
+        }
+        fn nth(&mut self, n: usize) -> Option<K> {
+            self.inner.nth(n).map(K::from_index)
+        }
+        fn last(self) -> Option<K> {
+            self.inner.last().map(K::from_index)
+        }
+        fn fold<B, F>(self, init: B, mut f: F) -> B
+            where F: FnMut(B, K) -> B
+        {
+            self.inner.fold(init, |acc, k| f(acc, K::from_index(k)))
+        }
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            self.inner.size_hint()
+        }
+    }
+}
+impl_any_alloc_wrapped! {
+    impl[K: CompactMapKey, V] (DoubleEndedIterator) for IntoKeys<K, V> {
+        fn next_back(&mut self) -> Option<K> {
+            self.inner.next_back().map(K::from_index)
+        }
+    }
+}
+impl_any_alloc_wrapped! {
+    impl[K: CompactMapKey, V] (FusedIterator) for IntoKeys<K, V> {}
+}
+#[cfg(not(feature = "allocator_api"))]
+impl<K: CompactMapKey, V: Clone> Clone for IntoKeys<K, V> {
+    fn clone(&self) -> Self {
+        IntoKeys {
+            inner: self.inner.clone(),
+            _pd: PhantomData,
+        }
+    }
+}
+#[cfg(feature = "allocator_api")]
+impl<K: CompactMapKey, V: Clone, A: Allocator + Clone> Clone for IntoKeys<K, V, A> {
+    fn clone(&self) -> Self {
+        IntoKeys {
+            inner: self.inner.clone(),
+            _pd: PhantomData,
+        }
+    }
+}
+
+/// A consuming iterator over the values of a map.
+#[cfg(not(feature = "allocator_api"))]
+pub struct IntoValues<K: CompactMapKey, V> {
+    inner: super::IntoValues<V>,
+    _pd: PhantomData<K>,
+}
+
+/// A consuming iterator over the values of a map.
+#[cfg(feature = "allocator_api")]
+pub struct IntoValues<K: CompactMapKey, V, A: Allocator = Global> {
+    inner: super::IntoValues<V, A>,
+    _pd: PhantomData<K>,
+}
+impl_any_alloc_wrapped! {
+    impl[K: CompactMapKey, V] (Iterator) for IntoValues<K, V> {
+        type Item = V;
+
+        fn next(&mut self) -> Option<V> {
+            self.inner.next()
+        }
+        fn nth(&mut self, n: usize) -> Option<V> {
+            self.inner.nth(n)
+        }
+        fn last(self) -> Option<V> {
+            self.inner.last()
+        }
+        fn fold<B, F>(self, init: B, f: F) -> B
+            where F: FnMut(B, V) -> B
+        {
+            self.inner.fold(init, f)
+        }
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            self.inner.size_hint()
+        }
+    }
+}
+impl_any_alloc_wrapped! {
+    impl[K: CompactMapKey, V] (DoubleEndedIterator) for IntoValues<K, V> {
+        fn next_back(&mut self) -> Option<V> {
+            self.inner.next_back()
+        }
+    }
+}
+impl_any_alloc_wrapped! {
+    impl[K: CompactMapKey, V] (FusedIterator) for IntoValues<K, V> {}
+}
+#[cfg(not(feature = "allocator_api"))]
+impl<K: CompactMapKey, V: Clone> Clone for IntoValues<K, V> {
+    fn clone(&self) -> Self {
+        IntoValues {
+            inner: self.inner.clone(),
+            _pd: PhantomData,
+        }
+    }
+}
+#[cfg(feature = "allocator_api")]
+impl<K: CompactMapKey, V: Clone, A: Allocator + Clone> Clone for IntoValues<K, V, A> {
+    fn clone(&self) -> Self {
+        IntoValues {
+            inner: self.inner.clone(),
+            _pd: PhantomData,
+        }
     }
 }
 
 
 /// A draining iterator over the key-value pairs of a map.
-pub struct Drain<'a, K : Into<usize> + From<usize>, V: 'a> {
+#[cfg(not(feature = "allocator_api"))]
+pub struct Drain<'a, K: CompactMapKey, V: 'a> {
     inner: super::Drain<'a, V>,
     _pd: PhantomData<K>,
 }
 
-impl<'a, K : Into<usize> + From<usize>, V> Iterator for Drain<'a, K, V> {
+/// A draining iterator over the key-value pairs of a map.
+#[cfg(feature = "allocator_api")]
+pub struct Drain<'a, K: CompactMapKey, V: 'a, A: Allocator + 'a = Global> {
+    inner: super::Drain<'a, V, A>,
+    _pd: PhantomData<K>,
+}
+
+impl_any_alloc_wrapped! {
+    impl['a, K: CompactMapKey, V] (Iterator) for Drain<'a, K, V> {
+        type Item = (K, V);
+
+        fn next(&mut self) -> Option<(K, V)> {
+            self.inner.next().map(|(k,v)|(K::from_index(k),v)) 
+        }
+        fn size_hint(&self) -> (usize, Option<usize>) { self.inner.size_hint() }
+    }
+}
+impl_any_alloc_wrapped! {
+    impl['a, K: CompactMapKey, V] (DoubleEndedIterator) for Drain<'a, K, V> {
+        fn next_back(&mut self) -> Option<(K, V)> {
+            self.inner.next_back().map(|(k,v)|(K::from_index(k),v)) 
+        }
+    }
+}
+impl_any_alloc_wrapped! {
+    impl['a, K: CompactMapKey, V] (FusedIterator) for Drain<'a, K, V> {}
+}
+
+/// A draining iterator over the key-value pairs of a map that owns the entries.
+pub struct DrainOwned<K: CompactMapKey, V> {
+    inner: super::DrainOwned<V>,
+    _pd: PhantomData<K>,
+}
+
+impl<K: CompactMapKey, V> Iterator for DrainOwned<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        self.inner.next().map(|(k, v)| (K::from_index(k), v))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) { self.inner.size_hint() }
+}
+impl<K: CompactMapKey, V> DoubleEndedIterator for DrainOwned<K, V> {
+    fn next_back(&mut self) -> Option<(K, V)> {
+        self.inner.next_back().map(|(k, v)| (K::from_index(k), v))
+    }
+}
+impl<K: CompactMapKey, V> FusedIterator for DrainOwned<K, V> {}
+
+/// A draining iterator over the key-value pairs of a map within a key range.
+pub struct DrainRange<'a, K: CompactMapKey, V: 'a> {
+    inner: super::DrainRange<'a, V>,
+    _pd: PhantomData<K>,
+}
+
+impl<'a, K: CompactMapKey, V> Iterator for DrainRange<'a, K, V> {
     type Item = (K, V);
 
     fn next(&mut self) -> Option<(K, V)> {
-        self.inner.next().map(|(k,v)|(From::from(k),v)) 
+        self.inner.next().map(|(k,v)|(K::from_index(k),v))
     }
     fn size_hint(&self) -> (usize, Option<usize>) { self.inner.size_hint() }
 }
-impl<'a, K : Into<usize> + From<usize>, V> DoubleEndedIterator for Drain<'a, K, V> {
+impl<'a, K: CompactMapKey, V> DoubleEndedIterator for DrainRange<'a, K, V> {
     fn next_back(&mut self) -> Option<(K, V)> {
-        self.inner.next_back().map(|(k,v)|(From::from(k),v)) 
+        self.inner.next_back().map(|(k,v)|(K::from_index(k),v))
+    }
+}
+impl<'a, K: CompactMapKey, V> FusedIterator for DrainRange<'a, K, V> {}
+
+
+/// Key type of [the wrapper](struct.CompactMap.html).
+///
+/// The trait is sealed: it is implemented for types created with
+/// [`declare_compactmap_token!`](../macro.declare_compactmap_token.html),
+/// for `usize`, `u32` and `NonZeroUsize`. Other types with conversions
+/// to and from `usize` are not accepted:
+///
+/// ```compile_fail
+/// use compactmap::wrapped::CompactMap;
+///
+/// struct Handmade(usize);
+/// impl From<usize> for Handmade {
+///     fn from(x: usize) -> Handmade { Handmade(x) }
+/// }
+/// impl From<Handmade> for usize {
+///     fn from(x: Handmade) -> usize { x.0 }
+/// }
+///
+/// let map: CompactMap<Handmade, u8> = CompactMap::new();
+/// ```
+pub trait CompactMapKey: Sized + __sealed::Sealed {
+    /// Highest underlying key the type can represent
+    const MAX: usize;
+
+    /// Converts from the underlying key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `i` is greater than `MAX`.
+    fn from_index(i: usize) -> Self;
+
+    /// Converts to the underlying key.
+    fn into_index(self) -> usize;
+}
+
+#[doc(hidden)]
+pub mod __sealed {
+    /// Only reachable by `declare_compactmap_token!`
+    pub trait Sealed {}
+    impl Sealed for usize {}
+    impl Sealed for u32 {}
+    impl Sealed for ::std::num::NonZeroUsize {}
+}
+
+impl CompactMapKey for usize {
+    const MAX: usize = usize::MAX;
+
+    #[inline]
+    fn from_index(i: usize) -> usize {
+        i
+    }
+
+    #[inline]
+    fn into_index(self) -> usize {
+        self
+    }
+}
+
+/// Keys beyond `u32::MAX` only exist on 64-bit targets. Converting them
+/// panics instead of truncating, so a map with `u32` keys panics rather
+/// than hand out a key above `u32::MAX`.
+impl CompactMapKey for u32 {
+    const MAX: usize = u32::MAX as usize;
+
+    #[inline]
+    fn from_index(i: usize) -> u32 {
+        assert!(i <= <u32 as CompactMapKey>::MAX, "key {} does not fit into u32", i);
+        i as u32
+    }
+
+    #[inline]
+    fn into_index(self) -> usize {
+        self as usize
     }
 }
 
+/// Key `k` is stored at underlying index `k - 1`, as in [`nz`](../nz/index.html).
+impl CompactMapKey for ::std::num::NonZeroUsize {
+    const MAX: usize = usize::MAX - 1;
 
-/// Create usize-equivalent struct that implements `From<usize>` and `Into<usize>`
+    #[inline]
+    fn from_index(i: usize) -> ::std::num::NonZeroUsize {
+        assert!(i <= <::std::num::NonZeroUsize as CompactMapKey>::MAX, "key {} does not fit into NonZeroUsize", i);
+        ::std::num::NonZeroUsize::new(i + 1).unwrap()
+    }
+
+    #[inline]
+    fn into_index(self) -> usize {
+        self.get() - 1
+    }
+}
+
+/// Create usize-equivalent struct that implements `From<usize>`, `Into<usize>`
+/// and [`CompactMapKey`](wrapped/trait.CompactMapKey.html)
 ///
 /// For [the wrapper](wrapped/struct.CompactMap.html).
 ///
@@ -418,17 +1803,50 @@ impl<'a, K : Into<usize> + From<usize>, V> DoubleEndedIterator for Drain<'a, K,
 /// declare_compactmap_token!(MyCompactmapIndex);
 /// # fn main(){}
 /// ```
+///
+/// Visibility of the struct can be specified before the name:
+///
+/// ```
+/// #[macro_use] extern crate compactmap;
+/// mod tokens {
+///     declare_compactmap_token!(pub Index);
+/// }
+/// # fn main(){
+/// assert_eq!(usize::from(tokens::Index(3)), 3);
+/// # }
+/// ```
+///
+/// Attributes before it are added to the struct, e.g. for deriving more traits
+/// (`Copy`, `Clone`, `Ord`, `PartialOrd`, `Eq`, `PartialEq`, `Hash` and `Debug`
+/// are always derived):
+///
+/// ```
+/// #[macro_use] extern crate compactmap;
+/// declare_compactmap_token!(#[derive(Default)] #[doc = "Key of a node"] pub NodeIndex);
+/// # fn main(){
+/// assert_eq!(NodeIndex::default(), NodeIndex(0));
+/// # }
+/// ```
 #[macro_export]
 macro_rules! declare_compactmap_token {
-    ($x:ident) => {
+    ($(#[$attr:meta])* $vis:vis $x:ident) => {
         #[derive(Copy,Clone,Ord,PartialOrd,Eq,PartialEq,Hash,Debug)]
-        struct $x(usize);
+        $(#[$attr])*
+        $vis struct $x(pub usize);
         impl From<usize> for $x {
             fn from(x:usize) -> Self { $x(x) }
         }
         impl From<$x> for usize {
             fn from(x:$x) -> usize {x.0}
         }
+        impl $crate::wrapped::__sealed::Sealed for $x {}
+        impl $crate::wrapped::CompactMapKey for $x {
+            const MAX: usize = usize::MAX;
+            #[inline]
+            fn from_index(i: usize) -> Self { $x(i) }
+            #[inline]
+            fn into_index(self) -> usize { self.0 }
+        }
     }
 }
 
@@ -436,9 +1854,9 @@ macro_rules! declare_compactmap_token {
 mod serdizer {
     extern crate serde;
 
-    use super::CompactMap;
+    use super::{CompactMap, CompactMapKey};
 
-    impl<K: Into<usize> + From<usize>, V: serde::Serialize> serde::Serialize for CompactMap<K, V> {
+    impl<K: CompactMapKey, V: serde::Serialize> serde::Serialize for CompactMap<K, V> {
         fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
             self.inner.serialize(s)
         }
@@ -447,7 +1865,7 @@ mod serdizer {
     use self::serde::de::{Deserialize, Deserializer};
 
     // This is the trait that informs Serde how to deserialize MyMap.
-    impl<'de, K: Into<usize> + From<usize>, V> Deserialize<'de> for CompactMap<K, V>
+    impl<'de, K: CompactMapKey, V> Deserialize<'de> for CompactMap<K, V>
     where
         V: Deserialize<'de>,
     {
@@ -460,3 +1878,162 @@ mod serdizer {
         }
     }
 }
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary_impls {
+    extern crate arbitrary;
+
+    use super::{CompactMap, CompactMapKey};
+    use self::arbitrary::{Arbitrary, Result, Unstructured};
+
+    impl<'a, K: CompactMapKey, V: Arbitrary<'a>> Arbitrary<'a> for CompactMap<K, V> {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            Ok(CompactMap::from_unwrapped(Arbitrary::arbitrary(u)?))
+        }
+    }
+}
+
+#[cfg(feature = "schemars")]
+mod schemars_impls {
+    extern crate schemars;
+
+    use super::{CompactMap, CompactMapKey};
+    use self::schemars::JsonSchema;
+    use self::schemars::gen::SchemaGenerator;
+    use self::schemars::schema::Schema;
+
+    // Same serde format as the unwrapped map
+    impl<K: CompactMapKey, V: JsonSchema> JsonSchema for CompactMap<K, V> {
+        fn schema_name() -> String {
+            super::super::CompactMap::<V>::schema_name()
+        }
+
+        fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+            super::super::CompactMap::<V>::json_schema(gen)
+        }
+    }
+}
+
+#[cfg(feature = "bincode2")]
+mod bincode_impls {
+    extern crate bincode2 as bincode;
+
+    use super::{CompactMap, CompactMapKey};
+    use self::bincode::{BorrowDecode, Decode, Encode};
+    use self::bincode::de::{BorrowDecoder, Decoder};
+    use self::bincode::enc::Encoder;
+    use self::bincode::error::{DecodeError, EncodeError};
+
+    impl<K: CompactMapKey, V: Encode> Encode for CompactMap<K, V> {
+        fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+            self.inner.encode(encoder)
+        }
+    }
+
+    impl<Context, K: CompactMapKey, V: Decode<Context>> Decode<Context> for CompactMap<K, V> {
+        fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+            Ok(CompactMap::from_unwrapped(Decode::decode(decoder)?))
+        }
+    }
+
+    impl<'de, Context, K, V> BorrowDecode<'de, Context> for CompactMap<K, V>
+        where K: CompactMapKey, V: BorrowDecode<'de, Context>
+    {
+        fn borrow_decode<D: BorrowDecoder<'de, Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+            Ok(CompactMap::from_unwrapped(BorrowDecode::borrow_decode(decoder)?))
+        }
+    }
+}
+
+#[cfg(feature = "rand")]
+mod rand_impls {
+    extern crate rand;
+
+    use super::{CompactMap, CompactMapKey};
+    use self::rand::Rng;
+
+    impl<K: CompactMapKey, V> CompactMap<K, V> {
+        /// See [`super::CompactMap::sample_entry`](../struct.CompactMap.html#method.sample_entry)
+        pub fn sample_entry<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<(K, &V)> {
+            self.inner.sample_entry(rng).map(|(k, v)| (K::from_index(k), v))
+        }
+
+        /// See [`super::CompactMap::sample_key`](../struct.CompactMap.html#method.sample_key)
+        pub fn sample_key<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<K> {
+            self.inner.sample_key(rng).map(K::from_index)
+        }
+    }
+}
+
+#[cfg(feature = "proptest")]
+mod proptest_impls {
+    use ::std::fmt;
+    use ::std::marker::PhantomData;
+    use proptest::strategy::{NewTree, Strategy, ValueTree};
+    use proptest::test_runner::TestRunner;
+
+    use super::{CompactMap, CompactMapKey};
+
+    /// See [`super::compactmap_strategy`](../fn.compactmap_strategy.html)
+    pub fn compactmap_strategy<K, S>(value_strategy: S) -> CompactMapStrategy<K, S>
+    where
+        K: CompactMapKey,
+        S: Strategy,
+    {
+        From::from(::compactmap_strategy(value_strategy))
+    }
+
+    /// Proptest strategy generating wrapped `CompactMap`s.
+    /// Configured by wrapping an unwrapped [`CompactMapStrategy`](../struct.CompactMapStrategy.html).
+    pub struct CompactMapStrategy<K: CompactMapKey, S> {
+        inner: ::CompactMapStrategy<S>,
+        _pd: PhantomData<fn() -> K>,
+    }
+
+    impl<K: CompactMapKey, S> From<::CompactMapStrategy<S>> for CompactMapStrategy<K, S> {
+        fn from(inner: ::CompactMapStrategy<S>) -> Self {
+            CompactMapStrategy { inner, _pd: PhantomData }
+        }
+    }
+
+    impl<K: CompactMapKey, S: fmt::Debug> fmt::Debug for CompactMapStrategy<K, S> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            self.inner.fmt(f)
+        }
+    }
+
+    impl<K: CompactMapKey, S: Strategy> Strategy for CompactMapStrategy<K, S> {
+        type Tree = CompactMapValueTree<K, S::Tree>;
+        type Value = CompactMap<K, S::Value>;
+
+        fn new_tree(&self, runner: &mut TestRunner) -> NewTree<Self> {
+            Ok(CompactMapValueTree {
+                inner: self.inner.new_tree(runner)?,
+                _pd: PhantomData,
+            })
+        }
+    }
+
+    /// `ValueTree` of [`CompactMapStrategy`](struct.CompactMapStrategy.html)
+    pub struct CompactMapValueTree<K: CompactMapKey, T> {
+        inner: ::CompactMapValueTree<T>,
+        _pd: PhantomData<fn() -> K>,
+    }
+
+    impl<K: CompactMapKey, T: ValueTree> ValueTree for CompactMapValueTree<K, T> {
+        type Value = CompactMap<K, T::Value>;
+
+        fn current(&self) -> CompactMap<K, T::Value> {
+            CompactMap::from_unwrapped(self.inner.current())
+        }
+        fn simplify(&mut self) -> bool {
+            self.inner.simplify()
+        }
+        fn complicate(&mut self) -> bool {
+            self.inner.complicate()
+        }
+    }
+}
+
+#[cfg(feature = "proptest")]
+pub use self::proptest_impls::{compactmap_strategy, CompactMapStrategy, CompactMapValueTree};